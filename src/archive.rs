@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use log::warn;
+use thiserror::Error;
+use zip::{CompressionMethod, ZipArchive};
+use zip::result::ZipError;
+
+use crate::collation::compare_text;
+use crate::db::Track;
+
+/// Extensions of the files in an archive that are taken for tracks, for the formats the
+/// decoders know.
+const AUDIO_EXTENSIONS: &[&str] = &["aac", "flac", "m4a", "mp3", "mp4", "oga", "ogg", "wav"];
+
+/// Between an archive's path and the name of an entry in it, in the path of a track inside one.
+const ENTRY_SEPARATOR: &str = "!/";
+
+/// Ids for tracks inside archives, which count down so they never clash with the database's.
+static NEXT_ENTRY_ID: AtomicI64 = AtomicI64::new(-1);
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("it isn't a zip archive that can be read ({0})")]
+    Zip(#[from] ZipError),
+    #[error("{0} is compressed in a way that isn't supported")]
+    UnsupportedCompression(String),
+}
+
+/// Whether the file at `path` is an archive that tracks can be played from.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// The path of a track inside the archive at `archive`.
+pub fn entry_path(archive: &str, entry: &str) -> String {
+    format!("{}{}{}", archive, ENTRY_SEPARATOR, entry)
+}
+
+/// The archive and entry a track's path is for, if it's inside one.
+pub fn split_entry_path(path: &str) -> Option<(&str, &str)> {
+    path.match_indices(ENTRY_SEPARATOR)
+        .map(|(index, _)| (&path[..index], &path[index + ENTRY_SEPARATOR.len()..]))
+        .find(|(archive, _)| is_archive(Path::new(archive)))
+}
+
+/// The names of the tracks in the archive `reader` reads, in natural order. Entries compressed
+/// in a way that can't be read are left out.
+pub fn audio_entries<R: Read + Seek>(reader: R) -> Result<Vec<String>, ArchiveError> {
+    let mut archive = ZipArchive::new(reader)?;
+
+    let mut names = Vec::new();
+    for index in 0..archive.len() {
+        // Raw, so entries that can't be decompressed can still be named
+        let entry = archive.by_index_raw(index)?;
+        if entry.is_dir() || !is_audio(entry.name()) {
+            continue;
+        }
+        if !is_supported(entry.compression()) {
+            warn!("Skipping {}, it is compressed with {:?}", entry.name(), entry.compression());
+            continue;
+        }
+
+        names.push(entry.name().to_string());
+    }
+    names.sort_by(|a, b| compare_text(a, b));
+
+    Ok(names)
+}
+
+/// The whole of the entry `name` in the archive `reader` reads, uncompressed in memory.
+pub fn read_entry<R: Read + Seek>(reader: R, name: &str) -> Result<Arc<[u8]>, ArchiveError> {
+    let mut archive = ZipArchive::new(reader)?;
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.name() == name && !is_supported(entry.compression()) {
+            return Err(ArchiveError::UnsupportedCompression(name.to_string()));
+        }
+    }
+
+    let mut entry = archive.by_name(name)?;
+    let mut contents = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut contents)?;
+
+    Ok(contents.into())
+}
+
+/// A reader over the entry `name` in the archive at `archive`, to decode it from without
+/// extracting it.
+pub fn open_entry(archive: &str, name: &str) -> Result<Cursor<Arc<[u8]>>, ArchiveError> {
+    let file = BufReader::new(File::open(archive)?);
+    Ok(Cursor::new(read_entry(file, name)?))
+}
+
+/// The tracks in the archive at `archive`, to queue. They're named after their files, and
+/// aren't added to the database, so they only last as long as the queue does.
+pub fn archive_tracks(archive: &str) -> Result<Vec<Track>, ArchiveError> {
+    let album = Path::new(archive).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+    let names = audio_entries(BufReader::new(File::open(archive)?))?;
+
+    Ok(names.into_iter()
+        .map(|name| {
+            let title = Path::new(&name).file_stem()
+                .map_or_else(|| name.clone(), |stem| stem.to_string_lossy().into_owned());
+            let id = NEXT_ENTRY_ID.fetch_sub(1, Ordering::Relaxed);
+            Track::ephemeral(id, entry_path(archive, &name), title, album.clone())
+        })
+        .collect())
+}
+
+fn is_audio(name: &str) -> bool {
+    Path::new(name).extension().map_or(false, |extension| {
+        AUDIO_EXTENSIONS.iter().any(|audio| extension.eq_ignore_ascii_case(audio))
+    })
+}
+
+fn is_supported(compression: CompressionMethod) -> bool {
+    matches!(compression, CompressionMethod::Stored | CompressionMethod::Deflated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use rodio::Source;
+    use tempfile::TempDir;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use crate::decode::{decode_with, DECODERS};
+    use crate::test_support::write_wav;
+    use super::*;
+
+    /// A zip of `entries`, as (name, contents) and compressed with `compression`.
+    fn zip_of(entries: &[(&str, &[u8])], compression: CompressionMethod) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(compression);
+        for &(name, contents) in entries {
+            if name.ends_with('/') {
+                zip.add_directory(name, options).unwrap();
+            } else {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(contents).unwrap();
+            }
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn wav(dir: &TempDir, seconds: u32) -> Vec<u8> {
+        let path = dir.path().join("track.wav");
+        write_wav(&path, seconds, 0);
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn only_the_tracks_in_an_archive_are_listed_in_natural_order() {
+        let dir = TempDir::new().unwrap();
+        let wav: &[u8] = &wav(&dir, 1);
+        let zip = zip_of(&[("10 - Ten.wav", wav),
+                           ("cover.jpg", b"not a track"),
+                           ("2 - Two.FLAC", wav),
+                           ("bonus/", &[]),
+                           ("bonus/1 - One.mp3", wav)], CompressionMethod::Deflated);
+
+        assert_eq!(audio_entries(Cursor::new(zip)).unwrap(),
+                   vec!["2 - Two.FLAC", "10 - Ten.wav", "bonus/1 - One.mp3"]);
+    }
+
+    #[test]
+    fn entries_compressed_in_unsupported_ways_are_skipped() {
+        let mut zip = zip_of(&[("1.wav", &b"RIFF"[..])], CompressionMethod::Stored);
+        // Mark it as bzip2 in both its local and central headers
+        for (signature, offset) in &[(b"PK\x03\x04", 8), (b"PK\x01\x02", 10)] {
+            let header = zip.windows(4).position(|window| window == *signature).unwrap();
+            zip[header + offset..header + offset + 2].copy_from_slice(&12u16.to_le_bytes());
+        }
+
+        assert!(audio_entries(Cursor::new(zip.clone())).unwrap().is_empty());
+        assert!(matches!(read_entry(Cursor::new(zip), "1.wav"),
+                         Err(ArchiveError::UnsupportedCompression(name)) if name == "1.wav"));
+    }
+
+    #[test]
+    fn entries_play_straight_from_the_archive() {
+        let dir = TempDir::new().unwrap();
+        let zip = zip_of(&[("1.wav", &wav(&dir, 2)[..])], CompressionMethod::Deflated);
+
+        let contents = read_entry(Cursor::new(zip), "1.wav").unwrap();
+        let decoded = decode_with(DECODERS, || Ok(Cursor::new(contents.clone()))).unwrap();
+        assert_eq!(decoded.source.channels(), 1);
+        assert_eq!(decoded.source.sample_rate(), 8000);
+        assert_eq!(decoded.source.count(), 2 * 8000);
+    }
+
+    #[test]
+    fn archive_tracks_are_named_after_their_files_and_never_saved() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("Album.zip");
+        let wav: &[u8] = &wav(&dir, 1);
+        let zip = zip_of(&[("1 - One.wav", wav), ("2 - Two.wav", wav)],
+                         CompressionMethod::Deflated);
+        fs::write(&path, zip).unwrap();
+        let archive = path.to_str().unwrap();
+
+        let tracks = archive_tracks(archive).unwrap();
+        let titles: Vec<_> = tracks.iter().map(|track| track.title().unwrap()).collect();
+        assert_eq!(titles, vec!["1 - One", "2 - Two"]);
+        assert!(tracks.iter().all(|track| track.album() == Some("Album") && track.is_ephemeral()));
+        assert_ne!(tracks[0].id(), tracks[1].id());
+
+        assert_eq!(split_entry_path(tracks[1].path()), Some((archive, "2 - Two.wav")));
+        let mut entry = open_entry(archive, "2 - Two.wav").unwrap();
+        assert_eq!(entry.get_ref().len(), wav.len());
+        assert!(entry.read(&mut [0; 4]).is_ok());
+    }
+
+    #[test]
+    fn paths_inside_archives_split_at_the_archive() {
+        assert_eq!(split_entry_path("/music/a!/b.zip!/c/d.flac"),
+                   Some(("/music/a!/b.zip", "c/d.flac")));
+        assert_eq!(split_entry_path("/music/A.ZIP!/1.mp3"), Some(("/music/A.ZIP", "1.mp3")));
+        assert_eq!(split_entry_path("/music/hey!/1.mp3"), None);
+        assert_eq!(split_entry_path("/music/1.mp3"), None);
+        assert_eq!(entry_path("/music/a.zip", "1.mp3"), "/music/a.zip!/1.mp3");
+    }
+}
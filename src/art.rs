@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use druid::{BoxConstraints, Data, Env, Event, EventCtx, ImageBuf, LayoutCtx, LifeCycle, LifeCycleCtx,
+            PaintCtx, Size, UpdateCtx, Widget};
+use druid::widget::{FillStrat, Image};
+use log::warn;
+
+use crate::db::{Database, Track};
+
+/// Images looked for next to a track when it has no art of its own, in order of preference.
+const FOLDER_ART: [&str; 6] = ["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg",
+                               "front.png"];
+/// Decoded images kept around at most. Covers are small, but libraries aren't.
+const CACHE_SIZE: usize = 64;
+
+/// The first of `FOLDER_ART` in the same folder as the track at `path`.
+pub fn folder_art(path: &str) -> Option<Vec<u8>> {
+    let folder = Path::new(path).parent()?;
+    FOLDER_ART.iter().find_map(|name| fs::read(folder.join(name)).ok())
+}
+
+/// Decoded album art by track id, shared between everything that shows it. Tracks without art
+/// are cached too, so they aren't looked up again.
+#[derive(Clone, Default)]
+pub struct ArtCache(Arc<RwLock<HashMap<i64, Option<ImageBuf>>>>);
+
+impl Data for ArtCache {
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl ArtCache {
+    pub fn get(&self, db: &Database, track: &Track) -> Option<ImageBuf> {
+        if track.is_ephemeral() {
+            return None;
+        }
+        if let Some(image) = self.0.read().unwrap().get(&track.id()) {
+            return image.clone();
+        }
+
+        let image = match db.album_art(track.id()) {
+            Ok(Some(data)) => ImageBuf::from_data(&data)
+                .map_err(|e| warn!("Could not decode the art of {}: {}", track.path(), e))
+                .ok(),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Could not look up the art of {}: {}", track.path(), e);
+                None
+            }
+        };
+
+        let mut cache = self.0.write().unwrap();
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(track.id(), image.clone());
+
+        image
+    }
+}
+
+/// Album art, scaled to fit. Shows nothing when there's no art.
+pub struct CoverArt {
+    image: Image,
+}
+
+impl CoverArt {
+    pub fn new() -> Self {
+        CoverArt {
+            image: Image::new(ImageBuf::empty()).fill_mode(FillStrat::Contain),
+        }
+    }
+}
+
+impl Widget<Option<ImageBuf>> for CoverArt {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Option<ImageBuf>, env: &Env) {
+        self.image.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &Option<ImageBuf>,
+                 env: &Env) {
+        if let LifeCycle::WidgetAdded = event {
+            self.image.set_image_data(data.clone().unwrap_or_else(ImageBuf::empty));
+        }
+        self.image.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Option<ImageBuf>, data: &Option<ImageBuf>,
+              env: &Env) {
+        if !old_data.same(data) {
+            self.image.set_image_data(data.clone().unwrap_or_else(ImageBuf::empty));
+            ctx.request_paint();
+        }
+        self.image.update(ctx, old_data, data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &Option<ImageBuf>,
+              env: &Env) -> Size {
+        self.image.layout(ctx, bc, data, env)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Option<ImageBuf>, env: &Env) {
+        self.image.paint(ctx, data, env);
+    }
+}
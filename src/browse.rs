@@ -0,0 +1,510 @@
+use std::sync::Arc;
+
+use druid::{Data, Env, Lens, RenderContext, Widget, WidgetExt};
+use druid::lens;
+use druid::widget::{Either, Flex, Label, List, Painter, SizedBox};
+
+use crate::colors::ALT_BACKGROUND_COLOR;
+use crate::commands::{PLAY_TRACK, SHOW_SCOPE, TOGGLE_ALBUM_NODE, TOGGLE_ARTIST_NODE};
+use crate::collation::{compare_text, fold};
+use crate::db::{Database, DatabaseError, Track};
+
+const EXPANDED: &str = "▾";
+const COLLAPSED: &str = "▸";
+/// How far each level of the tree is indented from the one above.
+const INDENT: f64 = 12.0;
+
+/// Part of the library, as picked in the artist tree.
+#[derive(Debug, Clone, Data, PartialEq)]
+pub enum Scope {
+    Artist(Group),
+    /// An album, and the artist it's under.
+    Album(Group, Group),
+}
+
+impl Scope {
+    pub fn contains(&self, track: &Track) -> bool {
+        match self {
+            Scope::Artist(artist) => artist.contains(track.artist()),
+            Scope::Album(artist, album) => {
+                artist.contains(track.artist()) && album.contains(track.album())
+            }
+        }
+    }
+}
+
+/// An artist or album in the tree, and every tag that's grouped under it, e.g. "Miles Davis"
+/// for "Miles Davis" and "Miles Davis feat. John Coltrane". The tags themselves are left as
+/// they are. `None` is a missing artist or album.
+#[derive(Debug, Clone, Data, PartialEq)]
+pub struct Group {
+    name: Option<String>,
+    tags: Arc<Vec<Option<String>>>,
+}
+
+impl Group {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn contains(&self, tag: Option<&str>) -> bool {
+        self.tags.iter().any(|candidate| candidate.as_deref() == tag)
+    }
+}
+
+/// How tags are grouped in the tree.
+#[derive(Debug, Clone, Data, PartialEq)]
+pub struct Grouping {
+    /// Words that introduce a featured artist, like "feat.", lowercase. Artists are grouped
+    /// without them and what follows.
+    featured_artist_patterns: Arc<Vec<String>>,
+    /// Words that mark an edition of an album, like "deluxe", lowercase. Albums are grouped
+    /// without a suffix that has one.
+    album_edition_patterns: Arc<Vec<String>>,
+}
+
+impl Grouping {
+    pub fn new(featured_artist_patterns: Vec<String>, album_edition_patterns: Vec<String>) -> Self {
+        Grouping {
+            featured_artist_patterns: Arc::new(featured_artist_patterns),
+            album_edition_patterns: Arc::new(album_edition_patterns),
+        }
+    }
+
+    /// The name `artist` is grouped under.
+    fn artist_name<'a>(&self, artist: &'a str) -> &'a str {
+        primary_artist(artist, &self.featured_artist_patterns)
+    }
+
+    /// The name `album` is grouped under.
+    fn album_name<'a>(&self, album: &'a str) -> &'a str {
+        album_base(album, &self.album_edition_patterns)
+    }
+}
+
+/// `artist` without a featured artist, so "Artist feat. Guest" and "Artist (ft. Guest)" are
+/// just "Artist". `patterns` are the words that introduce one, and only match whole words,
+/// in any case. An artist that's nothing but a featured one is left as it is.
+pub fn primary_artist<'a>(artist: &'a str, patterns: &[String]) -> &'a str {
+    let artist = artist.trim();
+    let mut previous = None;
+
+    for (index, c) in artist.char_indices() {
+        let word_start = matches!(previous, Some(p) if p == '(' || p == '[' || p.is_whitespace());
+        previous = Some(c);
+        if !word_start {
+            continue;
+        }
+
+        let rest = &artist[index..];
+        let featured = patterns.iter().any(|pattern| {
+            match rest.get(..pattern.len()) {
+                Some(start) if start.eq_ignore_ascii_case(pattern) => {
+                    rest[pattern.len()..].starts_with(char::is_whitespace)
+                }
+                _ => false,
+            }
+        });
+
+        if featured {
+            let primary = artist[..index].trim_end()
+                .trim_end_matches(|c| c == '(' || c == '[')
+                .trim_end();
+            if !primary.is_empty() {
+                return primary;
+            }
+        }
+    }
+
+    artist
+}
+
+/// `album` without an edition suffix, so "Album (Deluxe Edition)", "Album [2011 Remaster]" and
+/// "Album - Remastered" are all just "Album". A suffix is only dropped if it has one of
+/// `patterns` in it, in any case, so "Album (Live)" is left alone unless "live" is one of them.
+pub fn album_base<'a>(album: &'a str, patterns: &[String]) -> &'a str {
+    let mut album = album.trim();
+
+    // For suffixes on suffixes, like "Album (Deluxe) [Remastered]"
+    loop {
+        let suffix_start = if album.ends_with(')') {
+            album.rfind('(')
+        } else if album.ends_with(']') {
+            album.rfind('[')
+        } else {
+            album.rfind(" - ")
+        };
+
+        let start = match suffix_start {
+            Some(start) => start,
+            None => return album,
+        };
+
+        let suffix = album[start..].to_lowercase();
+        let base = album[..start].trim_end();
+        if base.is_empty() || !patterns.iter().any(|pattern| suffix.contains(pattern.as_str())) {
+            return album;
+        }
+        album = base;
+    }
+}
+
+/// Group tags that are the same once `name` has normalized them and case and accents are
+/// ignored. Each group is shown as its first tag, normalized. Groups come alphabetically, with
+/// the missing one last.
+fn group_tags(tags: impl IntoIterator<Item = Option<String>>,
+                  name: impl Fn(&str) -> &str) -> Vec<Group> {
+    let mut groups: Vec<(Option<String>, Option<String>, Vec<Option<String>>)> = Vec::new();
+
+    for tag in tags {
+        let display = tag.as_deref().map(|tag| name(tag).to_string());
+        let key = display.as_deref().map(fold);
+        match groups.iter_mut().find(|(group_key, _, _)| *group_key == key) {
+            Some((_, _, group_tags)) => group_tags.push(tag),
+            None => groups.push((key, display, vec![tag])),
+        }
+    }
+
+    groups.sort_by(|(_, a, _), (_, b, _)| match (a, b) {
+        (Some(a), Some(b)) => compare_text(a, b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+
+    groups.into_iter()
+        .map(|(_, name, tags)| Group { name, tags: Arc::new(tags) })
+        .collect()
+}
+
+/// An artist in the tree. Its albums are read from the database when it's expanded.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct ArtistNode {
+    artist: Group,
+    expanded: bool,
+    albums: Arc<Vec<AlbumNode>>,
+}
+
+/// An album in the tree. Its tracks are read from the database when it's expanded.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct AlbumNode {
+    artist: Group,
+    album: Group,
+    expanded: bool,
+    tracks: Arc<Vec<Track>>,
+}
+
+/// Every artist, collapsed.
+pub fn artist_tree(db: &Database,
+                   grouping: &Grouping) -> Result<Arc<Vec<ArtistNode>>, DatabaseError> {
+    let artists = group_tags(db.distinct_artists()?, |artist| grouping.artist_name(artist));
+
+    Ok(Arc::new(artists.into_iter()
+        .map(|artist| ArtistNode {
+            artist,
+            expanded: false,
+            albums: Arc::new(Vec::new()),
+        })
+        .collect()))
+}
+
+/// Re-read the tree after the library changes, keeping whatever was expanded expanded.
+pub fn refresh_artist_tree(db: &Database, grouping: &Grouping,
+                           old: &[ArtistNode]) -> Result<Arc<Vec<ArtistNode>>, DatabaseError> {
+    let mut nodes = artist_tree(db, grouping)?.to_vec();
+
+    for node in &mut nodes {
+        let old_node = old.iter().find(|old| old.expanded && old.artist.name == node.artist.name);
+        let old_node = match old_node {
+            Some(old_node) => old_node,
+            None => continue,
+        };
+
+        let mut albums = album_nodes(db, grouping, &node.artist)?;
+        for album in &mut albums {
+            let was_expanded = old_node.albums.iter()
+                .any(|old| old.expanded && old.album.name == album.album.name);
+            if was_expanded {
+                album.tracks = Arc::new(album_tracks(db, &album.artist, &album.album)?);
+                album.expanded = true;
+            }
+        }
+
+        node.albums = Arc::new(albums);
+        node.expanded = true;
+    }
+
+    Ok(Arc::new(nodes))
+}
+
+/// Expand the artist shown as `artist`, reading its albums, or collapse it.
+pub fn toggle_artist(db: &Database, grouping: &Grouping, tree: &mut Arc<Vec<ArtistNode>>,
+                     artist: &Option<String>) -> Result<(), DatabaseError> {
+    // A new Arc, so the tree sees the change
+    let mut nodes = tree.to_vec();
+    if let Some(node) = nodes.iter_mut().find(|node| &node.artist.name == artist) {
+        if !node.expanded {
+            node.albums = Arc::new(album_nodes(db, grouping, &node.artist)?);
+        }
+        node.expanded = !node.expanded;
+    }
+
+    *tree = Arc::new(nodes);
+    Ok(())
+}
+
+/// Expand an artist's album, reading its tracks, or collapse it.
+pub fn toggle_album(db: &Database, tree: &mut Arc<Vec<ArtistNode>>,
+                    artist: &Option<String>, album: &Option<String>) -> Result<(), DatabaseError> {
+    let mut nodes = tree.to_vec();
+    if let Some(node) = nodes.iter_mut().find(|node| &node.artist.name == artist) {
+        let mut albums = node.albums.to_vec();
+        if let Some(album_node) = albums.iter_mut().find(|node| &node.album.name == album) {
+            if !album_node.expanded {
+                album_node.tracks = Arc::new(album_tracks(db, &album_node.artist,
+                                                          &album_node.album)?);
+            }
+            album_node.expanded = !album_node.expanded;
+        }
+        node.albums = Arc::new(albums);
+    }
+
+    *tree = Arc::new(nodes);
+    Ok(())
+}
+
+/// Every album with a track by any of `artist`'s tags.
+fn album_nodes(db: &Database, grouping: &Grouping,
+               artist: &Group) -> Result<Vec<AlbumNode>, DatabaseError> {
+    let mut albums = Vec::new();
+    for tag in artist.tags.iter() {
+        albums.extend(db.albums_for_artist(tag.as_deref())?);
+    }
+
+    Ok(group_tags(albums, |album| grouping.album_name(album)).into_iter()
+        .map(|album| AlbumNode {
+            artist: artist.clone(),
+            album,
+            expanded: false,
+            tracks: Arc::new(Vec::new()),
+        })
+        .collect())
+}
+
+/// The tracks of `album` by `artist`, in track order, whichever of their tags they have.
+fn album_tracks(db: &Database, artist: &Group, album: &Group) -> Result<Vec<Track>, DatabaseError> {
+    let mut tracks = Vec::new();
+    for artist in artist.tags.iter() {
+        for album in album.tags.iter() {
+            tracks.extend(db.tracks_for_album(artist.as_deref(), album.as_deref())?);
+        }
+    }
+
+    // Missing track numbers first, as in the database
+    tracks.sort_by_key(|track| (track.disc(), track.track()));
+    Ok(tracks)
+}
+
+/// Artists, which expand to their albums, which expand to their tracks. Clicking an artist or
+/// album shows just its tracks in the main track list, and clicking a track plays it. Each node
+/// needs to know what's being shown, to highlight it.
+pub fn make_artist_tree() -> impl Widget<(Option<Scope>, Arc<Vec<ArtistNode>>)> {
+    List::new(make_artist_node)
+}
+
+fn make_artist_node() -> impl Widget<(Option<Scope>, ArtistNode)> {
+    let background = Painter::new(|ctx, (scope, node): &(Option<Scope>, ArtistNode), env| {
+        if *scope == Some(Scope::Artist(node.artist.clone())) {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    let row = Flex::row()
+        .with_child(Label::new(|(_, node): &(Option<Scope>, ArtistNode), _env: &Env| {
+                disclosure(node.expanded).to_string()
+            })
+            .on_click(|ctx, (_, node): &mut (Option<Scope>, ArtistNode), _env| {
+                ctx.submit_command(TOGGLE_ARTIST_NODE.with(node.artist.name.clone()));
+            }))
+        .with_flex_child(Label::new(|(_, node): &(Option<Scope>, ArtistNode), _env: &Env| {
+                node.artist.name().unwrap_or("Unknown Artist").to_string()
+            })
+            .expand_width()
+            .on_click(|ctx, (_, node): &mut (Option<Scope>, ArtistNode), _env| {
+                ctx.submit_command(SHOW_SCOPE.with(Scope::Artist(node.artist.clone())));
+            }), 1.0)
+        .padding((6., 3.))
+        .background(background);
+
+    let albums = List::new(make_album_node)
+        .lens(lens::Map::new(
+            |(scope, node): &(Option<Scope>, ArtistNode)| (scope.clone(), node.albums.clone()),
+            |_data: &mut (Option<Scope>, ArtistNode), _albums| ()))
+        .padding((INDENT, 0., 0., 0.));
+
+    Flex::column()
+        .with_child(row.expand_width())
+        .with_child(Either::new(|(_, node): &(Option<Scope>, ArtistNode), _env| node.expanded,
+                                albums.expand_width(),
+                                SizedBox::empty()))
+}
+
+fn make_album_node() -> impl Widget<(Option<Scope>, AlbumNode)> {
+    let background = Painter::new(|ctx, (scope, node): &(Option<Scope>, AlbumNode), env| {
+        if *scope == Some(Scope::Album(node.artist.clone(), node.album.clone())) {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    let row = Flex::row()
+        .with_child(Label::new(|(_, node): &(Option<Scope>, AlbumNode), _env: &Env| {
+                disclosure(node.expanded).to_string()
+            })
+            .on_click(|ctx, (_, node): &mut (Option<Scope>, AlbumNode), _env| {
+                ctx.submit_command(TOGGLE_ALBUM_NODE.with((node.artist.name.clone(),
+                                                             node.album.name.clone())));
+            }))
+        .with_flex_child(Label::new(|(_, node): &(Option<Scope>, AlbumNode), _env: &Env| {
+                node.album.name().unwrap_or("Unknown Album").to_string()
+            })
+            .expand_width()
+            .on_click(|ctx, (_, node): &mut (Option<Scope>, AlbumNode), _env| {
+                ctx.submit_command(SHOW_SCOPE.with(Scope::Album(node.artist.clone(),
+                                                                node.album.clone())));
+            }), 1.0)
+        .padding((6., 3.))
+        .background(background);
+
+    let tracks = List::new(make_track_node)
+        .lens(lens::Map::new(
+            |(_, node): &(Option<Scope>, AlbumNode)| node.tracks.clone(),
+            |_data: &mut (Option<Scope>, AlbumNode), _tracks| ()))
+        .padding((INDENT, 0., 0., 0.));
+
+    Flex::column()
+        .with_child(row.expand_width())
+        .with_child(Either::new(|(_, node): &(Option<Scope>, AlbumNode), _env| node.expanded,
+                                tracks.expand_width(),
+                                SizedBox::empty()))
+}
+
+fn make_track_node() -> impl Widget<Track> {
+    Label::new(|track: &Track, _env: &Env| track.title().unwrap_or("Unknown Title").to_string())
+        .expand_width()
+        .padding((6., 3.))
+        .on_click(|ctx, track: &mut Track, _env| ctx.submit_command(PLAY_TRACK.with(track.clone())))
+}
+
+fn disclosure(expanded: bool) -> &'static str {
+    if expanded { EXPANDED } else { COLLAPSED }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn featured() -> Vec<String> {
+        vec!["feat.".to_string(), "ft.".to_string(), "featuring".to_string()]
+    }
+
+    fn editions() -> Vec<String> {
+        vec!["deluxe".to_string(), "remaster".to_string()]
+    }
+
+    fn tags(tags: &[Option<&str>]) -> Vec<Option<String>> {
+        tags.iter().map(|tag| tag.map(str::to_string)).collect()
+    }
+
+    fn group(name: Option<&str>, group_tags: &[Option<&str>]) -> Group {
+        Group { name: name.map(str::to_string), tags: Arc::new(tags(group_tags)) }
+    }
+
+    #[test]
+    fn featured_artists_are_dropped() {
+        let primary = |artist| primary_artist(artist, &featured());
+
+        assert_eq!(primary("Miles Davis feat. John Coltrane"), "Miles Davis");
+        assert_eq!(primary("Miles Davis FT. John Coltrane"), "Miles Davis");
+        assert_eq!(primary("Miles Davis (featuring John Coltrane)"), "Miles Davis");
+        assert_eq!(primary("Miles Davis [Feat. Coltrane & Evans]"), "Miles Davis");
+        assert_eq!(primary("  Miles Davis  "), "Miles Davis");
+    }
+
+    #[test]
+    fn only_whole_words_introduce_featured_artists() {
+        let primary = |artist| primary_artist(artist, &featured());
+
+        assert_eq!(primary("The Left Feet. Band"), "The Left Feet. Band");
+        assert_eq!(primary("Soft. Cell"), "Soft. Cell");
+        assert_eq!(primary("Loft. Jazz"), "Loft. Jazz");
+        // Nothing follows it
+        assert_eq!(primary("Artist feat."), "Artist feat.");
+        // Nothing would be left
+        assert_eq!(primary("feat. Someone"), "feat. Someone");
+        assert_eq!(primary_artist("Miles Davis feat. Coltrane", &[]), "Miles Davis feat. Coltrane");
+    }
+
+    #[test]
+    fn artists_are_grouped_under_their_primary_artist() {
+        let grouping = Grouping::new(featured(), editions());
+        let artists = tags(&[None, Some("miles davis"), Some("Bill Evans"),
+                             Some("Miles Davis feat. John Coltrane"), Some("Miles Davis")]);
+
+        assert_eq!(group_tags(artists, |artist| grouping.artist_name(artist)),
+                   vec![group(Some("Bill Evans"), &[Some("Bill Evans")]),
+                        group(Some("miles davis"), &[Some("miles davis"),
+                                                     Some("Miles Davis feat. John Coltrane"),
+                                                     Some("Miles Davis")]),
+                        group(None, &[None])]);
+    }
+
+    #[test]
+    fn scopes_contain_every_tag_in_their_group() {
+        let artist = group(Some("Miles Davis"), &[Some("Miles Davis"),
+                                                  Some("Miles Davis ft. Gil Evans")]);
+        let album = group(Some("Sketches of Spain"), &[Some("Sketches of Spain")]);
+        let track = Track::for_test(1, "Solea").with_artist("Miles Davis ft. Gil Evans")
+            .with_album("Sketches of Spain");
+
+        assert!(Scope::Artist(artist.clone()).contains(&track));
+        assert!(Scope::Album(artist.clone(), album).contains(&track));
+        assert!(!Scope::Album(artist, group(None, &[None])).contains(&track));
+        assert!(!Scope::Artist(group(Some("Gil Evans"), &[Some("Gil Evans")])).contains(&track));
+    }
+
+    #[test]
+    fn edition_suffixes_are_dropped() {
+        let base = |album| album_base(album, &editions());
+
+        assert_eq!(base("Kind of Blue (Deluxe Edition)"), "Kind of Blue");
+        assert_eq!(base("Kind of Blue [2011 REMASTER]"), "Kind of Blue");
+        assert_eq!(base("Kind of Blue - Remastered"), "Kind of Blue");
+        assert_eq!(base("Kind of Blue (Deluxe) [Remastered] "), "Kind of Blue");
+        assert_eq!(base("  Kind of Blue "), "Kind of Blue");
+    }
+
+    #[test]
+    fn other_suffixes_are_kept() {
+        let base = |album| album_base(album, &editions());
+
+        assert_eq!(base("At Newport (Live)"), "At Newport (Live)");
+        assert_eq!(base("Kind of Blue (Deluxe) (Live)"), "Kind of Blue (Deluxe) (Live)");
+        assert_eq!(base("Volume 2 - The Sequel"), "Volume 2 - The Sequel");
+        // Nothing would be left
+        assert_eq!(base("(Deluxe)"), "(Deluxe)");
+        assert_eq!(album_base("Kind of Blue (Deluxe)", &[]), "Kind of Blue (Deluxe)");
+    }
+
+    #[test]
+    fn near_identical_albums_are_grouped_together() {
+        let grouping = Grouping::new(featured(), editions());
+        let albums = tags(&[Some("Kind of Blue"), Some("kind of blue "),
+                            Some("Kind of Blue (Deluxe Edition)"), Some("Milestones"), None]);
+
+        assert_eq!(group_tags(albums, |album| grouping.album_name(album)),
+                   vec![group(Some("Kind of Blue"), &[Some("Kind of Blue"), Some("kind of blue "),
+                                                      Some("Kind of Blue (Deluxe Edition)")]),
+                        group(Some("Milestones"), &[Some("Milestones")]),
+                        group(None, &[None])]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use log::warn;
+
+/// What mus was asked to do on the command line.
+#[derive(Debug, PartialEq)]
+pub enum Invocation {
+    /// Just open the library.
+    Normal,
+    /// Play these files, and the files in these folders (e.g. from "Open with mus" in a file
+    /// manager). Paths are canonical.
+    Open(Vec<PathBuf>),
+}
+
+/// Classify the arguments (without the program name). There are no subcommands or options yet,
+/// so anything starting with `-` is rejected rather than mistaken for a file; use `--` to open
+/// a file whose name starts with a dash.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Invocation, String> {
+    let mut files = Vec::new();
+    let mut options_done = false;
+
+    for arg in args {
+        if !options_done && arg == "--" {
+            options_done = true;
+        } else if !options_done && arg.starts_with('-') {
+            return Err(format!("Unknown option {}", arg));
+        } else {
+            match PathBuf::from(&arg).canonicalize() {
+                Ok(path) if path.is_file() || path.is_dir() => files.push(path),
+                _ => warn!("Ignoring {}, it is not a file or folder", arg),
+            }
+        }
+    }
+
+    if files.is_empty() {
+        Ok(Invocation::Normal)
+    } else {
+        Ok(Invocation::Open(files))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn no_arguments_opens_the_library() {
+        assert_eq!(parse_args(Vec::new()), Ok(Invocation::Normal));
+    }
+
+    #[test]
+    fn files_are_opened_by_their_canonical_paths() {
+        let dir = TempDir::new().unwrap();
+        let a = dir.path().join("a.mp3");
+        let b = dir.path().join("b.flac");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+        let relative = dir.path().join(".").join("b.flac");
+
+        let invocation = parse_args(args(&[a.to_str().unwrap(), relative.to_str().unwrap()]));
+
+        assert_eq!(invocation, Ok(Invocation::Open(vec![a.canonicalize().unwrap(),
+                                                        b.canonicalize().unwrap()])));
+    }
+
+    #[test]
+    fn folders_are_opened_and_missing_files_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing.mp3");
+
+        let invocation = parse_args(args(&[dir.path().to_str().unwrap(),
+                                           missing.to_str().unwrap()]));
+        assert_eq!(invocation, Ok(Invocation::Open(vec![dir.path().canonicalize().unwrap()])));
+
+        assert_eq!(parse_args(args(&[missing.to_str().unwrap()])), Ok(Invocation::Normal));
+    }
+
+    #[test]
+    fn options_are_rejected() {
+        assert!(parse_args(args(&["--help"])).is_err());
+        assert!(parse_args(args(&["-v", "song.mp3"])).is_err());
+    }
+
+    #[test]
+    fn anything_after_a_double_dash_is_a_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.mp3");
+        File::create(&file).unwrap();
+
+        let invocation = parse_args(args(&["--", "-v", file.to_str().unwrap()]));
+
+        // -v isn't a file here, but it isn't an option either
+        assert_eq!(invocation, Ok(Invocation::Open(vec![file.canonicalize().unwrap()])));
+    }
+}
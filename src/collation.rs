@@ -0,0 +1,129 @@
+use std::cmp::Ordering;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// `text` with case and accents folded away, so that "Ångström", "angstrom" and "ANGSTROM" all
+/// come out as "angstrom".
+pub fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Compare text the way people expect, ignoring case and accents and with numbers in natural
+/// order. Text that only differs in case or accents still gets a consistent order, by falling
+/// back to `compare_natural` on the text as it is.
+pub fn compare_text(a: &str, b: &str) -> Ordering {
+    compare_natural(&fold(a), &fold(b)).then_with(|| compare_natural(a, b))
+}
+
+/// Compare text by code point, except that runs of digits compare as numbers, so "Track 2"
+/// comes before "Track 10". Numbers that are only different in leading zeros put the shorter
+/// one first, so that only identical text compares equal.
+pub fn compare_natural(mut a: &str, mut b: &str) -> Ordering {
+    loop {
+        let (x, y) = match (a.chars().next(), b.chars().next()) {
+            (Some(x), Some(y)) => (x, y),
+            (x, y) => return x.cmp(&y),
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (a_digits, a_rest) = split_digits(a);
+            let (b_digits, b_rest) = split_digits(b);
+            match compare_digits(a_digits, b_digits) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+            a = a_rest;
+            b = b_rest;
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            a = &a[x.len_utf8()..];
+            b = &b[y.len_utf8()..];
+        }
+    }
+}
+
+/// The run of ASCII digits at the start of `text`, and what's left after it.
+fn split_digits(text: &str) -> (&str, &str) {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+/// Compare two runs of digits as numbers, however long they are.
+fn compare_digits(a: &str, b: &str) -> Ordering {
+    let a_value = a.trim_start_matches('0');
+    let b_value = b.trim_start_matches('0');
+    a_value.len().cmp(&b_value.len())
+        .then_with(|| a_value.cmp(b_value))
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut words: Vec<&str>, compare: fn(&str, &str) -> Ordering) -> Vec<&str> {
+        words.sort_by(|a, b| compare(a, b));
+        words
+    }
+
+    #[test]
+    fn folding_drops_case_and_accents() {
+        assert_eq!(fold("Ångström"), "angstrom");
+        assert_eq!(fold("ANGSTROM"), "angstrom");
+        assert_eq!(fold("Beyoncé"), "beyonce");
+        assert_eq!(fold("Motörhead"), "motorhead");
+        // Already decomposed
+        assert_eq!(fold("Beyonce\u{301}"), "beyonce");
+    }
+
+    #[test]
+    fn text_sorts_ignoring_case_and_accents() {
+        assert_eq!(sorted(vec!["banana", "Ångström", "apple", "Apple", "Zebra", "cherry"],
+                          compare_text),
+                   vec!["Ångström", "Apple", "apple", "banana", "cherry", "Zebra"]);
+        assert_eq!(sorted(vec!["Émilie", "Eve", "eric"], compare_text),
+                   vec!["Émilie", "eric", "Eve"]);
+    }
+
+    #[test]
+    fn text_that_only_differs_in_case_or_accents_is_not_equal() {
+        assert_ne!(compare_text("apple", "Apple"), Ordering::Equal);
+        assert_ne!(compare_text("resume", "résumé"), Ordering::Equal);
+        assert_eq!(compare_text("Apple", "Apple"), Ordering::Equal);
+    }
+
+    #[test]
+    fn strict_order_goes_by_code_point() {
+        assert_eq!(sorted(vec!["banana", "Ångström", "apple", "Apple", "Zebra"], compare_natural),
+                   vec!["Apple", "Zebra", "apple", "banana", "Ångström"]);
+    }
+
+    #[test]
+    fn numbers_in_text_sort_in_natural_order() {
+        assert_eq!(compare_natural("Track 2", "Track 10"), Ordering::Less);
+        assert_eq!(sorted(vec!["Track 10", "Track 2", "Track 1", "Track 20"], compare_natural),
+                   vec!["Track 1", "Track 2", "Track 10", "Track 20"]);
+        assert_eq!(sorted(vec!["10 Songs", "2 Songs", "Songs"], compare_text),
+                   vec!["2 Songs", "10 Songs", "Songs"]);
+    }
+
+    #[test]
+    fn numbers_of_any_length_compare_as_numbers() {
+        assert_eq!(compare_natural("99999999999999999999 b", "100000000000000000000 a"),
+                   Ordering::Less);
+        assert_eq!(compare_natural("a9b", "a10a"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(compare_natural("Track 02", "Track 10"), Ordering::Less);
+        assert_eq!(compare_natural("Track 2", "Track 02"), Ordering::Less);
+        assert_eq!(compare_natural("Track 02", "Track 2"), Ordering::Greater);
+        assert_eq!(compare_natural("Track 02", "Track 02"), Ordering::Equal);
+    }
+}
@@ -1,4 +1,7 @@
 use druid::{Color, Key};
 
 /// Color for alt. rows in lists, important panels, etc.
-pub const ALT_BACKGROUND_COLOR: Key<Color> = Key::new("org.majora320.mus.alt-background-color");
\ No newline at end of file
+pub const ALT_BACKGROUND_COLOR: Key<Color> = Key::new("org.majora320.mus.alt-background-color");
+
+/// Color for less important text, e.g. the second line of a compact track list row.
+pub const SECONDARY_TEXT_COLOR: Key<Color> = Key::new("org.majora320.mus.secondary-text-color");
\ No newline at end of file
@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use druid::{FileInfo, Selector, WindowId};
+
+use crate::browse::Scope;
+use crate::db::{DuplicateKey, Library, Track, TrackField};
+use crate::libraries::ScanEvent;
+use crate::loudness::Normalization;
+use crate::notifications::Severity;
+use crate::settings::View;
+use crate::watcher::LibraryChanges;
+
+/// Tracks whose files have changed since their tags were read, found by the background check.
+pub const STALE_TRACKS_FOUND: Selector<Vec<i64>> = Selector::new("org.majora320.mus.stale-tracks-found");
+
+/// Re-read the tags of the track with this id from its file.
+pub const REFRESH_TRACK: Selector<i64> = Selector::new("org.majora320.mus.refresh-track");
+
+/// The track with this id has played for long enough to count as played.
+pub const RECORD_PLAY: Selector<i64> = Selector::new("org.majora320.mus.record-play");
+
+/// Give the track with this id this many stars, or clear its rating.
+pub const SET_RATING: Selector<(i64, Option<i32>)> = Selector::new("org.majora320.mus.set-rating");
+
+/// Give every selected track in the main track list this many stars, or clear their ratings.
+pub const RATE_SELECTION: Selector<Option<i32>> = Selector::new("org.majora320.mus.rate-selection");
+
+/// Put back the ratings the last `RATE_SELECTION` replaced.
+pub const UNDO_RATE_SELECTION: Selector = Selector::new("org.majora320.mus.undo-rate-selection");
+
+/// Open the metadata editor for the first selected track in the main track list.
+pub const EDIT_METADATA: Selector = Selector::new("org.majora320.mus.edit-metadata");
+
+/// Save the metadata editor's fields, then close its window, which has this id.
+pub const SAVE_METADATA: Selector<WindowId> = Selector::new("org.majora320.mus.save-metadata");
+
+/// Remove the selected tracks in the main track list from the library, leaving their files.
+pub const REMOVE_SELECTION: Selector = Selector::new("org.majora320.mus.remove-selection");
+
+/// Ask before removing the selected tracks and deleting their files.
+pub const DELETE_SELECTION: Selector = Selector::new("org.majora320.mus.delete-selection");
+
+/// The user confirmed deleting the files in the window with this id.
+pub const CONFIRM_DELETE: Selector<WindowId> = Selector::new("org.majora320.mus.confirm-delete");
+
+/// The user picked a folder for a new library.
+pub const NEW_LIBRARY_FOLDER: Selector<FileInfo> = Selector::new("org.majora320.mus.new-library-folder");
+
+/// Add the library in the add library dialog, then close its window, which has this id.
+pub const ADD_LIBRARY: Selector<WindowId> = Selector::new("org.majora320.mus.add-library");
+
+/// How far a scan has got: what's being scanned, as the status bar puts it, how much of it has
+/// been, and how much there is in all. None once it's over.
+pub const SCAN_PROGRESS: Selector<Option<(String, usize, usize)>> = Selector::new("org.majora320.mus.scan-progress");
+
+/// How the scan of the library with this id is going, for the sidebar. Sent by whatever's
+/// scanning it.
+pub const LIBRARY_SCAN_STATUS: Selector<(i64, ScanEvent)> = Selector::new("org.majora320.mus.library-scan-status");
+
+/// Clear the library with this id out and scan it again from scratch, asking first if that
+/// would lose playlists, plays or ratings.
+pub const FULL_RESCAN: Selector<i64> = Selector::new("org.majora320.mus.full-rescan");
+
+/// The user confirmed the full rescan of a library in the window with this id: the window, then
+/// the library.
+pub const CONFIRM_FULL_RESCAN: Selector<(WindowId, i64)> = Selector::new("org.majora320.mus.confirm-full-rescan");
+
+/// A full rescan has cleared this library out; its tracks will be found again as it goes.
+pub const LIBRARY_CLEARED: Selector<i64> = Selector::new("org.majora320.mus.library-cleared");
+
+/// Tracks a scan has just added, sent in batches while it runs.
+pub const TRACKS_FOUND: Selector<Vec<Track>> = Selector::new("org.majora320.mus.tracks-found");
+
+/// A library has been scanned: everything now in it, and how many of those tracks are new. Sent
+/// by the scanning thread.
+pub const LIBRARY_SCANNED: Selector<(Library, Vec<Track>, usize)> = Selector::new("org.majora320.mus.library-scanned");
+
+/// Files in a library have been added, removed or modified outside of mus, and the database
+/// has caught up. Sent by the library's watcher.
+pub const LIBRARY_CHANGED: Selector<LibraryChanges> = Selector::new("org.majora320.mus.library-changed");
+
+/// Open the library manager.
+pub const MANAGE_LIBRARIES: Selector = Selector::new("org.majora320.mus.manage-libraries");
+
+/// Rename the library with this id.
+pub const RENAME_LIBRARY: Selector<(i64, String)> = Selector::new("org.majora320.mus.rename-library");
+
+/// Remove the library with this id, and its tracks.
+pub const REMOVE_LIBRARY: Selector<i64> = Selector::new("org.majora320.mus.remove-library");
+
+/// Select a random track in the main track list and scroll to it.
+pub const GO_TO_RANDOM_TRACK: Selector = Selector::new("org.majora320.mus.go-to-random-track");
+
+/// Open a window showing what's queued, and how far through it playback is.
+pub const SHOW_QUEUE: Selector = Selector::new("org.majora320.mus.show-queue");
+
+/// Apply ratings from JSON sidecar files in every library.
+pub const IMPORT_SIDECARS: Selector = Selector::new("org.majora320.mus.import-sidecars");
+
+/// Sidecars have been imported in the background: the tracks they rated, as they are now, and
+/// how many sidecars were skipped.
+pub const SIDECARS_IMPORTED: Selector<(Vec<Track>, usize)> = Selector::new("org.majora320.mus.sidecars-imported");
+
+/// Measure the loudness and silence of every track that hasn't been, and their albums'
+/// loudness, in the background.
+pub const ANALYZE_TRACKS: Selector = Selector::new("org.majora320.mus.analyze-tracks");
+
+/// Tracks have been analyzed in the background: every track that's been analyzed, as they are
+/// now, since their albums' loudness can change too; how many tracks were analyzed this time;
+/// and how many couldn't be.
+pub const TRACKS_ANALYZED: Selector<(Vec<Track>, usize, usize)> = Selector::new("org.majora320.mus.tracks-analyzed");
+
+/// Compact the database file.
+pub const VACUUM_DATABASE: Selector = Selector::new("org.majora320.mus.vacuum-database");
+
+/// Show a transient notification. Use `notifications::notify` to build it.
+pub const NOTIFY: Selector<(Severity, String)> = Selector::new("org.majora320.mus.notify");
+
+/// Close the notification with this id.
+pub const DISMISS_NOTIFICATION: Selector<u64> = Selector::new("org.majora320.mus.dismiss-notification");
+
+/// Stop whatever is playing and play this track instead.
+pub const PLAY_TRACK: Selector<Track> = Selector::new("org.majora320.mus.play-track");
+
+/// The sink has run out of audio. Sent by the playback watcher thread.
+pub const TRACK_FINISHED: Selector = Selector::new("org.majora320.mus.track-finished");
+/// Fade into the next track in the queue over this long. Sent just before the current one ends.
+pub const CROSSFADE: Selector<Duration> = Selector::new("org.majora320.mus.crossfade");
+
+/// Open the output device picker.
+pub const SHOW_OUTPUT_DEVICES: Selector = Selector::new("org.majora320.mus.show-output-devices");
+/// Play through the output device with this name, or the system default if None.
+pub const SET_OUTPUT_DEVICE: Selector<Option<String>> = Selector::new("org.majora320.mus.set-output-device");
+/// Output devices were plugged in or unplugged. Sent by the device watcher thread.
+pub const OUTPUT_DEVICES_CHANGED: Selector<Vec<String>> = Selector::new("org.majora320.mus.output-devices-changed");
+
+/// Skip to the next track in the queue.
+pub const PLAY_NEXT: Selector = Selector::new("org.majora320.mus.play-next");
+
+/// Go back to the previous track in the queue.
+pub const PLAY_PREVIOUS: Selector = Selector::new("org.majora320.mus.play-previous");
+
+/// Play the selected tracks in the main track list now, ahead of the rest of the queue.
+pub const PLAY_SELECTION: Selector = Selector::new("org.majora320.mus.play-selection");
+
+/// Open the folders holding the selected tracks in the system file manager.
+pub const OPEN_CONTAINING_FOLDER: Selector = Selector::new("org.majora320.mus.open-containing-folder");
+
+/// Add the selected tracks in the main track list to the end of the queue.
+pub const ENQUEUE_SELECTION: Selector = Selector::new("org.majora320.mus.enqueue-selection");
+
+/// Jump to this far into the current track.
+pub const SEEK: Selector<Duration> = Selector::new("org.majora320.mus.seek");
+
+/// The main track list's columns were resized, added or removed. Each has its share of the width.
+pub const COLUMNS_CHANGED: Selector<Vec<(TrackField, f64)>> = Selector::new("org.majora320.mus.columns-changed");
+
+/// Show the column for this field in the main track list if it's hidden, or hide it if it's shown.
+pub const TOGGLE_COLUMN: Selector<TrackField> = Selector::new("org.majora320.mus.toggle-column");
+
+/// Switch the main track list between grouping tracks by album and not.
+pub const TOGGLE_ALBUM_GROUPS: Selector = Selector::new("org.majora320.mus.toggle-album-groups");
+
+/// Switch the main track list between sorting text with and without regard to case and accents.
+pub const TOGGLE_STRICT_SORT: Selector = Selector::new("org.majora320.mus.toggle-strict-sort");
+
+/// Switch the filter box between searching paths and comments too and only titles, artists and
+/// albums.
+pub const TOGGLE_SEARCH_EVERYWHERE: Selector =
+    Selector::new("org.majora320.mus.toggle-search-everywhere");
+
+/// Switch the now-playing display between fading in each new track and changing straight away.
+pub const TOGGLE_REDUCE_MOTION: Selector = Selector::new("org.majora320.mus.toggle-reduce-motion");
+
+/// Turn normalizing each track's volume on or off.
+pub const TOGGLE_REPLAY_GAIN: Selector = Selector::new("org.majora320.mus.toggle-replay-gain");
+
+/// Normalize volumes by ReplayGain tags or measured loudness from now on, turning normalizing on.
+pub const SET_NORMALIZATION: Selector<Normalization> = Selector::new("org.majora320.mus.set-normalization");
+
+/// Turn skipping the silence before and after each track on or off.
+pub const TOGGLE_TRIM_SILENCE: Selector = Selector::new("org.majora320.mus.toggle-trim-silence");
+
+/// Switch between keeping the main window above other windows and not.
+pub const TOGGLE_ALWAYS_ON_TOP: Selector = Selector::new("org.majora320.mus.toggle-always-on-top");
+
+/// Move the queue entry at the first index to the gap before the entry at the second, which
+/// counts from before the move. Sent by dragging in the queue window.
+pub const MOVE_QUEUE_ENTRY: Selector<(usize, usize)> = Selector::new("org.majora320.mus.move-queue-entry");
+
+/// Ask for a file to put in the queue before the entry at this index.
+pub const INSERT_FILE_INTO_QUEUE: Selector<usize> = Selector::new("org.majora320.mus.insert-file-into-queue");
+
+/// Ask for a folder whose files go in the queue before the entry at this index.
+pub const INSERT_FOLDER_INTO_QUEUE: Selector<usize> = Selector::new("org.majora320.mus.insert-folder-into-queue");
+
+/// Put this file, or the files in this folder, in the queue where it was asked for, chosen in the
+/// open dialog.
+pub const INSERT_CHOSEN_INTO_QUEUE: Selector<FileInfo> = Selector::new("org.majora320.mus.insert-chosen-into-queue");
+
+/// Shuffle the tracks in the queue that haven't played yet.
+pub const SHUFFLE_QUEUE: Selector = Selector::new("org.majora320.mus.shuffle-queue");
+
+/// Switch shuffling between favouring well-rated, rarely played tracks and treating all alike.
+pub const TOGGLE_SMART_SHUFFLE: Selector = Selector::new("org.majora320.mus.toggle-smart-shuffle");
+
+/// Show the library, or a playlist, in the main track list.
+pub const SHOW_VIEW: Selector<View> = Selector::new("org.majora320.mus.show-view");
+
+/// Show only the library's tracks by this artist, or on this album, in the main track list.
+pub const SHOW_SCOPE: Selector<Scope> = Selector::new("org.majora320.mus.show-scope");
+
+/// Expand this artist in the artist tree, or collapse it.
+pub const TOGGLE_ARTIST_NODE: Selector<Option<String>> = Selector::new("org.majora320.mus.toggle-artist-node");
+
+/// Expand this artist's album in the artist tree, or collapse it.
+pub const TOGGLE_ALBUM_NODE: Selector<(Option<String>, Option<String>)> = Selector::new("org.majora320.mus.toggle-album-node");
+
+/// Make a new, empty playlist and show it.
+pub const NEW_PLAYLIST: Selector = Selector::new("org.majora320.mus.new-playlist");
+
+/// Open the smart playlist dialog to make a new one.
+pub const NEW_SMART_PLAYLIST: Selector = Selector::new("org.majora320.mus.new-smart-playlist");
+
+/// Open the smart playlist dialog to change the name and criteria of the one with this id.
+pub const EDIT_SMART_PLAYLIST: Selector<i64> = Selector::new("org.majora320.mus.edit-smart-playlist");
+
+/// Save what's in the smart playlist dialog, then close the dialog's window.
+pub const SAVE_SMART_PLAYLIST: Selector<WindowId> = Selector::new("org.majora320.mus.save-smart-playlist");
+
+/// Add the selected tracks to the end of the playlist with this id.
+pub const ADD_SELECTION_TO_PLAYLIST: Selector<i64> = Selector::new("org.majora320.mus.add-selection-to-playlist");
+
+/// Take the selected tracks out of the playlist being shown.
+pub const REMOVE_SELECTION_FROM_PLAYLIST: Selector = Selector::new("org.majora320.mus.remove-selection-from-playlist");
+
+/// A playlist's rows have been dragged into a new order: its id, and its track ids in that order.
+pub const REORDER_PLAYLIST: Selector<(i64, Vec<i64>)> = Selector::new("org.majora320.mus.reorder-playlist");
+
+/// Export the playlist being shown to this file, chosen in the save dialog.
+pub const EXPORT_PLAYLIST: Selector<FileInfo> = Selector::new("org.majora320.mus.export-playlist");
+
+/// Make a playlist from this M3U file, chosen in the open dialog.
+pub const IMPORT_PLAYLIST: Selector<FileInfo> = Selector::new("org.majora320.mus.import-playlist");
+
+/// Delete the playlist with this id.
+pub const DELETE_PLAYLIST: Selector<i64> = Selector::new("org.majora320.mus.delete-playlist");
+
+/// Look for copies of the same song in the library, in the background.
+pub const FIND_DUPLICATES: Selector<DuplicateKey> = Selector::new("org.majora320.mus.find-duplicates");
+
+/// The background search for duplicates is done: groups of copies of the same song.
+pub const DUPLICATES_FOUND: Selector<Vec<Vec<Track>>> = Selector::new("org.majora320.mus.duplicates-found");
+
+/// Remove the copies picked in the duplicates dialog from the library. If true, delete their
+/// files too, once the user confirms it.
+pub const REMOVE_DUPLICATES: Selector<bool> = Selector::new("org.majora320.mus.remove-duplicates");
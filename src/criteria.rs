@@ -0,0 +1,253 @@
+use crate::db::TrackField;
+
+/// How a `Criterion` compares a track's field with its value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Is,
+    IsNot,
+    /// Text fields only.
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// How operators are written in a query, longest first so that `<=` isn't read as `<`.
+const OPERATORS: [(&str, Operator); 7] = [
+    ("contains", Operator::Contains),
+    ("!=", Operator::IsNot),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("=", Operator::Is),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+impl Operator {
+    /// How it's written in a query.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Is             => "=",
+            Operator::IsNot          => "!=",
+            Operator::Contains       => "contains",
+            Operator::Less           => "<",
+            Operator::LessOrEqual    => "<=",
+            Operator::Greater        => ">",
+            Operator::GreaterOrEqual => ">=",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Operator> {
+        OPERATORS.iter()
+            .find(|(candidate, _)| *candidate == symbol)
+            .map(|&(_, operator)| operator)
+    }
+}
+
+/// One condition of a smart playlist, e.g. `rating >= 4`. A track has to meet every criterion
+/// to be in the playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Criterion {
+    pub field: TrackField,
+    pub operator: Operator,
+    /// A number for numeric fields.
+    pub value: String,
+}
+
+/// Read criteria written like `rating >= 4 and genre = 'Smooth Jazz'`. Field names are those of
+/// `TrackField::column`; values with spaces in them need quotes. Returns a message for the user
+/// if it can't be read.
+pub fn parse_criteria(text: &str) -> Result<Vec<Criterion>, String> {
+    let mut criteria = Vec::new();
+    let mut rest = text.trim();
+
+    while !rest.is_empty() {
+        let (criterion, after) = parse_criterion(rest)?;
+        criteria.push(criterion);
+
+        rest = after.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        rest = strip_word(rest, "and")
+            .ok_or_else(|| format!("Expected 'and' before '{}'.", rest))?
+            .trim_start();
+        if rest.is_empty() {
+            return Err("Expected another criterion after 'and'.".to_string());
+        }
+    }
+
+    Ok(criteria)
+}
+
+/// Write criteria the way `parse_criteria` reads them, for editing.
+pub fn format_criteria(criteria: &[Criterion]) -> String {
+    let criteria: Vec<String> = criteria.iter()
+        .map(|criterion| format!("{} {} {}", criterion.field.column(), criterion.operator.symbol(),
+                                 quote(&criterion.value)))
+        .collect();
+
+    criteria.join(" and ")
+}
+
+/// One criterion from the start of `text`, and what's left after it.
+fn parse_criterion(text: &str) -> Result<(Criterion, &str), String> {
+    let end = text.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(text.len());
+    let (name, rest) = text.split_at(end);
+    let field = TrackField::from_column(&name.to_lowercase())
+        .ok_or_else(|| format!("There's no field called '{}'.", name))?;
+
+    let rest = rest.trim_start();
+    let (operator, rest) = OPERATORS.iter()
+        .find_map(|&(symbol, operator)| strip_operator(rest, symbol).map(|rest| (operator, rest)))
+        .ok_or_else(|| format!("Expected a comparison after '{}'.", name))?;
+
+    let (value, rest) = parse_value(rest.trim_start())
+        .ok_or_else(|| format!("Expected a value after '{} {}'.", name, operator.symbol()))?;
+
+    if field.is_numeric() {
+        if operator == Operator::Contains {
+            return Err(format!("'{}' is a number, so it can't contain anything.", name));
+        }
+        if value.parse::<i64>().is_err() {
+            return Err(format!("'{}' is a number, not '{}'.", name, value));
+        }
+    }
+
+    Ok((Criterion { field, operator, value }, rest))
+}
+
+/// A quoted or bare value from the start of `text`, and what's left after it.
+fn parse_value(text: &str) -> Option<(String, &str)> {
+    let quote = text.chars().next().filter(|&c| c == '\'' || c == '"');
+    match quote {
+        Some(quote) => {
+            let end = text[1..].find(quote)? + 1;
+            Some((text[1..end].to_string(), &text[end + 1..]))
+        }
+        None => {
+            let end = text.find(char::is_whitespace).unwrap_or(text.len());
+            if end == 0 {
+                None
+            } else {
+                Some((text[..end].to_string(), &text[end..]))
+            }
+        }
+    }
+}
+
+/// `text` without `word` at its start, if it starts with the whole word in any case.
+fn strip_word<'a>(text: &'a str, word: &str) -> Option<&'a str> {
+    let rest = strip_prefix_ignore_case(text, word)?;
+    if rest.starts_with(|c: char| c.is_alphanumeric()) {
+        None
+    } else {
+        Some(rest)
+    }
+}
+
+fn strip_operator<'a>(text: &'a str, symbol: &str) -> Option<&'a str> {
+    if symbol.starts_with(char::is_alphabetic) {
+        strip_word(text, symbol)
+    } else {
+        text.strip_prefix(symbol)
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    match text.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&text[prefix.len()..]),
+        _ => None,
+    }
+}
+
+/// Quote a value if it wouldn't be read back as a single bare word.
+fn quote(value: &str) -> String {
+    let bare = !value.is_empty()
+        && !value.contains(char::is_whitespace)
+        && !value.starts_with(|c| c == '\'' || c == '"');
+
+    if bare {
+        value.to_string()
+    } else if value.contains('\'') {
+        format!("\"{}\"", value)
+    } else {
+        format!("'{}'", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn criterion(field: TrackField, operator: Operator, value: &str) -> Criterion {
+        Criterion { field, operator, value: value.to_string() }
+    }
+
+    #[test]
+    fn parses_criteria_joined_by_and() {
+        assert_eq!(parse_criteria("rating >= 4 AND genre = 'Smooth Jazz' and title contains Peg"),
+                   Ok(vec![
+                       criterion(TrackField::Rating, Operator::GreaterOrEqual, "4"),
+                       criterion(TrackField::Genre, Operator::Is, "Smooth Jazz"),
+                       criterion(TrackField::Title, Operator::Contains, "Peg"),
+                   ]));
+    }
+
+    #[test]
+    fn longer_operators_win() {
+        assert_eq!(parse_criteria("year<=1980"),
+                   Ok(vec![criterion(TrackField::Year, Operator::LessOrEqual, "1980")]));
+        assert_eq!(parse_criteria("Artist != \"Steely Dan\""),
+                   Ok(vec![criterion(TrackField::Artist, Operator::IsNot, "Steely Dan")]));
+    }
+
+    #[test]
+    fn nothing_is_no_criteria() {
+        assert_eq!(parse_criteria("   "), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn unreadable_criteria_say_why() {
+        assert_eq!(parse_criteria("colour = red"), Err("There's no field called 'colour'.".into()));
+        assert_eq!(parse_criteria("year 1980"),
+                   Err("Expected a comparison after 'year'.".into()));
+        assert_eq!(parse_criteria("year ="), Err("Expected a value after 'year ='.".into()));
+        assert_eq!(parse_criteria("year = soon"), Err("'year' is a number, not 'soon'.".into()));
+        assert_eq!(parse_criteria("rating contains 4"),
+                   Err("'rating' is a number, so it can't contain anything.".into()));
+        assert_eq!(parse_criteria("rating = 4 or rating = 5"),
+                   Err("Expected 'and' before 'or rating = 5'.".into()));
+        assert_eq!(parse_criteria("rating = 4 and"),
+                   Err("Expected another criterion after 'and'.".into()));
+        assert_eq!(parse_criteria("title = 'Aja"),
+                   Err("Expected a value after 'title ='.".into()));
+    }
+
+    #[test]
+    fn formatted_criteria_parse_back() {
+        let criteria = vec![
+            criterion(TrackField::Genre, Operator::Is, "Smooth Jazz"),
+            criterion(TrackField::Title, Operator::Contains, "Don't Take Me Alive"),
+            criterion(TrackField::Comment, Operator::IsNot, ""),
+            criterion(TrackField::Rating, Operator::Greater, "3"),
+            criterion(TrackField::PlayCount, Operator::Less, "10"),
+        ];
+
+        let text = format_criteria(&criteria);
+        assert_eq!(text, "genre = 'Smooth Jazz' and title contains \"Don't Take Me Alive\" \
+                          and comment != '' and rating > 3 and play_count < 10");
+        assert_eq!(parse_criteria(&text), Ok(criteria));
+    }
+
+    #[test]
+    fn operators_round_trip_through_their_symbols() {
+        for &(symbol, operator) in OPERATORS.iter() {
+            assert_eq!(operator.symbol(), symbol);
+            assert_eq!(Operator::from_symbol(symbol), Some(operator));
+        }
+        assert_eq!(Operator::from_symbol("=="), None);
+    }
+}
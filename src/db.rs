@@ -1,14 +1,51 @@
-use std::fs::create_dir_all;
+use std::collections::HashMap;
+use std::fs::{self, create_dir_all, remove_file};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use directories::ProjectDirs;
 use druid::Data;
-use log::{info, trace};
-use rusqlite::{Connection, NO_PARAMS, params, Transaction};
+use log::{info, trace, warn};
+use rusqlite::{Connection, NO_PARAMS, OptionalExtension, params, Row, Transaction};
 use rusqlite::Error::QueryReturnedNoRows;
-use taglib::File;
+use rusqlite::types::Value;
 use thiserror::Error;
 use thiserror::private::PathAsDisplay;
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::criteria::{Criterion, Operator};
+use crate::fingerprint::{audio_hash, content_hash};
+use crate::loudness::album_loudness;
+use crate::m3u::{parse_m3u, write_m3u};
+use crate::art::folder_art;
+use crate::tags::{embedded_art, file_mtime, is_stale, write_tags, TagWriteError, TrackTags};
+
+/// The columns of `track` that `track_from_row` reads, in the order it reads them. Listed rather
+/// than `SELECT *`, so that adding a column to the table doesn't shift them. A macro so that
+/// queries can `concat!` it.
+macro_rules! track_columns {
+    () => {
+        "track.id, track.library_id, track.path, track.title, track.artist, track.album, \
+         track.comment, track.genre, track.year, track.track, track.length, track.bitrate, \
+         track.samplerate, track.rating, track.disc, track.mtime, track.hash, track.replay_gain, \
+         track.play_count, track.last_played, track.loudness, track.album_loudness, \
+         track.sound_start_ms, track.sound_end_ms"
+    };
+}
+
+/// How many columns `track_columns!` lists, i.e. the index of the first column after them.
+const TRACK_COLUMN_COUNT: usize = 24;
+
+/// What the library holding individual tracks (path 'NONE') is called in the UI.
+pub const INDIVIDUAL_TRACKS_NAME: &str = "Individual Tracks";
+
+/// Ratings are a number of stars from 1 to MAX_RATING; unrated tracks have no rating.
+pub const MAX_RATING: i32 = 5;
+
+/// How long to wait for another connection to finish writing before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Database {
     conn: Connection
@@ -22,6 +59,10 @@ pub struct Library {
 }
 
 impl Library {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
     /// Returns the path of this library, or None for the 'Individual Tracks' library.
     pub fn path(&self) -> Option<&String> {
         if self.path == "NONE" {
@@ -34,6 +75,36 @@ impl Library {
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    /// Whether `path` lives under this library's root. Anything can go in 'Individual Tracks'.
+    pub fn contains_path(&self, path: &str) -> bool {
+        match self.path() {
+            Some(root) => Path::new(path).starts_with(root),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    id: i64,
+    name: String,
+    /// Whether it holds criteria rather than tracks.
+    smart: bool,
+}
+
+impl Playlist {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn is_smart(&self) -> bool {
+        self.smart
+    }
 }
 
 #[derive(Debug, Clone, Data)]
@@ -52,6 +123,71 @@ pub struct Track {
     bitrate: i32,
     samplerate: i32,
     rating: Option<i32>,
+    disc: Option<i32>,
+    mtime: Option<i64>,
+    hash: Option<String>,
+    /// ReplayGain adjustment in dB.
+    replay_gain: Option<f64>,
+    play_count: i32,
+    /// Seconds since the Unix epoch.
+    last_played: Option<i64>,
+    /// Integrated loudness in LUFS, of the track and of its album, once they've been measured.
+    loudness: Option<f64>,
+    album_loudness: Option<f64>,
+    /// Where the track's sound starts and ends, in milliseconds, between the silence before
+    /// and after it, once that's been measured.
+    sound_start_ms: Option<i64>,
+    sound_end_ms: Option<i64>,
+}
+
+impl TrackField {
+    /// The field's column in `track`. Also how it's named in settings and smart playlists.
+    pub fn column(self) -> &'static str {
+        match self {
+            TrackField::Path       => "path",
+            TrackField::Title      => "title",
+            TrackField::Artist     => "artist",
+            TrackField::Album      => "album",
+            TrackField::Comment    => "comment",
+            TrackField::Genre      => "genre",
+            TrackField::Year       => "year",
+            TrackField::Track      => "track",
+            TrackField::Length     => "length",
+            TrackField::Bitrate    => "bitrate",
+            TrackField::Samplerate => "samplerate",
+            TrackField::Rating     => "rating",
+            TrackField::Disc       => "disc",
+            TrackField::PlayCount  => "play_count",
+            TrackField::LastPlayed => "last_played",
+        }
+    }
+
+    pub fn from_column(name: &str) -> Option<TrackField> {
+        match name {
+            "path"       => Some(TrackField::Path),
+            "title"      => Some(TrackField::Title),
+            "artist"     => Some(TrackField::Artist),
+            "album"      => Some(TrackField::Album),
+            "comment"    => Some(TrackField::Comment),
+            "genre"      => Some(TrackField::Genre),
+            "year"       => Some(TrackField::Year),
+            "track"      => Some(TrackField::Track),
+            "length"     => Some(TrackField::Length),
+            "bitrate"    => Some(TrackField::Bitrate),
+            "samplerate" => Some(TrackField::Samplerate),
+            "rating"     => Some(TrackField::Rating),
+            "disc"       => Some(TrackField::Disc),
+            "play_count"  => Some(TrackField::PlayCount),
+            "last_played" => Some(TrackField::LastPlayed),
+            _ => None
+        }
+    }
+
+    /// Whether the field holds numbers rather than text.
+    pub fn is_numeric(self) -> bool {
+        !matches!(self, TrackField::Path | TrackField::Title | TrackField::Artist
+                        | TrackField::Album | TrackField::Comment | TrackField::Genre)
+    }
 }
 
 impl Track {
@@ -64,34 +200,323 @@ impl Track {
             TrackField::Comment    => self.comment.clone().unwrap_or_default(),
             TrackField::Genre      => self.genre.clone().unwrap_or_default(),
             TrackField::Year       => self.year.map(|y| y.to_string()).unwrap_or(String::new()),
-            TrackField::Track      => self.year.map(|t| t.to_string()).unwrap_or(String::new()),
+            TrackField::Track      => self.track.map(|t| t.to_string()).unwrap_or(String::new()),
             TrackField::Length     => self.length.to_string(),
             TrackField::Bitrate    => self.bitrate.to_string(),
             TrackField::Samplerate => self.samplerate.to_string(),
             TrackField::Rating     => self.rating.unwrap_or(-1).to_string(),
+            TrackField::Disc       => self.disc.map(|d| d.to_string()).unwrap_or(String::new()),
+            TrackField::PlayCount  => self.play_count.to_string(),
+            TrackField::LastPlayed => self.last_played.map(|t| t.to_string()).unwrap_or(String::new()),
         }
     }
 }
 
+/// The tags that can be edited by hand. None clears a tag.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub comment: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<i32>,
+}
+
 #[derive(Debug, Copy, Clone, Data, PartialEq)]
 pub enum TrackField {
     Path, Title, Artist, Album, Comment, Genre, Year,
-    Track, Length, Bitrate, Samplerate, Rating
+    Track, Length, Bitrate, Samplerate, Rating, Disc, PlayCount, LastPlayed
 }
 
 impl Track {
+    pub fn id(&self)         -> i64 { self.id }
+    pub fn library_id(&self) -> i64 { self.library_id }
     pub fn path(&self)        -> &str { &self.path }
     pub fn title(&self)      -> Option<&str> { self.title.as_deref() }
     pub fn artist(&self)     -> Option<&str> { self.artist.as_deref() }
     pub fn album(&self)      -> Option<&str> { self.album.as_deref() }
     pub fn comment(&self)    -> Option<&str> { self.comment.as_deref() }
-    pub fn genre(&self)      -> Option<&str> { self.title.as_deref() }
+    pub fn genre(&self)      -> Option<&str> { self.genre.as_deref() }
     pub fn year(&self)       -> Option<i32> { self.year }
     pub fn track(&self)      -> Option<i32> { self.track }
     pub fn length(&self)     -> i32 { self.length }
     pub fn bitrate(&self)    -> i32 { self.bitrate }
     pub fn samplerate(&self) -> i32 { self.samplerate }
     pub fn rating(&self)     -> Option<i32> { self.rating }
+    pub fn replay_gain(&self) -> Option<f64> { self.replay_gain }
+    pub fn play_count(&self) -> i32 { self.play_count }
+    pub fn last_played(&self) -> Option<i64> { self.last_played }
+    pub fn loudness(&self)   -> Option<f64> { self.loudness }
+    pub fn album_loudness(&self) -> Option<f64> { self.album_loudness }
+    pub fn sound(&self) -> Option<(Duration, Duration)> {
+        match (self.sound_start_ms, self.sound_end_ms) {
+            (Some(start), Some(end)) => Some((Duration::from_millis(start.max(0) as u64),
+                                              Duration::from_millis(end.max(0) as u64))),
+            _ => None,
+        }
+    }
+    pub fn metadata(&self) -> TrackMetadata {
+        TrackMetadata {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            album: self.album.clone(),
+            comment: self.comment.clone(),
+            genre: self.genre.clone(),
+            year: self.year,
+            track: self.track,
+        }
+    }
+
+    /// Tracks without a disc number are treated as being on the first disc.
+    pub fn disc(&self)       -> i32 { self.disc.unwrap_or(1) }
+
+    /// A track that's only ever queued, like one inside an archive, and so never in the
+    /// database. Its `id` has to be negative, so it can't be mistaken for one that is.
+    pub fn ephemeral(id: i64, path: String, title: String, album: Option<String>) -> Track {
+        debug_assert!(id < 0);
+        Track {
+            id,
+            library_id: 0,
+            path,
+            title: Some(title),
+            artist: None,
+            album,
+            comment: None,
+            genre: None,
+            year: None,
+            track: None,
+            length: 0,
+            bitrate: 0,
+            samplerate: 0,
+            rating: None,
+            disc: None,
+            mtime: None,
+            hash: None,
+            replay_gain: None,
+            play_count: 0,
+            last_played: None,
+            loudness: None,
+            album_loudness: None,
+            sound_start_ms: None,
+            sound_end_ms: None,
+        }
+    }
+
+    /// Whether the track isn't in the database, so there's nowhere to note plays or errors.
+    pub fn is_ephemeral(&self) -> bool {
+        self.id < 0
+    }
+}
+
+/// Tracks for tests that don't need a database, built up from just a title.
+#[cfg(test)]
+impl Track {
+    pub fn for_test(id: i64, title: &str) -> Track {
+        Track {
+            id,
+            library_id: 1,
+            path: format!("/music/{}.flac", id),
+            title: Some(title.to_string()),
+            artist: None,
+            album: None,
+            comment: None,
+            genre: None,
+            year: None,
+            track: None,
+            length: 180,
+            bitrate: 320,
+            samplerate: 44100,
+            rating: None,
+            disc: None,
+            mtime: None,
+            hash: None,
+            replay_gain: None,
+            play_count: 0,
+            last_played: None,
+            loudness: None,
+            album_loudness: None,
+            sound_start_ms: None,
+            sound_end_ms: None,
+        }
+    }
+
+    pub fn with_artist(mut self, artist: &str) -> Track {
+        self.artist = Some(artist.to_string());
+        self
+    }
+
+    pub fn with_album(mut self, album: &str) -> Track {
+        self.album = Some(album.to_string());
+        self
+    }
+
+    pub fn with_comment(mut self, comment: &str) -> Track {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn with_genre(mut self, genre: &str) -> Track {
+        self.genre = Some(genre.to_string());
+        self
+    }
+
+    pub fn with_disc(mut self, disc: Option<i32>, track: Option<i32>) -> Track {
+        self.disc = disc;
+        self.track = track;
+        self
+    }
+
+    pub fn with_rating(mut self, rating: i32) -> Track {
+        self.rating = Some(rating);
+        self
+    }
+
+    pub fn with_plays(mut self, play_count: i32, last_played: i64) -> Track {
+        self.play_count = play_count;
+        self.last_played = Some(last_played);
+        self
+    }
+
+    pub fn with_replay_gain(mut self, replay_gain: f64) -> Track {
+        self.replay_gain = Some(replay_gain);
+        self
+    }
+
+    pub fn with_loudness(mut self, loudness: f64, album_loudness: Option<f64>) -> Track {
+        self.loudness = Some(loudness);
+        self.album_loudness = album_loudness;
+        self
+    }
+
+    pub fn with_sound(mut self, start_ms: i64, end_ms: i64) -> Track {
+        self.sound_start_ms = Some(start_ms);
+        self.sound_end_ms = Some(end_ms);
+        self
+    }
+}
+
+/// What a full rescan of a library would lose. Its tracks are found again as new ones, so they
+/// drop out of playlists and forget their plays and ratings.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct RescanLoss {
+    /// Tracks in playlists, counted once for each time they're in one.
+    pub playlist_entries: usize,
+    pub played_tracks: usize,
+    pub rated_tracks: usize,
+}
+
+impl RescanLoss {
+    pub fn is_empty(&self) -> bool {
+        *self == RescanLoss::default()
+    }
+}
+
+/// How a track failed to play.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlaybackFailure {
+    /// It wouldn't start at all, e.g. because its file is missing or in an unknown format.
+    Open,
+    /// It started, but stopped partway through, e.g. because its file is damaged there.
+    Partway,
+}
+
+impl PlaybackFailure {
+    fn column_value(self) -> &'static str {
+        match self {
+            PlaybackFailure::Open    => "open",
+            PlaybackFailure::Partway => "partway",
+        }
+    }
+
+    fn from_column_value(kind: &str) -> Option<PlaybackFailure> {
+        match kind {
+            "open"    => Some(PlaybackFailure::Open),
+            "partway" => Some(PlaybackFailure::Partway),
+            _ => None,
+        }
+    }
+}
+
+/// What `Database::remove_track` does with a track's file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FileRemoval {
+    /// Leave it be, and only forget the track.
+    Keep,
+    /// Move it to the system trash, where it can be restored from.
+    Trash,
+    /// Delete it for good.
+    Delete,
+}
+
+impl FileRemoval {
+    /// What to do with the files of tracks being removed: nothing unless they're being deleted
+    /// too, and then whatever the user chose for deleted files.
+    pub fn choose(delete_files: bool, delete_to_trash: bool) -> FileRemoval {
+        match (delete_files, delete_to_trash) {
+            (false, _) => FileRemoval::Keep,
+            (true, true) => FileRemoval::Trash,
+            (true, false) => FileRemoval::Delete,
+        }
+    }
+}
+
+/// What `Database::find_duplicates` counts as the same song.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DuplicateKey {
+    /// The same artist and title, ignoring case and spacing, and the same length.
+    Tags,
+    /// The same decoded audio (see `fingerprint::audio_hash`), however it's tagged.
+    Audio,
+}
+
+/// Knobs for `Database::scan_library`.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Minimum time between reading one file and the next, so that scanning a slow disk doesn't
+    /// make the rest of the system unresponsive. None scans at full speed.
+    pub throttle: Option<Duration>,
+    /// Skip hidden files and everything in hidden directories (e.g. `.trash`, `.sync`).
+    pub skip_hidden: bool,
+    /// Fingerprint new files as they're added, rather than when a hash is first needed.
+    pub hash_files: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            throttle: None,
+            skip_hidden: true,
+            hash_files: false,
+        }
+    }
+}
+
+/// Spaces out work so that consecutive items start at least `interval` apart.
+struct Pacer {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(interval: Duration) -> Self {
+        Pacer {
+            interval,
+            last: None,
+        }
+    }
+
+    /// How long to wait before starting the next item, if we're ready to start it at `now`.
+    fn delay(&mut self, now: Instant) -> Duration {
+        let delay = match self.last {
+            Some(last) => (last + self.interval).saturating_duration_since(now),
+            None => Duration::from_secs(0),
+        };
+
+        self.last = Some(now + delay);
+        delay
+    }
 }
 
 #[derive(Error, Debug)]
@@ -102,6 +527,34 @@ pub enum DatabaseError {
     SqliteError(#[from] rusqlite::Error),
     #[error("A directory does not exist.")]
     WalkDirError(#[from] walkdir::Error),
+    #[error("Library names cannot be empty or '{}'.", INDIVIDUAL_TRACKS_NAME)]
+    InvalidLibraryName,
+    #[error("A library named '{0}' already exists.")]
+    LibraryNameTaken(String),
+    #[error("That library no longer exists.")]
+    NoSuchLibrary,
+    #[error("Ratings must be between 1 and {max}, not {0}.", max = MAX_RATING)]
+    InvalidRating(i32),
+    #[error("A playlist with that name already exists. Maybe try '{suggestion}'?")]
+    PlaylistNameTaken { suggestion: String },
+    #[error("{} files do not exist at their new paths.", .0.len())]
+    MissingFiles(Vec<String>),
+    #[error("A library can only be merged into another one that exists, and '{}' can't be merged away.", INDIVIDUAL_TRACKS_NAME)]
+    InvalidMerge,
+    #[error("The '{}' library can't be removed or renamed.", INDIVIDUAL_TRACKS_NAME)]
+    ProtectedLibrary,
+    #[error("That playlist has changed in the meantime.")]
+    PlaylistChanged,
+    #[error("Smart playlists pick their own tracks, so tracks can't be added to them.")]
+    SmartPlaylist,
+    #[error("That folder overlaps the library '{0}', and libraries can't be nested.")]
+    LibraryOverlaps(String),
+    #[error("Could not access a file: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not write tags to {0}: {1}.")]
+    TagWriteError(String, TagWriteError),
+    #[error("Removed {0} from the library, but could not move it to the trash: {1}")]
+    TrashError(String, trash::Error),
 }
 
 impl Database {
@@ -117,7 +570,19 @@ impl Database {
 
         info!("Data path: {}", path.as_display());
 
-        let conn = Connection::open(path)?;
+        Database::open(Connection::open(path)?)
+    }
+
+    /// A fresh database that only lasts as long as it's open.
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Database, DatabaseError> {
+        Database::open(Connection::open_in_memory()?)
+    }
+
+    /// Set up the schema on `conn` if it's new, and bring it up to date.
+    fn open(conn: Connection) -> Result<Database, DatabaseError> {
+        // Scans write from their own connections, so wait for them rather than failing
+        conn.busy_timeout(BUSY_TIMEOUT)?;
 
         trace!("Connection established");
 
@@ -133,6 +598,8 @@ impl Database {
             check?;
         }
 
+        migrate(&conn)?;
+
         Ok(Database {
             conn
         })
@@ -142,7 +609,7 @@ impl Database {
     pub fn libraries(&self) -> Result<Vec<Library>, DatabaseError> {
         let mut stmt = self.conn.prepare("SELECT id, path, name FROM library;")?;
         let rows = stmt.query_map(NO_PARAMS, |row| {
-            let name: Option<String> = row.get(1)?;
+            let name: Option<String> = row.get(2)?;
 
             Ok(Library {
                 id: row.get(0)?,
@@ -159,8 +626,29 @@ impl Database {
         Ok(res)
     }
 
-    /// Libraries cannot be nested.
+    pub fn library_exists(&self, id: i64) -> Result<bool, DatabaseError> {
+        Ok(self.conn.query_row("SELECT 1 FROM library WHERE id = ?1",
+                               params![id],
+                               |_row| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    /// Libraries can't be nested, so `path` can't be inside an existing library or contain one.
     pub fn add_library(&mut self, path: String, name: String) -> Result<Library, DatabaseError> {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.eq_ignore_ascii_case(INDIVIDUAL_TRACKS_NAME) {
+            return Err(DatabaseError::InvalidLibraryName);
+        }
+
+        for library in self.libraries()? {
+            if let Some(root) = library.path() {
+                if Path::new(&path).starts_with(root) || Path::new(root).starts_with(&path) {
+                    return Err(DatabaseError::LibraryOverlaps(library.name().clone()));
+                }
+            }
+        }
+
         info!("Adding library {} at {}", name, path);
 
         self.conn.execute("INSERT INTO library (name, path) VALUES (?1, ?2)",
@@ -176,37 +664,254 @@ impl Database {
         })
     }
 
+    /// Remove a library along with its tracks, which are also taken out of every playlist. The
+    /// files are left alone.
+    pub fn remove_library(&mut self, id: i64) -> Result<(), DatabaseError> {
+        let path: Option<String> = self.conn.query_row("SELECT path FROM library WHERE id = ?1",
+                                                       params![id],
+                                                       |row| row.get(0))
+            .optional()?;
+        if path.as_deref() == Some("NONE") {
+            return Err(DatabaseError::ProtectedLibrary);
+        }
+
+        info!("Removing library {}", id);
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE \
+            FROM playlist_tracks
+            WHERE track_id IN
+                (SELECT id \
+                FROM track \
+                WHERE library_id = ?1);",
+            params![id])?;
+        tx.execute("DELETE FROM track WHERE library_id = ?1", params![id])?;
+        tx.execute("DELETE FROM library WHERE id = ?1", params![id])?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Names are trimmed, and can't be empty, clash with 'Individual Tracks', or be taken by
+    /// another library. 'Individual Tracks' itself can't be renamed.
+    pub fn rename_library(&mut self, id: i64, new_name: String) -> Result<(), DatabaseError> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() || new_name.eq_ignore_ascii_case(INDIVIDUAL_TRACKS_NAME) {
+            return Err(DatabaseError::InvalidLibraryName);
+        }
+
+        let taken = self.conn.query_row("SELECT 1 FROM library WHERE name = ?1 AND id != ?2",
+                                        params![new_name, id],
+                                        |_row| Ok(()))
+            .optional()?
+            .is_some();
+        if taken {
+            return Err(DatabaseError::LibraryNameTaken(new_name.to_string()));
+        }
+
+        info!("Renaming library {} to {}", id, new_name);
+        let renamed = self.conn.execute(
+            "UPDATE library SET name = ?1 WHERE id = ?2 AND path != 'NONE'",
+            params![new_name, id])?;
+
+        if renamed == 0 {
+            return Err(if self.library_exists(id)? {
+                DatabaseError::ProtectedLibrary
+            } else {
+                DatabaseError::NoSuchLibrary
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Remove all of a library's tracks (and their playlist entries), but keep the library itself
+    /// so it can be scanned again from scratch.
+    pub fn clear_library(&mut self, id: i64) -> Result<(), DatabaseError> {
+        trace!("Clearing library {}", id);
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE \
+            FROM playlist_tracks
+            WHERE track_id IN
+                (SELECT id \
+                FROM track \
+                WHERE library_id = ?1);",
+            params![id])?;
+        tx.execute(
+            "DELETE \
+            FROM track
+            WHERE library_id = ?1",
+            params![id])?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// What clearing the library with this id would throw away.
+    pub fn rescan_loss(&self, id: i64) -> Result<RescanLoss, DatabaseError> {
+        let playlist_entries: i64 = self.conn.query_row(
+            "SELECT COUNT(*) \
+             FROM playlist_tracks \
+             WHERE track_id IN (SELECT id FROM track WHERE library_id = ?1)",
+            params![id],
+            |row| row.get(0))?;
+        let (played_tracks, rated_tracks): (i64, i64) = self.conn.query_row(
+            "SELECT IFNULL(SUM(play_count > 0 OR last_played IS NOT NULL), 0), \
+                    IFNULL(SUM(rating IS NOT NULL), 0) \
+             FROM track \
+             WHERE library_id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(RescanLoss {
+            playlist_entries: playlist_entries as usize,
+            played_tracks: played_tracks as usize,
+            rated_tracks: rated_tracks as usize,
+        })
+    }
+
+    /// Point every track under `old_root` at the same relative path under `new_root`, e.g. after
+    /// moving the whole music folder, without losing anything a rescan would. Libraries rooted
+    /// under `old_root` move along with their tracks. Nothing is changed unless every new path
+    /// exists. Returns the number of tracks updated.
+    pub fn rewrite_root(&mut self, old_root: &Path, new_root: &Path) -> Result<usize, DatabaseError> {
+        info!("Moving tracks from {} to {}", old_root.display(), new_root.display());
+        let tx = self.conn.transaction()?;
+        let mut updated = 0;
+
+        {
+            let mut tracks = tx.prepare("SELECT id, path FROM track;")?;
+            let mut rewrites = Vec::new();
+            let mut missing = Vec::new();
+
+            for row in tracks.query_map(NO_PARAMS, |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))? {
+                let (id, path) = row?;
+                if let Some(new_path) = rewrite_path(&path, old_root, new_root) {
+                    if Path::new(&new_path).exists() {
+                        rewrites.push((id, new_path));
+                    } else {
+                        missing.push(new_path);
+                    }
+                }
+            }
+
+            if !missing.is_empty() {
+                return Err(DatabaseError::MissingFiles(missing));
+            }
+
+            let mut update = tx.prepare("UPDATE track SET path = ?1 WHERE id = ?2")?;
+            for (id, new_path) in rewrites {
+                update.execute(params![new_path, id])?;
+                updated += 1;
+            }
+
+            let mut libraries = tx.prepare("SELECT id, path FROM library WHERE path != 'NONE';")?;
+            let mut update = tx.prepare("UPDATE library SET path = ?1 WHERE id = ?2")?;
+            for row in libraries.query_map(NO_PARAMS, |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))? {
+                let (id, path) = row?;
+                if let Some(new_path) = rewrite_path(&path, old_root, new_root) {
+                    update.execute(params![new_path, id])?;
+                }
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    /// Move all of `src`'s tracks into `dst` and delete `src`, e.g. when the same music was added
+    /// as two libraries. Where both have a copy of the same file, going by its content hash,
+    /// `dst`'s copy is kept, taking `src`'s rating if it has none, and `src`'s playlist entries
+    /// are pointed at it. Tracks that haven't been hashed yet are hashed first, which reads them.
+    pub fn merge_libraries(&mut self, src: i64, dst: i64) -> Result<(), DatabaseError> {
+        // None if there's no such library
+        let is_individual = |conn: &Connection, id: i64| conn.query_row(
+            "SELECT path = 'NONE' FROM library WHERE id = ?1",
+            params![id],
+            |row| row.get::<_, bool>(0))
+            .optional();
+
+        if src == dst || is_individual(&self.conn, src)? != Some(false)
+            || is_individual(&self.conn, dst)?.is_none() {
+            return Err(DatabaseError::InvalidMerge);
+        }
+
+        let unhashed: Vec<i64> = self.conn
+            .prepare("SELECT id FROM track WHERE library_id IN (?1, ?2) AND hash IS NULL;")?
+            .query_map(params![src, dst], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        for id in unhashed {
+            self.track_hash(id)?;
+        }
+
+        info!("Merging library {} into {}", src, dst);
+        let tx = self.conn.transaction()?;
+
+        {
+            // Paths are unique, so copies can only be told apart by their contents. Files that
+            // couldn't be hashed have no hash, and so no copies.
+            let mut duplicates = tx.prepare(
+                "SELECT src_track.id, MIN(dst_track.id) \
+                FROM track AS src_track \
+                JOIN track AS dst_track ON dst_track.hash = src_track.hash \
+                WHERE src_track.library_id = ?1 AND dst_track.library_id = ?2 \
+                GROUP BY src_track.id;"
+            )?;
+            let mut keep_rating = tx.prepare(
+                "UPDATE track \
+                SET rating = (SELECT rating FROM track WHERE id = ?1) \
+                WHERE id = ?2 AND rating IS NULL;"
+            )?;
+            let mut move_playlist_entries = tx.prepare(
+                "UPDATE playlist_tracks SET track_id = ?2 WHERE track_id = ?1;"
+            )?;
+            let mut delete_track = tx.prepare("DELETE FROM track WHERE id = ?1;")?;
+
+            let pairs = duplicates.query_map(params![src, dst], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (src_track, dst_track) in pairs {
+                keep_rating.execute(params![src_track, dst_track])?;
+                move_playlist_entries.execute(params![src_track, dst_track])?;
+                delete_track.execute(params![src_track])?;
+            }
+        }
+
+        tx.execute("UPDATE track SET library_id = ?2 WHERE library_id = ?1;", params![src, dst])?;
+        tx.execute("DELETE FROM library WHERE id = ?1;", params![src])?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
     /// Scan the library given. If `full_rescan` is true, then we will clear out the library
-    /// completely and then repopulate it; otherwise, we will ignore tracks we already have.
+    /// completely and then repopulate it; otherwise, we will only read new files, and files
+    /// modified since we last read them.
     /// Returns the list of tracks that are no longer in the library that were there before, if any.
-    pub fn scan_library(&mut self, library: Library, full_rescan: bool) -> Result<Vec<String>, DatabaseError> {
+    /// `progress` is called with how many files have been read so far, and how many there are
+    /// to read in total, once they've all been found. `found` is given each new track as it's added.
+    pub fn scan_library(&mut self, library: Library, full_rescan: bool, options: &ScanOptions,
+                        mut progress: Option<&mut dyn FnMut(usize, usize)>,
+                        mut found: Option<&mut dyn FnMut(Track)>) -> Result<Vec<String>, DatabaseError> {
         trace!("Performing rescan on library {}", library.name);
 
         if full_rescan {
-            trace!("Clearing library {}", library.name);
-            let tx = self.conn.transaction()?;
-            tx.execute(
-                "DELETE \
-                FROM playlist_tracks
-                WHERE track_id IN
-                    (SELECT track_id \
-                    FROM track \
-                    WHERE library_id = ?1);",
-                params![library.id])?;
-            tx.execute(
-                "DELETE \
-                FROM track
-                WHERE library_id = ?1",
-                params![library.id])?;
-
-            tx.commit()?;
+            self.clear_library(library.id)?;
         }
 
         trace!("Collecting paths...");
         // Collect all of the paths into a list
         // May include non-track files
         let mut new_tracks: Vec<String> = Vec::new();
-        for entry in WalkDir::new(&library.path).follow_links(true) {
+        let walk = WalkDir::new(&library.path).follow_links(true).into_iter()
+            // The library root itself may well be hidden, that's fine
+            .filter_entry(|entry| !(options.skip_hidden && entry.depth() > 0 && is_hidden(entry)));
+        for entry in walk {
             let entry = entry?;
 
             if entry.file_type().is_file() {
@@ -222,6 +927,8 @@ impl Database {
 
         // Tracks that are now missing
         let mut res: Vec<String> = Vec::new();
+        // Ids of tracks whose files have changed since we read them
+        let mut modified: Vec<i64> = Vec::new();
 
         // Remove tracks that are already in the database and tracks that are now missing if we
         // aren't doing a full rescan
@@ -239,10 +946,7 @@ impl Database {
                 }
             }
 
-            // Remove tracks from that database that are missing
-            remove_missing_tracks(&tx, &library, &mut res)?;
-
-            // And remove tracks from the new_tracks list that are already in the library
+            // Remove tracks from the new_tracks list that are already in the library
             new_tracks.clear();
 
             {
@@ -250,7 +954,7 @@ impl Database {
                     "SELECT scan_results.path \
                 FROM scan_results \
                 LEFT JOIN track ON track.path = scan_results.path \
-                WHERE track.path IS NULL:"
+                WHERE track.path IS NULL;"
                 )?;
 
                 for track in remove_duplicates.query_map(NO_PARAMS, |row|
@@ -260,6 +964,15 @@ impl Database {
                 }
             }
 
+            // Tracks we already have only need reading again if they've been modified since
+            modified = modified_tracks(&tx, &library)?;
+
+            // And remove tracks from the database that are missing, unless they were just moved
+            remove_missing_tracks(&tx, &library, &mut new_tracks, &mut res)?;
+
+            // Temporary tables last as long as the connection, which may scan another library
+            tx.execute("DROP TABLE scan_results;", NO_PARAMS)?;
+
             tx.commit()?;
         }
 
@@ -267,107 +980,2480 @@ impl Database {
         // add directly to the database. We have to process them to extract their metadata (and
         // determine if they are in fact valid tracks)
 
-        let mut stmt = self.conn.prepare(
-            "INSERT INTO track (library_id, path, title, artist, album, comment, genre, year, track, length, bitrate, samplerate, rating) \
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);"
-        )?;
+        let mut pacer = options.throttle.map(Pacer::new);
+        let total = modified.len() + new_tracks.len();
+        if let Some(progress) = progress.as_mut() {
+            progress(0, total);
+        }
+
+        for (scanned, &id) in modified.iter().enumerate() {
+            if let Some(pacer) = pacer.as_mut() {
+                thread::sleep(pacer.delay(Instant::now()));
+            }
 
-        for path in new_tracks {
-            if let Ok(file) = File::new(&path) {
-                if let (Ok(tag), Ok(properties)) = (file.tag(), file.audioproperties()) {
-                    let initial_rating: Option<u8> = None;
-                    trace!("Adding track {} located at {}", tag.title().unwrap_or("?".to_string()), path);
-                    stmt.execute(params![
-                        library.id,
-                        path,
-                        tag.title(),
-                        tag.artist(),
-                        tag.album(),
-                        tag.comment(),
-                        tag.genre(),
-                        tag.year(),
-                        tag.track(),
-                        properties.length(),
-                        properties.bitrate(),
-                        properties.samplerate(),
-                        initial_rating // TODO: implement rating,
-                    ])?;
+            self.refresh_track(id)?;
+
+            if let Some(progress) = progress.as_mut() {
+                progress(scanned + 1, total);
+            }
+        }
+
+        for (scanned, path) in (modified.len()..).zip(new_tracks) {
+            if let Some(pacer) = pacer.as_mut() {
+                thread::sleep(pacer.delay(Instant::now()));
+            }
+
+            let added = insert_track(&self.conn, library.id, &path, options.hash_files)?;
+
+            if let Some(found) = found.as_mut().filter(|_| added) {
+                if let Some(track) = self.track_by_path(&path)? {
+                    found(track);
                 }
             }
+
+            if let Some(progress) = progress.as_mut() {
+                progress(scanned + 1, total);
+            }
         }
 
         Ok(res)
     }
 
-    pub fn dump_all_tracks(&self) -> Result<Vec<Track>, DatabaseError> {
-        trace!("Dumping tracks");
-        let mut stmt = self.conn.prepare("SELECT * FROM track;")?;
+    /// Add a single file to the 'Individual Tracks' library, or return it as-is if we already
+    /// know about it. `path` must be absolute. Returns None if the file isn't a readable track.
+    pub fn add_individual_track(&mut self, path: &str) -> Result<Option<Track>, DatabaseError> {
+        if let Some(track) = self.track_by_path(path)? {
+            return Ok(Some(track));
+        }
+
+        let library_id: i64 = self.conn.query_row("SELECT id FROM library WHERE path = 'NONE'",
+                                                  NO_PARAMS,
+                                                  |row| row.get(0))?;
+
+        if insert_track(&self.conn, library_id, path, false)? {
+            self.track_by_path(path)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read one file under `library` that has just appeared or been modified, adding it if we
+    /// don't know about it yet. `path` must be absolute. Returns the track and whether it's new,
+    /// or None if the file isn't a readable track.
+    pub fn scan_file(&mut self, library: &Library, path: &str,
+                     options: &ScanOptions) -> Result<Option<(Track, bool)>, DatabaseError> {
+        if let Some(track) = self.track_by_path(path)? {
+            return Ok(self.refresh_track(track.id())?.map(|track| (track, false)));
+        }
+
+        if insert_track(&self.conn, library.id, path, options.hash_files)? {
+            Ok(self.track_by_path(path)?.map(|track| (track, true)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Ids of tracks whose files have been modified since we read their tags, e.g. because they
+    /// were retagged in another program. This stats every file, so don't run it on the UI thread.
+    pub fn stale_tracks(&self) -> Result<Vec<i64>, DatabaseError> {
+        trace!("Checking for stale tracks");
+        let mut stmt = self.conn.prepare("SELECT id, path, mtime FROM track;")?;
 
         let mut res = Vec::new();
-        for track in stmt.query_map(params![], |row| {
-            Ok(Track {
-                id:         row.get::<_, Option<i64>>(0)?.unwrap(),
-                library_id: row.get::<_, Option<i64>>(1)?.unwrap(),
-                path:       row.get::<_, Option<String>>(2)?.unwrap(),
-                title:      row.get(3)?,
-                artist:     row.get(4)?,
-                album:      row.get(5)?,
-                comment:    row.get(6)?,
-                genre:      row.get(7)?,
-                year:       row.get(8)?,
-                track:      row.get(9)?,
-                length:     row.get::<_, Option<i32>>(10)?.unwrap(),
-                bitrate:    row.get::<_, Option<i32>>(11)?.unwrap(),
-                samplerate: row.get::<_, Option<i32>>(12)?.unwrap(),
-                rating:     row.get(13)?
-            })
+        for row in stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i64>>(2)?))
         })? {
-            res.push(track?);
+            let (id, path, mtime) = row?;
+            if is_stale(mtime, file_mtime(&path)) {
+                res.push(id);
+            }
         }
 
         Ok(res)
     }
-}
 
-impl Drop for Database {
-    fn drop(&mut self) {
-        // TODO
+    /// Re-read a track's tags from its file. Returns the updated track, or None if the file is
+    /// gone or no longer readable (in which case the row is left alone).
+    pub fn refresh_track(&mut self, id: i64) -> Result<Option<Track>, DatabaseError> {
+        let path: String = self.conn.query_row("SELECT path FROM track WHERE id = ?1",
+                                               params![id],
+                                               |row| row.get(0))?;
+
+        let tags = match TrackTags::read(&path) {
+            Some(tags) => tags,
+            None => return Ok(None),
+        };
+
+        trace!("Refreshing tags of {}", path);
+        self.conn.execute(
+            "UPDATE track \
+            SET title = ?1, artist = ?2, album = ?3, comment = ?4, genre = ?5, year = ?6, \
+                track = ?7, length = ?8, bitrate = ?9, samplerate = ?10, disc = ?11, mtime = ?12, \
+                replay_gain = ?13, hash = NULL, audio_hash = NULL, loudness = NULL, \
+                sound_start_ms = NULL, sound_end_ms = NULL, silence_threshold = NULL \
+            WHERE id = ?14",
+            params![
+                tags.title,
+                tags.artist,
+                tags.album,
+                tags.comment,
+                tags.genre,
+                tags.year,
+                tags.track,
+                tags.length,
+                tags.bitrate,
+                tags.samplerate,
+                tags.disc,
+                tags.mtime,
+                tags.replay_gain,
+                id,
+            ])?;
+
+        self.track_by_path(&path)
     }
-}
 
-fn init_db(conn: &Connection) -> rusqlite::Result<()> {
-    trace!("Creating database schema");
-    let create = include_str!("create.sql");
-    conn.execute_batch(create)
-}
+    /// The track's content hash (see `fingerprint::content_hash`), computing and storing it first
+    /// if it wasn't hashed during its scan. None if the file can't be read.
+    pub fn track_hash(&mut self, id: i64) -> Result<Option<String>, DatabaseError> {
+        let (path, hash): (String, Option<String>) = self.conn.query_row(
+            "SELECT path, hash FROM track WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)))?;
 
-fn remove_missing_tracks(tx: &Transaction, library: &Library, res: &mut Vec<String>) -> Result<(), DatabaseError> {
-    // Remove tracks in the library that are no longer present on disk
-    // We unfortunately need to do this in two queries because we have to return the tracks
-    // that were removed
+        if hash.is_some() {
+            return Ok(hash);
+        }
 
-    let mut missing_tracks = tx.prepare(
-        "WITH current_paths AS
-                    (SELECT path
-                    FROM track
-                    WHERE library_id = ?1)
-               SELECT current_paths.path
-               FROM current_paths
-                   LEFT JOIN scan_results ON current_paths.path = scan_results.path
-               WHERE scan_results.path IS NULL);"
-    )?;
+        match content_hash(&path) {
+            Ok(hash) => {
+                self.conn.execute("UPDATE track SET hash = ?1 WHERE id = ?2", params![hash, id])?;
+                Ok(Some(hash))
+            }
+            Err(e) => {
+                warn!("Could not hash {}: {}", path, e);
+                Ok(None)
+            }
+        }
+    }
 
-    let mut delete_missing_tracks = tx.prepare(
-        "DELETE FROM track WHERE path = ?"
-    )?;
+    /// Replace the saved queue with these tracks. `current` is the index of the one playing and
+    /// how far into it playback is.
+    pub fn save_queue(&mut self, track_ids: &[i64],
+                      current: Option<(usize, Duration)>) -> Result<(), DatabaseError> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM queue;", NO_PARAMS)?;
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO queue (position, track_id, elapsed_ms) VALUES (?1, ?2, ?3)"
+            )?;
+            for (position, id) in track_ids.iter().enumerate() {
+                let elapsed_ms = current
+                    .filter(|&(index, _)| index == position)
+                    .map(|(_, elapsed)| elapsed.as_millis() as i64);
+                insert.execute(params![position as i64, id, elapsed_ms])?;
+            }
+        }
 
-    for track in missing_tracks.query_map(params!(library.id), |row|
-        row.get(0),
-    )? {
-        let track = track?;
-        delete_missing_tracks.execute(params![&track])?;
-        res.push(track);
+        tx.commit()?;
+        Ok(())
     }
 
-    Ok(())
-}
\ No newline at end of file
+    /// The queue saved by `save_queue`, minus any tracks that have since been removed, and the
+    /// index of the one that was playing and how far into it playback was.
+    pub fn saved_queue(&self) -> Result<(Vec<Track>, Option<(usize, Duration)>), DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), ", queue.elapsed_ms \
+                     FROM queue \
+                     JOIN track ON track.id = queue.track_id \
+                     ORDER BY queue.position;")
+        )?;
+
+        let mut tracks = Vec::new();
+        let mut current = None;
+        for row in stmt.query_map(params![], |row| {
+            Ok((track_from_row(row)?, row.get::<_, Option<i64>>(TRACK_COLUMN_COUNT)?))
+        })? {
+            let (track, elapsed_ms) = row?;
+            if let Some(elapsed_ms) = elapsed_ms {
+                current = Some((tracks.len(), Duration::from_millis(elapsed_ms.max(0) as u64)));
+            }
+            tracks.push(track);
+        }
+
+        Ok((tracks, current))
+    }
+
+    /// Groups of two or more tracks that look like copies of the same song, in library order.
+    /// Audio keys are computed and stored for tracks that don't have one yet, which means
+    /// decoding them, so this can take a long time.
+    pub fn find_duplicates(&mut self, key: DuplicateKey) -> Result<Vec<Vec<Track>>, DatabaseError> {
+        let tracks = self.dump_all_tracks()?;
+        info!("Looking for duplicates among {} tracks by {:?}", tracks.len(), key);
+
+        let mut groups: HashMap<String, Vec<Track>> = HashMap::new();
+        let mut order = Vec::new();
+        for track in tracks {
+            let key = match key {
+                DuplicateKey::Tags  => tag_key(&track),
+                DuplicateKey::Audio => self.track_audio_hash(track.id, &track.path)?,
+            };
+
+            if let Some(key) = key {
+                let group = groups.entry(key.clone()).or_default();
+                if group.is_empty() {
+                    order.push(key);
+                }
+                group.push(track);
+            }
+        }
+
+        Ok(order.into_iter()
+            .filter_map(|key| groups.remove(&key))
+            .filter(|group| group.len() > 1)
+            .collect())
+    }
+
+    /// The track's audio hash, computing and storing it first if it doesn't have one. None if
+    /// the file can't be decoded.
+    fn track_audio_hash(&mut self, id: i64, path: &str) -> Result<Option<String>, DatabaseError> {
+        let hash: Option<String> = self.conn.query_row(
+            "SELECT audio_hash FROM track WHERE id = ?1",
+            params![id],
+            |row| row.get(0))?;
+
+        if hash.is_some() {
+            return Ok(hash);
+        }
+
+        match audio_hash(path) {
+            Ok(hash) => {
+                self.conn.execute("UPDATE track SET audio_hash = ?1 WHERE id = ?2", params![hash, id])?;
+                Ok(Some(hash))
+            }
+            Err(e) => {
+                warn!("Could not decode {}: {}", path, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// A track's cover art, still encoded: its own if it has any, otherwise a cover image in its
+    /// folder. This reads the file, so cache the result.
+    pub fn album_art(&self, track_id: i64) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let path: String = self.conn.query_row("SELECT path FROM track WHERE id = ?1",
+                                               params![track_id],
+                                               |row| row.get(0))?;
+
+        Ok(embedded_art(&path).or_else(|| folder_art(&path)))
+    }
+
+    /// Set a track's rating, or clear it with None.
+    pub fn set_rating(&mut self, id: i64, rating: Option<i32>) -> Result<(), DatabaseError> {
+        check_rating(rating)?;
+
+        self.conn.execute("UPDATE track SET rating = ?1 WHERE id = ?2",
+                          params![rating, id])?;
+
+        Ok(())
+    }
+
+    /// Set several tracks' ratings at once, or none of them if any rating is invalid. Returns
+    /// what the ratings were before, for the tracks that still exist, so that setting those puts
+    /// them back.
+    pub fn set_ratings(&mut self, ratings: &[(i64, Option<i32>)])
+                       -> Result<Vec<(i64, Option<i32>)>, DatabaseError> {
+        for &(_, rating) in ratings {
+            check_rating(rating)?;
+        }
+
+        let tx = self.conn.transaction()?;
+        let mut previous = Vec::new();
+
+        for &(id, rating) in ratings {
+            let old: Option<Option<i32>> = tx.query_row("SELECT rating FROM track WHERE id = ?1",
+                                                        params![id],
+                                                        |row| row.get(0))
+                .optional()?;
+
+            if let Some(old) = old {
+                tx.execute("UPDATE track SET rating = ?1 WHERE id = ?2", params![rating, id])?;
+                previous.push((id, old));
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(previous)
+    }
+
+    /// Replace a track's editable tags in the database, returning the updated track. This doesn't
+    /// touch the file; follow it with `write_tags_to_file` or a rescan will undo it.
+    pub fn update_track_metadata(&mut self, id: i64, metadata: &TrackMetadata) -> Result<Option<Track>, DatabaseError> {
+        self.conn.execute("UPDATE track
+                           SET title = ?1, artist = ?2, album = ?3, comment = ?4, genre = ?5,
+                               year = ?6, track = ?7
+                           WHERE id = ?8",
+                          params![metadata.title, metadata.artist, metadata.album,
+                                  metadata.comment, metadata.genre, metadata.year, metadata.track,
+                                  id])?;
+
+        self.track(id)
+    }
+
+    /// Count a play of the track with this id, which was played just now.
+    pub fn record_play(&mut self, id: i64) -> Result<(), DatabaseError> {
+        trace!("Recording a play of track {}", id);
+        self.conn.execute("UPDATE track \
+                           SET play_count = play_count + 1, \
+                               last_played = CAST(strftime('%s', 'now') AS INTEGER) \
+                           WHERE id = ?1",
+                          params![id])?;
+
+        Ok(())
+    }
+
+    /// Note that the track with this id failed to play just now, replacing whatever failure was
+    /// noted before.
+    pub fn record_playback_error(&mut self, id: i64, failure: PlaybackFailure,
+                                 message: &str) -> Result<(), DatabaseError> {
+        trace!("Recording that track {} failed to play", id);
+        self.conn.execute("UPDATE track SET playback_error_kind = ?1, playback_error = ?2 \
+                           WHERE id = ?3",
+                          params![failure.column_value(), message, id])?;
+
+        Ok(())
+    }
+
+    /// How the track with this id last failed to play, and why, if it ever has.
+    pub fn playback_error(&self,
+                          id: i64) -> Result<Option<(PlaybackFailure, String)>, DatabaseError> {
+        let error: Option<(Option<String>, Option<String>)> = self.conn
+            .query_row("SELECT playback_error_kind, playback_error FROM track WHERE id = ?1",
+                       params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+
+        Ok(match error {
+            Some((Some(kind), message)) => PlaybackFailure::from_column_value(&kind)
+                .map(|failure| (failure, message.unwrap_or_default())),
+            _ => None,
+        })
+    }
+
+    /// The ids and paths of the tracks that haven't been analyzed yet, or whose silence was
+    /// found at another threshold than `silence_threshold`. Loudness and silence are measured
+    /// together, so tracks too quiet to have a loudness still count as analyzed.
+    pub fn unanalyzed_tracks(&self,
+                             silence_threshold: f64) -> Result<Vec<(i64, String)>, DatabaseError> {
+        Ok(self.conn
+            .prepare("SELECT id, path FROM track \
+                      WHERE silence_threshold IS NULL OR silence_threshold != ?1 \
+                      ORDER BY id;")?
+            .query_map(params![silence_threshold], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Note the integrated loudness of the track with this id, in LUFS. Follow it with
+    /// `update_album_loudness` once its album's other tracks have been measured.
+    pub fn set_loudness(&mut self, id: i64, loudness: f64) -> Result<(), DatabaseError> {
+        self.conn.execute("UPDATE track SET loudness = ?1 WHERE id = ?2",
+                          params![loudness, id])?;
+
+        Ok(())
+    }
+
+    /// Note where the sound of the track with this id starts and ends, between the silence
+    /// before and after it at `threshold` dB, or that it's silent throughout with None.
+    pub fn set_sound(&mut self, id: i64, threshold: f64,
+                     sound: Option<(Duration, Duration)>) -> Result<(), DatabaseError> {
+        let millis = |time: Duration| time.as_millis() as i64;
+        self.conn.execute("UPDATE track \
+                           SET sound_start_ms = ?1, sound_end_ms = ?2, silence_threshold = ?3 \
+                           WHERE id = ?4",
+                          params![sound.map(|(start, _)| millis(start)),
+                                  sound.map(|(_, end)| millis(end)), threshold, id])?;
+
+        Ok(())
+    }
+
+    /// Work out every album's loudness again from its tracks', for normalizing by album. Tracks
+    /// in the same library with the same album tag are taken to be an album; tracks without one
+    /// aren't on any, so they have no album loudness.
+    pub fn update_album_loudness(&mut self) -> Result<(), DatabaseError> {
+        let tx = self.conn.transaction()?;
+
+        let mut albums: HashMap<(i64, String), Vec<(i64, f64, i32)>> = HashMap::new();
+        {
+            let mut measured = tx.prepare("SELECT id, library_id, album, loudness, length \
+                                           FROM track \
+                                           WHERE album IS NOT NULL AND loudness IS NOT NULL;")?;
+            let rows = measured.query_map(NO_PARAMS, |row| {
+                Ok(((row.get(1)?, row.get(2)?), (row.get(0)?, row.get(3)?, row.get(4)?)))
+            })?;
+            for row in rows {
+                let (album, track): ((i64, String), (i64, f64, i32)) = row?;
+                albums.entry(album).or_default().push(track);
+            }
+        }
+
+        tx.execute("UPDATE track SET album_loudness = NULL", NO_PARAMS)?;
+        for tracks in albums.values() {
+            let loudness: Vec<_> = tracks.iter().map(|&(_, loudness, length)| (loudness, length))
+                .collect();
+            for &(id, _, _) in tracks {
+                tx.execute("UPDATE track SET album_loudness = ?1 WHERE id = ?2",
+                           params![album_loudness(&loudness), id])?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Remove a track from its library and every playlist, and then do `removal` to its file.
+    /// The file is only touched once the track is gone, so a failed removal never loses a file
+    /// that's still in the library. If the file can't be trashed or deleted, it's left where it
+    /// is; a file that can't be trashed is never deleted instead.
+    pub fn remove_track(&mut self, id: i64, removal: FileRemoval) -> Result<(), DatabaseError> {
+        trace!("Removing track {}", id);
+        let tx = self.conn.transaction()?;
+        let path: Option<String> = tx.query_row("SELECT path FROM track WHERE id = ?1",
+                                                params![id],
+                                                |row| row.get(0))
+            .optional()?;
+
+        tx.execute("DELETE FROM playlist_tracks WHERE track_id = ?1", params![id])?;
+        tx.execute("DELETE FROM track WHERE id = ?1", params![id])?;
+        tx.commit()?;
+
+        match (removal, path) {
+            (FileRemoval::Trash, Some(path)) => {
+                trash::delete(&path).map_err(|e| DatabaseError::TrashError(path, e))?;
+            }
+            (FileRemoval::Delete, Some(path)) => remove_file(&path)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Write a track's editable tags to its file, so they match the database and survive a
+    /// rescan.
+    pub fn write_tags_to_file(&mut self, track: &Track) -> Result<(), DatabaseError> {
+        write_tags(track.path(), &track.metadata())
+            .map_err(|e| DatabaseError::TagWriteError(track.path().to_string(), e))?;
+
+        // We changed the file ourselves, so it isn't stale
+        self.conn.execute("UPDATE track SET mtime = ?1 WHERE id = ?2",
+                          params![file_mtime(track.path()), track.id()])?;
+
+        Ok(())
+    }
+
+    pub fn track(&self, id: i64) -> Result<Option<Track>, DatabaseError> {
+        Ok(self.conn.query_row(concat!("SELECT ", track_columns!(), " FROM track WHERE id = ?1"),
+                               params![id],
+                               track_from_row)
+            .optional()?)
+    }
+
+    pub fn track_by_path(&self, path: &str) -> Result<Option<Track>, DatabaseError> {
+        Ok(self.conn.query_row(concat!("SELECT ", track_columns!(), " FROM track WHERE path = ?1"),
+                               params![path],
+                               track_from_row)
+            .optional()?)
+    }
+
+    /// Point the track at `from` at `to` instead, after its file was renamed, keeping its rating
+    /// and playlists. Returns the moved track, or None if there's no track at `from`.
+    pub fn move_track(&mut self, from: &str, to: &str) -> Result<Option<Track>, DatabaseError> {
+        trace!("Moving track at {} to {}", from, to);
+        let moved = self.conn.execute("UPDATE track SET path = ?1 WHERE path = ?2",
+                                      params![to, from])?;
+
+        if moved == 0 {
+            return Ok(None);
+        }
+
+        self.track_by_path(to)
+    }
+
+    /// Every track whose file is somewhere under `folder`.
+    pub fn tracks_under(&self, folder: &Path) -> Result<Vec<Track>, DatabaseError> {
+        let prefix = match folder.join("").into_os_string().into_string() {
+            Ok(prefix) => prefix,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        // substr rather than LIKE, so that % and _ in folder names aren't wildcards
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track WHERE substr(path, 1, ?1) = ?2;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![prefix.chars().count() as i64, prefix],
+                                    track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    pub fn dump_all_tracks(&self) -> Result<Vec<Track>, DatabaseError> {
+        trace!("Dumping tracks");
+        // Multi-disc albums play disc 1 before disc 2; tracks without a disc number count as disc 1
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     ORDER BY artist, album, IFNULL(disc, 1), track;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    pub fn library_tracks(&self, library_id: i64) -> Result<Vec<Track>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     WHERE library_id = ?1 \
+                     ORDER BY artist, album, IFNULL(disc, 1), track;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![library_id], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// The `limit` tracks that have been played the most, most played first. Tracks that have
+    /// never been played aren't included.
+    pub fn most_played(&self, limit: u32) -> Result<Vec<Track>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     WHERE play_count > 0 \
+                     ORDER BY play_count DESC, last_played DESC \
+                     LIMIT ?1;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![limit], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// The last `limit` tracks played, most recent first.
+    pub fn recently_played(&self, limit: u32) -> Result<Vec<Track>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     WHERE last_played IS NOT NULL \
+                     ORDER BY last_played DESC \
+                     LIMIT ?1;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![limit], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// Every artist in the library, alphabetically, with tracks that have no artist last.
+    pub fn distinct_artists(&self) -> Result<Vec<Option<String>>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT artist FROM track ORDER BY artist IS NULL, artist COLLATE NOCASE;"
+        )?;
+
+        let mut res = Vec::new();
+        for artist in stmt.query_map(params![], |row| row.get(0))? {
+            res.push(artist?);
+        }
+
+        Ok(res)
+    }
+
+    /// Every album with a track by `artist`, alphabetically, with tracks that have no album last.
+    /// `None` matches tracks without an artist.
+    pub fn albums_for_artist(&self, artist: Option<&str>) -> Result<Vec<Option<String>>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT album FROM track WHERE artist IS ?1 ORDER BY album IS NULL, album COLLATE NOCASE;"
+        )?;
+
+        let mut res = Vec::new();
+        for album in stmt.query_map(params![artist], |row| row.get(0))? {
+            res.push(album?);
+        }
+
+        Ok(res)
+    }
+
+    /// `artist`'s tracks on `album`, in track order. `None` matches a missing artist or album.
+    pub fn tracks_for_album(&self, artist: Option<&str>, album: Option<&str>) -> Result<Vec<Track>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     WHERE artist IS ?1 AND album IS ?2 \
+                     ORDER BY IFNULL(disc, 1), track;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![artist, album], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// Move tracks into `library` without touching the files themselves, e.g. after reorganizing
+    /// folders. Tracks whose paths aren't under the library's root are left where they are, and
+    /// their paths are returned.
+    pub fn set_tracks_library(&mut self, track_ids: &[i64], library: &Library) -> Result<Vec<String>, DatabaseError> {
+        trace!("Moving {} tracks into library {}", track_ids.len(), library.name);
+
+        let mut rejected = Vec::new();
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut path = tx.prepare("SELECT path FROM track WHERE id = ?1")?;
+            let mut update = tx.prepare("UPDATE track SET library_id = ?1 WHERE id = ?2")?;
+
+            for &id in track_ids {
+                let track_path: String = path.query_row(params![id], |row| row.get(0))?;
+
+                if library.contains_path(&track_path) {
+                    update.execute(params![library.id, id])?;
+                } else {
+                    warn!("{} is not under {}, not moving it", track_path, library.path);
+                    rejected.push(track_path);
+                }
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(rejected)
+    }
+
+    pub fn playlist_exists(&self, id: i64) -> Result<bool, DatabaseError> {
+        Ok(self.conn.query_row("SELECT 1 FROM playlist WHERE id = ?1",
+                               params![id],
+                               |_row| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    pub fn list_playlists(&self) -> Result<Vec<Playlist>, DatabaseError> {
+        let mut stmt = self.conn.prepare("SELECT id, name, smart FROM playlist ORDER BY name;")?;
+
+        let mut res = Vec::new();
+        for playlist in stmt.query_map(NO_PARAMS, |row| {
+            Ok(Playlist {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                smart: row.get(2)?,
+            })
+        })? {
+            res.push(playlist?);
+        }
+
+        Ok(res)
+    }
+
+    /// Make a new, empty playlist, returning its id.
+    pub fn create_playlist(&mut self, name: String) -> Result<i64, DatabaseError> {
+        let suggestion = self.unique_playlist_name(&name)?;
+        if suggestion != name {
+            return Err(DatabaseError::PlaylistNameTaken { suggestion });
+        }
+
+        info!("Creating playlist {}", name);
+        self.conn.execute("INSERT INTO playlist (name) VALUES (?1)", params![name])?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Make a new smart playlist, returning its id.
+    pub fn create_smart_playlist(&mut self, name: String,
+                                 criteria: &[Criterion]) -> Result<i64, DatabaseError> {
+        let suggestion = self.unique_playlist_name(&name)?;
+        if suggestion != name {
+            return Err(DatabaseError::PlaylistNameTaken { suggestion });
+        }
+
+        info!("Creating smart playlist {}", name);
+        let tx = self.conn.transaction()?;
+        tx.execute("INSERT INTO playlist (name, smart) VALUES (?1, 1)", params![name])?;
+        let id = tx.last_insert_rowid();
+        insert_criteria(&tx, id, criteria)?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    /// Replace a smart playlist's name and criteria.
+    pub fn update_smart_playlist(&mut self, id: i64, name: String,
+                                 criteria: &[Criterion]) -> Result<(), DatabaseError> {
+        let current: String = self.conn.query_row("SELECT name FROM playlist WHERE id = ?1",
+                                                  params![id],
+                                                  |row| row.get(0))?;
+        if name != current {
+            let suggestion = self.unique_playlist_name(&name)?;
+            if suggestion != name {
+                return Err(DatabaseError::PlaylistNameTaken { suggestion });
+            }
+        }
+
+        info!("Updating smart playlist {}", id);
+        let tx = self.conn.transaction()?;
+        tx.execute("UPDATE playlist SET name = ?1 WHERE id = ?2 AND smart", params![name, id])?;
+        tx.execute("DELETE FROM playlist_criteria WHERE playlist_id = ?1", params![id])?;
+        insert_criteria(&tx, id, criteria)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// A smart playlist's criteria, or None if it's an ordinary playlist. Criteria that can't
+    /// be read are left out.
+    pub fn playlist_criteria(&self, id: i64) -> Result<Option<Vec<Criterion>>, DatabaseError> {
+        let smart: bool = self.conn.query_row("SELECT smart FROM playlist WHERE id = ?1",
+                                              params![id],
+                                              |row| row.get(0))?;
+        if !smart {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT field, operator, value FROM playlist_criteria \
+            WHERE playlist_id = ?1 \
+            ORDER BY position;"
+        )?;
+
+        let mut res = Vec::new();
+        for row in stmt.query_map(params![id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })? {
+            let (field, operator, value) = row?;
+            match (TrackField::from_column(&field), Operator::from_symbol(&operator)) {
+                (Some(field), Some(operator)) => res.push(Criterion { field, operator, value }),
+                _ => warn!("Ignoring unreadable criterion {} {} {:?} of playlist {}",
+                           field, operator, value, id),
+            }
+        }
+
+        Ok(Some(res))
+    }
+
+    /// Every track meeting all of the criteria, in library order. Values are always passed as
+    /// parameters, never put into the SQL.
+    pub fn query_tracks(&self, criteria: &[Criterion]) -> Result<Vec<Track>, DatabaseError> {
+        let (conditions, values) = criteria_sql(criteria);
+        let mut stmt = self.conn.prepare(&format!(
+            concat!("SELECT ", track_columns!(), " FROM track \
+                     WHERE {} \
+                     ORDER BY artist, album, IFNULL(disc, 1), track;"),
+            conditions))?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(&values, track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// Delete a playlist. Its tracks stay in the library.
+    pub fn delete_playlist(&mut self, id: i64) -> Result<(), DatabaseError> {
+        info!("Deleting playlist {}", id);
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM playlist_tracks WHERE id = ?1", params![id])?;
+        tx.execute("DELETE FROM playlist_criteria WHERE playlist_id = ?1", params![id])?;
+        tx.execute("DELETE FROM playlist WHERE id = ?1", params![id])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Add tracks to the end of a playlist, in the order given. A track can be in a playlist
+    /// more than once.
+    pub fn add_to_playlist(&mut self, playlist_id: i64, track_ids: &[i64]) -> Result<(), DatabaseError> {
+        if self.playlist_criteria(playlist_id)?.is_some() {
+            return Err(DatabaseError::SmartPlaylist);
+        }
+
+        let tx = self.conn.transaction()?;
+        let next: i64 = tx.query_row(
+            "SELECT IFNULL(MAX(position) + 1, 0) FROM playlist_tracks WHERE id = ?1",
+            params![playlist_id],
+            |row| row.get(0))?;
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO playlist_tracks (id, track_id, position) VALUES (?1, ?2, ?3)"
+            )?;
+            for (position, track_id) in (next..).zip(track_ids) {
+                insert.execute(params![playlist_id, track_id, position])?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Take every entry of these tracks out of a playlist, closing up the gaps they leave.
+    pub fn remove_from_playlist(&mut self, playlist_id: i64, track_ids: &[i64]) -> Result<(), DatabaseError> {
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut delete = tx.prepare(
+                "DELETE FROM playlist_tracks WHERE id = ?1 AND track_id = ?2"
+            )?;
+            for track_id in track_ids {
+                delete.execute(params![playlist_id, track_id])?;
+            }
+        }
+
+        renumber_playlist(&tx, playlist_id)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Put a playlist's entries in a new order, given as every one of its track ids (repeated as
+    /// often as they're in it) in that order. Fails if those aren't the tracks in the playlist.
+    pub fn reorder_playlist(&mut self, playlist_id: i64, ordered_track_ids: &[i64]) -> Result<(), DatabaseError> {
+        let tx = self.conn.transaction()?;
+
+        // Only entries the UI can see, i.e. whose tracks still exist. Any others are dropped by
+        // the rewrite below.
+        let mut current = Vec::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT playlist_tracks.track_id \
+                FROM playlist_tracks \
+                    JOIN track ON track.id = playlist_tracks.track_id \
+                WHERE playlist_tracks.id = ?1"
+            )?;
+            for track_id in stmt.query_map(params![playlist_id], |row| row.get::<_, i64>(0))? {
+                current.push(track_id?);
+            }
+        }
+
+        let mut ordered = ordered_track_ids.to_vec();
+        current.sort_unstable();
+        ordered.sort_unstable();
+        if current != ordered {
+            return Err(DatabaseError::PlaylistChanged);
+        }
+
+        trace!("Reordering playlist {}", playlist_id);
+        tx.execute("DELETE FROM playlist_tracks WHERE id = ?1", params![playlist_id])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO playlist_tracks (id, track_id, position) VALUES (?1, ?2, ?3)"
+            )?;
+            for (position, track_id) in ordered_track_ids.iter().enumerate() {
+                insert.execute(params![playlist_id, track_id, position as i64])?;
+            }
+        }
+
+        // All at once, so the playlist is never left half reordered
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// The tracks in the playlist, in order.
+    pub fn playlist_tracks(&self, id: i64) -> Result<Vec<Track>, DatabaseError> {
+        let mut stmt = self.conn.prepare(
+            concat!("SELECT ", track_columns!(), " \
+                     FROM playlist_tracks \
+                     JOIN track ON track.id = playlist_tracks.track_id \
+                     WHERE playlist_tracks.id = ?1 \
+                     ORDER BY playlist_tracks.position;")
+        )?;
+
+        let mut res = Vec::new();
+        for track in stmt.query_map(params![id], track_from_row)? {
+            res.push(track?);
+        }
+
+        Ok(res)
+    }
+
+    /// Write a playlist out as an extended M3U file, for other players.
+    pub fn export_playlist_m3u(&self, playlist_id: i64, mut writer: impl Write) -> Result<(), DatabaseError> {
+        let tracks = match self.playlist_criteria(playlist_id)? {
+            Some(criteria) => self.query_tracks(&criteria)?,
+            None => self.playlist_tracks(playlist_id)?,
+        };
+        info!("Exporting {} tracks of playlist {}", tracks.len(), playlist_id);
+        write_m3u(&mut writer, &tracks)?;
+
+        Ok(())
+    }
+
+    /// Make a playlist from an M3U file, named after it. Entries that aren't in a library yet are
+    /// added to 'Individual Tracks'; ones that are missing or unreadable are skipped. Returns the
+    /// new playlist's id.
+    pub fn import_playlist_m3u(&mut self, path: &Path) -> Result<i64, DatabaseError> {
+        // M3U files are often in some legacy encoding, which shouldn't stop the rest being read
+        let contents = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let name = path.file_stem()
+            .map_or_else(|| "Imported Playlist".to_string(),
+                         |name| name.to_string_lossy().into_owned());
+
+        info!("Importing playlist {}", path.as_display());
+        let mut track_ids = Vec::new();
+        for entry in parse_m3u(&contents, base) {
+            let canonical = entry.canonicalize().ok()
+                .and_then(|canonical| canonical.into_os_string().into_string().ok());
+            let entry = match canonical {
+                Some(entry) => entry,
+                None => {
+                    warn!("Skipping missing playlist entry {}", entry.display());
+                    continue;
+                }
+            };
+
+            match self.track_by_path(&entry)? {
+                Some(track) => track_ids.push(track.id),
+                None => match self.add_individual_track(&entry)? {
+                    Some(track) => track_ids.push(track.id),
+                    None => warn!("Skipping unreadable playlist entry {}", entry),
+                },
+            }
+        }
+
+        let name = self.unique_playlist_name(&name)?;
+        let id = self.create_playlist(name)?;
+        self.add_to_playlist(id, &track_ids)?;
+
+        Ok(id)
+    }
+
+    /// Rebuild the database file to reclaim the space left behind by deleted rows. This can be
+    /// slow and locks the database while it runs, so don't call it on the UI thread.
+    pub fn vacuum(&self) -> Result<(), DatabaseError> {
+        let before = self.size()?;
+        trace!("Vacuuming database");
+        self.conn.execute_batch("VACUUM;")?;
+        let after = self.size()?;
+
+        info!("Vacuumed database from {} to {} bytes", before, after);
+        Ok(())
+    }
+
+    /// Size of the database file in bytes.
+    fn size(&self) -> Result<i64, DatabaseError> {
+        Ok(self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size();",
+            NO_PARAMS,
+            |row| row.get(0))?)
+    }
+
+    /// Copy a playlist's tracks, in order, into a new playlist. The copy is independent of the
+    /// original. Returns the new playlist's id.
+    pub fn duplicate_playlist(&mut self, id: i64, new_name: String) -> Result<i64, DatabaseError> {
+        let suggestion = self.unique_playlist_name(&new_name)?;
+        if suggestion != new_name {
+            return Err(DatabaseError::PlaylistNameTaken { suggestion });
+        }
+
+        info!("Duplicating playlist {} as {}", id, new_name);
+        let tx = self.conn.transaction()?;
+
+        tx.execute("INSERT INTO playlist (name, smart) \
+                    SELECT ?1, smart FROM playlist WHERE id = ?2",
+                   params![new_name, id])?;
+        let new_id = tx.last_insert_rowid();
+
+        tx.execute(
+            "INSERT INTO playlist_criteria (playlist_id, position, field, operator, value) \
+            SELECT ?1, position, field, operator, value \
+            FROM playlist_criteria \
+            WHERE playlist_id = ?2;",
+            params![new_id, id])?;
+
+        tx.execute(
+            "INSERT INTO playlist_tracks (id, track_id, position) \
+            SELECT ?1, track_id, position \
+            FROM playlist_tracks \
+            WHERE id = ?2 \
+            ORDER BY position;",
+            params![new_id, id])?;
+
+        tx.commit()?;
+
+        Ok(new_id)
+    }
+
+    /// `name` if no playlist has it yet, otherwise the first free one of "name (2)", "name (3)", …
+    pub fn unique_playlist_name(&self, name: &str) -> Result<String, DatabaseError> {
+        let mut stmt = self.conn.prepare("SELECT 1 FROM playlist WHERE name = ?1")?;
+
+        let mut candidate = name.to_string();
+        let mut n = 2;
+        while stmt.exists(params![candidate])? {
+            candidate = format!("{} ({})", name, n);
+            n += 1;
+        }
+
+        Ok(candidate)
+    }
+
+    pub fn setting(&self, key: &str) -> Result<Option<String>, DatabaseError> {
+        Ok(self.conn.query_row("SELECT value FROM setting WHERE key = ?1",
+                               params![key],
+                               |row| row.get(0))
+            .optional()?)
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<(), DatabaseError> {
+        self.conn.execute("INSERT OR REPLACE INTO setting (key, value) VALUES (?1, ?2)",
+                          params![key, value])?;
+        Ok(())
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // TODO
+    }
+}
+
+/// Read the tags of the file at `path` and add it to the library. Returns false if it isn't
+/// something taglib recognizes as a track.
+fn insert_track(conn: &Connection, library_id: i64, path: &str, hash_file: bool) -> Result<bool, DatabaseError> {
+    let tags = match TrackTags::read(path) {
+        Some(tags) => tags,
+        None => return Ok(false),
+    };
+
+    let hash = if hash_file { content_hash(path).ok() } else { None };
+
+    let initial_rating: Option<u8> = None;
+    trace!("Adding track {} located at {}", tags.title.as_deref().unwrap_or("?"), path);
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO track (library_id, path, title, artist, album, comment, genre, year, track, length, bitrate, samplerate, rating, disc, mtime, hash, replay_gain) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17);"
+    )?;
+
+    stmt.execute(params![
+        library_id,
+        path,
+        tags.title,
+        tags.artist,
+        tags.album,
+        tags.comment,
+        tags.genre,
+        tags.year,
+        tags.track,
+        tags.length,
+        tags.bitrate,
+        tags.samplerate,
+        initial_rating, // TODO: implement rating
+        tags.disc,
+        tags.mtime,
+        hash,
+        tags.replay_gain,
+    ])?;
+
+    Ok(true)
+}
+
+/// Expects the columns listed by `track_columns!`, in that order.
+fn track_from_row(row: &Row) -> rusqlite::Result<Track> {
+    Ok(Track {
+        id:         row.get::<_, Option<i64>>(0)?.unwrap(),
+        library_id: row.get::<_, Option<i64>>(1)?.unwrap(),
+        path:       row.get::<_, Option<String>>(2)?.unwrap(),
+        title:      row.get(3)?,
+        artist:     row.get(4)?,
+        album:      row.get(5)?,
+        comment:    row.get(6)?,
+        genre:      row.get(7)?,
+        year:       row.get(8)?,
+        track:      row.get(9)?,
+        length:     row.get::<_, Option<i32>>(10)?.unwrap(),
+        bitrate:    row.get::<_, Option<i32>>(11)?.unwrap(),
+        samplerate: row.get::<_, Option<i32>>(12)?.unwrap(),
+        rating:     row.get(13)?,
+        disc:       row.get(14)?,
+        mtime:      row.get(15)?,
+        hash:       row.get(16)?,
+        replay_gain: row.get(17)?,
+        play_count: row.get(18)?,
+        last_played: row.get(19)?,
+        loudness:   row.get(20)?,
+        album_loudness: row.get(21)?,
+        sound_start_ms: row.get(22)?,
+        sound_end_ms: row.get(23)?,
+    })
+}
+
+fn init_db(conn: &Connection) -> rusqlite::Result<()> {
+    trace!("Creating database schema");
+    let create = include_str!("create.sql");
+    conn.execute_batch(create)
+}
+
+/// Schema changes applied on top of create.sql, in order. The number of migrations that have
+/// been applied is kept in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_disc_number.sql"),
+    include_str!("migrations/0002_settings.sql"),
+    include_str!("migrations/0003_mtime.sql"),
+    include_str!("migrations/0004_content_hash.sql"),
+    include_str!("migrations/0005_playlist_position.sql"),
+    include_str!("migrations/0006_audio_hash.sql"),
+    include_str!("migrations/0007_queue.sql"),
+    include_str!("migrations/0008_smart_playlists.sql"),
+    include_str!("migrations/0009_replay_gain.sql"),
+    include_str!("migrations/0010_play_count.sql"),
+    include_str!("migrations/0011_playback_error.sql"),
+    include_str!("migrations/0012_loudness.sql"),
+    include_str!("migrations/0013_silence.sql"),
+];
+
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        trace!("Applying migration {}", i + 1);
+        conn.execute_batch(&format!("BEGIN;\n{}\nPRAGMA user_version = {};\nCOMMIT;",
+                                    migration, i + 1))?;
+    }
+
+    Ok(())
+}
+
+fn insert_criteria(tx: &Transaction, playlist_id: i64, criteria: &[Criterion]) -> Result<(), DatabaseError> {
+    let mut insert = tx.prepare(
+        "INSERT INTO playlist_criteria (playlist_id, position, field, operator, value) \
+        VALUES (?1, ?2, ?3, ?4, ?5)"
+    )?;
+    for (position, criterion) in criteria.iter().enumerate() {
+        insert.execute(params![playlist_id, position as i64, criterion.field.column(),
+                               criterion.operator.symbol(), criterion.value])?;
+    }
+
+    Ok(())
+}
+
+/// A WHERE clause matching tracks that meet every criterion, with a `?` for each value, and the
+/// values to bind to them. Column names come from `TrackField::column` and operators from a
+/// fixed list, so nothing from the user ends up in the SQL itself. Text is compared ignoring
+/// case. No criteria match every track.
+fn criteria_sql(criteria: &[Criterion]) -> (String, Vec<Value>) {
+    if criteria.is_empty() {
+        return ("1".to_string(), Vec::new());
+    }
+
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    for criterion in criteria {
+        let column = match criterion.field {
+            // Tracks without a disc number are on the first disc, as in `Track::disc`
+            TrackField::Disc => "IFNULL(disc, 1)".to_string(),
+            field => field.column().to_string(),
+        };
+        let collation = if criterion.field.is_numeric() { "" } else { " COLLATE NOCASE" };
+
+        let condition = match criterion.operator {
+            Operator::Is             => format!("{} = ?{}", column, collation),
+            // Missing values aren't anything in particular, so they count as different
+            Operator::IsNot          => format!("{} IS NOT ?{}", column, collation),
+            Operator::Contains       => format!("{} LIKE ? ESCAPE '\\'", column),
+            Operator::Less           => format!("{} < ?{}", column, collation),
+            Operator::LessOrEqual    => format!("{} <= ?{}", column, collation),
+            Operator::Greater        => format!("{} > ?{}", column, collation),
+            Operator::GreaterOrEqual => format!("{} >= ?{}", column, collation),
+        };
+        conditions.push(condition);
+
+        values.push(match criterion.operator {
+            Operator::Contains => {
+                let escaped = criterion.value.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+                Value::Text(format!("%{}%", escaped))
+            }
+            _ if criterion.field.is_numeric() => {
+                // Checked by `parse_criteria`, but the database could have been edited
+                Value::Integer(criterion.value.parse().unwrap_or_default())
+            }
+            _ => Value::Text(criterion.value.clone()),
+        });
+    }
+
+    (conditions.join(" AND "), values)
+}
+
+/// Number a playlist's entries from 0 again, keeping their order, after some have been removed.
+fn renumber_playlist(tx: &Transaction, playlist_id: i64) -> Result<(), DatabaseError> {
+    let mut stmt = tx.prepare(
+        "SELECT rowid FROM playlist_tracks WHERE id = ?1 ORDER BY position;"
+    )?;
+    let mut entries = Vec::new();
+    for entry in stmt.query_map(params![playlist_id], |row| row.get::<_, i64>(0))? {
+        entries.push(entry?);
+    }
+
+    let mut update = tx.prepare("UPDATE playlist_tracks SET position = ?1 WHERE rowid = ?2")?;
+    for (position, rowid) in entries.into_iter().enumerate() {
+        update.execute(params![position as i64, rowid])?;
+    }
+
+    Ok(())
+}
+
+/// Ratings are 1 to `MAX_RATING` stars, or None for unrated.
+fn check_rating(rating: Option<i32>) -> Result<(), DatabaseError> {
+    match rating {
+        Some(rating) if rating < 1 || rating > MAX_RATING => {
+            Err(DatabaseError::InvalidRating(rating))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Dotfiles, plus anything with the hidden attribute on Windows.
+fn is_hidden(entry: &DirEntry) -> bool {
+    if entry.file_name().to_str().map_or(false, |name| name.starts_with('.')) {
+        return true;
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+
+    false
+}
+
+/// `path` moved from under `old_root` to the same place under `new_root`, or None if it isn't
+/// under `old_root`.
+fn rewrite_path(path: &str, old_root: &Path, new_root: &Path) -> Option<String> {
+    let relative = Path::new(path).strip_prefix(old_root).ok()?;
+    new_root.join(relative).into_os_string().into_string().ok()
+}
+
+/// Artist, title and length, normalized so that differences in case and spacing don't matter.
+/// Like `MoveKey`, untitled tracks don't get one.
+fn tag_key(track: &Track) -> Option<String> {
+    let normalize = |tag: &str| tag.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let title = normalize(track.title.as_deref()?);
+    let artist = track.artist.as_deref().map(normalize).unwrap_or_default();
+    Some(format!("{}\u{0}{}\u{0}{}", artist, title, track.length))
+}
+
+/// Tags that identify a track well enough to recognize it after it has been moved.
+#[derive(PartialEq, Eq, Hash)]
+struct MoveKey {
+    title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    track: Option<i64>,
+    length: i64,
+}
+
+impl MoveKey {
+    /// Untitled tracks are too easy to mix up, so they don't get a key.
+    fn new(title: Option<String>, artist: Option<String>, album: Option<String>,
+           track: Option<i64>, length: i64) -> Option<MoveKey> {
+        Some(MoveKey {
+            title: title?,
+            artist,
+            album,
+            track,
+            length,
+        })
+    }
+}
+
+/// Ids of the tracks in `library` that are still on disk (i.e. in `scan_results`), but have been
+/// modified since we last read their tags.
+fn modified_tracks(tx: &Transaction, library: &Library) -> Result<Vec<i64>, DatabaseError> {
+    let mut stmt = tx.prepare(
+        "SELECT track.id, track.path, track.mtime \
+        FROM track \
+            JOIN scan_results ON track.path = scan_results.path \
+        WHERE track.library_id = ?1;"
+    )?;
+
+    let mut res = Vec::new();
+    for row in stmt.query_map(params![library.id], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i64>>(2)?))
+    })? {
+        let (id, path, mtime) = row?;
+        if is_stale(mtime, file_mtime(&path)) {
+            res.push(id);
+        }
+    }
+
+    Ok(res)
+}
+
+fn remove_missing_tracks(tx: &Transaction, library: &Library, new_tracks: &mut Vec<String>,
+                         res: &mut Vec<String>) -> Result<(), DatabaseError> {
+    // Remove tracks in the library that are no longer present on disk
+    // We unfortunately need to do this in two queries because we have to return the tracks
+    // that were removed
+    // If a missing track's content hash or tags match one of the new paths, it's been moved
+    // within the library rather than removed, so we update its path in place to keep its rating
+    // and playlists
+
+    let mut missing_tracks = tx.prepare(
+        "WITH current_paths AS
+                    (SELECT id, path, title, artist, album, track, length, hash
+                    FROM track
+                    WHERE library_id = ?1)
+               SELECT current_paths.id, current_paths.path, current_paths.title,
+                      current_paths.artist, current_paths.album, current_paths.track,
+                      current_paths.length, current_paths.hash
+               FROM current_paths
+                   LEFT JOIN scan_results ON current_paths.path = scan_results.path
+               WHERE scan_results.path IS NULL;"
+    )?;
+
+    let mut missing = Vec::new();
+    for track in missing_tracks.query_map(params!(library.id), |row| {
+        let key = MoveKey::new(row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?);
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, key, row.get::<_, Option<String>>(7)?))
+    })? {
+        missing.push(track?);
+    }
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    // Only worth reading the new files if something has gone missing, and only worth hashing
+    // them if a missing track has a hash to compare with
+    let hash_new_tracks = missing.iter().any(|(_, _, _, hash)| hash.is_some());
+    let mut candidates = HashMap::new();
+    let mut hash_candidates = HashMap::new();
+    for path in new_tracks.iter() {
+        if let Some(tags) = TrackTags::read(path) {
+            let key = MoveKey::new(tags.title, tags.artist, tags.album,
+                                   tags.track.map(i64::from), tags.length.into());
+            if let Some(key) = key {
+                candidates.entry(key).or_insert_with(|| path.clone());
+            }
+        }
+
+        if hash_new_tracks {
+            if let Ok(hash) = content_hash(path) {
+                hash_candidates.entry(hash).or_insert_with(|| path.clone());
+            }
+        }
+    }
+
+    let mut delete_playlist_entries = tx.prepare(
+        "DELETE FROM playlist_tracks WHERE track_id = ?"
+    )?;
+
+    let mut delete_missing_tracks = tx.prepare(
+        "DELETE FROM track WHERE id = ?"
+    )?;
+
+    let mut relocate_track = tx.prepare(
+        "UPDATE track SET path = ?1, mtime = ?2 WHERE id = ?3"
+    )?;
+
+    for (id, track, key, hash) in missing {
+        // The hash is the stronger match, so it goes first
+        let new_path = hash.and_then(|hash| hash_candidates.remove(&hash))
+            .or_else(|| key.and_then(|key| candidates.remove(&key)));
+
+        if let Some(new_path) = new_path {
+            trace!("{} was moved to {}", track, new_path);
+            relocate_track.execute(params![new_path, file_mtime(&new_path), id])?;
+            new_tracks.retain(|path| path != &new_path);
+            candidates.retain(|_, path| path != &new_path);
+            hash_candidates.retain(|_, path| path != &new_path);
+        } else {
+            // Gone from its playlists too, as with `Database::remove_track`
+            delete_playlist_entries.execute(params![id])?;
+            delete_missing_tracks.execute(params![id])?;
+            res.push(track);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::test_support::{canonical, write_wav};
+
+    use super::*;
+
+    /// A library rooted at `dir`.
+    fn add_library(db: &mut Database, dir: &Path, name: &str) -> Library {
+        db.add_library(canonical(dir), name.to_string()).unwrap()
+    }
+
+    /// A track row at `path`, without a file behind it.
+    fn insert_fake_track(db: &Database, library_id: i64, path: &str) -> i64 {
+        db.conn.execute("INSERT INTO track (library_id, path, title, length, bitrate, samplerate) \
+                         VALUES (?1, ?2, ?2, 180, 320, 44100)",
+                        params![library_id, path])
+            .unwrap();
+        db.conn.last_insert_rowid()
+    }
+
+    fn playlist_ids(db: &Database, playlist_id: i64) -> Vec<i64> {
+        db.playlist_tracks(playlist_id).unwrap().iter().map(Track::id).collect()
+    }
+
+    fn playlist_entry_count(db: &Database) -> i64 {
+        db.conn.query_row("SELECT COUNT(*) FROM playlist_tracks", NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn rescan_removes_missing_tracks_and_their_playlist_entries() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("kept.wav"), 1, 1);
+        write_wav(&dir.path().join("removed.wav"), 1, 2);
+
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let options = ScanOptions::default();
+        db.scan_library(library.clone(), true, &options, None, None).unwrap();
+
+        let kept = db.track_by_path(&canonical(&dir.path().join("kept.wav"))).unwrap().unwrap();
+        let removed_path = canonical(&dir.path().join("removed.wav"));
+        let removed = db.track_by_path(&removed_path).unwrap().unwrap();
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &[removed.id(), kept.id()]).unwrap();
+
+        fs::remove_file(&removed_path).unwrap();
+        let missing = db.scan_library(library, false, &options, None, None).unwrap();
+
+        assert_eq!(missing, vec![removed_path.clone()]);
+        assert!(db.track_by_path(&removed_path).unwrap().is_none());
+        let still_there = db.track(kept.id()).unwrap().unwrap();
+        assert_eq!(still_there.path(), kept.path());
+        assert_eq!(still_there.length(), kept.length());
+
+        assert_eq!(playlist_ids(&db, playlist), vec![kept.id()]);
+        assert_eq!(playlist_entry_count(&db), 1);
+    }
+
+    #[test]
+    fn migrations_bring_an_old_database_up_to_date() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn).unwrap();
+        conn.execute("INSERT INTO track (library_id, path, length, bitrate, samplerate) \
+                      VALUES (1, '/music/old.flac', 180, 320, 44100)",
+                     NO_PARAMS)
+            .unwrap();
+        let id = conn.last_insert_rowid();
+
+        let db = Database::open(conn).unwrap();
+
+        let version: i64 = db.conn.query_row("PRAGMA user_version;", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        // Tracks from before disc numbers were read are on the first disc
+        let track = db.track(id).unwrap().unwrap();
+        assert_eq!(track.disc(), 1);
+        assert_eq!(track.play_count(), 0);
+    }
+
+    #[test]
+    fn library_tracks_come_in_disc_then_track_order() {
+        let db = Database::in_memory().unwrap();
+        for (path, disc, track) in [("/music/2-1.flac", Some(2), 1), ("/music/x-2.flac", None, 2),
+                                    ("/music/1-1.flac", Some(1), 1)].iter() {
+            db.conn.execute("INSERT INTO track (library_id, path, artist, album, disc, track, \
+                                                length, bitrate, samplerate) \
+                             VALUES (1, ?1, 'Artist', 'Album', ?2, ?3, 180, 320, 44100)",
+                            params![path, disc, track])
+                .unwrap();
+        }
+
+        let paths: Vec<String> = db.library_tracks(1).unwrap().iter()
+            .map(|track| track.path().to_string())
+            .collect();
+
+        // Without a disc number, a track is on the first disc
+        assert_eq!(paths, vec!["/music/1-1.flac", "/music/x-2.flac", "/music/2-1.flac"]);
+    }
+
+    /// A playlist holding three fake tracks, in order.
+    fn playlist_of_three(db: &mut Database) -> (i64, Vec<i64>) {
+        let tracks: Vec<i64> = (1..=3)
+            .map(|i| insert_fake_track(db, 1, &format!("/music/{}.flac", i)))
+            .collect();
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &tracks).unwrap();
+        (playlist, tracks)
+    }
+
+    #[test]
+    fn reorder_playlist_saves_the_new_order() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, tracks) = playlist_of_three(&mut db);
+
+        let reordered = vec![tracks[2], tracks[0], tracks[1]];
+        db.reorder_playlist(playlist, &reordered).unwrap();
+
+        assert_eq!(playlist_ids(&db, playlist), reordered);
+    }
+
+    #[test]
+    fn reorder_playlist_rejects_a_stale_list() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, tracks) = playlist_of_three(&mut db);
+
+        let missing_one = db.reorder_playlist(playlist, &[tracks[1], tracks[0]]);
+        assert!(matches!(missing_one, Err(DatabaseError::PlaylistChanged)));
+
+        let extra = insert_fake_track(&db, 1, "/music/extra.flac");
+        let with_extra = db.reorder_playlist(playlist, &[tracks[1], tracks[0], tracks[2], extra]);
+        assert!(matches!(with_extra, Err(DatabaseError::PlaylistChanged)));
+
+        assert_eq!(playlist_ids(&db, playlist), tracks);
+    }
+
+    #[test]
+    fn reorder_playlist_ignores_entries_whose_tracks_are_gone() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, tracks) = playlist_of_three(&mut db);
+        // As left behind by older versions, which removed missing tracks but not their entries
+        db.conn.execute("DELETE FROM track WHERE id = ?1", params![tracks[1]]).unwrap();
+
+        db.reorder_playlist(playlist, &[tracks[2], tracks[0]]).unwrap();
+
+        assert_eq!(playlist_ids(&db, playlist), vec![tracks[2], tracks[0]]);
+        assert_eq!(playlist_entry_count(&db), 2);
+    }
+
+    fn library_of(db: &Database, track_id: i64) -> i64 {
+        db.track(track_id).unwrap().unwrap().library_id()
+    }
+
+    #[test]
+    fn set_tracks_library_moves_tracks_under_the_new_root() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let inside = insert_fake_track(&db, 1, "/music/rock/a.flac");
+        let nested = insert_fake_track(&db, 1, "/music/rock/album/b.flac");
+
+        let rejected = db.set_tracks_library(&[inside, nested], &rock).unwrap();
+
+        assert!(rejected.is_empty());
+        assert_eq!(library_of(&db, inside), rock.id());
+        assert_eq!(library_of(&db, nested), rock.id());
+    }
+
+    #[test]
+    fn set_tracks_library_leaves_tracks_from_elsewhere_alone() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let inside = insert_fake_track(&db, 1, "/music/rock/a.flac");
+        let outside = insert_fake_track(&db, 1, "/music/jazz/b.flac");
+        // Only whole folder names count
+        let lookalike = insert_fake_track(&db, 1, "/music/rockabilly/c.flac");
+
+        let rejected = db.set_tracks_library(&[inside, outside, lookalike], &rock).unwrap();
+
+        assert_eq!(rejected, vec!["/music/jazz/b.flac", "/music/rockabilly/c.flac"]);
+        assert_eq!(library_of(&db, inside), rock.id());
+        assert_eq!(library_of(&db, outside), 1);
+        assert_eq!(library_of(&db, lookalike), 1);
+    }
+
+    #[test]
+    fn individual_tracks_takes_tracks_from_anywhere() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let id = insert_fake_track(&db, rock.id(), "/music/rock/a.flac");
+        let individual = db.libraries().unwrap().into_iter()
+            .find(|library| library.path().is_none())
+            .unwrap();
+
+        let rejected = db.set_tracks_library(&[id], &individual).unwrap();
+
+        assert!(rejected.is_empty());
+        assert_eq!(library_of(&db, id), 1);
+    }
+
+    #[test]
+    fn retagged_files_are_stale_until_they_are_refreshed() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        let mut db = Database::in_memory().unwrap();
+        let id = db.add_individual_track(&canonical(&dir.path().join("a.wav"))).unwrap().unwrap()
+            .id();
+        assert!(db.stale_tracks().unwrap().is_empty());
+
+        // As if it were retagged since it was read
+        db.conn.execute("UPDATE track SET mtime = mtime - 10 WHERE id = ?1", params![id]).unwrap();
+        assert_eq!(db.stale_tracks().unwrap(), vec![id]);
+
+        db.set_loudness(id, -14.).unwrap();
+        db.set_sound(id, -60., None).unwrap();
+        db.refresh_track(id).unwrap().unwrap();
+        assert!(db.stale_tracks().unwrap().is_empty());
+        // Its audio may have changed too, so it's analyzed again
+        assert_eq!(db.unanalyzed_tracks(-60.).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn edited_comments_are_saved_to_the_track_and_its_file() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        let path = canonical(&dir.path().join("a.wav"));
+        let mut db = Database::in_memory().unwrap();
+        let id = db.add_individual_track(&path).unwrap().unwrap().id();
+        let metadata = TrackMetadata {
+            title: Some("Song".to_string()),
+            comment: Some("Ripped from vinyl\nSide B".to_string()),
+            ..TrackMetadata::default()
+        };
+
+        let track = db.update_track_metadata(id, &metadata).unwrap().unwrap();
+        db.write_tags_to_file(&track).unwrap();
+
+        let comment: Option<String> = db.conn
+            .query_row("SELECT comment FROM track WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(comment.as_deref(), Some("Ripped from vinyl\nSide B"));
+        assert_eq!(TrackTags::read(&path).unwrap().comment.as_deref(),
+                   Some("Ripped from vinyl\nSide B"));
+
+        // Clearing it clears both
+        let cleared = TrackMetadata { comment: None, ..metadata };
+        let track = db.update_track_metadata(id, &cleared).unwrap().unwrap();
+        db.write_tags_to_file(&track).unwrap();
+        assert_eq!(track.comment(), None);
+        assert_eq!(TrackTags::read(&path).unwrap().comment.filter(|c| !c.is_empty()), None);
+    }
+
+    #[test]
+    fn albums_are_measured_from_their_tracks() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let ids: Vec<i64> = ["/music/a.flac", "/music/b.flac", "/music/c.flac", "/music/d.flac"]
+            .iter()
+            .map(|path| insert_fake_track(&db, library.id(), path))
+            .collect();
+        for &(id, album) in &[(ids[0], Some("Loud")), (ids[1], Some("Loud")),
+                              (ids[2], Some("Quiet")), (ids[3], None)] {
+            db.conn.execute("UPDATE track SET album = ?1 WHERE id = ?2", params![album, id])
+                .unwrap();
+        }
+
+        for (&id, &loudness) in ids.iter().zip(&[-10., -20., -15., -12.]) {
+            db.set_loudness(id, loudness).unwrap();
+        }
+        db.update_album_loudness().unwrap();
+
+        let album = |id| db.track(id).unwrap().unwrap().album_loudness();
+        let loud = album_loudness(&[(-10., 180), (-20., 180)]);
+        assert_eq!((album(ids[0]), album(ids[1])), (loud, loud));
+        assert_eq!(album(ids[2]), Some(-15.));
+        assert_eq!(album(ids[3]), None);
+        assert_eq!(db.track(ids[0]).unwrap().unwrap().loudness(), Some(-10.));
+    }
+
+    #[test]
+    fn tracks_are_analyzed_again_at_a_new_silence_threshold() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let loud = insert_fake_track(&db, library.id(), "/music/a.flac");
+        let silent = insert_fake_track(&db, library.id(), "/music/b.flac");
+        assert_eq!(db.unanalyzed_tracks(-60.).unwrap().len(), 2);
+
+        let sound = (Duration::from_millis(1500), Duration::from_millis(178_250));
+        db.set_loudness(loud, -9.).unwrap();
+        db.set_sound(loud, -60., Some(sound)).unwrap();
+        // Too quiet to have a loudness, but analyzed all the same
+        db.set_sound(silent, -60., None).unwrap();
+        assert!(db.unanalyzed_tracks(-60.).unwrap().is_empty());
+        assert_eq!(db.track(loud).unwrap().unwrap().sound(), Some(sound));
+        assert_eq!(db.track(silent).unwrap().unwrap().sound(), None);
+
+        let ids: Vec<i64> = db.unanalyzed_tracks(-50.).unwrap().iter().map(|&(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec![loud, silent]);
+    }
+
+    #[test]
+    fn playback_errors_are_recorded_against_the_track() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let id = insert_fake_track(&db, library.id(), "/music/a.flac");
+        assert_eq!(db.playback_error(id).unwrap(), None);
+
+        db.record_playback_error(id, PlaybackFailure::Open, "its file is missing").unwrap();
+        assert_eq!(db.playback_error(id).unwrap(),
+                   Some((PlaybackFailure::Open, "its file is missing".to_string())));
+
+        // Only the latest is kept
+        db.record_playback_error(id, PlaybackFailure::Partway, "it stopped at 1:02").unwrap();
+        assert_eq!(db.playback_error(id).unwrap(),
+                   Some((PlaybackFailure::Partway, "it stopped at 1:02".to_string())));
+        assert_eq!(db.playback_error(id + 1).unwrap(), None);
+    }
+
+    fn library_name(db: &Database, id: i64) -> String {
+        db.libraries().unwrap().into_iter()
+            .find(|library| library.id() == id)
+            .unwrap()
+            .name()
+            .clone()
+    }
+
+    #[test]
+    fn renamed_libraries_keep_their_new_name() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+
+        db.rename_library(rock.id(), "  Rock & Roll ".to_string()).unwrap();
+
+        assert_eq!(library_name(&db, rock.id()), "Rock & Roll");
+    }
+
+    #[test]
+    fn libraries_cannot_be_renamed_to_empty_or_reserved_names() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+
+        for name in ["", "   ", INDIVIDUAL_TRACKS_NAME, "individual tracks"].iter() {
+            assert!(matches!(db.rename_library(rock.id(), name.to_string()),
+                             Err(DatabaseError::InvalidLibraryName)));
+        }
+        assert_eq!(library_name(&db, rock.id()), "Rock");
+    }
+
+    #[test]
+    fn libraries_cannot_take_another_librarys_name() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        db.add_library("/music/jazz".to_string(), "Jazz".to_string()).unwrap();
+
+        assert!(matches!(db.rename_library(rock.id(), "Jazz".to_string()),
+                         Err(DatabaseError::LibraryNameTaken(name)) if name == "Jazz"));
+        assert_eq!(library_name(&db, rock.id()), "Rock");
+        // Its own name is fine
+        db.rename_library(rock.id(), "Rock".to_string()).unwrap();
+    }
+
+    #[test]
+    fn only_existing_libraries_can_be_renamed() {
+        let mut db = Database::in_memory().unwrap();
+
+        assert!(matches!(db.rename_library(42, "Rock".to_string()),
+                         Err(DatabaseError::NoSuchLibrary)));
+        assert!(matches!(db.rename_library(1, "Loose Tracks".to_string()),
+                         Err(DatabaseError::ProtectedLibrary)));
+        // It's shown as 'Individual Tracks', but has no name of its own
+        assert_eq!(library_name(&db, 1), "");
+    }
+
+    /// Scan a library holding just `a.wav`, rate it and put it in a playlist, then move it to
+    /// `moved/b.wav` and rescan. Returns the track from before the move, its playlist, and what
+    /// the rescan reported missing.
+    fn move_rated_track(db: &mut Database, dir: &Path,
+                        options: &ScanOptions) -> (Track, i64, Vec<String>) {
+        let library = add_library(db, dir, "Music");
+        db.scan_library(library.clone(), true, options, None, None).unwrap();
+        let track = db.track_by_path(&canonical(&dir.join("a.wav"))).unwrap().unwrap();
+        db.set_rating(track.id(), Some(4)).unwrap();
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &[track.id()]).unwrap();
+
+        fs::create_dir(dir.join("moved")).unwrap();
+        fs::rename(dir.join("a.wav"), dir.join("moved").join("b.wav")).unwrap();
+        let missing = db.scan_library(library, false, options, None, None).unwrap();
+
+        (track, playlist, missing)
+    }
+
+    #[test]
+    fn rescans_follow_tracks_moved_within_the_library_by_hash() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        let mut db = Database::in_memory().unwrap();
+        let options = ScanOptions { hash_files: true, ..ScanOptions::default() };
+
+        let (track, playlist, missing) = move_rated_track(&mut db, dir.path(), &options);
+
+        assert!(missing.is_empty());
+        let moved = db.track(track.id()).unwrap().unwrap();
+        assert_eq!(moved.path(), canonical(&dir.path().join("moved").join("b.wav")));
+        assert_eq!(moved.rating(), Some(4));
+        assert_eq!(playlist_ids(&db, playlist), vec![track.id()]);
+        let count: i64 = db.conn.query_row("SELECT COUNT(*) FROM track", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn rescans_follow_tracks_moved_within_the_library_by_tags() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.wav");
+        write_wav(&path, 1, 1);
+        let metadata = TrackMetadata {
+            title: Some("Song".to_string()),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            comment: None,
+            genre: None,
+            year: None,
+            track: Some(1),
+        };
+        crate::tags::write_tags(path.to_str().unwrap(), &metadata).unwrap();
+        let mut db = Database::in_memory().unwrap();
+
+        let (track, playlist, missing) = move_rated_track(&mut db, dir.path(),
+                                                          &ScanOptions::default());
+
+        assert!(missing.is_empty());
+        let moved = db.track(track.id()).unwrap().unwrap();
+        assert_eq!(moved.path(), canonical(&dir.path().join("moved").join("b.wav")));
+        assert_eq!(moved.rating(), Some(4));
+        assert_eq!(playlist_ids(&db, playlist), vec![track.id()]);
+    }
+
+    #[test]
+    fn rescans_do_not_mistake_a_different_file_for_a_moved_one() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        let mut db = Database::in_memory().unwrap();
+        let options = ScanOptions { hash_files: true, ..ScanOptions::default() };
+        let library = add_library(&mut db, dir.path(), "Music");
+        db.scan_library(library.clone(), true, &options, None, None).unwrap();
+        let old = db.track_by_path(&canonical(&dir.path().join("a.wav"))).unwrap().unwrap();
+
+        fs::remove_file(dir.path().join("a.wav")).unwrap();
+        write_wav(&dir.path().join("b.wav"), 2, 2);
+        let missing = db.scan_library(library, false, &options, None, None).unwrap();
+
+        assert_eq!(missing, vec![old.path().to_string()]);
+        assert!(db.track(old.id()).unwrap().is_none());
+        assert!(db.track_by_path(&canonical(&dir.path().join("b.wav"))).unwrap().is_some());
+    }
+
+    #[test]
+    fn vacuuming_reclaims_the_space_of_deleted_rows() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(Connection::open(dir.path().join("data.sq3")).unwrap()).unwrap();
+        let padding = "x".repeat(1000);
+        for i in 0..2000 {
+            insert_fake_track(&db, 1, &format!("/music/{}/{}.flac", padding, i));
+        }
+        db.conn.execute("DELETE FROM track", NO_PARAMS).unwrap();
+        let before = db.size().unwrap();
+
+        db.vacuum().unwrap();
+
+        assert!(db.size().unwrap() < before);
+        // The database still works afterwards
+        insert_fake_track(&db, 1, "/music/after.flac");
+        assert!(db.track_by_path("/music/after.flac").unwrap().is_some());
+    }
+
+    #[test]
+    fn clearing_a_library_keeps_the_library_but_not_its_tracks() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let cleared = insert_fake_track(&db, rock.id(), "/music/rock/a.flac");
+        let kept = insert_fake_track(&db, 1, "/elsewhere/b.flac");
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &[cleared, kept, cleared]).unwrap();
+
+        db.clear_library(rock.id()).unwrap();
+
+        assert!(db.library_tracks(rock.id()).unwrap().is_empty());
+        assert!(db.track(cleared).unwrap().is_none());
+        assert!(db.library_exists(rock.id()).unwrap());
+        assert_eq!(library_name(&db, rock.id()), "Rock");
+        assert!(db.track(kept).unwrap().is_some());
+        assert_eq!(playlist_ids(&db, playlist), vec![kept]);
+        assert_eq!(playlist_entry_count(&db), 1);
+    }
+
+    #[test]
+    fn duplicated_playlists_have_the_same_tracks_in_the_same_order() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, tracks) = playlist_of_three(&mut db);
+        let reordered = vec![tracks[2], tracks[0], tracks[1]];
+        db.reorder_playlist(playlist, &reordered).unwrap();
+
+        let copy = db.duplicate_playlist(playlist, "Mix copy".to_string()).unwrap();
+
+        assert_ne!(copy, playlist);
+        assert_eq!(playlist_ids(&db, copy), reordered);
+        let names: Vec<String> = db.list_playlists().unwrap().iter()
+            .map(|playlist| playlist.name().clone())
+            .collect();
+        assert_eq!(names, vec!["Mix", "Mix copy"]);
+    }
+
+    #[test]
+    fn duplicated_playlists_change_independently() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, tracks) = playlist_of_three(&mut db);
+        let copy = db.duplicate_playlist(playlist, "Mix copy".to_string()).unwrap();
+
+        db.remove_from_playlist(copy, &[tracks[0]]).unwrap();
+        let extra = insert_fake_track(&db, 1, "/music/4.flac");
+        db.add_to_playlist(playlist, &[extra]).unwrap();
+
+        assert_eq!(playlist_ids(&db, playlist), vec![tracks[0], tracks[1], tracks[2], extra]);
+        assert_eq!(playlist_ids(&db, copy), vec![tracks[1], tracks[2]]);
+
+        db.delete_playlist(playlist).unwrap();
+        assert_eq!(playlist_ids(&db, copy), vec![tracks[1], tracks[2]]);
+    }
+
+    #[test]
+    fn duplicating_onto_a_taken_name_suggests_a_free_one() {
+        let mut db = Database::in_memory().unwrap();
+        let (playlist, _) = playlist_of_three(&mut db);
+        db.create_playlist("Mix (2)".to_string()).unwrap();
+
+        let result = db.duplicate_playlist(playlist, "Mix".to_string());
+
+        assert!(matches!(result, Err(DatabaseError::PlaylistNameTaken { suggestion })
+                                 if suggestion == "Mix (3)"));
+        assert_eq!(db.list_playlists().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn pacers_space_items_out_by_the_interval() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(Duration::from_millis(100));
+
+        // The first item starts straight away
+        assert_eq!(pacer.delay(start), Duration::from_millis(0));
+        // The next ones wait out the rest of the interval
+        assert_eq!(pacer.delay(start + Duration::from_millis(30)), Duration::from_millis(70));
+        assert_eq!(pacer.delay(start + Duration::from_millis(100)), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn pacers_do_not_hold_up_items_that_are_already_late() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(Duration::from_millis(100));
+        pacer.delay(start);
+
+        // e.g. a slow file took longer than the interval to read
+        assert_eq!(pacer.delay(start + Duration::from_millis(250)), Duration::from_millis(0));
+        // And the interval starts over from there, rather than catching up
+        assert_eq!(pacer.delay(start + Duration::from_millis(260)), Duration::from_millis(90));
+    }
+
+    #[test]
+    fn throttled_scans_find_the_same_tracks() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..3 {
+            write_wav(&dir.path().join(format!("{}.wav", i)), 1, i);
+        }
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let options = ScanOptions { throttle: Some(Duration::from_millis(50)),
+                                    ..ScanOptions::default() };
+
+        let start = Instant::now();
+        db.scan_library(library.clone(), true, &options, None, None).unwrap();
+
+        // Two waits between three files
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(db.library_tracks(library.id()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn paths_under_the_old_root_are_moved_to_the_new_one() {
+        let (old, new) = (Path::new("/old/music"), Path::new("/new/music"));
+
+        assert_eq!(rewrite_path("/old/music/a.flac", old, new),
+                   Some("/new/music/a.flac".to_string()));
+        assert_eq!(rewrite_path("/old/music/Artist/Album/01.flac", old, new),
+                   Some("/new/music/Artist/Album/01.flac".to_string()));
+        assert_eq!(rewrite_path("/old/music", old, new), Some("/new/music".to_string()));
+    }
+
+    #[test]
+    fn paths_outside_the_old_root_are_not_moved() {
+        let (old, new) = (Path::new("/old/music"), Path::new("/new/music"));
+
+        assert_eq!(rewrite_path("/elsewhere/a.flac", old, new), None);
+        assert_eq!(rewrite_path("/old/musical/a.flac", old, new), None);
+        assert_eq!(rewrite_path("/old/a.flac", old, new), None);
+    }
+
+    #[test]
+    fn rewriting_a_root_moves_its_tracks_and_libraries() {
+        let dir = TempDir::new().unwrap();
+        let new_root = dir.path().join("music");
+        fs::create_dir_all(new_root.join("rock")).unwrap();
+        fs::write(new_root.join("rock").join("a.flac"), "").unwrap();
+        let new_root = new_root.canonicalize().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/old/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let moved = insert_fake_track(&db, rock.id(), "/old/music/rock/a.flac");
+        let elsewhere = insert_fake_track(&db, 1, "/elsewhere/b.flac");
+
+        let updated = db.rewrite_root(Path::new("/old/music"), &new_root).unwrap();
+
+        assert_eq!(updated, 1);
+        assert_eq!(db.track(moved).unwrap().unwrap().path(),
+                   canonical(&new_root.join("rock").join("a.flac")));
+        assert_eq!(db.track(elsewhere).unwrap().unwrap().path(), "/elsewhere/b.flac");
+        let library = db.libraries().unwrap().into_iter()
+            .find(|library| library.id() == rock.id())
+            .unwrap();
+        assert_eq!(library.path(), Some(&canonical(&new_root.join("rock"))));
+    }
+
+    #[test]
+    fn rewriting_a_root_changes_nothing_if_files_are_missing() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.flac"), "").unwrap();
+        let new_root = dir.path().canonicalize().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let there = insert_fake_track(&db, 1, "/old/music/a.flac");
+        let missing = insert_fake_track(&db, 1, "/old/music/b.flac");
+
+        let result = db.rewrite_root(Path::new("/old/music"), &new_root);
+
+        assert!(matches!(result, Err(DatabaseError::MissingFiles(paths))
+                                 if paths == vec![format!("{}/b.flac", canonical(&new_root))]));
+        assert_eq!(db.track(there).unwrap().unwrap().path(), "/old/music/a.flac");
+        assert_eq!(db.track(missing).unwrap().unwrap().path(), "/old/music/b.flac");
+    }
+
+    /// A library folder, itself hidden, holding a visible track, a hidden one, and one in a
+    /// hidden folder. Returns the paths of the tracks a scan finds.
+    fn scan_hidden_files(skip_hidden: bool) -> Vec<String> {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join(".music");
+        fs::create_dir_all(root.join(".trash")).unwrap();
+        write_wav(&root.join("visible.wav"), 1, 1);
+        write_wav(&root.join(".hidden.wav"), 1, 2);
+        write_wav(&root.join(".trash").join("deleted.wav"), 1, 3);
+
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, &root, "Music");
+        let options = ScanOptions { skip_hidden, ..ScanOptions::default() };
+        db.scan_library(library.clone(), true, &options, None, None).unwrap();
+
+        let root = canonical(&root);
+        let mut paths: Vec<String> = db.library_tracks(library.id()).unwrap().iter()
+            .map(|track| track.path().strip_prefix(&root).unwrap().to_string())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn scans_skip_hidden_files_and_folders_if_asked() {
+        assert_eq!(scan_hidden_files(true), vec!["/visible.wav"]);
+    }
+
+    #[test]
+    fn scans_include_hidden_files_and_folders_otherwise() {
+        assert_eq!(scan_hidden_files(false),
+                   vec!["/.hidden.wav", "/.trash/deleted.wav", "/visible.wav"]);
+    }
+
+    fn track_count(db: &Database) -> i64 {
+        db.conn.query_row("SELECT COUNT(*) FROM track", NO_PARAMS, |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn merging_libraries_moves_tracks_with_their_ratings_and_playlists() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::create_dir(dir.path().join("b")).unwrap();
+        write_wav(&dir.path().join("a").join("kept.wav"), 1, 1);
+        write_wav(&dir.path().join("b").join("moved.wav"), 1, 2);
+        let mut db = Database::in_memory().unwrap();
+        let dst = add_library(&mut db, &dir.path().join("a"), "A");
+        let src = add_library(&mut db, &dir.path().join("b"), "B");
+        for library in [dst.clone(), src.clone()].iter() {
+            db.scan_library(library.clone(), true, &ScanOptions::default(), None, None).unwrap();
+        }
+        let moved = db.library_tracks(src.id()).unwrap()[0].id();
+        db.set_rating(moved, Some(3)).unwrap();
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &[moved]).unwrap();
+
+        db.merge_libraries(src.id(), dst.id()).unwrap();
+
+        assert!(!db.library_exists(src.id()).unwrap());
+        assert_eq!(db.library_tracks(dst.id()).unwrap().len(), 2);
+        let track = db.track(moved).unwrap().unwrap();
+        assert_eq!(track.library_id(), dst.id());
+        assert_eq!(track.rating(), Some(3));
+        assert_eq!(playlist_ids(&db, playlist), vec![moved]);
+    }
+
+    #[test]
+    fn merging_libraries_keeps_one_copy_of_the_same_file() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::create_dir(dir.path().join("b")).unwrap();
+        // The same bytes in both
+        write_wav(&dir.path().join("a").join("song.wav"), 1, 1);
+        write_wav(&dir.path().join("b").join("copy.wav"), 1, 1);
+        let mut db = Database::in_memory().unwrap();
+        let dst = add_library(&mut db, &dir.path().join("a"), "A");
+        let src = add_library(&mut db, &dir.path().join("b"), "B");
+        for library in [dst.clone(), src.clone()].iter() {
+            db.scan_library(library.clone(), true, &ScanOptions::default(), None, None).unwrap();
+        }
+        let kept = db.library_tracks(dst.id()).unwrap()[0].id();
+        let copy = db.library_tracks(src.id()).unwrap()[0].id();
+        db.set_rating(copy, Some(5)).unwrap();
+        let playlist = db.create_playlist("Mix".to_string()).unwrap();
+        db.add_to_playlist(playlist, &[copy, kept]).unwrap();
+
+        db.merge_libraries(src.id(), dst.id()).unwrap();
+
+        assert_eq!(track_count(&db), 1);
+        assert!(db.track(copy).unwrap().is_none());
+        // The kept copy had no rating, so it takes the other's
+        assert_eq!(db.track(kept).unwrap().unwrap().rating(), Some(5));
+        assert_eq!(playlist_ids(&db, playlist), vec![kept, kept]);
+    }
+
+    #[test]
+    fn libraries_can_only_be_merged_into_other_existing_ones() {
+        let mut db = Database::in_memory().unwrap();
+        let rock = db.add_library("/music/rock".to_string(), "Rock".to_string()).unwrap();
+        let track = insert_fake_track(&db, rock.id(), "/music/rock/a.flac");
+
+        for &(src, dst) in [(rock.id(), rock.id()), (rock.id(), 42), (42, rock.id()),
+                            (1, rock.id())].iter() {
+            assert!(matches!(db.merge_libraries(src, dst), Err(DatabaseError::InvalidMerge)));
+        }
+
+        assert!(db.library_exists(rock.id()).unwrap());
+        assert_eq!(db.track(track).unwrap().unwrap().library_id(), rock.id());
+    }
+
+    /// A track with a different value in every field.
+    fn distinct_track() -> Track {
+        Track {
+            id: 7,
+            library_id: 1,
+            path: "/music/path.flac".to_string(),
+            title: Some("Title".to_string()),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            comment: Some("Comment".to_string()),
+            genre: Some("Genre".to_string()),
+            year: Some(1999),
+            track: Some(3),
+            length: 245,
+            bitrate: 256,
+            samplerate: 48000,
+            rating: Some(4),
+            disc: Some(2),
+            mtime: Some(1_600_000_000),
+            hash: Some("0123456789abcdef".to_string()),
+            replay_gain: Some(-6.5),
+            play_count: 11,
+            last_played: Some(1_700_000_000),
+            loudness: Some(-9.25),
+            album_loudness: Some(-10.5),
+            sound_start_ms: Some(1200),
+            sound_end_ms: Some(243_500),
+        }
+    }
+
+    #[test]
+    fn track_accessors_return_their_own_fields() {
+        let track = distinct_track();
+
+        assert_eq!(track.id(), 7);
+        assert_eq!(track.library_id(), 1);
+        assert_eq!(track.path(), "/music/path.flac");
+        assert_eq!(track.title(), Some("Title"));
+        assert_eq!(track.artist(), Some("Artist"));
+        assert_eq!(track.album(), Some("Album"));
+        assert_eq!(track.comment(), Some("Comment"));
+        assert_eq!(track.genre(), Some("Genre"));
+        assert_eq!(track.year(), Some(1999));
+        assert_eq!(track.track(), Some(3));
+        assert_eq!(track.length(), 245);
+        assert_eq!(track.bitrate(), 256);
+        assert_eq!(track.samplerate(), 48000);
+        assert_eq!(track.rating(), Some(4));
+        assert_eq!(track.disc(), 2);
+        assert_eq!(track.replay_gain(), Some(-6.5));
+        assert_eq!(track.play_count(), 11);
+        assert_eq!(track.last_played(), Some(1_700_000_000));
+        assert_eq!(track.loudness(), Some(-9.25));
+        assert_eq!(track.album_loudness(), Some(-10.5));
+        assert_eq!(track.sound(),
+                   Some((Duration::from_millis(1200), Duration::from_millis(243_500))));
+    }
+
+    #[test]
+    fn track_metadata_comes_from_the_matching_fields() {
+        assert_eq!(distinct_track().metadata(), TrackMetadata {
+            title: Some("Title".to_string()),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            comment: Some("Comment".to_string()),
+            genre: Some("Genre".to_string()),
+            year: Some(1999),
+            track: Some(3),
+        });
+    }
+
+    /// What `distinct_track` shows for each field. Matching on every field keeps this in step
+    /// with `TrackField`.
+    fn distinct_field_text(field: TrackField) -> &'static str {
+        match field {
+            TrackField::Path       => "/music/path.flac",
+            TrackField::Title      => "Title",
+            TrackField::Artist     => "Artist",
+            TrackField::Album      => "Album",
+            TrackField::Comment    => "Comment",
+            TrackField::Genre      => "Genre",
+            TrackField::Year       => "1999",
+            TrackField::Track      => "3",
+            TrackField::Length     => "245",
+            TrackField::Bitrate    => "256",
+            TrackField::Samplerate => "48000",
+            TrackField::Rating     => "4",
+            TrackField::Disc       => "2",
+            TrackField::PlayCount  => "11",
+            TrackField::LastPlayed => "1700000000",
+        }
+    }
+
+    #[test]
+    fn every_field_formats_its_own_value() {
+        let track = distinct_track();
+        let fields = [
+            TrackField::Path, TrackField::Title, TrackField::Artist, TrackField::Album,
+            TrackField::Comment, TrackField::Genre, TrackField::Year, TrackField::Track,
+            TrackField::Length, TrackField::Bitrate, TrackField::Samplerate, TrackField::Rating,
+            TrackField::Disc, TrackField::PlayCount, TrackField::LastPlayed,
+        ];
+
+        for &field in fields.iter() {
+            assert_eq!(track.get_field_as_string(field), distinct_field_text(field),
+                       "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn missing_fields_format_as_nothing() {
+        let track = Track::for_test(1, "Title");
+
+        assert_eq!(track.get_field_as_string(TrackField::Year), "");
+        assert_eq!(track.get_field_as_string(TrackField::Track), "");
+        assert_eq!(track.get_field_as_string(TrackField::Disc), "");
+        assert_eq!(track.get_field_as_string(TrackField::LastPlayed), "");
+    }
+
+    #[test]
+    fn every_track_field_survives_the_database() {
+        let db = Database::in_memory().unwrap();
+        let t = distinct_track();
+        db.conn.execute(
+            "INSERT INTO track (id, library_id, path, title, artist, album, comment, genre, year, \
+                                track, length, bitrate, samplerate, rating, disc, mtime, hash, \
+                                replay_gain, play_count, last_played, loudness, album_loudness, \
+                                sound_start_ms, sound_end_ms) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, \
+                    ?18, ?19, ?20, ?21, ?22, ?23, ?24)",
+            params![t.id, t.library_id, t.path, t.title, t.artist, t.album, t.comment, t.genre,
+                    t.year, t.track, t.length, t.bitrate, t.samplerate, t.rating, t.disc, t.mtime,
+                    t.hash, t.replay_gain, t.play_count, t.last_played, t.loudness,
+                    t.album_loudness, t.sound_start_ms, t.sound_end_ms])
+            .unwrap();
+
+        let tracks = db.dump_all_tracks().unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        let read = &tracks[0];
+        assert_eq!((read.id, read.library_id, &read.path), (t.id, t.library_id, &t.path));
+        assert_eq!((&read.title, &read.artist, &read.album), (&t.title, &t.artist, &t.album));
+        assert_eq!((&read.comment, &read.genre), (&t.comment, &t.genre));
+        assert_eq!((read.year, read.track, read.disc), (t.year, t.track, t.disc));
+        assert_eq!((read.length, read.bitrate, read.samplerate),
+                   (t.length, t.bitrate, t.samplerate));
+        assert_eq!((read.rating, read.mtime, &read.hash), (t.rating, t.mtime, &t.hash));
+        assert_eq!(read.replay_gain, t.replay_gain);
+        assert_eq!((read.play_count, read.last_played), (t.play_count, t.last_played));
+        assert_eq!((read.loudness, read.album_loudness), (t.loudness, t.album_loudness));
+        assert_eq!((read.sound_start_ms, read.sound_end_ms), (t.sound_start_ms, t.sound_end_ms));
+    }
+
+    #[test]
+    fn track_columns_are_all_counted() {
+        assert_eq!(track_columns!().split(',').count(), TRACK_COLUMN_COUNT);
+    }
+
+    #[test]
+    fn deleted_files_go_to_the_trash_unless_turned_off() {
+        assert_eq!(FileRemoval::choose(false, true), FileRemoval::Keep);
+        assert_eq!(FileRemoval::choose(false, false), FileRemoval::Keep);
+        assert_eq!(FileRemoval::choose(true, true), FileRemoval::Trash);
+        assert_eq!(FileRemoval::choose(true, false), FileRemoval::Delete);
+    }
+
+    #[test]
+    fn removing_a_track_keeps_or_deletes_its_file() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("kept.wav"), 1, 1);
+        write_wav(&dir.path().join("deleted.wav"), 1, 2);
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let kept = insert_fake_track(&db, library.id(), &canonical(&dir.path().join("kept.wav")));
+        let deleted = insert_fake_track(&db, library.id(),
+                                        &canonical(&dir.path().join("deleted.wav")));
+
+        db.remove_track(kept, FileRemoval::Keep).unwrap();
+        db.remove_track(deleted, FileRemoval::Delete).unwrap();
+
+        assert_eq!(track_count(&db), 0);
+        assert!(dir.path().join("kept.wav").exists());
+        assert!(!dir.path().join("deleted.wav").exists());
+    }
+
+    #[test]
+    fn files_that_cant_be_trashed_are_left_alone() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let library = add_library(&mut db, dir.path(), "Music");
+        let missing = dir.path().join("missing.wav");
+        let id = insert_fake_track(&db, library.id(), missing.to_str().unwrap());
+
+        match db.remove_track(id, FileRemoval::Trash) {
+            Err(DatabaseError::TrashError(path, _)) => assert_eq!(path, missing.to_str().unwrap()),
+            other => panic!("Expected a trash error, not {:?}", other),
+        }
+        // The track was removed before the file was tried
+        assert_eq!(track_count(&db), 0);
+    }
+
+    fn ratings(db: &Database, ids: &[i64]) -> Vec<Option<i32>> {
+        ids.iter().map(|&id| db.track(id).unwrap().unwrap().rating()).collect()
+    }
+
+    /// Three tracks, rated nothing, two and five stars.
+    fn variously_rated(db: &mut Database) -> Vec<i64> {
+        let ids: Vec<i64> = ["/a.flac", "/b.flac", "/c.flac"].iter()
+            .map(|path| insert_fake_track(db, 1, path))
+            .collect();
+        db.set_rating(ids[1], Some(2)).unwrap();
+        db.set_rating(ids[2], Some(5)).unwrap();
+        ids
+    }
+
+    #[test]
+    fn rating_several_tracks_gives_back_their_old_ratings() {
+        let mut db = Database::in_memory().unwrap();
+        let ids = variously_rated(&mut db);
+
+        let set: Vec<(i64, Option<i32>)> = ids.iter().map(|&id| (id, Some(4))).collect();
+        let previous = db.set_ratings(&set).unwrap();
+        assert_eq!(ratings(&db, &ids), vec![Some(4), Some(4), Some(4)]);
+        assert_eq!(previous, vec![(ids[0], None), (ids[1], Some(2)), (ids[2], Some(5))]);
+
+        // Which undoes it
+        db.set_ratings(&previous).unwrap();
+        assert_eq!(ratings(&db, &ids), vec![None, Some(2), Some(5)]);
+    }
+
+    #[test]
+    fn clearing_several_ratings_can_be_undone() {
+        let mut db = Database::in_memory().unwrap();
+        let ids = variously_rated(&mut db);
+
+        let clear: Vec<(i64, Option<i32>)> = ids.iter().map(|&id| (id, None)).collect();
+        let previous = db.set_ratings(&clear).unwrap();
+        assert_eq!(ratings(&db, &ids), vec![None, None, None]);
+
+        assert_eq!(db.set_ratings(&previous).unwrap(), clear);
+        assert_eq!(ratings(&db, &ids), vec![None, Some(2), Some(5)]);
+    }
+
+    #[test]
+    fn an_invalid_rating_leaves_every_track_alone() {
+        let mut db = Database::in_memory().unwrap();
+        let ids = variously_rated(&mut db);
+
+        match db.set_ratings(&[(ids[0], Some(3)), (ids[1], Some(MAX_RATING + 1))]) {
+            Err(DatabaseError::InvalidRating(rating)) => assert_eq!(rating, MAX_RATING + 1),
+            other => panic!("Expected an invalid rating, not {:?}", other),
+        }
+        assert_eq!(ratings(&db, &ids), vec![None, Some(2), Some(5)]);
+    }
+
+    #[test]
+    fn rating_tracks_that_are_gone_skips_them() {
+        let mut db = Database::in_memory().unwrap();
+        let ids = variously_rated(&mut db);
+        db.remove_track(ids[1], FileRemoval::Keep).unwrap();
+
+        let previous = db.set_ratings(&[(ids[0], Some(1)), (ids[1], Some(1))]).unwrap();
+        assert_eq!(previous, vec![(ids[0], None)]);
+        assert_eq!(ratings(&db, &[ids[0], ids[2]]), vec![Some(1), Some(5)]);
+    }
+
+    #[test]
+    fn rescan_losses_count_what_clearing_a_library_throws_away() {
+        let (music_dir, other_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let mut db = Database::in_memory().unwrap();
+        let music = add_library(&mut db, music_dir.path(), "Music");
+        let other = add_library(&mut db, other_dir.path(), "Other");
+        assert!(db.rescan_loss(music.id()).unwrap().is_empty());
+
+        let played = insert_fake_track(&db, music.id(), "/music/played.flac");
+        let rated = insert_fake_track(&db, music.id(), "/music/rated.flac");
+        let elsewhere = insert_fake_track(&db, other.id(), "/other/elsewhere.flac");
+        db.record_play(played).unwrap();
+        db.set_rating(rated, Some(4)).unwrap();
+        db.set_rating(elsewhere, Some(2)).unwrap();
+        let first = db.create_playlist("First".to_string()).unwrap();
+        let second = db.create_playlist("Second".to_string()).unwrap();
+        db.add_to_playlist(first, &[played, rated, elsewhere]).unwrap();
+        db.add_to_playlist(second, &[played]).unwrap();
+
+        assert_eq!(db.rescan_loss(music.id()).unwrap(),
+                   RescanLoss { playlist_entries: 3, played_tracks: 1, rated_tracks: 1 });
+
+        db.clear_library(music.id()).unwrap();
+        assert!(db.rescan_loss(music.id()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use log::{info, warn};
+use rodio::{Decoder, Source};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{self, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use thiserror::Error;
+
+/// Anything a track can be decoded from: a file, or a file read into memory.
+pub trait TrackReader: Read + Seek + Send + Sync {}
+
+impl<R: Read + Seek + Send + Sync> TrackReader for R {}
+
+/// A decoded track, ready to play.
+pub type TrackSource = Box<dyn Source<Item = i16> + Send>;
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("its format isn't supported")]
+    Unsupported,
+    #[error("{0}")]
+    Failed(String),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+/// One way of decoding tracks. Each says whether it doesn't know a format at all, so that the
+/// next one can be tried.
+pub trait TrackDecoder: Sync {
+    /// What to call it in the log.
+    fn name(&self) -> &'static str;
+
+    fn decode(&self, reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError>;
+}
+
+/// The decoders to try, in order. rodio's goes first since it's what mus has always played
+/// with; symphonia's picks up the formats it doesn't know, like AAC and ALAC in MP4s.
+pub const DECODERS: &[&dyn TrackDecoder] = &[&RodioDecoder, &SymphoniaDecoder];
+
+/// A decoded track, along with which decoder did it.
+pub struct Decoded {
+    pub source: TrackSource,
+    pub decoder: &'static str,
+}
+
+/// Decode whatever `open` opens with the first of `decoders` that can, opening it again for each
+/// one that's tried. If none can, the first real failure is reported rather than a format being
+/// unsupported, since it says more about what's wrong.
+pub fn decode_with<R, F>(decoders: &[&dyn TrackDecoder],
+                         mut open: F) -> Result<Decoded, DecodeError>
+    where R: TrackReader + 'static, F: FnMut() -> io::Result<R> {
+    let mut failure = None;
+
+    for decoder in decoders {
+        match decoder.decode(Box::new(open()?)) {
+            Ok(source) => return Ok(Decoded { source, decoder: decoder.name() }),
+            Err(DecodeError::Unsupported) => (),
+            Err(e) => {
+                info!("{} could not decode it: {}", decoder.name(), e);
+                failure = failure.or(Some(e));
+            }
+        }
+    }
+
+    Err(failure.unwrap_or(DecodeError::Unsupported))
+}
+
+pub struct RodioDecoder;
+
+impl TrackDecoder for RodioDecoder {
+    fn name(&self) -> &'static str {
+        "rodio"
+    }
+
+    fn decode(&self, reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError> {
+        // rodio only reports formats it doesn't recognize; damage further in just ends the track
+        match Decoder::new(reader) {
+            Ok(decoder) => Ok(Box::new(decoder)),
+            Err(_) => Err(DecodeError::Unsupported),
+        }
+    }
+}
+
+pub struct SymphoniaDecoder;
+
+impl TrackDecoder for SymphoniaDecoder {
+    fn name(&self) -> &'static str {
+        "symphonia"
+    }
+
+    fn decode(&self, reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError> {
+        let stream = MediaSourceStream::new(Box::new(Media(reader)), Default::default());
+        let probed = symphonia::default::get_probe()
+            .format(&Hint::new(), stream, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| match e {
+                // It looked all the way through without finding anything it knows
+                SymphoniaError::IoError(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    DecodeError::Unsupported
+                }
+                e => symphonia_error(e),
+            })?;
+
+        Ok(Box::new(SymphoniaSource::new(probed.format)?))
+    }
+}
+
+fn symphonia_error(e: SymphoniaError) -> DecodeError {
+    match e {
+        SymphoniaError::Unsupported(_) => DecodeError::Unsupported,
+        SymphoniaError::IoError(e) => DecodeError::Io(e),
+        e => DecodeError::Failed(e.to_string()),
+    }
+}
+
+/// symphonia wants to know how long its sources are, which a reader doesn't say.
+struct Media(Box<dyn TrackReader>);
+
+impl Read for Media {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for Media {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl MediaSource for Media {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A track's first audio stream, decoded a packet at a time as rodio asks for samples.
+struct SymphoniaSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track_id: u32,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+    /// The latest packet's samples, interleaved, and how many of them have been played.
+    samples: Vec<i16>,
+    played: usize,
+}
+
+impl SymphoniaSource {
+    /// Decodes the first packet too, since that's where the channels and sample rate are sure
+    /// to be known.
+    fn new(format: Box<dyn FormatReader>) -> Result<SymphoniaSource, DecodeError> {
+        let track = format.tracks().iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(DecodeError::Unsupported)?;
+        let params = &track.codec_params;
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())
+            .map_err(symphonia_error)?;
+        let total_duration = params.time_base.zip(params.n_frames)
+            .map(|(base, frames)| {
+                let time = base.calc_time(frames);
+                Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+            });
+
+        let mut source = SymphoniaSource {
+            track_id: track.id,
+            format,
+            decoder,
+            channels: 0,
+            sample_rate: 0,
+            total_duration,
+            samples: Vec::new(),
+            played: 0,
+        };
+        if !source.decode_packet()? {
+            return Err(DecodeError::Failed("it has no audio".to_string()));
+        }
+
+        Ok(source)
+    }
+
+    /// Replace `samples` with the next packet's. False at the end of the track.
+    fn decode_packet(&mut self) -> Result<bool, DecodeError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(e) => return Err(symphonia_error(e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Just this packet is damaged, so skip it
+                Err(SymphoniaError::DecodeError(e)) => {
+                    warn!("Skipping a packet that could not be decoded: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(symphonia_error(e)),
+            };
+
+            let spec = *decoded.spec();
+            let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+            if buffer.samples().is_empty() {
+                continue;
+            }
+
+            self.channels = spec.channels.count() as u16;
+            self.sample_rate = spec.rate;
+            self.samples.clear();
+            self.samples.extend_from_slice(buffer.samples());
+            self.played = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl Iterator for SymphoniaSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = *self.samples.get(self.played)?;
+        self.played += 1;
+
+        // Decoded straight away, so current_frame_len only runs out at the end
+        if self.played == self.samples.len() {
+            match self.decode_packet() {
+                Ok(true) => (),
+                Ok(false) => self.samples.clear(),
+                Err(e) => {
+                    warn!("Stopping partway through a track that could not be decoded: {}", e);
+                    self.samples.clear();
+                }
+            }
+            if self.samples.is_empty() {
+                self.played = 0;
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len() - self.played)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    use rodio::buffer::SamplesBuffer;
+    use tempfile::TempDir;
+
+    use crate::test_support::write_wav;
+
+    use super::*;
+
+    struct Unsupported;
+
+    impl TrackDecoder for Unsupported {
+        fn name(&self) -> &'static str {
+            "unsupported"
+        }
+
+        fn decode(&self, _reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError> {
+            Err(DecodeError::Unsupported)
+        }
+    }
+
+    struct Broken;
+
+    impl TrackDecoder for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn decode(&self, _reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError> {
+            Err(DecodeError::Failed("it's broken".to_string()))
+        }
+    }
+
+    /// Plays whatever bytes it's given as samples.
+    struct Raw;
+
+    impl TrackDecoder for Raw {
+        fn name(&self) -> &'static str {
+            "raw"
+        }
+
+        fn decode(&self, mut reader: Box<dyn TrackReader>) -> Result<TrackSource, DecodeError> {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            let samples: Vec<i16> = bytes.into_iter().map(i16::from).collect();
+            Ok(Box::new(SamplesBuffer::new(1, 8000, samples)))
+        }
+    }
+
+    #[test]
+    fn unsupported_formats_fall_back_to_the_next_decoder() {
+        let mut opened = 0;
+        let decoded = decode_with(&[&Unsupported, &Raw], || {
+            opened += 1;
+            Ok(Cursor::new(vec![1, 2, 3]))
+        }).unwrap();
+
+        assert_eq!(decoded.decoder, "raw");
+        assert_eq!(decoded.source.collect::<Vec<i16>>(), vec![1, 2, 3]);
+        // Once for each decoder tried, since a failed one may have read some of it
+        assert_eq!(opened, 2);
+    }
+
+    #[test]
+    fn failures_are_reported_over_unsupported_formats() {
+        let open = || Ok(Cursor::new(Vec::new()));
+        assert!(matches!(decode_with(&[&Unsupported, &Broken, &Unsupported], open),
+                         Err(DecodeError::Failed(_))));
+        assert!(matches!(decode_with(&[&Unsupported], open), Err(DecodeError::Unsupported)));
+        assert!(matches!(decode_with(&[&Broken, &Raw], open).map(|decoded| decoded.decoder),
+                         Ok("raw")));
+    }
+
+    #[test]
+    fn files_that_cant_be_opened_cant_be_decoded() {
+        let result = decode_with(&[&Raw], || -> io::Result<Cursor<Vec<u8>>> {
+            Err(io::Error::new(ErrorKind::NotFound, "gone"))
+        });
+        assert!(matches!(result, Err(DecodeError::Io(_))));
+    }
+
+    #[test]
+    fn rodio_and_symphonia_decode_the_same_samples() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.wav");
+        write_wav(&path, 1, 1);
+        let decode = |decoder: &dyn TrackDecoder| {
+            decoder.decode(Box::new(BufReader::new(File::open(&path).unwrap()))).unwrap()
+        };
+
+        let (rodio, symphonia) = (decode(&RodioDecoder), decode(&SymphoniaDecoder));
+        assert_eq!((symphonia.channels(), symphonia.sample_rate()), (1, 8000));
+        assert_eq!(symphonia.total_duration(), Some(Duration::from_secs(1)));
+        assert_eq!(rodio.collect::<Vec<i16>>(), symphonia.collect::<Vec<i16>>());
+    }
+
+    #[test]
+    fn neither_decoder_knows_what_isnt_audio() {
+        let text = || Ok(Cursor::new(b"not audio at all".to_vec()));
+        assert!(matches!(decode_with(DECODERS, text), Err(DecodeError::Unsupported)));
+    }
+}
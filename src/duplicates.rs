@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use druid::{Data, Env, Lens, Widget, WidgetExt};
+use druid::widget::{Button, Checkbox, Flex, Label, List, Scroll};
+
+use crate::colors::SECONDARY_TEXT_COLOR;
+use crate::commands::REMOVE_DUPLICATES;
+use crate::db::Track;
+
+/// What's in the duplicates dialog: groups of copies of the same song.
+#[derive(Debug, Clone, Data, Lens, Default)]
+pub struct DuplicatesForm {
+    groups: Arc<Vec<DuplicateGroup>>,
+}
+
+#[derive(Debug, Clone, Data, Lens)]
+pub struct DuplicateGroup {
+    copies: Arc<Vec<DuplicateCopy>>,
+}
+
+/// One copy of a song, and whether it's been picked to be removed.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct DuplicateCopy {
+    track: Track,
+    remove: bool,
+}
+
+impl DuplicatesForm {
+    /// Nothing starts out picked, so every removal is a deliberate choice.
+    pub fn new(groups: Vec<Vec<Track>>) -> Self {
+        DuplicatesForm {
+            groups: Arc::new(groups.into_iter()
+                .map(|tracks| DuplicateGroup {
+                    copies: Arc::new(tracks.into_iter()
+                        .map(|track| DuplicateCopy { track, remove: false })
+                        .collect()),
+                })
+                .collect()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Ids of the copies picked to be removed.
+    pub fn picked(&self) -> Vec<i64> {
+        self.groups.iter()
+            .flat_map(|group| group.copies.iter())
+            .filter(|copy| copy.remove)
+            .map(|copy| copy.track.id())
+            .collect()
+    }
+
+    /// Drop tracks that have been removed from the library, and any group that no longer has
+    /// more than one copy.
+    pub fn remove_tracks(&mut self, ids: &HashSet<i64>) {
+        self.groups = Arc::new(self.groups.iter()
+            .map(|group| DuplicateGroup {
+                copies: Arc::new(group.copies.iter()
+                    .filter(|copy| !ids.contains(&copy.track.id()))
+                    .cloned()
+                    .collect()),
+            })
+            .filter(|group| group.copies.len() > 1)
+            .collect());
+    }
+}
+
+pub fn make_duplicates_dialog() -> impl Widget<DuplicatesForm> {
+    let groups = Scroll::new(List::new(make_duplicate_group).lens(DuplicatesForm::groups))
+        .vertical();
+
+    Flex::column()
+        .with_child(Label::new(|data: &DuplicatesForm, _env: &Env| match data.groups.len() {
+                0 => "No duplicates left.".to_string(),
+                1 => "1 song has more than one copy. Pick the copies to remove.".to_string(),
+                n => format!("{} songs have more than one copy. Pick the copies to remove.", n),
+            })
+            .align_left())
+        .with_spacer(8.)
+        .with_flex_child(groups, 1.0)
+        .with_spacer(8.)
+        .with_child(Flex::row()
+            .with_child(Button::new("Remove from Library")
+                .on_click(|ctx, _data: &mut DuplicatesForm, _env| {
+                    ctx.submit_command(REMOVE_DUPLICATES.with(false));
+                }))
+            .with_spacer(8.)
+            .with_child(Button::new("Delete Files…")
+                .on_click(|ctx, _data: &mut DuplicatesForm, _env| {
+                    ctx.submit_command(REMOVE_DUPLICATES.with(true));
+                }))
+            .align_right())
+        .padding(10.)
+}
+
+fn make_duplicate_group() -> impl Widget<DuplicateGroup> {
+    let title = Label::new(|data: &DuplicateGroup, _env: &Env| match data.copies.first() {
+        Some(copy) => format!("{} — {}",
+                              copy.track.artist().unwrap_or("Unknown Artist"),
+                              copy.track.title().unwrap_or("Unknown Title")),
+        None => String::new(),
+    });
+
+    Flex::column()
+        .with_child(title.align_left())
+        .with_child(List::new(make_duplicate_copy).lens(DuplicateGroup::copies))
+        .padding((0., 4.))
+}
+
+fn make_duplicate_copy() -> impl Widget<DuplicateCopy> {
+    // The path is what tells copies apart, and bitrate and rating help pick which to keep
+    let details = Label::new(|data: &DuplicateCopy, _env: &Env| {
+        let rating = data.track.rating().map_or(String::new(), |rating| format!(" · {}★", rating));
+        format!("{} · {} kb/s{}", data.track.path(), data.track.bitrate(), rating)
+    }).with_text_color(SECONDARY_TEXT_COLOR);
+
+    Flex::row()
+        .with_child(Checkbox::new("").lens(DuplicateCopy::remove))
+        .with_flex_child(details.align_left(), 1.0)
+        .padding((8., 2.))
+}
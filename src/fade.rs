@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use rodio::{Sample, Source};
+
+/// Whether playing tracks should be heard or faded out, for pausing and resuming without a
+/// click. Shared by every `Faded` source, which ramp their gain towards it.
+#[derive(Debug, Default)]
+pub struct PauseFade {
+    /// How long a fade takes. Zero cuts straight to silence and back.
+    duration: Duration,
+    faded_out: AtomicBool,
+    /// Changed by every fade, so a pause waiting on a fade out can tell it's been overtaken.
+    generation: AtomicU64,
+}
+
+impl PauseFade {
+    pub fn new(duration: Duration) -> Self {
+        PauseFade {
+            duration,
+            ..PauseFade::default()
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn is_faded_out(&self) -> bool {
+        self.faded_out.load(Ordering::Relaxed)
+    }
+
+    /// Start fading playing tracks out, or back in. Returns the fade's generation, which is
+    /// current until the next fade.
+    pub fn set_faded_out(&self, faded_out: bool) -> u64 {
+        self.faded_out.store(faded_out, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Whether nothing else has faded since the fade that returned `generation`.
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
+}
+
+/// A source that follows a `PauseFade`, turning itself down to silence while it's faded out.
+pub struct Faded<S> {
+    source: S,
+    fade: Arc<PauseFade>,
+    gain: f32,
+}
+
+impl<S> Faded<S> {
+    /// Sources start out at where the fade is, rather than fading from the other end.
+    pub fn new(source: S, fade: Arc<PauseFade>) -> Self {
+        let gain = if fade.is_faded_out() { 0. } else { 1. };
+        Faded { source, fade, gain }
+    }
+}
+
+impl<S: Source> Iterator for Faded<S> where S::Item: Sample {
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let target = if self.fade.is_faded_out() { 0. } else { 1. };
+        if self.gain != target {
+            let samples = ramp_samples(self.fade.duration, self.source.sample_rate(),
+                                       self.source.channels());
+            let step = if samples == 0 { 1. } else { 1. / samples as f32 };
+            self.gain = if target > self.gain {
+                (self.gain + step).min(target)
+            } else {
+                (self.gain - step).max(target)
+            };
+        }
+
+        self.source.next().map(|sample| sample.amplify(self.gain))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S: Source> Source for Faded<S> where S::Item: Sample {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+/// How many samples a fade of `duration` ramps over at `sample_rate`, counting each of the
+/// `channels` separately, since they're interleaved. A whole number of frames, so every channel
+/// fades alike.
+pub fn ramp_samples(duration: Duration, sample_rate: u32, channels: u16) -> u64 {
+    let frames = (duration.as_secs_f64() * sample_rate as f64).round() as u64;
+    frames * channels as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    /// A millisecond at 4 kHz, so fades take four samples.
+    fn quick_fade() -> Arc<PauseFade> {
+        Arc::new(PauseFade::new(Duration::from_millis(1)))
+    }
+
+    fn ones(fade: &Arc<PauseFade>, count: usize) -> Faded<SamplesBuffer<f32>> {
+        Faded::new(SamplesBuffer::new(1, 4000, vec![1.0; count]), fade.clone())
+    }
+
+    #[test]
+    fn ramps_cover_the_fade_in_every_channel() {
+        assert_eq!(ramp_samples(Duration::from_millis(50), 44100, 2), 4410);
+        assert_eq!(ramp_samples(Duration::from_millis(50), 48000, 1), 2400);
+        assert_eq!(ramp_samples(Duration::from_millis(1), 44100, 2), 88);
+        assert_eq!(ramp_samples(Duration::default(), 44100, 2), 0);
+    }
+
+    #[test]
+    fn sources_fade_out_and_back_in() {
+        let fade = quick_fade();
+        let mut source = ones(&fade, 12);
+        assert_eq!(source.next(), Some(1.0));
+
+        fade.set_faded_out(true);
+        let faded: Vec<f32> = source.by_ref().take(6).collect();
+        assert_eq!(faded, vec![0.75, 0.5, 0.25, 0.0, 0.0, 0.0]);
+
+        fade.set_faded_out(false);
+        assert_eq!(source.collect::<Vec<f32>>(), vec![0.25, 0.5, 0.75, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn sources_start_at_the_fade() {
+        let fade = quick_fade();
+        fade.set_faded_out(true);
+        assert_eq!(ones(&fade, 2).collect::<Vec<f32>>(), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn fades_without_a_length_cut() {
+        let fade = Arc::new(PauseFade::new(Duration::default()));
+        let mut source = ones(&fade, 2);
+
+        fade.set_faded_out(true);
+        assert_eq!(source.next(), Some(0.0));
+        fade.set_faded_out(false);
+        assert_eq!(source.next(), Some(1.0));
+    }
+
+    #[test]
+    fn later_fades_overtake_earlier_ones() {
+        let fade = quick_fade();
+        let pause = fade.set_faded_out(true);
+        assert!(fade.is_current(pause));
+
+        fade.set_faded_out(false);
+        assert!(!fade.is_current(pause));
+    }
+}
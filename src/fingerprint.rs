@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use rodio::{Decoder, Source};
+
+/// How much of each end of the file goes into its fingerprint.
+const CHUNK: u64 = 64 * 1024;
+/// How many decoded samples go into an audio fingerprint: about 12 seconds of 44.1 kHz stereo.
+const AUDIO_SAMPLES: usize = 1024 * 1024;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A quick fingerprint of a file's contents: its size plus its first and last 64 KiB, hashed
+/// with 64-bit FNV-1a. It depends only on the bytes, not on the file's name or location, so it
+/// survives moves. Returned as 16 hex digits.
+pub fn content_hash(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &size.to_le_bytes());
+
+    let mut chunk = Vec::with_capacity(CHUNK as usize);
+    (&mut file).take(CHUNK).read_to_end(&mut chunk)?;
+    hash = fnv1a(hash, &chunk);
+
+    if size > CHUNK {
+        // Don't hash any bytes twice if the file is smaller than two chunks
+        file.seek(SeekFrom::Start(CHUNK.max(size - CHUNK)))?;
+        chunk.clear();
+        file.take(CHUNK).read_to_end(&mut chunk)?;
+        hash = fnv1a(hash, &chunk);
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// A fingerprint of a file's decoded audio: its channels, sample rate and first samples, hashed
+/// like `content_hash`. Unlike that, it ignores the tags, so two copies of a song that were
+/// tagged differently still match. Decoding is much slower than reading the ends of the file.
+pub fn audio_hash(path: &str) -> io::Result<String> {
+    let decoder = Decoder::new(BufReader::new(File::open(path)?))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &decoder.channels().to_le_bytes());
+    hash = fnv1a(hash, &decoder.sample_rate().to_le_bytes());
+    for sample in decoder.take(AUDIO_SAMPLES) {
+        hash = fnv1a(hash, &sample.to_le_bytes());
+    }
+
+    Ok(format!("{:016x}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::db::TrackMetadata;
+    use crate::tags::write_tags;
+    use crate::test_support::write_wav;
+
+    use super::*;
+
+    fn hash_of(dir: &TempDir, name: &str, contents: &[u8]) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        content_hash(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_same_bytes_hash_the_same_wherever_they_are() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("moved")).unwrap();
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+        let original = hash_of(&dir, "a.flac", &contents);
+
+        assert_eq!(hash_of(&dir, "moved/b.flac", &contents), original);
+        assert_eq!(original.len(), 16);
+    }
+
+    #[test]
+    fn different_bytes_hash_differently() {
+        let dir = TempDir::new().unwrap();
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let original = hash_of(&dir, "a.flac", &contents);
+
+        let mut changed_start = contents.clone();
+        changed_start[0] ^= 1;
+        let mut changed_end = contents.clone();
+        *changed_end.last_mut().unwrap() ^= 1;
+        let longer = [&contents[..], &[0]].concat();
+
+        assert_ne!(hash_of(&dir, "start.flac", &changed_start), original);
+        assert_ne!(hash_of(&dir, "end.flac", &changed_end), original);
+        assert_ne!(hash_of(&dir, "longer.flac", &longer), original);
+        assert_ne!(hash_of(&dir, "small.flac", b"a"), hash_of(&dir, "other.flac", b"b"));
+    }
+
+    #[test]
+    fn missing_files_have_no_hash() {
+        let dir = TempDir::new().unwrap();
+        assert!(content_hash(dir.path().join("missing.flac").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn audio_hashes_ignore_tags() {
+        let dir = TempDir::new().unwrap();
+        let (plain, tagged, other) = (dir.path().join("plain.wav"), dir.path().join("tagged.wav"),
+                                      dir.path().join("other.wav"));
+        write_wav(&plain, 1, 1);
+        write_wav(&tagged, 1, 1);
+        write_wav(&other, 1, 2);
+        let metadata = TrackMetadata {
+            title: Some("Song".to_string()),
+            artist: None,
+            album: None,
+            comment: None,
+            genre: None,
+            year: None,
+            track: None,
+        };
+        write_tags(tagged.to_str().unwrap(), &metadata).unwrap();
+        let hash = |path: &std::path::Path| audio_hash(path.to_str().unwrap()).unwrap();
+
+        assert_ne!(content_hash(plain.to_str().unwrap()).unwrap(),
+                   content_hash(tagged.to_str().unwrap()).unwrap());
+        assert_eq!(hash(&plain), hash(&tagged));
+        assert_ne!(hash(&plain), hash(&other));
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use druid::{KbKey, KeyEvent};
+use log::{info, warn};
+use thiserror::Error;
+
+/// Something a key can be bound to in the keybindings file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KeyAction {
+    TogglePause,
+    PlayNext,
+    PlayPrevious,
+    GoToRandomTrack,
+    ShowQueue,
+}
+
+/// How actions are named in the keybindings file.
+const ACTIONS: [(&str, KeyAction); 5] = [
+    ("toggle_pause", KeyAction::TogglePause),
+    ("play_next", KeyAction::PlayNext),
+    ("play_previous", KeyAction::PlayPrevious),
+    ("go_to_random_track", KeyAction::GoToRandomTrack),
+    ("show_queue", KeyAction::ShowQueue),
+];
+
+impl KeyAction {
+    /// How it's named in the keybindings file.
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter()
+            .find(|&&(_, action)| action == self)
+            .map(|&(name, _)| name)
+            .unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyAction> {
+        ACTIONS.iter()
+            .find(|(candidate, _)| *candidate == name)
+            .map(|&(_, action)| action)
+    }
+}
+
+/// Keys that aren't written as the character they type, by their name in the keybindings file.
+const NAMED_KEYS: [(&str, KbKey); 19] = [
+    ("enter", KbKey::Enter),
+    ("tab", KbKey::Tab),
+    ("escape", KbKey::Escape),
+    ("esc", KbKey::Escape),
+    ("backspace", KbKey::Backspace),
+    ("delete", KbKey::Delete),
+    ("insert", KbKey::Insert),
+    ("left", KbKey::ArrowLeft),
+    ("right", KbKey::ArrowRight),
+    ("up", KbKey::ArrowUp),
+    ("down", KbKey::ArrowDown),
+    ("home", KbKey::Home),
+    ("end", KbKey::End),
+    ("pageup", KbKey::PageUp),
+    ("pagedown", KbKey::PageDown),
+    ("mediaplaypause", KbKey::MediaPlayPause),
+    ("mediastop", KbKey::MediaStop),
+    ("mediatracknext", KbKey::MediaTrackNext),
+    ("mediatrackprevious", KbKey::MediaTrackPrevious),
+];
+
+/// A key, with the modifiers that have to be held down with it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    meta: bool,
+    /// Characters are lowercase, so that Shift+R is the same combo whatever the keyboard sends.
+    key: KbKey,
+}
+
+impl KeyCombo {
+    /// Read a combo like `Ctrl+Shift+R`, `Space` or `F5`, ignoring case.
+    pub fn parse(text: &str) -> Option<KeyCombo> {
+        let mut parts: Vec<String> = text.split('+')
+            .map(|part| part.trim().to_lowercase())
+            .collect();
+        let key = parse_key(&parts.pop()?)?;
+
+        let mut combo = KeyCombo { ctrl: false, alt: false, shift: false, meta: false, key };
+        for modifier in parts {
+            let held = match modifier.as_str() {
+                "ctrl" | "control" => &mut combo.ctrl,
+                "alt" | "option" => &mut combo.alt,
+                "shift" => &mut combo.shift,
+                "meta" | "cmd" | "super" => &mut combo.meta,
+                _ => return None,
+            };
+            *held = true;
+        }
+        Some(combo)
+    }
+
+    pub fn from_event(event: &KeyEvent) -> KeyCombo {
+        KeyCombo {
+            ctrl: event.mods.ctrl(),
+            alt: event.mods.alt(),
+            shift: event.mods.shift(),
+            meta: event.mods.meta(),
+            key: lowercase(event.key.clone()),
+        }
+    }
+}
+
+fn parse_key(name: &str) -> Option<KbKey> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => return Some(KbKey::Character(c.to_string())),
+        (None, _) => return None,
+        _ => (),
+    }
+
+    if name == "space" {
+        return Some(KbKey::Character(" ".to_string()));
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+        return function_key(n);
+    }
+    NAMED_KEYS.iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, key)| key.clone())
+}
+
+fn function_key(n: u32) -> Option<KbKey> {
+    let keys = [KbKey::F1, KbKey::F2, KbKey::F3, KbKey::F4, KbKey::F5, KbKey::F6, KbKey::F7,
+                KbKey::F8, KbKey::F9, KbKey::F10, KbKey::F11, KbKey::F12];
+    keys.get((n as usize).checked_sub(1)?).cloned()
+}
+
+fn lowercase(key: KbKey) -> KbKey {
+    match key {
+        KbKey::Character(c) => KbKey::Character(c.to_lowercase()),
+        key => key,
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KeyMapError {
+    #[error("line {0}: expected a binding like 'Ctrl+R = go_to_random_track'")]
+    Malformed(usize),
+    #[error("line {0}: '{1}' isn't a key mus knows")]
+    UnknownKey(usize, String),
+    #[error("line {0}: '{1}' isn't a command mus knows")]
+    UnknownAction(usize, String),
+    #[error("line {0}: '{1}' is already bound to {2}")]
+    Conflict(usize, String, &'static str),
+}
+
+/// Which action each key combo sets off, for the keys the focused widget doesn't use itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    bindings: HashMap<KeyCombo, KeyAction>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = [
+            ("Space", KeyAction::TogglePause),
+            ("MediaPlayPause", KeyAction::TogglePause),
+            ("MediaTrackNext", KeyAction::PlayNext),
+            ("MediaTrackPrevious", KeyAction::PlayPrevious),
+        ];
+
+        KeyMap {
+            bindings: bindings.iter()
+                .map(|&(combo, action)| (KeyCombo::parse(combo).unwrap(), action))
+                .collect(),
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn get(&self, combo: &KeyCombo) -> Option<KeyAction> {
+        self.bindings.get(combo).copied()
+    }
+
+    pub fn action(&self, event: &KeyEvent) -> Option<KeyAction> {
+        self.get(&KeyCombo::from_event(event))
+    }
+}
+
+/// Read a keybindings file, one `Ctrl+R = go_to_random_track` per line, with `#` comments.
+/// Bindings are added to the defaults: a combo in the file replaces its default action, and an
+/// action in the file loses its default combos. Binding one combo to two actions is an error.
+pub fn parse_keybindings(text: &str) -> Result<KeyMap, KeyMapError> {
+    let mut bindings: HashMap<KeyCombo, KeyAction> = HashMap::new();
+
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (combo, action) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+            None => return Err(KeyMapError::Malformed(number)),
+        };
+        if combo.is_empty() || action.is_empty() {
+            return Err(KeyMapError::Malformed(number));
+        }
+
+        let key = KeyCombo::parse(combo)
+            .ok_or_else(|| KeyMapError::UnknownKey(number, combo.to_string()))?;
+        let action = KeyAction::from_name(action)
+            .ok_or_else(|| KeyMapError::UnknownAction(number, action.to_string()))?;
+        match bindings.insert(key, action) {
+            Some(bound) if bound != action => {
+                return Err(KeyMapError::Conflict(number, combo.to_string(), bound.name()));
+            }
+            _ => (),
+        }
+    }
+
+    let mut keymap = KeyMap::default();
+    keymap.bindings.retain(|combo, action| {
+        !bindings.contains_key(combo) && !bindings.values().any(|bound| *bound == *action)
+    });
+    keymap.bindings.extend(bindings);
+    Ok(keymap)
+}
+
+/// Where the keybindings file goes, next to the other settings files of the platform.
+pub fn keybindings_path() -> Option<PathBuf> {
+    ProjectDirs::from("org", "Jesus Software Corp.", "mus")
+        .map(|dirs| dirs.config_dir().join("keybindings"))
+}
+
+/// Load the keybindings file at `path`. Without one the defaults are used, and so they are if
+/// it can't be read, with a warning.
+pub fn load_keymap(path: &Path) -> KeyMap {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return KeyMap::default(),
+        Err(e) => {
+            warn!("Could not read {}, using the default keybindings: {}", path.display(), e);
+            return KeyMap::default();
+        }
+    };
+
+    match parse_keybindings(&text) {
+        Ok(keymap) => {
+            info!("Keybindings loaded from {}", path.display());
+            keymap
+        }
+        Err(e) => {
+            warn!("Ignoring {}, using the default keybindings: {}", path.display(), e);
+            KeyMap::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn combo(text: &str) -> KeyCombo {
+        KeyCombo::parse(text).unwrap()
+    }
+
+    #[test]
+    fn combos_are_read_whatever_their_case() {
+        assert_eq!(combo("ctrl+shift+r"), combo("Shift + Ctrl + R"));
+        assert_eq!(combo("Space").key, KbKey::Character(" ".to_string()));
+        assert_eq!(combo("F5").key, KbKey::F5);
+        assert_eq!(combo("Cmd+Right"), KeyCombo { meta: true, ..combo("right") });
+        assert_ne!(combo("Ctrl+R"), combo("R"));
+
+        assert_eq!(KeyCombo::parse("Hyper+R"), None);
+        assert_eq!(KeyCombo::parse("F13"), None);
+        assert_eq!(KeyCombo::parse("F0"), None);
+        assert_eq!(KeyCombo::parse("Ctrl+"), None);
+        assert_eq!(KeyCombo::parse("Spacebar"), None);
+    }
+
+    #[test]
+    fn actions_are_named_in_the_file() {
+        for &(name, action) in ACTIONS.iter() {
+            assert_eq!(action.name(), name);
+            assert_eq!(KeyAction::from_name(name), Some(action));
+        }
+        assert_eq!(KeyAction::from_name("self_destruct"), None);
+    }
+
+    #[test]
+    fn bindings_are_added_to_the_defaults() {
+        let keymap = parse_keybindings(
+            "# Mine\n\nCtrl+R = go_to_random_track\n  Q=show_queue  \nCtrl+Right = play_next\n",
+        ).unwrap();
+
+        assert_eq!(keymap.get(&combo("Ctrl+R")), Some(KeyAction::GoToRandomTrack));
+        assert_eq!(keymap.get(&combo("q")), Some(KeyAction::ShowQueue));
+        assert_eq!(keymap.get(&combo("Ctrl+Right")), Some(KeyAction::PlayNext));
+        // Play next lost its default, the rest kept theirs
+        assert_eq!(keymap.get(&combo("MediaTrackNext")), None);
+        assert_eq!(keymap.get(&combo("Space")), Some(KeyAction::TogglePause));
+        assert_eq!(keymap.get(&combo("R")), None);
+    }
+
+    #[test]
+    fn bindings_replace_the_default_for_their_combo() {
+        let keymap = parse_keybindings("space = show_queue").unwrap();
+        assert_eq!(keymap.get(&combo("Space")), Some(KeyAction::ShowQueue));
+        assert_eq!(keymap.get(&combo("MediaPlayPause")), Some(KeyAction::TogglePause));
+        assert_eq!(parse_keybindings("").unwrap(), KeyMap::default());
+    }
+
+    #[test]
+    fn combos_bound_twice_conflict() {
+        assert_eq!(parse_keybindings("Ctrl+R = show_queue\nctrl+r = play_next"),
+                   Err(KeyMapError::Conflict(2, "ctrl+r".to_string(), "show_queue")));
+        // Saying the same thing twice isn't a conflict
+        assert!(parse_keybindings("Ctrl+R = show_queue\nctrl + r = show_queue").is_ok());
+    }
+
+    #[test]
+    fn unreadable_bindings_are_errors() {
+        assert_eq!(parse_keybindings("Ctrl+R go_to_random_track"), Err(KeyMapError::Malformed(1)));
+        assert_eq!(parse_keybindings("\n= play_next"), Err(KeyMapError::Malformed(2)));
+        assert_eq!(parse_keybindings("Ctrl+Banana = play_next"),
+                   Err(KeyMapError::UnknownKey(1, "Ctrl+Banana".to_string())));
+        assert_eq!(parse_keybindings("Ctrl+R = self_destruct"),
+                   Err(KeyMapError::UnknownAction(1, "self_destruct".to_string())));
+    }
+
+    #[test]
+    fn keybindings_files_fall_back_to_the_defaults() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("keybindings");
+        assert_eq!(load_keymap(&path), KeyMap::default());
+
+        fs::write(&path, "Ctrl+R = go_to_random_track\nCtrl+R = play_next").unwrap();
+        assert_eq!(load_keymap(&path), KeyMap::default());
+
+        fs::write(&path, "Ctrl+R = go_to_random_track").unwrap();
+        assert_eq!(load_keymap(&path).get(&combo("Ctrl+R")), Some(KeyAction::GoToRandomTrack));
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use druid::{Data, Env, Lens, Widget, WidgetExt};
+use druid::widget::{Button, Flex, Label, List, Scroll, TextBox};
+
+use crate::commands::{ADD_LIBRARY, FULL_RESCAN, REMOVE_LIBRARY, RENAME_LIBRARY};
+use crate::db::Library;
+
+/// The add library dialog's fields.
+#[derive(Debug, Clone, Data, Lens, Default)]
+pub struct NewLibraryForm {
+    path: String,
+    name: String,
+}
+
+impl NewLibraryForm {
+    /// The name starts out as the folder's name.
+    pub fn new(path: &Path) -> Self {
+        NewLibraryForm {
+            path: path.to_string_lossy().into_owned(),
+            name: path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+pub fn make_add_library_dialog() -> impl Widget<NewLibraryForm> {
+    Flex::column()
+        .with_child(Label::new(|data: &NewLibraryForm, _env: &Env| data.path.clone()).align_left())
+        .with_spacer(8.)
+        .with_child(Flex::row()
+            .with_child(Label::new("Name").fix_width(60.))
+            .with_flex_child(TextBox::new().expand_width().lens(NewLibraryForm::name), 1.0))
+        .with_spacer(8.)
+        .with_child(Button::new("Add and Scan")
+            .on_click(|ctx, _data: &mut NewLibraryForm, _env| {
+                ctx.submit_command(ADD_LIBRARY.with(ctx.window_id()));
+            })
+            .align_right())
+        .padding(10.)
+}
+
+/// A library as shown in the library manager, with its name as edited so far.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct LibraryEntry {
+    id: i64,
+    path: String,
+    name: String,
+}
+
+/// Entries for the library manager. 'Individual Tracks' can't be renamed or removed, so it's
+/// left out.
+pub fn library_entries(libraries: Vec<Library>) -> Arc<Vec<LibraryEntry>> {
+    Arc::new(libraries.into_iter()
+        .filter_map(|library| {
+            let path = library.path()?.clone();
+            Some(LibraryEntry {
+                id: library.id(),
+                path,
+                name: library.name().clone(),
+            })
+        })
+        .collect())
+}
+
+/// What's happened to a library's scan, as reported by whatever's scanning it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanEvent {
+    Started,
+    /// Files read so far, out of how many there are to read.
+    Progress(usize, usize),
+    Finished,
+    /// The scan stopped with this error.
+    Failed(String),
+}
+
+/// Whether a library is being scanned, as shown next to it in the sidebar.
+#[derive(Debug, Clone, Data, PartialEq)]
+pub enum ScanStatus {
+    Idle,
+    /// Files read so far, and how many there are. Both are 0 until the files have been counted.
+    Scanning(usize, usize),
+    /// The last scan failed with this error. Stays until the next scan starts.
+    Failed(String),
+}
+
+impl ScanStatus {
+    /// The status once `event` has happened. Progress only counts while scanning, so a late
+    /// report can't bring back a scan that's over.
+    pub fn after(&self, event: &ScanEvent) -> ScanStatus {
+        match (self, event) {
+            (_, ScanEvent::Started) => ScanStatus::Scanning(0, 0),
+            (ScanStatus::Scanning(..), &ScanEvent::Progress(scanned, total)) => {
+                ScanStatus::Scanning(scanned, total)
+            }
+            (ScanStatus::Scanning(..), ScanEvent::Finished) => ScanStatus::Idle,
+            (ScanStatus::Scanning(..), ScanEvent::Failed(error)) => {
+                ScanStatus::Failed(error.clone())
+            }
+            (status, _) => status.clone(),
+        }
+    }
+
+    /// What goes next to the library's name: a spinner with how far along it is, or a warning.
+    pub fn badge(&self) -> String {
+        match self {
+            ScanStatus::Idle => String::new(),
+            ScanStatus::Scanning(_, 0) => "⟳".to_string(),
+            ScanStatus::Scanning(scanned, total) => format!("⟳ {}/{}", scanned, total),
+            ScanStatus::Failed(_) => "⚠".to_string(),
+        }
+    }
+}
+
+/// A library as listed in the sidebar, to show just its tracks.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct SidebarLibrary {
+    id: i64,
+    name: String,
+    status: ScanStatus,
+}
+
+impl SidebarLibrary {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> &ScanStatus {
+        &self.status
+    }
+}
+
+/// Entries for the sidebar. Unlike the library manager, this includes 'Individual Tracks'.
+/// Libraries that were already in `old` keep their scan status.
+pub fn sidebar_libraries(libraries: Vec<Library>,
+                         old: &[SidebarLibrary]) -> Arc<Vec<SidebarLibrary>> {
+    Arc::new(libraries.into_iter()
+        .map(|library| SidebarLibrary {
+            id: library.id(),
+            name: library.name().clone(),
+            status: old.iter()
+                .find(|old| old.id == library.id())
+                .map_or(ScanStatus::Idle, |old| old.status.clone()),
+        })
+        .collect())
+}
+
+/// Apply a scan event to the sidebar entry of the library with this id.
+pub fn update_scan_status(libraries: &mut Arc<Vec<SidebarLibrary>>, id: i64, event: &ScanEvent) {
+    // A new Arc, so the sidebar sees the change
+    let mut entries = libraries.to_vec();
+    if let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) {
+        entry.status = entry.status.after(event);
+    }
+    *libraries = Arc::new(entries);
+}
+
+/// Every library, each with its name editable in place.
+pub fn make_library_manager() -> impl Widget<Arc<Vec<LibraryEntry>>> {
+    Scroll::new(List::new(make_library_entry)).vertical().padding(10.)
+}
+
+fn make_library_entry() -> impl Widget<LibraryEntry> {
+    Flex::row()
+        .with_flex_child(Flex::column()
+            .with_child(TextBox::new().expand_width().lens(LibraryEntry::name))
+            .with_child(Label::new(|data: &LibraryEntry, _env: &Env| data.path.clone()).align_left()),
+            1.0)
+        .with_spacer(8.)
+        .with_child(Button::new("Rename").on_click(|ctx, data: &mut LibraryEntry, _env| {
+            ctx.submit_command(RENAME_LIBRARY.with((data.id, data.name.clone())));
+        }))
+        .with_child(Button::new("Full Rescan").on_click(|ctx, data: &mut LibraryEntry, _env| {
+            ctx.submit_command(FULL_RESCAN.with(data.id));
+        }))
+        .with_child(Button::new("Remove").on_click(|ctx, data: &mut LibraryEntry, _env| {
+            ctx.submit_command(REMOVE_LIBRARY.with(data.id));
+        }))
+        .padding((0., 4.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(status: ScanStatus, events: &[ScanEvent]) -> ScanStatus {
+        events.iter().fold(status, |status, event| status.after(event))
+    }
+
+    #[test]
+    fn scans_go_from_idle_to_scanning_and_back() {
+        assert_eq!(after(ScanStatus::Idle, &[ScanEvent::Started]), ScanStatus::Scanning(0, 0));
+        assert_eq!(after(ScanStatus::Idle, &[ScanEvent::Started, ScanEvent::Progress(25, 100)]),
+                   ScanStatus::Scanning(25, 100));
+        assert_eq!(after(ScanStatus::Idle, &[ScanEvent::Started, ScanEvent::Progress(100, 100),
+                                             ScanEvent::Finished]),
+                   ScanStatus::Idle);
+    }
+
+    #[test]
+    fn failed_scans_stay_failed_until_the_next_one() {
+        let failed = after(ScanStatus::Idle, &[ScanEvent::Started,
+                                               ScanEvent::Failed("disk full".to_string())]);
+        assert_eq!(failed, ScanStatus::Failed("disk full".to_string()));
+
+        assert_eq!(after(failed.clone(), &[ScanEvent::Progress(1, 2), ScanEvent::Finished]),
+                   failed);
+        assert_eq!(after(failed, &[ScanEvent::Started]), ScanStatus::Scanning(0, 0));
+    }
+
+    #[test]
+    fn events_outside_a_scan_are_ignored() {
+        assert_eq!(after(ScanStatus::Idle, &[ScanEvent::Progress(1, 2)]), ScanStatus::Idle);
+        assert_eq!(after(ScanStatus::Idle, &[ScanEvent::Failed("late".to_string())]),
+                   ScanStatus::Idle);
+    }
+
+    #[test]
+    fn badges_show_how_far_scans_are() {
+        assert_eq!(ScanStatus::Idle.badge(), "");
+        assert_eq!(ScanStatus::Scanning(0, 0).badge(), "⟳");
+        assert_eq!(ScanStatus::Scanning(25, 100).badge(), "⟳ 25/100");
+        assert_eq!(ScanStatus::Failed("disk full".to_string()).badge(), "⚠");
+    }
+}
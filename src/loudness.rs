@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use druid::Data;
+
+use crate::db::Track;
+
+/// The loudness tracks are normalized to, in LUFS. The same as ReplayGain 2's, so measured and
+/// tagged gains agree.
+pub const TARGET_LOUDNESS: f64 = -18.;
+
+/// Blocks quieter than this never count towards a track's loudness (EBU R128's absolute gate).
+const ABSOLUTE_GATE: f64 = -70.;
+/// Nor do blocks this much quieter than the rest (the relative gate).
+const RELATIVE_GATE: f64 = -10.;
+/// Loudness is measured over 400 ms blocks, each starting 100 ms after the last.
+const STEPS_PER_BLOCK: usize = 4;
+const STEPS_PER_SECOND: u32 = 10;
+
+/// What the volume of each track is normalized by, when normalization is on. Each falls back
+/// on the next best thing a track has: tagged ReplayGain for tracks that haven't been measured,
+/// and measurements for tracks without tags.
+#[derive(Debug, Copy, Clone, Data, PartialEq)]
+pub enum Normalization {
+    /// Each track's ReplayGain tag.
+    ReplayGain,
+    /// Each track's own measured loudness.
+    Track,
+    /// The measured loudness of the track's whole album, so its quiet and loud songs stay that
+    /// way.
+    Album,
+}
+
+impl Normalization {
+    pub fn to_setting(self) -> String {
+        match self {
+            Normalization::ReplayGain => "replay_gain",
+            Normalization::Track      => "track",
+            Normalization::Album      => "album",
+        }.to_string()
+    }
+
+    pub fn from_setting(value: &str) -> Option<Normalization> {
+        match value {
+            "replay_gain" => Some(Normalization::ReplayGain),
+            "track"       => Some(Normalization::Track),
+            "album"       => Some(Normalization::Album),
+            _ => None
+        }
+    }
+}
+
+/// How much to adjust `track`'s volume by, in dB, to normalize it by `normalization`.
+pub fn normalization_gain(track: &Track, normalization: Normalization) -> Option<f64> {
+    let measured = track.loudness().map(loudness_gain);
+    let album = track.album_loudness().map(loudness_gain);
+
+    match normalization {
+        Normalization::ReplayGain => track.replay_gain().or(measured),
+        Normalization::Track => measured.or_else(|| track.replay_gain()),
+        Normalization::Album => album.or(measured).or_else(|| track.replay_gain()),
+    }
+}
+
+/// The gain in dB that brings something `loudness` LUFS loud to `TARGET_LOUDNESS`.
+pub fn loudness_gain(loudness: f64) -> f64 {
+    TARGET_LOUDNESS - loudness
+}
+
+/// The loudness of an album, in LUFS, from its tracks' (loudness, length in seconds). It's the
+/// level of their average power, so longer tracks count for more, as they would measured
+/// end to end; tracks of unknown length count as one second. None without any tracks.
+pub fn album_loudness(tracks: &[(f64, i32)]) -> Option<f64> {
+    if tracks.is_empty() {
+        return None;
+    }
+
+    let (power, length) = tracks.iter().fold((0., 0.), |(power, length), &(loudness, secs)| {
+        let secs = secs.max(1) as f64;
+        (power + secs * 10f64.powf(loudness / 10.), length + secs)
+    });
+
+    Some(10. * (power / length).log10())
+}
+
+/// One section of a biquad filter, in direct form I.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, x: [0.; 2], y: [0.; 2] }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The K-weighting filters of ITU BS.1770 at `sample_rate`: a high shelf for the head's effect
+/// on what's heard, then a high pass for how little the lowest notes count.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad::new([(vh + vb * k / q + k * k) / a0,
+                             2. * (k * k - vh) / a0,
+                             (vh - vb * k / q + k * k) / a0],
+                            [2. * (k * k - 1.) / a0,
+                             (1. - k / q + k * k) / a0]);
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad::new([1., -2., 1.],
+                                [2. * (k * k - 1.) / a0,
+                                 (1. - k / q + k * k) / a0]);
+
+    [shelf, high_pass]
+}
+
+/// How much channel `index` of `channels` counts towards loudness. Surround channels count for
+/// more, and the LFE channel of 5.1 not at all.
+fn channel_weight(index: usize, channels: u16) -> f64 {
+    match (channels, index) {
+        (6, 3) => 0.,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.,
+    }
+}
+
+/// Measures the integrated loudness of a track, as EBU R128 defines it, from its interleaved
+/// samples.
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames per 100 ms step, and how many of them the current step has had.
+    step_frames: usize,
+    frames: usize,
+    /// The current frame's samples, until it's whole.
+    frame: Vec<f64>,
+    /// The weighted sum of each step's squared samples, for the last few steps.
+    steps: VecDeque<f64>,
+    step_energy: f64,
+    /// The mean square of every block so far.
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        LoudnessMeter {
+            channels: channels as usize,
+            weights: (0..channels as usize).map(|index| channel_weight(index, channels)).collect(),
+            filters: (0..channels).map(|_| k_weighting(sample_rate)).collect(),
+            step_frames: ((sample_rate / STEPS_PER_SECOND) as usize).max(1),
+            frames: 0,
+            frame: Vec::with_capacity(channels as usize),
+            steps: VecDeque::with_capacity(STEPS_PER_BLOCK),
+            step_energy: 0.,
+            blocks: Vec::new(),
+        }
+    }
+
+    pub fn add_samples(&mut self, samples: impl IntoIterator<Item = i16>) {
+        for sample in samples {
+            self.frame.push(sample as f64 / 32768.);
+            if self.frame.len() < self.channels {
+                continue;
+            }
+
+            for (channel, &sample) in self.frame.iter().enumerate() {
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let filtered = high_pass.process(shelf.process(sample));
+                self.step_energy += self.weights[channel] * filtered * filtered;
+            }
+            self.frame.clear();
+
+            self.frames += 1;
+            if self.frames == self.step_frames {
+                self.end_step();
+            }
+        }
+    }
+
+    fn end_step(&mut self) {
+        if self.steps.len() == STEPS_PER_BLOCK {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(self.step_energy);
+        self.step_energy = 0.;
+        self.frames = 0;
+
+        if self.steps.len() == STEPS_PER_BLOCK {
+            let energy: f64 = self.steps.iter().sum();
+            self.blocks.push(energy / (STEPS_PER_BLOCK * self.step_frames) as f64);
+        }
+    }
+
+    /// The integrated loudness of everything added, in LUFS. None if it's too short to have a
+    /// whole block, or silent throughout.
+    pub fn integrated(&self) -> Option<f64> {
+        let loudness = |mean_square: f64| -0.691 + 10. * mean_square.log10();
+        let gated_mean = |threshold: f64| {
+            let gated: Vec<f64> = self.blocks.iter().copied()
+                .filter(|&block| loudness(block) > threshold)
+                .collect();
+            if gated.is_empty() {
+                None
+            } else {
+                Some(gated.iter().sum::<f64>() / gated.len() as f64)
+            }
+        };
+
+        let relative_gate = loudness(gated_mean(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+        gated_mean(relative_gate.max(ABSOLUTE_GATE)).map(loudness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `seconds` of a sine at `frequency`, peaking at `peak` dBFS, in every one of `channels`.
+    fn sine(channels: u16, rate: u32, frequency: f64, peak: f64, seconds: f64) -> Vec<i16> {
+        let amplitude = 10f64.powf(peak / 20.) * 32767.;
+        (0..(seconds * rate as f64) as usize)
+            .flat_map(|i| {
+                let sample = (amplitude * (2. * PI * frequency * i as f64 / rate as f64).sin())
+                    .round() as i16;
+                std::iter::repeat(sample).take(channels as usize)
+            })
+            .collect()
+    }
+
+    fn measure(channels: u16, rate: u32, samples: Vec<i16>) -> Option<f64> {
+        let mut meter = LoudnessMeter::new(channels, rate);
+        meter.add_samples(samples);
+        meter.integrated()
+    }
+
+    #[test]
+    fn a_stereo_tone_measures_at_its_peak_level() {
+        // EBU Tech 3341's first test: 1 kHz at -23 dBFS is -23 LUFS
+        for &rate in &[44100, 48000] {
+            let loudness = measure(2, rate, sine(2, rate, 1000., -23., 20.)).unwrap();
+            assert!((loudness + 23.).abs() < 0.1, "{} LUFS at {} Hz", loudness, rate);
+        }
+
+        let loudness = measure(2, 48000, sine(2, 48000, 1000., -33., 20.)).unwrap();
+        assert!((loudness + 33.).abs() < 0.1, "{} LUFS", loudness);
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        assert_eq!(measure(2, 48000, vec![0; 2 * 48000 * 5]), None);
+        // Too short for a single block
+        assert_eq!(measure(2, 48000, sine(2, 48000, 1000., -23., 0.3)), None);
+
+        let mut samples = sine(2, 48000, 1000., -23., 10.);
+        samples.extend(vec![0; 2 * 48000 * 10]);
+        let loudness = measure(2, 48000, samples).unwrap();
+        assert!((loudness + 23.).abs() < 0.1, "{} LUFS", loudness);
+    }
+
+    #[test]
+    fn loudness_becomes_the_gain_to_the_target() {
+        assert_eq!(loudness_gain(-18.), 0.);
+        assert_eq!(loudness_gain(-8.), -10.);
+        assert_eq!(loudness_gain(-23.5), 5.5);
+    }
+
+    #[test]
+    fn albums_are_as_loud_as_their_average_power() {
+        assert_eq!(album_loudness(&[]), None);
+
+        let same = album_loudness(&[(-14., 200), (-14., 300), (-14., 0)]).unwrap();
+        assert!((same + 14.).abs() < 1e-9);
+
+        // Ten times the power, for the same length, is half of 11 times
+        let mixed = album_loudness(&[(-20., 100), (-10., 100)]).unwrap();
+        assert!((mixed - 10. * (0.11f64 / 2.).log10()).abs() < 1e-9, "{}", mixed);
+        // The louder track dominates, but not completely
+        assert!(mixed > -13. && mixed < -10.);
+
+        // Longer tracks count for more
+        let long_quiet = album_loudness(&[(-20., 900), (-10., 100)]).unwrap();
+        assert!(long_quiet < mixed);
+    }
+
+    #[test]
+    fn normalization_falls_back_on_what_a_track_has() {
+        let tagged = Track::for_test(1, "a").with_replay_gain(-3.);
+        let measured = Track::for_test(2, "b").with_loudness(-8., Some(-12.));
+        let both = Track::for_test(3, "c").with_replay_gain(-3.).with_loudness(-8., None);
+
+        assert_eq!(normalization_gain(&tagged, Normalization::ReplayGain), Some(-3.));
+        assert_eq!(normalization_gain(&measured, Normalization::ReplayGain), Some(-10.));
+        assert_eq!(normalization_gain(&both, Normalization::ReplayGain), Some(-3.));
+
+        assert_eq!(normalization_gain(&tagged, Normalization::Track), Some(-3.));
+        assert_eq!(normalization_gain(&measured, Normalization::Track), Some(-10.));
+        assert_eq!(normalization_gain(&both, Normalization::Track), Some(-10.));
+
+        assert_eq!(normalization_gain(&measured, Normalization::Album), Some(-6.));
+        assert_eq!(normalization_gain(&both, Normalization::Album), Some(-10.));
+        assert_eq!(normalization_gain(&Track::for_test(4, "d"), Normalization::Album), None);
+    }
+
+    #[test]
+    fn normalization_survives_being_saved() {
+        for &normalization in &[Normalization::ReplayGain, Normalization::Track,
+                                Normalization::Album] {
+            assert_eq!(Normalization::from_setting(&normalization.to_setting()),
+                       Some(normalization));
+        }
+        assert_eq!(Normalization::from_setting("loud"), None);
+    }
+}
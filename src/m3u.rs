@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use druid::FileSpec;
+
+use crate::db::Track;
+
+pub const M3U: FileSpec = FileSpec::new("M3U Playlist", &["m3u", "m3u8"]);
+
+/// Write tracks as an extended M3U playlist, with absolute paths.
+pub fn write_m3u(writer: &mut impl Write, tracks: &[Track]) -> io::Result<()> {
+    writeln!(writer, "#EXTM3U")?;
+
+    for track in tracks {
+        // -1 is how M3U says the length is unknown
+        let length = if track.length() > 0 { track.length() } else { -1 };
+        writeln!(writer, "#EXTINF:{},{}", length, display_name(track))?;
+        writeln!(writer, "{}", track.path())?;
+    }
+
+    writer.flush()
+}
+
+/// The entries of a plain or extended M3U playlist, in order. Relative paths are resolved against
+/// `base`, the folder the playlist is in.
+pub fn parse_m3u(contents: &str, base: &Path) -> Vec<PathBuf> {
+    contents.lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        // Extended M3U puts everything besides the paths on comment lines
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line.strip_prefix("file://").unwrap_or(line)))
+        .collect()
+}
+
+/// "Artist - Title", or as much of it as we know, or else the file name.
+fn display_name(track: &Track) -> String {
+    match (track.artist(), track.title()) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.to_string(),
+        (Some(artist), None) => artist.to_string(),
+        (None, None) => Path::new(track.path()).file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_extended_m3u() {
+        let tracks = vec![
+            Track::for_test(1, "Aja").with_artist("Steely Dan"),
+            Track::for_test(2, "Untitled"),
+        ];
+
+        let mut written = Vec::new();
+        write_m3u(&mut written, &tracks).unwrap();
+
+        assert_eq!(String::from_utf8(written).unwrap(),
+                   "#EXTM3U\n\
+                    #EXTINF:180,Steely Dan - Aja\n/music/1.flac\n\
+                    #EXTINF:180,Untitled\n/music/2.flac\n");
+    }
+
+    #[test]
+    fn parses_plain_and_extended_m3u() {
+        let contents = "\u{feff}#EXTM3U\r\n\
+                        #EXTINF:180,Steely Dan - Aja\r\n\
+                        /music/aja.flac\r\n\
+                        \r\n\
+                        # A comment\r\n\
+                        Deacon Blues.mp3  \r\n\
+                        file:///music/peg.flac\r\n";
+
+        assert_eq!(parse_m3u(contents, Path::new("/playlists")), vec![
+            PathBuf::from("/music/aja.flac"),
+            PathBuf::from("/playlists/Deacon Blues.mp3"),
+            PathBuf::from("/music/peg.flac"),
+        ]);
+    }
+
+    #[test]
+    fn written_playlists_parse_back() {
+        let tracks = vec![Track::for_test(1, "Aja"), Track::for_test(2, "Peg")];
+
+        let mut written = Vec::new();
+        write_m3u(&mut written, &tracks).unwrap();
+
+        let parsed = parse_m3u(&String::from_utf8(written).unwrap(), Path::new("/elsewhere"));
+        let paths: Vec<PathBuf> = tracks.iter().map(|track| PathBuf::from(track.path())).collect();
+        assert_eq!(parsed, paths);
+    }
+}
@@ -1,77 +1,2334 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::process;
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use druid::{AppLauncher, Color, Data, Lens, RenderContext, Size, Widget, WidgetExt, WindowDesc};
-use druid::widget::{Flex, Label, Painter};
-use rodio::{OutputStream, Sink};
+use druid::{AppDelegate, AppLauncher, Color, Command, Data, DelegateCtx, Env, Event, EventCtx,
+            ExtEventSink, FileDialogOptions, Handled, ImageBuf, Lens, LocalizedString,
+            MenuDesc, MenuItem, Point, RenderContext, Screen, Size, SysMods, Target, TimerToken,
+            UpdateCtx, Widget, WidgetExt, WindowDesc, WindowId};
+use druid::commands::{CLOSE_WINDOW, SHOW_OPEN_PANEL, SHOW_SAVE_PANEL};
+use druid::lens;
+use druid::widget::{Button, Checkbox, Controller, Either, Flex, Label, LineBreaking, Painter,
+                    ProgressBar, SizedBox, Slider};
+use log::{error, info, warn};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rodio::{OutputStream, OutputStreamHandle, PlayError, Sink, Source};
+use thiserror::Error;
 
-use crate::db::{Database, Track};
-use crate::tracklist::{TrackList, TrackListData};
-use crate::colors::ALT_BACKGROUND_COLOR;
+use crate::decode::{decode_with, DecodeError, TrackSource, DECODERS};
+use crate::db::{Database, DatabaseError, DuplicateKey, FileRemoval, Library, PlaybackFailure,
+                RescanLoss, ScanOptions, Track, TrackField};
+use crate::tracklist::{format_duration, make_filter_box, make_status_bar, ListPosition,
+                       SelectionController, TrackList, TrackListData};
+use crate::cli::{parse_args, Invocation};
+use crate::colors::{ALT_BACKGROUND_COLOR, SECONDARY_TEXT_COLOR};
+use crate::commands::{ADD_LIBRARY, ADD_SELECTION_TO_PLAYLIST, ANALYZE_TRACKS, COLUMNS_CHANGED,
+                      CONFIRM_DELETE, CONFIRM_FULL_RESCAN, CROSSFADE, DELETE_PLAYLIST,
+                      DELETE_SELECTION, DUPLICATES_FOUND, EDIT_METADATA, EDIT_SMART_PLAYLIST,
+                      ENQUEUE_SELECTION, EXPORT_PLAYLIST, FIND_DUPLICATES, FULL_RESCAN,
+                      GO_TO_RANDOM_TRACK, IMPORT_PLAYLIST, IMPORT_SIDECARS,
+                      INSERT_CHOSEN_INTO_QUEUE, INSERT_FILE_INTO_QUEUE, INSERT_FOLDER_INTO_QUEUE,
+                      LIBRARY_CHANGED, LIBRARY_CLEARED, LIBRARY_SCANNED, LIBRARY_SCAN_STATUS,
+                      MANAGE_LIBRARIES, MOVE_QUEUE_ENTRY, NEW_LIBRARY_FOLDER, NEW_PLAYLIST,
+                      NEW_SMART_PLAYLIST, NOTIFY, OPEN_CONTAINING_FOLDER, OUTPUT_DEVICES_CHANGED,
+                      PLAY_NEXT, PLAY_PREVIOUS, PLAY_SELECTION, PLAY_TRACK, RATE_SELECTION,
+                      RECORD_PLAY, REFRESH_TRACK, REMOVE_DUPLICATES, REMOVE_LIBRARY,
+                      REMOVE_SELECTION, REMOVE_SELECTION_FROM_PLAYLIST, RENAME_LIBRARY,
+                      REORDER_PLAYLIST, SAVE_METADATA, SAVE_SMART_PLAYLIST, SCAN_PROGRESS, SEEK,
+                      SET_NORMALIZATION, SET_OUTPUT_DEVICE, SET_RATING, SHOW_OUTPUT_DEVICES,
+                      SHOW_QUEUE, SHOW_SCOPE, SHOW_VIEW, SHUFFLE_QUEUE, SIDECARS_IMPORTED,
+                      STALE_TRACKS_FOUND, TOGGLE_ALBUM_GROUPS, TOGGLE_ALBUM_NODE,
+                      TOGGLE_ALWAYS_ON_TOP, TOGGLE_ARTIST_NODE, TOGGLE_REDUCE_MOTION,
+                      TOGGLE_REPLAY_GAIN, TOGGLE_SEARCH_EVERYWHERE, TOGGLE_SMART_SHUFFLE,
+                      TOGGLE_STRICT_SORT, TOGGLE_TRIM_SILENCE, TRACKS_ANALYZED, TRACKS_FOUND,
+                      TRACK_FINISHED, UNDO_RATE_SELECTION, VACUUM_DATABASE};
+use crate::archive::{archive_tracks, is_archive, open_entry, split_entry_path, ArchiveError};
+use crate::art::{ArtCache, CoverArt};
+use crate::duplicates::{make_duplicates_dialog, DuplicatesForm};
+use crate::browse::{artist_tree, refresh_artist_tree, toggle_album, toggle_artist, ArtistNode,
+                    Grouping};
+use crate::m3u::M3U;
+use crate::output::{make_output_device_dialog, open_output, output_devices};
+use crate::fade::{Faded, PauseFade};
+use crate::keys::{keybindings_path, load_keymap, KeyAction, KeyMap};
+use crate::watcher::LibraryWatcher;
+use crate::libraries::{library_entries, make_add_library_dialog, make_library_manager,
+                       sidebar_libraries, update_scan_status, LibraryEntry, NewLibraryForm,
+                       ScanEvent, SidebarLibrary};
+use crate::metadata::{make_metadata_editor, MetadataForm};
+use crate::notifications::{make_notification_area, notify, NotificationStack, Severity};
+use crate::playlists::{make_sidebar, make_smart_playlist_dialog, playlist_entries, PlaylistEntry,
+                       SmartPlaylistForm};
+use crate::loudness::{normalization_gain, LoudnessMeter, Normalization};
+use crate::prefetch::Prefetcher;
+use crate::queue::{expand_folders, make_queue_view, queue_rows, Queue};
+use crate::resample::{resample, ResampleQuality};
+use crate::seekbar::{PlaybackPosition, SeekBar};
+use crate::shuffle::{weighted_shuffle, ShuffleWeights};
+use crate::silence::SilenceDetector;
+use crate::transition::TrackTransition;
+use crate::on_top::{set_always_on_top, startup_on_top, StartupOnTop};
+use crate::sidecar::{import_sidecars, JsonSidecar};
+use crate::settings::{load_view_columns, replay_gain_factor, resolve_startup_view,
+                      save_always_on_top, save_confirm_full_rescan, save_last_view,
+                      save_normalization, save_output_device, save_reduce_motion, save_replay_gain,
+                      save_search_everywhere, save_smart_shuffle, save_strict_sort,
+                      save_trim_silence, save_view_columns, save_volume, save_window_geometry,
+                      startup_scan, volume_gain, Settings, View};
 
 mod db;
 mod tracklist;
 mod colors;
+mod tags;
+mod settings;
+mod cli;
+mod commands;
+mod sidecar;
+mod notifications;
+mod fingerprint;
+mod queue;
+mod seekbar;
+mod metadata;
+mod libraries;
+mod playlists;
+mod m3u;
+mod art;
+mod browse;
+mod duplicates;
+mod criteria;
+mod search;
+mod collation;
+mod output;
+mod fade;
+mod shuffle;
+mod on_top;
+mod decode;
+mod resample;
+mod archive;
+mod transition;
+mod prefetch;
+mod loudness;
+mod silence;
+mod keys;
+mod watcher;
+#[cfg(test)]
+mod test_support;
 
 type WrappedTrackList = Arc<RwLock<Vec<Track>>>;
 
+/// Scans report their progress, and send the tracks they've found, every this many files.
+const SCAN_PROGRESS_INTERVAL: usize = 25;
+
+/// Tracks are analyzed this many samples at a time, each going to every measurement in turn.
+const ANALYSIS_CHUNK: usize = 4096;
+
+/// How often the playback watcher checks whether the current track has finished, and how often
+/// we check whether it's time to crossfade into the next or count it as played.
+const PLAYBACK_POLL: Duration = Duration::from_millis(250);
+/// How long the volume has to stay put before it's saved, so a drag of the slider is saved once
+/// rather than for every step.
+const VOLUME_SAVE_DELAY: Duration = Duration::from_secs(1);
+/// How far short of its length a track can stop and still count as having finished, since
+/// lengths are only to the second, and only estimates for some formats.
+const EARLY_END_TOLERANCE: Duration = Duration::from_secs(5);
+/// How often the device watcher checks for output devices being plugged in or unplugged.
+const DEVICE_POLL: Duration = Duration::from_secs(5);
+/// How often a track that's fading out is turned down.
+const FADE_STEP: Duration = Duration::from_millis(50);
+/// How many tracks the Most Played and Recently Played views show.
+const PLAY_HISTORY_LENGTH: u32 = 100;
+
+/// The parts of the main window that don't stretch.
+const SIDEBAR_WIDTH: f64 = 180.;
+const COVER_ART_SIZE: f64 = 48.;
+const SEEK_BAR_WIDTH: f64 = 300.;
+const VOLUME_SLIDER_WIDTH: f64 = 120.;
+const BAR_SPACING: f64 = 8.;
+/// Room for the bottom bar's labels: what's playing, where it is in the queue, the track count
+/// and "Volume".
+const MIN_LABELS_WIDTH: f64 = 200.;
+/// The narrowest the track list gets beside the sidebar. It collapses to one column well before.
+const MIN_TRACK_LIST_WIDTH: f64 = 200.;
+
 #[derive(Clone, Data, Lens)]
 struct AppData {
     db: Arc<RwLock<Database>>,
     stream: Arc<RwLock<OutputStream>>,
+    /// For making new sinks, since a stopped one can't be restarted.
+    stream_handle: Arc<OutputStreamHandle>,
     sink: Arc<RwLock<Sink>>,
-    main_tracklist_data: TrackListData
+    /// The output device picked, by name. None is the system default.
+    output_device: Option<String>,
+    /// What's in the output device picker, if it's open.
+    output_devices: Arc<Vec<String>>,
+    /// Mirrors `Sink::is_paused`, so the UI updates when it changes. Pausing sets it straight
+    /// away, while the sink fades out first.
+    paused: bool,
+    /// Fades playback out on pausing and stopping, and in on resuming.
+    pause_fade: Arc<PauseFade>,
+    position: PlaybackPosition,
+    /// Whether the current track has played for long enough to count as played, and been
+    /// counted, so it isn't counted twice.
+    play_recorded: bool,
+    /// The current track's cover art.
+    art: Option<ImageBuf>,
+    art_cache: ArtCache,
+    /// Volume slider position; see `settings::volume_gain`.
+    volume: f64,
+    /// Whether tracks are played at their normalized volume.
+    replay_gain: bool,
+    /// What tracks' volumes are normalized by.
+    normalization: Normalization,
+    /// Whether the silence before and after each track is skipped, where it's been found.
+    trim_silence: bool,
+    /// The sample rate every track is played at, if not each at its own.
+    output_rate: Option<u32>,
+    resample_quality: ResampleQuality,
+    /// Decodes the start of the next track ahead of time, when `prefetch` is set.
+    prefetcher: Prefetcher,
+    /// How much of the next track to decode ahead of time, if any.
+    prefetch: Option<Duration>,
+    /// Whether the main window is meant to stay above other windows.
+    always_on_top: bool,
+    /// Whether track changes skip the now-playing display's fade.
+    reduce_motion: bool,
+    /// Whether shuffling the queue favours well-rated tracks that haven't played much.
+    smart_shuffle: bool,
+    queue: Queue,
+    /// What's in the metadata editor, if it's open.
+    metadata_form: MetadataForm,
+    /// What's in the add library dialog, if it's open.
+    new_library: NewLibraryForm,
+    /// What's in the smart playlist dialog, if it's open.
+    smart_playlist: SmartPlaylistForm,
+    /// What's in the library manager, if it's open.
+    libraries: Arc<Vec<LibraryEntry>>,
+    /// The library being scanned in the background, files read, and files to read.
+    scan_progress: Option<(String, usize, usize)>,
+    /// What's in the duplicates dialog, if it's open.
+    duplicates: DuplicatesForm,
+    /// Tracks whose files will be deleted once the user confirms it.
+    pending_deletion: Arc<Vec<i64>>,
+    /// The "don't ask again" box in the full rescan confirmation.
+    skip_rescan_confirmation: bool,
+    /// Ratings from before the last time the whole selection was rated, to put back on undo.
+    rating_undo: Arc<Vec<(i64, Option<i32>)>>,
+    /// What's in the main track list.
+    view: View,
+    /// Where each view's list was left, for going back to it. Empty unless that's turned on.
+    view_positions: Arc<HashMap<View, ListPosition>>,
+    remember_view_positions: bool,
+    sidebar_libraries: Arc<Vec<SidebarLibrary>>,
+    playlists: Arc<Vec<PlaylistEntry>>,
+    artists: Arc<Vec<ArtistNode>>,
+    /// How the artist tree groups tags.
+    grouping: Grouping,
+    main_tracklist_data: TrackListData,
+    notifications: NotificationStack,
 }
 
 fn main() {
     pretty_env_logger::init();
 
+    let invocation = match parse_args(std::env::args().skip(1)) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
     let mut db = Database::new().expect("Launch failed.");
-    let (stream, handle) = OutputStream::try_default().unwrap();
+    let mut settings = Settings::load(&db).expect("Could not load settings.");
+
+    let (stream, handle) = open_output(settings.output_device.as_deref()).unwrap();
     let sink = Sink::try_new(&handle).unwrap();
 
-    if db.libraries().unwrap().len() <= 1 {
-        let library = db.add_library("/data/Music".to_string(), "Music".to_string()).unwrap();
-        db.scan_library(library, true).unwrap();
+    let first_run = db.libraries().unwrap().len() <= 1;
+
+    let mut queue = Queue::default();
+    // Where to pick up the saved queue's current track, paused
+    let mut resume_at = None;
+    match invocation {
+        Invocation::Open(files) => {
+            // e.g. a folder dropped on mus
+            for file in expand_folders(&files) {
+                queue_file(&mut db, &mut queue, &file);
+            }
+            queue.play_next();
+        }
+        Invocation::Normal => match db.saved_queue() {
+            Ok((tracks, current)) => {
+                queue = Queue::restore(tracks, current.map(|(index, _)| index));
+                resume_at = current.map(|(_, elapsed)| elapsed);
+            }
+            Err(e) => warn!("Could not restore the queue: {}", e),
+        },
     }
 
-    let tracks = db.dump_all_tracks().expect("Could not dump tracks.")
-        .into_iter().collect();
+    let view = resolve_startup_view(settings.startup_view, settings.last_view,
+                                    |id| db.playlist_exists(id).unwrap_or(false),
+                                    |id| db.library_exists(id).unwrap_or(false));
+
+    let mut tracklist_data = load_view(&db, view).expect("Could not dump tracks.");
+    tracklist_data.set_strict_sort(settings.strict_sort);
+    tracklist_data.set_search_everywhere(settings.search_everywhere);
+    let libraries = sidebar_libraries(db.libraries().expect("Could not list libraries."), &[]);
+    let playlists = playlist_entries(db.list_playlists().expect("Could not list playlists."));
+    let grouping = settings.grouping();
+    let artists = artist_tree(&db, &grouping).expect("Could not list artists.");
+
+    settings.last_view = Some(view);
+    if startup_on_top(settings.always_on_top, on_top::SUPPORTED) == StartupOnTop::Unsupported {
+        warn!("Not keeping the window on top, that isn't supported on this platform");
+    }
+    settings.save(&db).expect("Could not save settings.");
 
-    let initial_state = AppData {
+    let mut initial_state = AppData {
         db: Arc::new(RwLock::new(db)),
         stream: Arc::new(RwLock::new(stream)),
+        stream_handle: Arc::new(handle),
         sink: Arc::new(RwLock::new(sink)),
-        main_tracklist_data: TrackListData::new(tracks)
+        output_device: settings.output_device.clone(),
+        output_devices: Arc::new(output_devices()),
+        paused: false,
+        pause_fade: Arc::new(PauseFade::new(settings.pause_fade())),
+        position: PlaybackPosition::default(),
+        play_recorded: false,
+        art: None,
+        art_cache: ArtCache::default(),
+        volume: settings.volume,
+        replay_gain: settings.replay_gain,
+        normalization: settings.normalization,
+        trim_silence: settings.trim_silence,
+        output_rate: settings.output_rate(),
+        resample_quality: settings.resample_quality,
+        prefetcher: Prefetcher::default(),
+        prefetch: settings.prefetch(),
+        smart_shuffle: settings.smart_shuffle,
+        always_on_top: settings.always_on_top,
+        reduce_motion: settings.reduce_motion,
+        queue,
+        metadata_form: MetadataForm::default(),
+        new_library: NewLibraryForm::default(),
+        smart_playlist: SmartPlaylistForm::default(),
+        libraries: Arc::new(Vec::new()),
+        scan_progress: None,
+        duplicates: DuplicatesForm::default(),
+        pending_deletion: Arc::new(Vec::new()),
+        skip_rescan_confirmation: false,
+        rating_undo: Arc::new(Vec::new()),
+        view,
+        view_positions: Arc::new(HashMap::new()),
+        remember_view_positions: settings.remember_view_positions,
+        sidebar_libraries: libraries,
+        playlists,
+        artists,
+        grouping,
+        main_tracklist_data: tracklist_data,
+        notifications: NotificationStack::default(),
     };
 
-    let main_window = WindowDesc::new(make_ui)
-        .title("mus")
-        .window_size(Size::new(1920.0, 1080.0));
+    if let Some(track) = initial_state.queue.current() {
+        let offset = resume_at.unwrap_or_default();
+        // Set first, so that resuming fades in
+        initial_state.paused = resume_at.is_some();
+        match play(&initial_state, Some(track), offset, Duration::default()) {
+            Ok(()) => {
+                let now = Instant::now();
+                initial_state.position = PlaybackPosition::start(track.length(), offset, now)
+                    .ending_at(trimmed_sound(&initial_state, track).map(|(_, end)| end));
+                // It was counted last time, when it got this far
+                initial_state.play_recorded = initial_state.position.is_past_halfway(now);
+                if initial_state.paused {
+                    initial_state.sink.read().unwrap().pause();
+                    initial_state.position.pause(now);
+                }
+                prefetch_next(&initial_state);
+            }
+            Err(e) => {
+                warn!("Could not resume {}: {}", track.path(), e);
+                initial_state.paused = false;
+            }
+        }
+        initial_state.art = initial_state.art_cache.get(&initial_state.db.read().unwrap(), track);
+    }
+
+    let (window_size, window_position) = fit_to_screen(settings.window_size,
+                                                       settings.window_position);
+    let ui_settings = settings.clone();
+    let ui_db = initial_state.db.clone();
+    let ui_art_cache = initial_state.art_cache.clone();
+    let ui_keymap = keybindings_path().map_or_else(KeyMap::default, |path| load_keymap(&path));
+    let title_format = settings.window_title_format.clone();
+    let main_window = WindowDesc::new(move || {
+        make_ui(&ui_settings, ui_db, ui_art_cache, ui_keymap)
+    })
+        .title(move |data: &AppData, _env: &Env| window_title(&title_format, data.queue.current()))
+        .menu(make_menu())
+        .window_size(window_size)
+        .with_min_size(min_window_size(settings.min_window_size));
+    let main_window = match window_position {
+        Some(position) => main_window.set_position(position),
+        None => main_window,
+    };
+
+    let main_window_id = main_window.id;
+    let launcher = AppLauncher::with_window(main_window);
+
+    let mut watchers = HashMap::new();
+    for library in initial_state.db.read().unwrap().libraries().unwrap() {
+        if library.path().is_some() {
+            watch_library(&mut watchers, library, settings.scan_options(),
+                          launcher.get_external_handle());
+        }
+    }
 
-    AppLauncher::with_window(main_window)
+    let launcher = launcher
+        .delegate(Delegate {
+            scan_options: settings.scan_options(),
+            crossfade: settings.crossfade(),
+            crossfade_on_skip: settings.crossfade_on_skip,
+            delete_to_trash: settings.delete_to_trash,
+            confirm_full_rescan: settings.confirm_full_rescan,
+            shuffle_weights: settings.shuffle_weights(),
+            rng: StdRng::from_entropy(),
+            main_window: main_window_id,
+            watchers,
+            queue_insert_at: 0,
+            silence_threshold: settings.silence_threshold_db,
+        })
         .configure_env(|env, _state| {
             env.set(ALT_BACKGROUND_COLOR, Color::grey8(60));
-        })
+            env.set(SECONDARY_TEXT_COLOR, Color::grey8(160));
+        });
+
+    let event_sink = launcher.get_external_handle();
+    thread::spawn(move || check_for_stale_tracks(event_sink));
+
+    if let Some(full_rescan) = startup_scan(settings.startup_scan, first_run) {
+        // Nothing to scan in 'Individual Tracks'
+        let libraries: Vec<Library> = initial_state.db.read().unwrap().libraries().unwrap()
+            .into_iter()
+            .filter(|library| library.path().is_some())
+            .collect();
+        let options = settings.scan_options();
+        let event_sink = launcher.get_external_handle();
+        thread::spawn(move || scan_libraries(libraries, full_rescan, options, event_sink));
+    }
+
+    let event_sink = launcher.get_external_handle();
+    let sink = initial_state.sink.clone();
+    thread::spawn(move || watch_playback(sink, event_sink));
+
+    let event_sink = launcher.get_external_handle();
+    let devices = initial_state.output_devices.to_vec();
+    thread::spawn(move || watch_output_devices(devices, event_sink));
+
+    launcher
         .launch(initial_state)
         .expect("launch failed");
 }
 
-fn make_ui() -> impl Widget<AppData> {
+struct Delegate {
+    /// For scanning libraries added while mus is running.
+    scan_options: ScanOptions,
+    crossfade: Duration,
+    /// Crossfade into tracks picked by hand, rather than only when one track runs into the next.
+    crossfade_on_skip: bool,
+    /// Move deleted tracks' files to the trash instead of deleting them for good.
+    delete_to_trash: bool,
+    /// Ask before a full rescan that would lose playlists, plays or ratings.
+    confirm_full_rescan: bool,
+    /// What smart shuffles weigh tracks by.
+    shuffle_weights: ShuffleWeights,
+    rng: StdRng,
+    /// When this closes, mus is on its way out, so that's when the queue is saved.
+    main_window: WindowId,
+    /// Following each library's folder for changes, by library id.
+    watchers: HashMap<i64, LibraryWatcher>,
+    /// Where in the queue the file or folder being picked goes.
+    queue_insert_at: usize,
+    /// Anything quieter than this, in dB, is silence when analyzing tracks.
+    silence_threshold: f64,
+}
+
+impl Delegate {
+    /// Ask for a file or folder to put in the queue before the entry at `index`, from the window
+    /// that asked.
+    fn pick_for_queue(&mut self, ctx: &mut DelegateCtx, target: Target, index: usize,
+                      options: FileDialogOptions) {
+        self.queue_insert_at = index;
+        let window = match target {
+            Target::Window(window) => window,
+            _ => self.main_window,
+        };
+        let options = options.accept_command(INSERT_CHOSEN_INTO_QUEUE);
+        ctx.submit_command(Command::new(SHOW_OPEN_PANEL, options, window));
+    }
+
+    /// How long to crossfade into the queue's current track after skipping to it by hand. Call
+    /// before playing it, while `data.position` is still that of the track being skipped.
+    fn skip_fade(&self, data: &AppData) -> Duration {
+        if !self.crossfade_on_skip || data.paused {
+            return Duration::default();
+        }
+
+        match (data.position.remaining(Instant::now()), data.queue.current()) {
+            (Some(remaining), Some(track)) => crossfade_length(self.crossfade, remaining, track),
+            _ => Duration::default(),
+        }
+    }
+
+    /// Ask before doing anything to the files of `data.pending_deletion`.
+    fn confirm_deletion(&self, ctx: &mut DelegateCtx, data: &AppData) {
+        let count = data.pending_deletion.len();
+        let removal = FileRemoval::choose(true, self.delete_to_trash);
+        let title = match removal {
+            FileRemoval::Trash => "Move Files to Trash",
+            _ => "Delete Files",
+        };
+        ctx.new_window(WindowDesc::new(move || make_delete_confirmation(count, removal))
+            .title(title)
+            .window_size((360., 120.)));
+    }
+
+    /// Clear `library` out and scan it again from scratch, on a thread of its own.
+    fn full_rescan(&self, ctx: &mut DelegateCtx, library: Library) {
+        ctx.submit_command(notify(Severity::Info, format!("Rescanning {}…", library.name())));
+        let event_sink = ctx.get_external_handle();
+        let options = self.scan_options.clone();
+        thread::spawn(move || scan_libraries(vec![library], true, options, event_sink));
+    }
+}
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(&mut self, ctx: &mut DelegateCtx, target: Target, cmd: &Command,
+               data: &mut AppData, _env: &Env) -> Handled {
+        if let Some(ids) = cmd.get(STALE_TRACKS_FOUND) {
+            data.main_tracklist_data.set_stale_tracks(ids.iter().copied());
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(REFRESH_TRACK) {
+            match data.db.write().unwrap().refresh_track(id) {
+                Ok(Some(track)) => data.main_tracklist_data.replace_track(track),
+                Ok(None) => {
+                    warn!("Could not re-read track {}, its file is missing or unreadable", id);
+                    ctx.submit_command(notify(Severity::Warning,
+                                              "That track's file is missing or unreadable."));
+                }
+                Err(e) => {
+                    error!("Could not refresh track {}: {}", id, e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if let Some(track) = cmd.get(PLAY_TRACK) {
+            data.queue.play_now(vec![track.clone()]);
+            let fade = self.skip_fade(data);
+            play_current(ctx, data, fade);
+            Handled::Yes
+        } else if cmd.is(PLAY_SELECTION) {
+            let selection = data.main_tracklist_data.selection();
+            if !selection.is_empty() {
+                data.queue.play_now(selection);
+                let fade = self.skip_fade(data);
+                play_current(ctx, data, fade);
+            }
+            Handled::Yes
+        } else if cmd.is(OPEN_CONTAINING_FOLDER) {
+            let folders: BTreeSet<_> = data.main_tracklist_data.selection().iter()
+                .filter_map(|track| Path::new(track.path()).parent().map(Path::to_path_buf))
+                .collect();
+
+            for folder in folders {
+                if let Err(e) = open_folder(&folder) {
+                    error!("Could not open {}: {}", folder.display(), e);
+                    ctx.submit_command(notify(Severity::Error,
+                                              format!("Could not open {}.", folder.display())));
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(TRACK_FINISHED) {
+            if let Some(at) = failed_partway(&data.position, Instant::now()) {
+                if let Some(track) = data.queue.current().cloned() {
+                    let reason = format!("it stopped at {}, where its file may be damaged",
+                                         format_duration(at.as_secs() as i64));
+                    report_playback_error(ctx, &track, &reason);
+                    record_playback_error(data, &track, PlaybackFailure::Partway, &reason);
+                }
+            }
+            // Either way, carry on with the next track
+            data.queue.play_next();
+            play_current(ctx, data, Duration::default());
+            Handled::Yes
+        } else if let Some(&fade) = cmd.get(CROSSFADE) {
+            data.queue.play_next();
+            play_current(ctx, data, fade);
+            Handled::Yes
+        } else if cmd.is(PLAY_NEXT) {
+            data.queue.play_next();
+            let fade = self.skip_fade(data);
+            play_current(ctx, data, fade);
+            Handled::Yes
+        } else if cmd.is(PLAY_PREVIOUS) {
+            data.queue.play_previous();
+            let fade = self.skip_fade(data);
+            play_current(ctx, data, fade);
+            Handled::Yes
+        } else if cmd.is(SHOW_OUTPUT_DEVICES) {
+            data.output_devices = Arc::new(output_devices());
+            ctx.new_window(WindowDesc::new(|| make_output_device_dialog()
+                    .lens(lens::Map::new(
+                        |data: &AppData| (data.output_device.clone(), data.output_devices.clone()),
+                        |_data: &mut AppData, _devices| ())))
+                .title("Output Device")
+                .window_size((360., 240.)));
+            Handled::Yes
+        } else if let Some(device) = cmd.get(SET_OUTPUT_DEVICE) {
+            data.output_device = device.clone();
+            if let Err(e) = save_output_device(&data.db.read().unwrap(), device.as_deref()) {
+                warn!("Could not save the output device: {}", e);
+            }
+            switch_output(ctx, data);
+            Handled::Yes
+        } else if let Some(devices) = cmd.get(OUTPUT_DEVICES_CHANGED) {
+            // Only the picked device coming or going matters; the default follows the system
+            let changed = data.output_device.as_ref().filter(|device| {
+                data.output_devices.contains(device) != devices.contains(device)
+            }).cloned();
+            data.output_devices = Arc::new(devices.clone());
+
+            if let Some(device) = changed {
+                if !devices.contains(&device) {
+                    warn!("Output device {} is gone, falling back to the default", device);
+                    ctx.submit_command(notify(Severity::Warning,
+                                              format!("{} was disconnected, so mus is playing \
+                                                       through the default device.", device)));
+                }
+                switch_output(ctx, data);
+            }
+            Handled::Yes
+        } else if let Some(&offset) = cmd.get(SEEK) {
+            seek(ctx, data, offset);
+            Handled::Yes
+        } else if let Some(columns) = cmd.get(COLUMNS_CHANGED) {
+            // Only for this view; the others keep theirs
+            if let Err(e) = save_view_columns(&data.db.read().unwrap(), data.view, columns) {
+                warn!("Could not save the columns: {}", e);
+            }
+            data.main_tracklist_data.set_columns(Some(columns.clone()));
+            Handled::Yes
+        } else if cmd.is(ENQUEUE_SELECTION) {
+            data.queue.enqueue(data.main_tracklist_data.selection());
+            if data.queue.current().is_none() {
+                data.queue.play_next();
+                play_current(ctx, data, Duration::default());
+            } else {
+                prefetch_next(data);
+            }
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(RECORD_PLAY) {
+            let mut db = data.db.write().unwrap();
+            match db.record_play(id).and_then(|()| db.track(id)) {
+                Ok(Some(track)) => data.main_tracklist_data.update_track(track),
+                Ok(None) => warn!("Played track {} no longer exists", id),
+                Err(e) => error!("Could not record a play of track {}: {}", id, e),
+            }
+            Handled::Yes
+        } else if let Some(&(id, rating)) = cmd.get(SET_RATING) {
+            let mut db = data.db.write().unwrap();
+            match db.set_rating(id, rating).and_then(|()| db.track(id)) {
+                Ok(Some(track)) => data.main_tracklist_data.update_track(track),
+                Ok(None) => warn!("Rated track {} no longer exists", id),
+                Err(e) => {
+                    error!("Could not rate track {}: {}", id, e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if let Some(&rating) = cmd.get(RATE_SELECTION) {
+            let ratings: Vec<(i64, Option<i32>)> = data.main_tracklist_data.selection().iter()
+                .map(|track| (track.id(), rating))
+                .collect();
+            if !ratings.is_empty() {
+                data.rating_undo = Arc::new(rate_tracks(ctx, data, &ratings));
+            }
+            Handled::Yes
+        } else if cmd.is(UNDO_RATE_SELECTION) {
+            let previous = std::mem::replace(&mut data.rating_undo, Arc::new(Vec::new()));
+            if previous.is_empty() {
+                ctx.submit_command(notify(Severity::Info, "There's no rating change to undo."));
+            } else {
+                rate_tracks(ctx, data, &previous);
+            }
+            Handled::Yes
+        } else if cmd.is(EDIT_METADATA) {
+            if let Some(track) = data.main_tracklist_data.selection().first() {
+                data.metadata_form = MetadataForm::from_track(track);
+                ctx.new_window(WindowDesc::new(|| make_metadata_editor().lens(AppData::metadata_form))
+                    .title("Edit Metadata")
+                    .window_size((400., 370.)));
+            }
+            Handled::Yes
+        } else if let Some(&window) = cmd.get(SAVE_METADATA) {
+            let id = data.metadata_form.track_id();
+            let result = data.metadata_form.to_metadata()
+                .and_then(|metadata| data.db.write().unwrap()
+                    .update_track_metadata(id, &metadata)
+                    .map_err(|e| e.to_string()));
+
+            match result {
+                Ok(track) => {
+                    if let Some(track) = track {
+                        if let Err(e) = data.db.write().unwrap().write_tags_to_file(&track) {
+                            warn!("{}", e);
+                            ctx.submit_command(notify(Severity::Warning,
+                                                      format!("Saved to the library only. {}", e)));
+                        }
+                        data.main_tracklist_data.update_track(track);
+                        // It may have moved to another artist or album
+                        refresh_artists(data);
+                    }
+                    ctx.submit_command(Command::new(CLOSE_WINDOW, (), window));
+                }
+                Err(message) => ctx.submit_command(notify(Severity::Error, message)),
+            }
+            Handled::Yes
+        } else if cmd.is(REMOVE_SELECTION) {
+            let ids = data.main_tracklist_data.selection().iter().map(Track::id).collect();
+            remove_tracks(ctx, data, ids, FileRemoval::Keep);
+            Handled::Yes
+        } else if cmd.is(DELETE_SELECTION) {
+            let ids: Vec<i64> = data.main_tracklist_data.selection().iter().map(Track::id).collect();
+            if !ids.is_empty() {
+                data.pending_deletion = Arc::new(ids);
+                self.confirm_deletion(ctx, data);
+            }
+            Handled::Yes
+        } else if let Some(&window) = cmd.get(CONFIRM_DELETE) {
+            let ids = data.pending_deletion.to_vec();
+            data.pending_deletion = Arc::new(Vec::new());
+            remove_tracks(ctx, data, ids, FileRemoval::choose(true, self.delete_to_trash));
+            ctx.submit_command(Command::new(CLOSE_WINDOW, (), window));
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(FULL_RESCAN) {
+            let db = data.db.read().unwrap();
+            let library = match scannable_library(&db, id) {
+                Some(library) => library,
+                None => return Handled::Yes,
+            };
+
+            match db.rescan_loss(id) {
+                Ok(loss) if needs_rescan_confirmation(self.confirm_full_rescan, &loss) => {
+                    data.skip_rescan_confirmation = false;
+                    let message = rescan_message(library.name(), &loss);
+                    ctx.new_window(WindowDesc::new(move || {
+                            make_rescan_confirmation(id, message.clone())
+                        })
+                        .title("Full Rescan")
+                        .window_size((400., 150.)));
+                }
+                Ok(_) => self.full_rescan(ctx, library),
+                Err(e) => {
+                    error!("Could not check what rescanning library {} would lose: {}", id, e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if let Some(&(window, id)) = cmd.get(CONFIRM_FULL_RESCAN) {
+            let db = data.db.read().unwrap();
+            if data.skip_rescan_confirmation {
+                self.confirm_full_rescan = false;
+                if let Err(e) = save_confirm_full_rescan(&db, false) {
+                    error!("Could not save the rescan confirmation setting: {}", e);
+                }
+            }
+            if let Some(library) = scannable_library(&db, id) {
+                self.full_rescan(ctx, library);
+            }
+            ctx.submit_command(Command::new(CLOSE_WINDOW, (), window));
+            Handled::Yes
+        } else if let Some(&key) = cmd.get(FIND_DUPLICATES) {
+            ctx.submit_command(notify(Severity::Info, "Looking for duplicates…"));
+
+            // Comparing audio means decoding every track, so it gets its own thread and connection
+            let event_sink = ctx.get_external_handle();
+            thread::spawn(move || {
+                match Database::new().and_then(|mut db| db.find_duplicates(key)) {
+                    Ok(groups) => {
+                        event_sink.submit_command(DUPLICATES_FOUND, groups, Target::Auto).ok();
+                    }
+                    Err(e) => {
+                        error!("Could not look for duplicates: {}", e);
+                        event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()),
+                                                  Target::Auto).ok();
+                    }
+                }
+            });
+            Handled::Yes
+        } else if let Some(groups) = cmd.get(DUPLICATES_FOUND) {
+            data.duplicates = DuplicatesForm::new(groups.clone());
+            if data.duplicates.is_empty() {
+                ctx.submit_command(notify(Severity::Info, "No duplicates found."));
+            } else {
+                ctx.new_window(WindowDesc::new(|| make_duplicates_dialog().lens(AppData::duplicates))
+                    .title("Duplicates")
+                    .window_size((700., 500.)));
+            }
+            Handled::Yes
+        } else if let Some(&delete_files) = cmd.get(REMOVE_DUPLICATES) {
+            let ids = data.duplicates.picked();
+            if delete_files && !ids.is_empty() {
+                data.pending_deletion = Arc::new(ids);
+                self.confirm_deletion(ctx, data);
+            } else {
+                remove_tracks(ctx, data, ids, FileRemoval::Keep);
+            }
+            Handled::Yes
+        } else if let Some(folder) = cmd.get(NEW_LIBRARY_FOLDER) {
+            data.new_library = NewLibraryForm::new(folder.path());
+            ctx.new_window(WindowDesc::new(|| make_add_library_dialog().lens(AppData::new_library))
+                .title("Add Library")
+                .window_size((400., 140.)));
+            Handled::Yes
+        } else if let Some(&window) = cmd.get(ADD_LIBRARY) {
+            let path = data.new_library.path().to_string();
+            let name = data.new_library.name().to_string();
+            let mut db = data.db.write().unwrap();
+            match db.add_library(path, name) {
+                Ok(library) => {
+                    data.sidebar_libraries = sidebar_libraries(db.libraries().unwrap_or_default(),
+                                                               &data.sidebar_libraries);
+                    ctx.submit_command(Command::new(CLOSE_WINDOW, (), window));
+                    ctx.submit_command(notify(Severity::Info, format!("Scanning {}…", library.name())));
+
+                    watch_library(&mut self.watchers, library.clone(), self.scan_options.clone(),
+                                  ctx.get_external_handle());
+
+                    // Scanning can take a while, so it gets its own thread and connection
+                    let event_sink = ctx.get_external_handle();
+                    let options = self.scan_options.clone();
+                    thread::spawn(move || scan_libraries(vec![library], true, options, event_sink));
+                }
+                Err(e) => ctx.submit_command(notify(Severity::Error, e.to_string())),
+            }
+            Handled::Yes
+        } else if cmd.is(SHOW_QUEUE) {
+            let rows = lens::Map::new(|data: &AppData| queue_rows(&data.queue, data.paused),
+                                      |_data: &mut AppData, _rows| ());
+            ctx.new_window(WindowDesc::new(move || make_queue_view().lens(rows))
+                .title("Queue")
+                .window_size((400., 500.)));
+            Handled::Yes
+        } else if let Some(&(from, to)) = cmd.get(MOVE_QUEUE_ENTRY) {
+            data.queue.move_entry(from, to);
+            prefetch_next(data);
+            Handled::Yes
+        } else if let Some(&index) = cmd.get(INSERT_FILE_INTO_QUEUE) {
+            self.pick_for_queue(ctx, target, index, FileDialogOptions::new());
+            Handled::Yes
+        } else if let Some(&index) = cmd.get(INSERT_FOLDER_INTO_QUEUE) {
+            self.pick_for_queue(ctx, target, index, FileDialogOptions::new().select_directories());
+            Handled::Yes
+        } else if let Some(file) = cmd.get(INSERT_CHOSEN_INTO_QUEUE) {
+            let mut db = data.db.write().unwrap();
+            let tracks = expand_folders(&[file.path().to_path_buf()]).iter()
+                .flat_map(|path| file_tracks(&mut db, path))
+                .collect();
+            data.queue.insert(self.queue_insert_at, tracks);
+            prefetch_next(data);
+            Handled::Yes
+        } else if cmd.is(MANAGE_LIBRARIES) {
+            data.libraries = library_entries(data.db.read().unwrap().libraries().unwrap_or_default());
+            ctx.new_window(WindowDesc::new(|| make_library_manager().lens(AppData::libraries))
+                .title("Libraries")
+                .window_size((500., 300.)));
+            Handled::Yes
+        } else if let Some((id, name)) = cmd.get(RENAME_LIBRARY) {
+            let mut db = data.db.write().unwrap();
+            if let Err(e) = db.rename_library(*id, name.clone()) {
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+            }
+            let libraries = db.libraries().unwrap_or_default();
+            data.sidebar_libraries = sidebar_libraries(libraries.clone(), &data.sidebar_libraries);
+            data.libraries = library_entries(libraries);
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(REMOVE_LIBRARY) {
+            let mut db = data.db.write().unwrap();
+            match db.remove_library(id) {
+                Ok(()) => {
+                    self.watchers.remove(&id);
+                    data.main_tracklist_data.remove_library(id);
+                }
+                Err(e) => {
+                    error!("Could not remove library {}: {}", id, e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            let libraries = db.libraries().unwrap_or_default();
+            data.sidebar_libraries = sidebar_libraries(libraries.clone(), &data.sidebar_libraries);
+            data.libraries = library_entries(libraries);
+            drop(db);
+
+            if data.view == View::SingleLibrary(id) {
+                show_view(ctx, data, View::Library);
+            }
+            refresh_artists(data);
+            Handled::Yes
+        } else if let Some(progress) = cmd.get(SCAN_PROGRESS) {
+            data.scan_progress = progress.clone();
+            Handled::Yes
+        } else if let Some((id, event)) = cmd.get(LIBRARY_SCAN_STATUS) {
+            update_scan_status(&mut data.sidebar_libraries, *id, event);
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(LIBRARY_CLEARED) {
+            if data.view.includes_library(id) {
+                data.main_tracklist_data.remove_library(id);
+            }
+            Handled::Yes
+        } else if let Some(tracks) = cmd.get(TRACKS_FOUND) {
+            let view = data.view;
+            let tracks: Vec<Track> = tracks.iter()
+                .filter(|track| view.includes_library(track.library_id()))
+                .cloned()
+                .collect();
+            if !tracks.is_empty() {
+                data.main_tracklist_data.add_tracks(tracks);
+            }
+            Handled::Yes
+        } else if let Some((library, tracks, added)) = cmd.get(LIBRARY_SCANNED) {
+            if *added > 0 {
+                ctx.submit_command(notify(Severity::Info, format!("Added {} tracks from {}.",
+                                                                  added, library.name())));
+            }
+            // The scan may also have removed or moved tracks, so swap in what's really there
+            if data.view.includes_library(library.id()) {
+                data.main_tracklist_data.remove_library(library.id());
+                data.main_tracklist_data.add_tracks(tracks.clone());
+            }
+            refresh_artists(data);
+            Handled::Yes
+        } else if let Some(changes) = cmd.get(LIBRARY_CHANGED) {
+            let view = data.view;
+            let added: Vec<Track> = changes.added.iter()
+                .filter(|track| view.includes_library(track.library_id()))
+                .cloned()
+                .collect();
+            if !added.is_empty() {
+                data.main_tracklist_data.add_tracks(added);
+            }
+            for track in &changes.changed {
+                data.main_tracklist_data.replace_track(track.clone());
+            }
+            if !changes.removed.is_empty() {
+                let removed: HashSet<i64> = changes.removed.iter().copied().collect();
+                data.main_tracklist_data.remove_tracks(&removed);
+                data.duplicates.remove_tracks(&removed);
+            }
+            refresh_artists(data);
+            Handled::Yes
+        } else if cmd.is(TOGGLE_ALBUM_GROUPS) {
+            let group_by_album = !data.main_tracklist_data.group_by_album();
+            data.main_tracklist_data.set_group_by_album(group_by_album);
+            Handled::Yes
+        } else if cmd.is(TOGGLE_STRICT_SORT) {
+            let strict_sort = !data.main_tracklist_data.strict_sort();
+            data.main_tracklist_data.set_strict_sort(strict_sort);
+            if let Err(e) = save_strict_sort(&data.db.read().unwrap(), strict_sort) {
+                warn!("Could not save the strict sort setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_REDUCE_MOTION) {
+            data.reduce_motion = !data.reduce_motion;
+            if let Err(e) = save_reduce_motion(&data.db.read().unwrap(), data.reduce_motion) {
+                warn!("Could not save the reduce motion setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_SEARCH_EVERYWHERE) {
+            let search_everywhere = !data.main_tracklist_data.search_everywhere();
+            data.main_tracklist_data.set_search_everywhere(search_everywhere);
+            if let Err(e) = save_search_everywhere(&data.db.read().unwrap(), search_everywhere) {
+                warn!("Could not save the search setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_REPLAY_GAIN) {
+            data.replay_gain = !data.replay_gain;
+            data.sink.read().unwrap().set_volume(playback_gain(data, data.queue.current()));
+            if let Err(e) = save_replay_gain(&data.db.read().unwrap(), data.replay_gain) {
+                warn!("Could not save the ReplayGain setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_TRIM_SILENCE) {
+            // From the next track on, since the current one's already been cut to length
+            data.trim_silence = !data.trim_silence;
+            if let Err(e) = save_trim_silence(&data.db.read().unwrap(), data.trim_silence) {
+                warn!("Could not save the silence trimming setting: {}", e);
+            }
+            Handled::Yes
+        } else if let Some(&normalization) = cmd.get(SET_NORMALIZATION) {
+            data.normalization = normalization;
+            data.replay_gain = true;
+            data.sink.read().unwrap().set_volume(playback_gain(data, data.queue.current()));
+            let db = data.db.read().unwrap();
+            if let Err(e) = save_normalization(&db, normalization)
+                .and_then(|()| save_replay_gain(&db, true)) {
+                warn!("Could not save the normalization setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(SHUFFLE_QUEUE) {
+            let weights = if data.smart_shuffle {
+                self.shuffle_weights
+            } else {
+                ShuffleWeights::UNIFORM
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs() as i64);
+            let rng = &mut self.rng;
+            data.queue.shuffle_upcoming(|tracks| weighted_shuffle(tracks, &weights, now, rng));
+            prefetch_next(data);
+            Handled::Yes
+        } else if cmd.is(TOGGLE_ALWAYS_ON_TOP) {
+            data.always_on_top = !data.always_on_top;
+            if let Err(e) = set_always_on_top(data.always_on_top) {
+                warn!("Could not keep the window on top: {}", e);
+            }
+            if let Err(e) = save_always_on_top(&data.db.read().unwrap(), data.always_on_top) {
+                warn!("Could not save the always on top setting: {}", e);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_SMART_SHUFFLE) {
+            data.smart_shuffle = !data.smart_shuffle;
+            if let Err(e) = save_smart_shuffle(&data.db.read().unwrap(), data.smart_shuffle) {
+                warn!("Could not save the smart shuffle setting: {}", e);
+            }
+            Handled::Yes
+        } else if let Some(&view) = cmd.get(SHOW_VIEW) {
+            show_view(ctx, data, view);
+            Handled::Yes
+        } else if let Some(scope) = cmd.get(SHOW_SCOPE) {
+            // Artists and albums are parts of the library, not of a playlist
+            if !matches!(data.view, View::Library | View::SingleLibrary(_)) {
+                show_view(ctx, data, View::Library);
+            }
+            data.main_tracklist_data.set_scope(Some(scope.clone()));
+            Handled::Yes
+        } else if let Some(artist) = cmd.get(TOGGLE_ARTIST_NODE) {
+            let result = toggle_artist(&data.db.read().unwrap(), &data.grouping,
+                                       &mut data.artists, artist);
+            if let Err(e) = result {
+                error!("Could not list the albums of {:?}: {}", artist, e);
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+            }
+            Handled::Yes
+        } else if let Some((artist, album)) = cmd.get(TOGGLE_ALBUM_NODE) {
+            let result = toggle_album(&data.db.read().unwrap(), &mut data.artists, artist, album);
+            if let Err(e) = result {
+                error!("Could not list the tracks of {:?}: {}", album, e);
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+            }
+            Handled::Yes
+        } else if cmd.is(NEW_PLAYLIST) {
+            let mut db = data.db.write().unwrap();
+            let created = db.unique_playlist_name("New Playlist")
+                .and_then(|name| db.create_playlist(name));
+            match created {
+                Ok(id) => {
+                    data.playlists = playlist_entries(db.list_playlists().unwrap_or_default());
+                    drop(db);
+                    show_view(ctx, data, View::Playlist(id));
+                }
+                Err(e) => {
+                    error!("Could not create a playlist: {}", e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(NEW_SMART_PLAYLIST) {
+            match data.db.read().unwrap().unique_playlist_name("New Smart Playlist") {
+                Ok(name) => {
+                    data.smart_playlist = SmartPlaylistForm::new(name);
+                    open_smart_playlist_dialog(ctx);
+                }
+                Err(e) => ctx.submit_command(notify(Severity::Error, e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(EDIT_SMART_PLAYLIST) {
+            let db = data.db.read().unwrap();
+            let playlist = db.list_playlists()
+                .map(|playlists| playlists.into_iter().find(|playlist| playlist.id() == id))
+                .and_then(|playlist| Ok((playlist, db.playlist_criteria(id)?)));
+            match playlist {
+                Ok((Some(playlist), Some(criteria))) => {
+                    data.smart_playlist = SmartPlaylistForm::edit(id, playlist.name().clone(),
+                                                                  &criteria);
+                    open_smart_playlist_dialog(ctx);
+                }
+                Ok(_) => warn!("Playlist {} isn't a smart playlist", id),
+                Err(e) => ctx.submit_command(notify(Severity::Error, e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(&window) = cmd.get(SAVE_SMART_PLAYLIST) {
+            let criteria = match data.smart_playlist.criteria() {
+                Ok(criteria) => criteria,
+                Err(message) => {
+                    ctx.submit_command(notify(Severity::Error, message));
+                    return Handled::Yes;
+                }
+            };
+
+            let name = data.smart_playlist.name().to_string();
+            let mut db = data.db.write().unwrap();
+            let saved = match data.smart_playlist.playlist() {
+                Some(id) => db.update_smart_playlist(id, name, &criteria).map(|()| id),
+                None => db.create_smart_playlist(name, &criteria),
+            };
+            match saved {
+                Ok(id) => {
+                    data.playlists = playlist_entries(db.list_playlists().unwrap_or_default());
+                    drop(db);
+                    show_view(ctx, data, View::Playlist(id));
+                    ctx.submit_command(Command::new(CLOSE_WINDOW, (), window));
+                }
+                Err(e) => {
+                    error!("Could not save a smart playlist: {}", e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(ADD_SELECTION_TO_PLAYLIST) {
+            let ids: Vec<i64> = data.main_tracklist_data.selection().iter().map(Track::id).collect();
+            let result = data.db.write().unwrap().add_to_playlist(id, &ids);
+            match result {
+                Ok(()) if data.view == View::Playlist(id) => show_view(ctx, data, View::Playlist(id)),
+                Ok(()) => (),
+                Err(e) => {
+                    error!("Could not add to playlist {}: {}", id, e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(REMOVE_SELECTION_FROM_PLAYLIST) {
+            if let Some(playlist) = data.main_tracklist_data.playlist() {
+                let ids: Vec<i64> = data.main_tracklist_data.selection().iter().map(Track::id).collect();
+                let removed = data.db.write().unwrap().remove_from_playlist(playlist, &ids);
+                match removed {
+                    Ok(()) => data.main_tracklist_data.remove_tracks(&ids.into_iter().collect()),
+                    Err(e) => {
+                        error!("Could not remove from playlist {}: {}", playlist, e);
+                        ctx.submit_command(notify(Severity::Error, e.to_string()));
+                    }
+                }
+            }
+            Handled::Yes
+        } else if let Some((id, ids)) = cmd.get(REORDER_PLAYLIST) {
+            let result = data.db.write().unwrap().reorder_playlist(*id, ids);
+            if let Err(e) = result {
+                error!("Could not reorder playlist {}: {}", id, e);
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+                // Put back the order that's really saved
+                show_view(ctx, data, View::Playlist(*id));
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(EXPORT_PLAYLIST) {
+            match data.view {
+                View::Playlist(id) => {
+                    let result = File::create(file.path())
+                        .map_err(DatabaseError::from)
+                        .and_then(|writer| data.db.read().unwrap()
+                            .export_playlist_m3u(id, BufWriter::new(writer)));
+                    match result {
+                        Ok(()) => ctx.submit_command(notify(Severity::Info,
+                                                            format!("Exported to {}.", file.path().display()))),
+                        Err(e) => {
+                            error!("Could not export playlist {}: {}", id, e);
+                            ctx.submit_command(notify(Severity::Error, e.to_string()));
+                        }
+                    }
+                }
+                View::Library | View::SingleLibrary(_) | View::MostPlayed
+                | View::RecentlyPlayed => {
+                    ctx.submit_command(notify(Severity::Warning, "Open a playlist to export it."));
+                }
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(IMPORT_PLAYLIST) {
+            let mut db = data.db.write().unwrap();
+            match db.import_playlist_m3u(file.path()) {
+                Ok(id) => {
+                    data.playlists = playlist_entries(db.list_playlists().unwrap_or_default());
+                    drop(db);
+                    show_view(ctx, data, View::Playlist(id));
+                }
+                Err(e) => {
+                    error!("Could not import {}: {}", file.path().display(), e);
+                    ctx.submit_command(notify(Severity::Error, e.to_string()));
+                }
+            }
+            Handled::Yes
+        } else if let Some(&id) = cmd.get(DELETE_PLAYLIST) {
+            let mut db = data.db.write().unwrap();
+            if let Err(e) = db.delete_playlist(id) {
+                error!("Could not delete playlist {}: {}", id, e);
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+            }
+            data.playlists = playlist_entries(db.list_playlists().unwrap_or_default());
+            drop(db);
+
+            if data.view == View::Playlist(id) {
+                show_view(ctx, data, View::Library);
+            }
+            Handled::Yes
+        } else if cmd.is(IMPORT_SIDECARS) {
+            // Walking every library folder can take a while
+            let event_sink = ctx.get_external_handle();
+            thread::spawn(move || import_all_sidecars(event_sink));
+            Handled::Yes
+        } else if let Some((tracks, skipped)) = cmd.get(SIDECARS_IMPORTED) {
+            for track in tracks {
+                data.main_tracklist_data.update_track(track.clone());
+            }
+            ctx.submit_command(notify(
+                if *skipped == 0 { Severity::Info } else { Severity::Warning },
+                format!("Imported ratings for {} tracks, skipped {} sidecars.",
+                        tracks.len(), skipped)));
+            Handled::Yes
+        } else if cmd.is(ANALYZE_TRACKS) {
+            let event_sink = ctx.get_external_handle();
+            let threshold = self.silence_threshold;
+            thread::spawn(move || analyze_tracks(threshold, event_sink));
+            Handled::Yes
+        } else if let Some((tracks, analyzed, failed)) = cmd.get(TRACKS_ANALYZED) {
+            data.main_tracklist_data.update_tracks(tracks.clone());
+            data.queue.update_tracks(tracks);
+            data.sink.read().unwrap().set_volume(playback_gain(data, data.queue.current()));
+            ctx.submit_command(notify(
+                if *failed == 0 { Severity::Info } else { Severity::Warning },
+                format!("Analyzed {} tracks, {} couldn't be analyzed.", analyzed, failed)));
+            Handled::Yes
+        } else if cmd.is(VACUUM_DATABASE) {
+            // VACUUM can take a while, so it gets its own thread and connection
+            let event_sink = ctx.get_external_handle();
+            thread::spawn(move || {
+                let (severity, message) = match Database::new().and_then(|db| db.vacuum()) {
+                    Ok(()) => (Severity::Info, "Database vacuumed.".to_string()),
+                    Err(e) => {
+                        error!("Could not vacuum the database: {}", e);
+                        (Severity::Error, e.to_string())
+                    }
+                };
+                event_sink.submit_command(NOTIFY, (severity, message), Target::Auto).ok();
+            });
+            Handled::Yes
+        } else {
+            Handled::No
+        }
+    }
+
+    fn window_added(&mut self, id: WindowId, data: &mut AppData, _env: &Env,
+                    _ctx: &mut DelegateCtx) {
+        let on_top = startup_on_top(data.always_on_top, on_top::SUPPORTED);
+        if id == self.main_window && on_top == StartupOnTop::Apply {
+            if let Err(e) = set_always_on_top(true) {
+                warn!("Could not keep the window on top: {}", e);
+            }
+        }
+    }
+
+    fn window_removed(&mut self, id: WindowId, data: &mut AppData, _env: &Env,
+                      _ctx: &mut DelegateCtx) {
+        if id == self.main_window {
+            save_queue(data);
+
+            if let Err(e) = save_volume(&data.db.read().unwrap(), data.volume) {
+                warn!("Could not save the volume: {}", e);
+            }
+        }
+    }
+}
+
+/// Whether a volume that changed at `changed` has stayed put long enough to save, as of `now`.
+fn volume_save_due(changed: Option<Instant>, now: Instant) -> bool {
+    changed.map_or(false, |changed| now.duration_since(changed) >= VOLUME_SAVE_DELAY)
+}
+
+/// Save the queue, and how far into the current track playback is, to pick up from next time.
+/// Tracks that aren't in the database, like those in archives, are left out.
+fn save_queue(data: &AppData) {
+    let (ids, current) = saved_entries(data.queue.tracks(), data.queue.current_index());
+    let current = current.map(|index| (index, data.position.elapsed(Instant::now())));
+
+    if let Err(e) = data.db.write().unwrap().save_queue(&ids, current) {
+        warn!("Could not save the queue: {}", e);
+    }
+}
+
+/// The ids of the queue's `tracks` that are in the database, and where `current` is among them,
+/// if it's one of them.
+fn saved_entries(tracks: &[Track], current: Option<usize>) -> (Vec<i64>, Option<usize>) {
+    let current = current
+        .filter(|&index| tracks.get(index).map_or(false, |track| !track.is_ephemeral()))
+        .map(|index| tracks[..index].iter().filter(|track| !track.is_ephemeral()).count());
+    let ids = tracks.iter().filter(|track| !track.is_ephemeral()).map(Track::id).collect();
+
+    (ids, current)
+}
+
+fn open_smart_playlist_dialog(ctx: &mut DelegateCtx) {
+    ctx.new_window(WindowDesc::new(|| make_smart_playlist_dialog().lens(AppData::smart_playlist))
+        .title("Smart Playlist")
+        .window_size((460., 180.)));
+}
+
+/// Apply the JSON sidecars in every library. This reads every file in every library folder, so
+/// it runs on its own thread with its own connection.
+fn import_all_sidecars(event_sink: ExtEventSink) {
+    let mut db = match Database::new() {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Could not open the database to import sidecars: {}", e);
+            event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()), Target::Auto).ok();
+            return;
+        }
+    };
+
+    let (mut rated, mut skipped) = (Vec::new(), 0);
+    for library in db.libraries().unwrap_or_default() {
+        if let Some(path) = library.path() {
+            match import_sidecars(&mut db, Path::new(path), &JsonSidecar) {
+                Ok(report) => {
+                    for sidecar in &report.unmatched {
+                        warn!("No track for sidecar {}", sidecar.display());
+                    }
+                    skipped += report.unmatched.len() + report.failed.len();
+                    rated.extend(report.applied.iter()
+                        .filter_map(|&id| db.track(id).ok().flatten()));
+                }
+                Err(e) => {
+                    error!("Could not import sidecars from {}: {}", path, e);
+                    event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()), Target::Auto)
+                        .ok();
+                }
+            }
+        }
+    }
+
+    if event_sink.submit_command(SIDECARS_IMPORTED, (rated, skipped), Target::Auto).is_err() {
+        warn!("Could not report the sidecar import, the app has already closed");
+    }
+}
+
+/// Measure the loudness and silence of every track that hasn't been, or whose silence was found
+/// at another threshold, then work out their albums' loudness again. That decodes each of them
+/// from start to end, so it runs on its own thread with its own connection.
+fn analyze_tracks(silence_threshold: f64, event_sink: ExtEventSink) {
+    let report_error = |e: DatabaseError| {
+        error!("Could not analyze tracks: {}", e);
+        event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()), Target::Auto).ok();
+    };
+    let (mut db, unanalyzed) = match Database::new().and_then(|db| {
+        db.unanalyzed_tracks(silence_threshold).map(|unanalyzed| (db, unanalyzed))
+    }) {
+        Ok(found) => found,
+        Err(e) => return report_error(e),
+    };
+
+    let (total, mut failed) = (unanalyzed.len(), 0);
+    for (done, (id, path)) in unanalyzed.iter().enumerate() {
+        match analyze_track(path, silence_threshold) {
+            Ok((loudness, sound)) => {
+                if loudness.is_none() {
+                    info!("{} is too short or quiet to measure the loudness of", path);
+                }
+                let saved = loudness.map_or(Ok(()), |loudness| db.set_loudness(*id, loudness))
+                    .and_then(|()| db.set_sound(*id, silence_threshold, sound));
+                if let Err(e) = saved {
+                    return report_error(e);
+                }
+            }
+            Err(e) => {
+                warn!("Could not analyze {}: {}", path, e);
+                failed += 1;
+            }
+        }
+        // Each track takes a while to decode, so every one is reported
+        event_sink.submit_command(SCAN_PROGRESS,
+                                  Some(("Analyzing tracks".to_string(), done + 1, total)),
+                                  Target::Auto)
+            .ok();
+    }
+    event_sink.submit_command(SCAN_PROGRESS, None, Target::Auto).ok();
+
+    let analyzed = match db.update_album_loudness().and_then(|()| db.dump_all_tracks()) {
+        Ok(tracks) => tracks.into_iter()
+            .filter(|track| track.loudness().is_some() || track.sound().is_some())
+            .collect(),
+        Err(e) => return report_error(e),
+    };
+    if event_sink.submit_command(TRACKS_ANALYZED, (analyzed, total - failed, failed),
+                                 Target::Auto).is_err() {
+        warn!("Could not report the tracks analyzed, the app has already closed");
+    }
+}
+
+/// Decode the whole of the track at `path` to find its integrated loudness in LUFS, and where
+/// its sound starts and ends between silence at `silence_threshold` dB. The loudness is None
+/// if it's too short or quiet to measure, and the sound if it's silent throughout.
+fn analyze_track(path: &str, silence_threshold: f64)
+                 -> Result<(Option<f64>, Option<(Duration, Duration)>), PlaybackError> {
+    let mut source = open_track(path)?;
+    let (channels, rate) = (source.channels(), source.sample_rate());
+    let mut meter = LoudnessMeter::new(channels, rate);
+    let mut silence = SilenceDetector::new(channels, rate, silence_threshold);
+
+    let mut chunk = Vec::with_capacity(ANALYSIS_CHUNK);
+    loop {
+        chunk.clear();
+        chunk.extend(source.by_ref().take(ANALYSIS_CHUNK));
+        if chunk.is_empty() {
+            break;
+        }
+        meter.add_samples(chunk.iter().copied());
+        silence.add_samples(chunk.iter().copied());
+    }
+
+    Ok((meter.integrated(), silence.sound()))
+}
+
+/// Look for tracks whose files were modified outside of mus. This stats every track, so it runs
+/// on its own thread with its own connection.
+fn check_for_stale_tracks(event_sink: ExtEventSink) {
+    let stale = match Database::new().and_then(|db| db.stale_tracks()) {
+        Ok(stale) => stale,
+        Err(e) => {
+            error!("Could not check for stale tracks: {}", e);
+            return;
+        }
+    };
+
+    if !stale.is_empty() {
+        info!("{} tracks have been modified since they were scanned", stale.len());
+        if event_sink.submit_command(STALE_TRACKS_FOUND, stale, Target::Auto).is_err() {
+            warn!("Could not report stale tracks, the app has already closed");
+        }
+    }
+}
+
+/// A view's tracks, shown with its own columns if it has them.
+fn load_view(db: &Database, view: View) -> Result<TrackListData, DatabaseError> {
+    let mut tracklist_data = match view {
+        View::Library           => TrackListData::new(db.dump_all_tracks()?),
+        View::SingleLibrary(id) => TrackListData::new(db.library_tracks(id)?),
+        View::MostPlayed        => TrackListData::new(db.most_played(PLAY_HISTORY_LENGTH)?),
+        View::RecentlyPlayed    => TrackListData::new(db.recently_played(PLAY_HISTORY_LENGTH)?),
+        // Smart playlists are worked out afresh every time, to keep up with tag changes
+        View::Playlist(id)      => match db.playlist_criteria(id)? {
+            Some(criteria) => TrackListData::new(db.query_tracks(&criteria)?),
+            None => TrackListData::for_playlist(id, db.playlist_tracks(id)?),
+        },
+    };
+
+    tracklist_data.set_columns(load_view_columns(db, view)?);
+    Ok(tracklist_data)
+}
+
+/// Load a view's tracks into the main track list, and remember it for next time.
+fn show_view(ctx: &mut DelegateCtx, data: &mut AppData, view: View) {
+    let db = data.db.read().unwrap();
+    match load_view(&db, view) {
+        Ok(mut tracklist_data) => {
+            tracklist_data.set_group_by_album(data.main_tracklist_data.group_by_album());
+            tracklist_data.set_strict_sort(data.main_tracklist_data.strict_sort());
+            tracklist_data.set_search_everywhere(data.main_tracklist_data.search_everywhere());
+            if data.remember_view_positions {
+                Arc::make_mut(&mut data.view_positions)
+                    .insert(data.view, data.main_tracklist_data.position());
+                if let Some(&position) = data.view_positions.get(&view) {
+                    tracklist_data.restore_position(position);
+                }
+            }
+            data.main_tracklist_data = tracklist_data;
+            data.view = view;
+
+            if let Err(e) = save_last_view(&db, view) {
+                warn!("Could not save the open view: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("Could not load {:?}: {}", view, e);
+            ctx.submit_command(notify(Severity::Error, e.to_string()));
+        }
+    }
+}
+
+/// Start following a library's folder for changes, logging why if we can't.
+fn watch_library(watchers: &mut HashMap<i64, LibraryWatcher>, library: Library,
+                 options: ScanOptions, event_sink: ExtEventSink) {
+    let id = library.id();
+    let name = library.name().clone();
+    match LibraryWatcher::new(library, options, event_sink) {
+        Ok(watcher) => {
+            watchers.insert(id, watcher);
+        }
+        Err(e) => warn!("Could not watch {} for changes: {}", name, e),
+    }
+}
+
+/// Scan libraries one after another, streaming what's found back to the UI. Scanning can take a
+/// while, so this runs on its own thread, with its own connection.
+fn scan_libraries(libraries: Vec<Library>, full_rescan: bool, options: ScanOptions,
+                  event_sink: ExtEventSink) {
+    let mut db = match Database::new() {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Could not open the database to scan: {}", e);
+            event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()), Target::Auto).ok();
+            return;
+        }
+    };
+
+    for library in libraries {
+        let id = library.id();
+        let name = library.name().clone();
+        event_sink.submit_command(LIBRARY_SCAN_STATUS, (id, ScanEvent::Started), Target::Auto).ok();
+
+        if full_rescan {
+            event_sink.submit_command(LIBRARY_CLEARED, id, Target::Auto).ok();
+        }
+
+        let mut batch = Vec::new();
+        let mut added = 0;
+        let mut report_progress = |scanned: usize, total: usize| {
+            // Every file would flood the event loop
+            if scanned % SCAN_PROGRESS_INTERVAL == 0 || scanned == total {
+                event_sink.submit_command(SCAN_PROGRESS,
+                                          Some((format!("Scanning {}", name), scanned, total)),
+                                          Target::Auto)
+                    .ok();
+                event_sink.submit_command(LIBRARY_SCAN_STATUS,
+                                          (id, ScanEvent::Progress(scanned, total)), Target::Auto)
+                    .ok();
+            }
+        };
+        let mut report_track = |track: Track| {
+            added += 1;
+            batch.push(track);
+            if batch.len() >= SCAN_PROGRESS_INTERVAL {
+                event_sink.submit_command(TRACKS_FOUND, std::mem::take(&mut batch), Target::Auto)
+                    .ok();
+            }
+        };
+        let result = db.scan_library(library.clone(), full_rescan, &options,
+                                     Some(&mut report_progress), Some(&mut report_track))
+            .and_then(|_missing| db.library_tracks(id));
+
+        let (event, sent) = match result {
+            Ok(tracks) => (ScanEvent::Finished,
+                           event_sink.submit_command(LIBRARY_SCANNED, (library, tracks, added),
+                                                     Target::Auto)),
+            Err(e) => {
+                error!("Could not scan library {}: {}", name, e);
+                (ScanEvent::Failed(e.to_string()),
+                 event_sink.submit_command(NOTIFY, (Severity::Error, e.to_string()), Target::Auto))
+            }
+        };
+        event_sink.submit_command(LIBRARY_SCAN_STATUS, (id, event), Target::Auto).ok();
+
+        if sent.is_err() {
+            warn!("Could not report the scan of {}, the app has already closed", name);
+            return;
+        }
+    }
+
+    event_sink.submit_command(SCAN_PROGRESS, None, Target::Auto).ok();
+}
+
+/// Set several tracks' ratings at once, returning what they were before.
+fn rate_tracks(ctx: &mut DelegateCtx, data: &mut AppData,
+               ratings: &[(i64, Option<i32>)]) -> Vec<(i64, Option<i32>)> {
+    let mut db = data.db.write().unwrap();
+    let previous = match db.set_ratings(ratings) {
+        Ok(previous) => previous,
+        Err(e) => {
+            error!("Could not rate {} tracks: {}", ratings.len(), e);
+            ctx.submit_command(notify(Severity::Error, e.to_string()));
+            return Vec::new();
+        }
+    };
+
+    for &(id, _) in &previous {
+        match db.track(id) {
+            Ok(Some(track)) => data.main_tracklist_data.update_track(track),
+            Ok(None) => warn!("Rated track {} no longer exists", id),
+            Err(e) => error!("Could not reload rated track {}: {}", id, e),
+        }
+    }
+
+    previous
+}
+
+/// Remove tracks from the library, and the main track list, doing `removal` to their files.
+fn remove_tracks(ctx: &mut DelegateCtx, data: &mut AppData, ids: Vec<i64>,
+                 removal: FileRemoval) {
+    let mut db = data.db.write().unwrap();
+    let mut removed = HashSet::new();
+
+    for id in ids {
+        match db.remove_track(id, removal) {
+            Ok(()) => { removed.insert(id); }
+            Err(e) => {
+                error!("Could not remove track {}: {}", id, e);
+                ctx.submit_command(notify(Severity::Error, e.to_string()));
+            }
+        }
+    }
+
+    data.main_tracklist_data.remove_tracks(&removed);
+    data.duplicates.remove_tracks(&removed);
+    drop(db);
+    refresh_artists(data);
+}
+
+/// Re-read the artist tree after tracks were added, removed or retagged.
+fn refresh_artists(data: &mut AppData) {
+    let artists = refresh_artist_tree(&data.db.read().unwrap(), &data.grouping, &data.artists);
+    match artists {
+        Ok(artists) => data.artists = artists,
+        Err(e) => warn!("Could not refresh the artist tree: {}", e),
+    }
+}
+
+/// Tell the app whenever the sink runs dry, so the queue can move on.
+fn watch_playback(sink: Arc<RwLock<Sink>>, event_sink: ExtEventSink) {
+    let mut was_playing = false;
+
+    loop {
+        thread::sleep(PLAYBACK_POLL);
+
+        let playing = !sink.read().unwrap().empty();
+        if was_playing && !playing
+            && event_sink.submit_command(TRACK_FINISHED, (), Target::Auto).is_err() {
+            // The app has closed
+            return;
+        }
+        was_playing = playing;
+    }
+}
+
+/// Tell the app when output devices are plugged in or unplugged, e.g. a USB DAC.
+fn watch_output_devices(mut devices: Vec<String>, event_sink: ExtEventSink) {
+    loop {
+        thread::sleep(DEVICE_POLL);
+
+        let current = output_devices();
+        if current != devices {
+            let sent = event_sink.submit_command(OUTPUT_DEVICES_CHANGED, current.clone(),
+                                                 Target::Auto);
+            if sent.is_err() {
+                // The app has closed
+                return;
+            }
+            devices = current;
+        }
+    }
+}
+
+/// Play the queue's current track, or stop if there isn't one, fading out of whatever was
+/// playing over `fade`. Tracks that can't be played, e.g. because their files have been moved
+/// since the last scan, are skipped.
+fn play_current(ctx: &mut DelegateCtx, data: &mut AppData, fade: Duration) {
+    loop {
+        // The new sink starts out playing
+        data.paused = false;
+        data.position = PlaybackPosition::default();
+        data.play_recorded = false;
+        let track = data.queue.current().cloned();
+        data.art = track.as_ref()
+            .and_then(|track| data.art_cache.get(&data.db.read().unwrap(), track));
+
+        let sound = track.as_ref().and_then(|track| trimmed_sound(data, track));
+        let start = sound.map_or(Duration::default(), |(start, _)| start);
+        let error = match play(data, track.as_ref(), start, fade) {
+            Ok(()) => {
+                if let Some(track) = track {
+                    data.position = PlaybackPosition::start(track.length(), start, Instant::now())
+                        .ending_at(sound.map(|(_, end)| end));
+                }
+                prefetch_next(data);
+                return;
+            }
+            Err(e) => e,
+        };
+
+        let track = match track {
+            Some(track) => track,
+            None => {
+                error!("Could not stop playback: {}", error);
+                return;
+            }
+        };
+
+        report_playback_error(ctx, &track, &error.to_string());
+        if !advances_after(&error) {
+            // The output's fault rather than the track's, so it isn't noted against the track
+            return;
+        }
+        record_playback_error(data, &track, PlaybackFailure::Open, &error.to_string());
+        data.queue.play_next();
+    }
+}
+
+/// Whether to go on to the next track after the current one couldn't be played. Not without an
+/// output, since the next track won't play either.
+fn advances_after(error: &PlaybackError) -> bool {
+    !matches!(error, PlaybackError::Output(_))
+}
+
+/// Where the current track stopped, if it stopped by itself well short of its end. Decoders
+/// just end a track they can't decode any further, so that's how a damaged file shows up.
+fn failed_partway(position: &PlaybackPosition, now: Instant) -> Option<Duration> {
+    let remaining = position.remaining(now)?;
+    if remaining > EARLY_END_TOLERANCE { Some(position.elapsed(now)) } else { None }
+}
+
+/// Log and show why `track` couldn't be played.
+fn report_playback_error(ctx: &mut DelegateCtx, track: &Track, reason: &str) {
+    error!("Could not play {}: {}", track.path(), reason);
+    ctx.submit_command(notify(Severity::Warning,
+                              format!("Could not play {}: {}.", track.path(), reason)));
+}
+
+/// Note against `track` that it couldn't be played, and why.
+fn record_playback_error(data: &AppData, track: &Track, failure: PlaybackFailure, reason: &str) {
+    if track.is_ephemeral() {
+        return;
+    }
+    if let Err(e) = data.db.write().unwrap().record_playback_error(track.id(), failure, reason) {
+        warn!("Could not record that {} failed to play: {}", track.path(), e);
+    }
+}
+
+/// Reopen the output for `data.output_device`, carrying on with the current track from where
+/// it was. The sink belongs to the old output, so it has to be replaced along with it.
+fn switch_output(ctx: &mut DelegateCtx, data: &mut AppData) {
+    let (stream, handle) = match open_output(data.output_device.as_deref()) {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Could not open an output device: {}", e);
+            ctx.submit_command(notify(Severity::Error, e.to_string()));
+            return;
+        }
+    };
+
+    let elapsed = data.position.elapsed(Instant::now());
+    *data.stream.write().unwrap() = stream;
+    data.stream_handle = Arc::new(handle);
+
+    if data.queue.current().is_some() {
+        seek(ctx, data, elapsed);
+    } else if let Err(e) = play(data, None, Duration::default(), Duration::default()) {
+        error!("Could not switch to the new output: {}", e);
+    }
+}
+
+/// Restart the current track from `offset`, keeping it paused if it was.
+fn seek(ctx: &mut DelegateCtx, data: &mut AppData, offset: Duration) {
+    let track = match data.queue.current().cloned() {
+        Some(track) => track,
+        None => return,
+    };
+
+    if let Err(e) = play(data, Some(&track), offset, Duration::default()) {
+        error!("Could not play {}: {}", track.path(), e);
+        ctx.submit_command(notify(Severity::Error,
+                                  format!("Could not play {}: {}.", track.path(), e)));
+        return;
+    }
+
+    let now = Instant::now();
+    data.position = PlaybackPosition::start(track.length(), offset, now)
+        .ending_at(trimmed_sound(data, &track).map(|(_, end)| end));
+    if data.paused {
+        data.sink.read().unwrap().pause();
+        data.position.pause(now);
+    }
+}
+
+/// Replace the sink with a new one playing `track` from `offset`, or nothing. Dropping the old
+/// sink stops it, and a stopped sink can't be restarted, so this is also how we stop. With a
+/// `fade`, the old sink is faded out instead while the track fades in. Stopping fades out at
+/// least as long as pausing does. The track starts out faded out if we're paused. If the track
+/// can't be played, the old sink is left alone, so the playback watcher doesn't see it run dry.
+fn play(data: &AppData, track: Option<&Track>, offset: Duration,
+        fade: Duration) -> Result<(), PlaybackError> {
+    let source = track
+        .map(|track| match data.prefetcher.take(track.id()) {
+            Some(source) => Ok(source),
+            None => open_track(track.path()),
+        })
+        .transpose()?
+        .map(|source| resample(source, data.output_rate, data.resample_quality));
+    let sink = Sink::try_new(&data.stream_handle)?;
+    sink.set_volume(playback_gain(data, track));
+    // Overtakes any pause still waiting on the old sink to fade out
+    data.pause_fade.set_faded_out(data.paused);
+
+    if let Some(source) = source {
+        let end = track.and_then(|track| trimmed_sound(data, track)).map(|(_, end)| end);
+        let source: TrackSource = match end {
+            Some(end) => Box::new(source.take_duration(end)),
+            None => source,
+        };
+        let source = source.skip_duration(offset);
+        let pause_fade = data.pause_fade.clone();
+        if fade > Duration::default() {
+            sink.append(Faded::new(source.fade_in(fade), pause_fade));
+        } else {
+            sink.append(Faded::new(source, pause_fade));
+        }
+    }
+
+    let fade = if track.is_none() { fade.max(data.pause_fade.duration()) } else { fade };
+    let old_sink = std::mem::replace(&mut *data.sink.write().unwrap(), sink);
+    if fade > Duration::default() && !old_sink.is_paused() && !old_sink.empty() {
+        thread::spawn(move || fade_out(old_sink, fade));
+    }
+    Ok(())
+}
+
+/// Where to start and stop playing `track`, to skip the silence before and after it, if that's
+/// turned on and it's been found.
+fn trimmed_sound(data: &AppData, track: &Track) -> Option<(Duration, Duration)> {
+    if data.trim_silence { track.sound() } else { None }
+}
+
+/// Start decoding the next track in the queue, if prefetching is on, discarding what was for
+/// one that's no longer next after a skip or reorder.
+fn prefetch_next(data: &AppData) {
+    match (data.prefetch, data.queue.next()) {
+        (Some(amount), Some(next)) => {
+            let path = next.path().to_string();
+            data.prefetcher.prefetch(next.id(), amount, move || open_track(&path).ok());
+        }
+        _ => data.prefetcher.cancel(),
+    }
+}
+
+/// Pause or resume playback. Pausing fades out first and only pauses the sink once that's
+/// done, unless playback has been resumed or moved on to another track by then.
+fn toggle_pause(data: &mut AppData) {
+    let now = Instant::now();
+    if data.paused {
+        data.pause_fade.set_faded_out(false);
+        data.sink.read().unwrap().play();
+        data.position.resume(now);
+    } else {
+        let generation = data.pause_fade.set_faded_out(true);
+        let (sink, pause_fade) = (data.sink.clone(), data.pause_fade.clone());
+        thread::spawn(move || {
+            thread::sleep(pause_fade.duration());
+            // Held while checking, so resuming can't slip in between
+            let sink = sink.write().unwrap();
+            if pause_fade.is_current(generation) {
+                sink.pause();
+            }
+        });
+        data.position.pause(now);
+    }
+
+    data.paused = !data.paused;
+}
+
+/// Turn a sink down to silence over `fade`, then drop it.
+fn fade_out(sink: Sink, fade: Duration) {
+    let volume = sink.volume();
+    let steps = (fade.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+
+    for step in 1..=steps {
+        thread::sleep(fade / steps);
+        sink.set_volume(volume * (steps - step) as f32 / steps as f32);
+    }
+}
+
+/// How long to crossfade from a track with `outgoing` left to play into `incoming`: as long as
+/// configured, but no more than what's left of the outgoing track, or half the incoming one.
+/// Tracks of unknown length are cut to rather than faded into.
+fn crossfade_length(crossfade: Duration, outgoing: Duration, incoming: &Track) -> Duration {
+    if incoming.length() <= 0 {
+        return Duration::default();
+    }
+
+    crossfade.min(outgoing).min(Duration::from_secs(incoming.length() as u64) / 2)
+}
+
+/// How loud to play a track: the volume slider's gain, adjusted to normalize the track if that's
+/// turned on and there's something to normalize it by.
+fn playback_gain(data: &AppData, track: Option<&Track>) -> f32 {
+    let gain = volume_gain(data.volume);
+    match track.and_then(|track| normalization_gain(track, data.normalization)) {
+        Some(adjustment) if data.replay_gain => gain * replay_gain_factor(adjustment),
+        _ => gain,
+    }
+}
+
+/// Show a folder in the system file manager.
+fn open_folder(folder: &Path) -> std::io::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+
+    process::Command::new(opener).arg(folder).spawn().map(|_| ())
+}
+
+/// Add the file to the 'Individual Tracks' library if we haven't seen it before, then queue it up.
+fn queue_file(db: &mut Database, queue: &mut Queue, path: &Path) {
+    queue.enqueue(file_tracks(db, path));
+}
+
+/// The track for a file, added to the 'Individual Tracks' library if we haven't seen it before,
+/// or the tracks in it if it's an archive. Empty if it isn't a track, or can't be added.
+fn file_tracks(db: &mut Database, path: &Path) -> Vec<Track> {
+    let path_str = match path.to_str() {
+        Some(path_str) => path_str,
+        None => {
+            warn!("Skipping {}, its path is not valid UTF-8", path.display());
+            return Vec::new();
+        }
+    };
+
+    if is_archive(path) {
+        return archive_tracks(path_str).unwrap_or_else(|e| {
+            warn!("Skipping {}, its tracks could not be listed: {}", path_str, e);
+            Vec::new()
+        });
+    }
+
+    match db.add_individual_track(path_str) {
+        Ok(Some(track)) => vec![track],
+        Ok(None) => {
+            warn!("Skipping {}, it is not a track", path_str);
+            Vec::new()
+        }
+        Err(e) => {
+            error!("Could not add {} to the database: {}", path_str, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Why a track couldn't be played.
+#[derive(Error, Debug)]
+enum PlaybackError {
+    #[error("its file is missing")]
+    Missing,
+    #[error("its file could not be opened ({0})")]
+    Open(#[from] io::Error),
+    #[error("its file could not be decoded ({0})")]
+    Decode(DecodeError),
+    #[error("its archive could not be read ({0})")]
+    Archive(#[from] ArchiveError),
+    #[error("there's nothing to play it through ({0})")]
+    Output(#[from] PlayError),
+}
+
+impl From<DecodeError> for PlaybackError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Io(e) => PlaybackError::Open(e),
+            e => PlaybackError::Decode(e),
+        }
+    }
+}
+
+/// Open a track's file for playback, with the first decoder that knows its format. Files that
+/// have gone missing since the last scan are told apart from ones that can't be read. Tracks
+/// inside archives are read into memory and decoded from there.
+fn open_track(path: &str) -> Result<TrackSource, PlaybackError> {
+    let entry = split_entry_path(path);
+    if !Path::new(entry.map_or(path, |(archive, _)| archive)).exists() {
+        return Err(PlaybackError::Missing);
+    }
+
+    let decoded = match entry {
+        Some((archive, name)) => {
+            let entry = open_entry(archive, name)?;
+            decode_with(DECODERS, || Ok(entry.clone()))?
+        }
+        None => decode_with(DECODERS, || File::open(path).map(BufReader::new))?,
+    };
+    info!("Decoding {} with {}", path, decoded.decoder);
+    Ok(decoded.source)
+}
+
+fn make_menu() -> MenuDesc<AppData> {
+    let view = MenuDesc::new(LocalizedString::new("mus-menu-view").with_placeholder("View"))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-random-track").with_placeholder("Go to Random Track"),
+            GO_TO_RANDOM_TRACK,
+        ).hotkey(SysMods::Cmd, "r"))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-show-queue").with_placeholder("Show Queue"),
+            SHOW_QUEUE,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-group-by-album").with_placeholder("Group by Album"),
+            TOGGLE_ALBUM_GROUPS,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-strict-sort").with_placeholder("Case-Sensitive Sorting"),
+            TOGGLE_STRICT_SORT,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-search-everywhere")
+                .with_placeholder("Search Paths and Comments"),
+            TOGGLE_SEARCH_EVERYWHERE,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-reduce-motion").with_placeholder("Reduce Motion"),
+            TOGGLE_REDUCE_MOTION,
+        ));
+    // Only offered where the window can actually be kept on top
+    let view = if on_top::SUPPORTED {
+        view.append_separator()
+            .append(MenuItem::new(
+                LocalizedString::new("mus-menu-always-on-top").with_placeholder("Keep on Top"),
+                TOGGLE_ALWAYS_ON_TOP,
+            ))
+    } else {
+        view
+    };
+
+    let add_library = FileDialogOptions::new()
+        .select_directories()
+        .accept_command(NEW_LIBRARY_FOLDER);
+
+    let export_playlist = FileDialogOptions::new()
+        .allowed_types(vec![M3U])
+        .accept_command(EXPORT_PLAYLIST);
+
+    let import_playlist = FileDialogOptions::new()
+        .allowed_types(vec![M3U])
+        .accept_command(IMPORT_PLAYLIST);
+
+    let file = MenuDesc::new(LocalizedString::new("mus-menu-file").with_placeholder("File"))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-add-library").with_placeholder("Add Library…"),
+            SHOW_OPEN_PANEL.with(add_library),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-manage-libraries").with_placeholder("Manage Libraries…"),
+            MANAGE_LIBRARIES,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-import-playlist").with_placeholder("Import Playlist…"),
+            SHOW_OPEN_PANEL.with(import_playlist),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-export-playlist").with_placeholder("Export Playlist…"),
+            SHOW_SAVE_PANEL.with(export_playlist),
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-import-sidecars").with_placeholder("Import Ratings from Sidecars"),
+            IMPORT_SIDECARS,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-duplicates-tags").with_placeholder("Find Duplicates by Tags"),
+            FIND_DUPLICATES.with(DuplicateKey::Tags),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-duplicates-audio").with_placeholder("Find Duplicates by Audio"),
+            FIND_DUPLICATES.with(DuplicateKey::Audio),
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-analyze-tracks")
+                .with_placeholder("Analyze Loudness and Silence"),
+            ANALYZE_TRACKS,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-vacuum").with_placeholder("Vacuum Database"),
+            VACUUM_DATABASE,
+        ));
+
+    let playback = MenuDesc::new(LocalizedString::new("mus-menu-playback").with_placeholder("Playback"))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-next").with_placeholder("Next Track"),
+            PLAY_NEXT,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-previous").with_placeholder("Previous Track"),
+            PLAY_PREVIOUS,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-enqueue").with_placeholder("Add Selection to Queue"),
+            ENQUEUE_SELECTION,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-shuffle-queue").with_placeholder("Shuffle Queue"),
+            SHUFFLE_QUEUE,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-smart-shuffle")
+                .with_placeholder("Favour Top-Rated and Rarely Played Tracks"),
+            TOGGLE_SMART_SHUFFLE,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-output-device").with_placeholder("Output Device…"),
+            SHOW_OUTPUT_DEVICES,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-replay-gain").with_placeholder("Normalize Volume"),
+            TOGGLE_REPLAY_GAIN,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-normalize-replay-gain")
+                .with_placeholder("Normalize by ReplayGain Tags"),
+            SET_NORMALIZATION.with(Normalization::ReplayGain),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-normalize-track")
+                .with_placeholder("Normalize by Track Loudness"),
+            SET_NORMALIZATION.with(Normalization::Track),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-normalize-album")
+                .with_placeholder("Normalize by Album Loudness"),
+            SET_NORMALIZATION.with(Normalization::Album),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-trim-silence")
+                .with_placeholder("Trim Silence Between Tracks"),
+            TOGGLE_TRIM_SILENCE,
+        ));
+
+    MenuDesc::empty()
+        .append(file)
+        .append(view)
+        .append(playback)
+}
+
+/// The main window's title: `format` filled in from the playing track, or just "mus" with
+/// nothing playing. `{field}` is replaced by the track's field, named as in the filter box.
+/// Missing titles, artists and albums are "Unknown", and other missing fields are left out.
+/// Braces around anything else are left as they are.
+fn window_title(format: &str, track: Option<&Track>) -> String {
+    let track = match track {
+        Some(track) if !format.is_empty() => track,
+        _ => return "mus".to_string(),
+    };
+
+    let mut title = String::new();
+    let mut rest = format;
+    while let Some(open) = rest.find('{') {
+        title.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let field = after.find('}')
+            .and_then(|close| TrackField::from_column(&after[..close]).map(|field| (field, close)));
+
+        match field {
+            Some((field, close)) => {
+                title.push_str(&title_field(track, field));
+                rest = &after[close + 1..];
+            }
+            None => {
+                title.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    title.push_str(rest);
+    title
+}
+
+/// One of a track's fields, as it's shown in the window title.
+fn title_field(track: &Track, field: TrackField) -> String {
+    match field {
+        TrackField::Title  => track.title().unwrap_or("Unknown Title").to_string(),
+        TrackField::Artist => track.artist().unwrap_or("Unknown Artist").to_string(),
+        TrackField::Album  => track.album().unwrap_or("Unknown Album").to_string(),
+        TrackField::Rating => track.rating().map(|rating| rating.to_string()).unwrap_or_default(),
+        field => track.get_field_as_string(field),
+    }
+}
+
+/// What the confirmation before doing `removal` to the files of `count` tracks asks.
+fn deletion_message(count: usize, removal: FileRemoval) -> String {
+    match (removal, count) {
+        (FileRemoval::Trash, 1) => "Move this track's file to the trash?".to_string(),
+        (FileRemoval::Trash, _) => {
+            format!("Move the files of these {} tracks to the trash?", count)
+        }
+        (_, 1) => "Delete this track's file? This can't be undone.".to_string(),
+        (_, _) => format!("Delete the files of these {} tracks? This can't be undone.", count),
+    }
+}
+
+/// The library with this id, if it's still there and has a folder to scan.
+fn scannable_library(db: &Database, id: i64) -> Option<Library> {
+    db.libraries().ok()?.into_iter()
+        .find(|library| library.id() == id && library.path().is_some())
+}
+
+/// Whether to ask before a full rescan that would lose `loss`, if `confirm` is on.
+fn needs_rescan_confirmation(confirm: bool, loss: &RescanLoss) -> bool {
+    confirm && !loss.is_empty()
+}
+
+/// What the confirmation before a full rescan of the library `name` says it'll lose.
+fn rescan_message(name: &str, loss: &RescanLoss) -> String {
+    let tracks = |count| match count {
+        1 => "1 track".to_string(),
+        count => format!("{} tracks", count),
+    };
+
+    let mut losses = Vec::new();
+    match loss.playlist_entries {
+        0 => (),
+        1 => losses.push("take 1 track out of its playlist".to_string()),
+        count => losses.push(format!("take {} entries out of playlists", count)),
+    }
+    if loss.played_tracks > 0 {
+        losses.push(format!("forget the plays of {}", tracks(loss.played_tracks)));
+    }
+    if loss.rated_tracks > 0 {
+        losses.push(format!("forget the ratings of {}", tracks(loss.rated_tracks)));
+    }
+
+    let losses = match losses.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => "lose nothing".to_string(),
+    };
+    format!("Rescanning {} from scratch will {}. This can't be undone.", name, losses)
+}
+
+fn make_rescan_confirmation(id: i64, message: String) -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(Label::new(message).with_line_break_mode(LineBreaking::WordWrap))
+        .with_spacer(8.)
+        .with_child(Checkbox::new("Don't ask again").lens(AppData::skip_rescan_confirmation))
+        .with_spacer(8.)
+        .with_child(Flex::row()
+            .with_child(Button::new("Cancel").on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(Command::new(CLOSE_WINDOW, (), ctx.window_id()));
+            }))
+            .with_spacer(8.)
+            .with_child(Button::new("Rescan").on_click(move |ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(CONFIRM_FULL_RESCAN.with((ctx.window_id(), id)));
+            })))
+        .padding(10.)
+}
+
+fn make_delete_confirmation(count: usize, removal: FileRemoval) -> impl Widget<AppData> {
+    let message = deletion_message(count, removal);
+    let confirm = if removal == FileRemoval::Trash { "Move to Trash" } else { "Delete" };
+
+    Flex::column()
+        .with_child(Label::new(message))
+        .with_spacer(8.)
+        .with_child(Flex::row()
+            .with_child(Button::new("Cancel").on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(Command::new(CLOSE_WINDOW, (), ctx.window_id()));
+            }))
+            .with_spacer(8.)
+            .with_child(Button::new(confirm).on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(CONFIRM_DELETE.with(ctx.window_id()));
+            })))
+        .padding(10.)
+}
+
+fn make_ui(settings: &Settings, db: Arc<RwLock<Database>>, art_cache: ArtCache,
+           keymap: KeyMap) -> impl Widget<AppData> {
     let sep = Painter::new(|ctx, _data, _env| {
         let bounds = ctx.size().to_rect();
         ctx.fill(bounds, &Color::WHITE);
     });
 
-    let bottom_bar = Label::new("Welcome to mus v0.0.0");
+    let now_playing = Label::new(|data: &AppData, _env: &Env| match data.queue.current() {
+        Some(track) => format!("{} {} — {}",
+                               if data.paused { "⏸" } else { "▶" },
+                               track.title().unwrap_or("Unknown Title"),
+                               track.artist().unwrap_or("Unknown Artist")),
+        None => "Welcome to mus v0.0.0".to_string(),
+    });
+
+    let scan_progress = Either::new(
+        |data: &AppData, _env| data.scan_progress.is_some(),
+        Flex::row()
+            .with_child(Label::new(|data: &AppData, _env: &Env| match &data.scan_progress {
+                Some((what, scanned, total)) => format!("{}: {}/{}", what, scanned, total),
+                None => String::new(),
+            }))
+            .with_child(ProgressBar::new()
+                .lens(lens::Map::new(
+                    |data: &AppData| match data.scan_progress {
+                        Some((_, scanned, total)) if total > 0 => scanned as f64 / total as f64,
+                        _ => 0.,
+                    },
+                    |_data: &mut AppData, _fraction| ())))
+            .with_spacer(8.),
+        SizedBox::empty());
+
+    let bottom_bar = Flex::row()
+        .with_child(TrackTransition::new(CoverArt::new()
+            .fix_size(COVER_ART_SIZE, COVER_ART_SIZE)
+            .lens(AppData::art)))
+        .with_spacer(BAR_SPACING)
+        .with_flex_child(TrackTransition::new(now_playing.expand_width()), 1.0)
+        .with_child(Label::new(|data: &AppData, _env: &Env| {
+            data.queue.position_text().unwrap_or_default()
+        }))
+        .with_spacer(BAR_SPACING)
+        .with_child(make_status_bar().lens(AppData::main_tracklist_data))
+        .with_spacer(BAR_SPACING)
+        .with_child(scan_progress)
+        .with_child(SeekBar::new()
+            .lens(AppData::position)
+            .fix_width(SEEK_BAR_WIDTH))
+        .with_spacer(BAR_SPACING)
+        .with_child(Label::new("Volume"))
+        .with_child(Slider::new()
+            .with_range(0., 1.)
+            .fix_width(VOLUME_SLIDER_WIDTH)
+            .lens(AppData::volume));
 
-    let table = TrackList::new();
+    let table = TrackList::new()
+        .with_album_art(db, art_cache)
+        .with_empty_area_action(settings.empty_area_double_click)
+        .with_two_line_rows(settings.two_line_rows)
+        .with_column_layout(&settings.columns)
+        .controller(SelectionController);
 
     let main_view = Flex::column()
+        .with_child(make_filter_box()
+            .lens(AppData::main_tracklist_data)
+            .padding((5., 5., 5., 0.)))
         .with_flex_child(Flex::row()
+            .with_child(make_sidebar()
+                .fix_width(SIDEBAR_WIDTH)
+                .padding((5., 5., 0., 5.)))
             .with_flex_child(
                 table.lens(AppData::main_tracklist_data)
                     .padding((5., 5.)),
                 1.0), 1.0)
+        .with_child(make_notification_area()
+            .lens(AppData::notifications)
+            .padding((5., 0.)))
         .with_child(sep
             .fix_height(2.)
             .expand_width())
@@ -81,4 +2338,389 @@ fn make_ui() -> impl Widget<AppData> {
             .align_left());
 
     main_view
+        .controller(PlaybackController::new(settings.crossfade(), keymap))
+        .controller(WindowGeometryController { size: Size::ZERO })
+}
+
+/// `configured`, or bigger if need be so that the window can't be shrunk past where the parts
+/// that don't stretch fit side by side. Below that they'd overlap.
+fn min_window_size(configured: (f64, f64)) -> Size {
+    // The bottom bar is padded by 4 on each side, and the sidebar and track list by 5
+    let bottom_bar = 4. + COVER_ART_SIZE + 4. * BAR_SPACING + SEEK_BAR_WIDTH + VOLUME_SLIDER_WIDTH
+        + MIN_LABELS_WIDTH + 4.;
+    let main_row = 5. + SIDEBAR_WIDTH + 5. + MIN_TRACK_LIST_WIDTH + 5.;
+
+    Size::new(configured.0.max(bottom_bar).max(main_row), configured.1)
+}
+
+/// Shrink and move a saved window so it fits on one monitor, in case it was saved on a bigger
+/// display, or one that's since been unplugged.
+fn fit_to_screen(size: (f64, f64), position: Option<(f64, f64)>) -> (Size, Option<Point>) {
+    let size = Size::from(size);
+    let position = position.map(Point::from);
+
+    // The monitor the window was on, otherwise the main one
+    let monitors = Screen::get_monitors();
+    let work_area = monitors.iter()
+        .find(|monitor| position.map_or(false, |position| monitor.virtual_rect().contains(position)))
+        .or_else(|| monitors.iter().find(|monitor| monitor.is_primary()))
+        .or_else(|| monitors.first())
+        .map(|monitor| monitor.virtual_work_rect());
+
+    let work_area = match work_area {
+        Some(work_area) => work_area,
+        None => return (size, position),
+    };
+
+    let size = Size::new(size.width.min(work_area.width()), size.height.min(work_area.height()));
+    let position = position.map(|position| Point::new(
+        position.x.clamp(work_area.x0, work_area.x1 - size.width),
+        position.y.clamp(work_area.y0, work_area.y1 - size.height)));
+    (size, position)
+}
+
+/// Saves the window's size and position when it's closed, so it opens the same way next time.
+struct WindowGeometryController {
+    size: Size,
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for WindowGeometryController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppData,
+             env: &Env) {
+        match event {
+            Event::WindowSize(size) => self.size = *size,
+            Event::WindowCloseRequested => {
+                let position = ctx.window().get_position();
+                let saved = save_window_geometry(&data.db.read().unwrap(),
+                                                 (self.size.width, self.size.height),
+                                                 Some((position.x, position.y)));
+                if let Err(e) = saved {
+                    warn!("Could not save the window's size and position: {}", e);
+                }
+            }
+            _ => (),
+        }
+
+        child.event(ctx, event, data, env);
+    }
+}
+
+/// Handles playback keys that weren't used by whatever has focus, e.g. a text box taking a space,
+/// starts crossfades on time, and counts tracks as played once they're halfway through.
+struct PlaybackController {
+    crossfade: Duration,
+    keymap: KeyMap,
+    timer: TimerToken,
+    /// When the volume last changed, if it hasn't been saved since.
+    volume_changed: Option<Instant>,
+}
+
+impl PlaybackController {
+    fn new(crossfade: Duration, keymap: KeyMap) -> Self {
+        PlaybackController {
+            crossfade,
+            keymap,
+            timer: TimerToken::INVALID,
+            volume_changed: None,
+        }
+    }
+
+    /// How long a crossfade into the next track should be, if it's time to start it.
+    fn due_crossfade(&self, data: &AppData) -> Option<Duration> {
+        if !data.position.is_playing() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let next = data.queue.next()?;
+        // Short tracks get at most half of themselves faded, so they're still heard
+        let fade = crossfade_length(self.crossfade, data.position.length()? / 2, next);
+        let remaining = data.position.remaining(now)?;
+
+        if fade > Duration::default() && remaining <= fade {
+            Some(fade)
+        } else {
+            None
+        }
+    }
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for PlaybackController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppData,
+             env: &Env) {
+        match event {
+            Event::WindowConnected => {
+                // Key events only go to the focused widget, so take focus until something else does
+                ctx.request_focus();
+                self.timer = ctx.request_timer(PLAYBACK_POLL);
+            }
+            Event::Timer(token) if *token == self.timer => {
+                self.timer = ctx.request_timer(PLAYBACK_POLL);
+                if let Some(fade) = self.due_crossfade(data) {
+                    ctx.submit_command(CROSSFADE.with(fade));
+                }
+                if volume_save_due(self.volume_changed, Instant::now()) {
+                    self.volume_changed = None;
+                    if let Err(e) = save_volume(&data.db.read().unwrap(), data.volume) {
+                        warn!("Could not save the volume: {}", e);
+                    }
+                }
+                if !data.play_recorded && data.position.is_past_halfway(Instant::now()) {
+                    if let Some(track) = data.queue.current() {
+                        if !track.is_ephemeral() {
+                            ctx.submit_command(RECORD_PLAY.with(track.id()));
+                        }
+                        data.play_recorded = true;
+                    }
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        child.event(ctx, event, data, env);
+
+        if ctx.is_handled() {
+            return;
+        }
+
+        if let Event::KeyDown(key) = event {
+            match self.keymap.action(key) {
+                Some(KeyAction::TogglePause) => toggle_pause(data),
+                Some(KeyAction::PlayNext) => ctx.submit_command(PLAY_NEXT),
+                Some(KeyAction::PlayPrevious) => ctx.submit_command(PLAY_PREVIOUS),
+                Some(KeyAction::GoToRandomTrack) => ctx.submit_command(GO_TO_RANDOM_TRACK),
+                Some(KeyAction::ShowQueue) => ctx.submit_command(SHOW_QUEUE),
+                None => return,
+            }
+            ctx.set_handled();
+        }
+    }
+
+    fn update(&mut self, child: &mut W, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData,
+              env: &Env) {
+        if !old_data.volume.same(&data.volume) {
+            data.sink.read().unwrap().set_volume(playback_gain(data, data.queue.current()));
+            self.volume_changed = Some(Instant::now());
+        }
+
+        child.update(ctx, old_data, data, env);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use tempfile::TempDir;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use crate::test_support::{canonical, write_wav};
+
+    use super::*;
+
+    #[test]
+    fn the_minimum_window_fits_everything_side_by_side() {
+        let min = min_window_size(Settings::default().min_window_size);
+
+        // The sidebar and track list, each padded by 5
+        let sidebar = (5., 5. + SIDEBAR_WIDTH);
+        let track_list = (sidebar.1 + 5., min.width - 5.);
+        assert!(track_list.1 - track_list.0 >= MIN_TRACK_LIST_WIDTH);
+
+        // The bottom bar's fixed parts leave enough room for its labels
+        let fixed = 4. + COVER_ART_SIZE + 4. * BAR_SPACING + SEEK_BAR_WIDTH + VOLUME_SLIDER_WIDTH
+            + 4.;
+        assert!(min.width - fixed >= MIN_LABELS_WIDTH);
+    }
+
+    #[test]
+    fn configured_minimum_sizes_are_only_raised_to_fit() {
+        assert_eq!(min_window_size((1000., 500.)), Size::new(1000., 500.));
+
+        let min = min_window_size((100., 100.));
+        assert!(min.width > 100.);
+        assert_eq!(min.height, 100.);
+    }
+
+    #[test]
+    fn opened_files_are_queued_as_individual_tracks() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        write_wav(&dir.path().join("b.wav"), 1, 2);
+        let a = dir.path().join("a.wav").canonicalize().unwrap();
+        let b = dir.path().join("b.wav").canonicalize().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let mut queue = Queue::default();
+
+        queue_file(&mut db, &mut queue, &a);
+        queue_file(&mut db, &mut queue, &b);
+
+        let paths: Vec<&str> = queue.tracks().iter().map(Track::path).collect();
+        assert_eq!(paths, vec![canonical(&a), canonical(&b)]);
+        assert!(queue.tracks().iter().all(|track| track.library_id() == 1));
+        assert_eq!(queue.play_next().map(Track::path), Some(canonical(&a).as_str()));
+    }
+
+    #[test]
+    fn opening_a_known_file_queues_the_same_track() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("a.wav"), 1, 1);
+        let a = dir.path().join("a.wav").canonicalize().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let mut queue = Queue::default();
+
+        queue_file(&mut db, &mut queue, &a);
+        queue_file(&mut db, &mut queue, &a);
+
+        assert_eq!(queue.tracks().len(), 2);
+        assert_eq!(queue.tracks()[0].id(), queue.tracks()[1].id());
+    }
+
+    #[test]
+    fn opened_files_that_are_not_tracks_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        let notes = dir.path().join("notes.txt");
+        fs::write(&notes, "not a track").unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let mut queue = Queue::default();
+
+        queue_file(&mut db, &mut queue, &notes);
+
+        assert!(queue.tracks().is_empty());
+        assert!(db.track_by_path(&canonical(&notes)).unwrap().is_none());
+    }
+
+    #[test]
+    fn opened_archives_queue_their_tracks_without_adding_them() {
+        let dir = TempDir::new().unwrap();
+        write_wav(&dir.path().join("1.wav"), 1, 1);
+        let archive = dir.path().join("album.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("1.wav", FileOptions::default()).unwrap();
+        zip.write_all(&fs::read(dir.path().join("1.wav")).unwrap()).unwrap();
+        zip.finish().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let mut queue = Queue::default();
+
+        queue_file(&mut db, &mut queue, &archive);
+
+        assert_eq!(queue.tracks().len(), 1);
+        assert!(queue.tracks()[0].is_ephemeral());
+        assert!(open_track(queue.tracks()[0].path()).is_ok());
+        assert!(db.track_by_path(&canonical(&archive)).unwrap().is_none());
+    }
+
+    #[test]
+    fn tracks_inside_archives_are_left_out_of_the_saved_queue() {
+        let entry = |id| Track::ephemeral(id, format!("/a.zip!/{}.wav", id), "a".to_string(), None);
+        let tracks = vec![entry(-1), Track::for_test(1, "a"), entry(-2), Track::for_test(2, "b")];
+
+        assert_eq!(saved_entries(&tracks, Some(3)), (vec![1, 2], Some(1)));
+        assert_eq!(saved_entries(&tracks, Some(2)), (vec![1, 2], None));
+        assert_eq!(saved_entries(&tracks, None), (vec![1, 2], None));
+    }
+
+    #[test]
+    fn deletion_confirmations_say_whether_it_can_be_undone() {
+        assert_eq!(deletion_message(1, FileRemoval::Trash), "Move this track's file to the trash?");
+        assert_eq!(deletion_message(3, FileRemoval::Trash),
+                   "Move the files of these 3 tracks to the trash?");
+        assert_eq!(deletion_message(1, FileRemoval::Delete),
+                   "Delete this track's file? This can't be undone.");
+        assert_eq!(deletion_message(3, FileRemoval::Delete),
+                   "Delete the files of these 3 tracks? This can't be undone.");
+    }
+
+    #[test]
+    fn window_titles_show_the_playing_track() {
+        let track = Track::for_test(1, "So What").with_artist("Miles Davis").with_genre("Jazz");
+        let format = "{artist} — {title} · mus";
+
+        assert_eq!(window_title(format, Some(&track)), "Miles Davis — So What · mus");
+        assert_eq!(window_title("{title} ({genre}, {length}s)", Some(&track)),
+                   "So What (Jazz, 180s)");
+        assert_eq!(window_title(format, None), "mus");
+        assert_eq!(window_title("", Some(&track)), "mus");
+    }
+
+    #[test]
+    fn window_titles_get_by_without_fields() {
+        let track = Track::for_test(1, "So What");
+
+        assert_eq!(window_title("{artist} — {title} from {album}", Some(&track)),
+                   "Unknown Artist — So What from Unknown Album");
+        assert_eq!(window_title("{title} [{year}] {rating}", Some(&track)), "So What [] ");
+        // Not fields
+        assert_eq!(window_title("{mood} {title} {", Some(&track)), "{mood} So What {");
+        assert_eq!(window_title("{{title}}", Some(&track)), "{So What}");
+    }
+
+    #[test]
+    fn full_rescans_are_confirmed_when_they_would_lose_something() {
+        let played = RescanLoss { played_tracks: 2, ..RescanLoss::default() };
+        let listed = RescanLoss { playlist_entries: 1, ..RescanLoss::default() };
+
+        assert!(needs_rescan_confirmation(true, &played));
+        assert!(needs_rescan_confirmation(true, &listed));
+        assert!(!needs_rescan_confirmation(true, &RescanLoss::default()));
+        // Turned off
+        assert!(!needs_rescan_confirmation(false, &played));
+        assert!(!needs_rescan_confirmation(false, &RescanLoss::default()));
+    }
+
+    #[test]
+    fn rescan_confirmations_say_what_will_be_lost() {
+        let everything = RescanLoss { playlist_entries: 3, played_tracks: 1, rated_tracks: 2 };
+        assert_eq!(rescan_message("Music", &everything),
+                   "Rescanning Music from scratch will take 3 entries out of playlists, forget the \
+                    plays of 1 track and forget the ratings of 2 tracks. This can't be undone.");
+
+        let listed = RescanLoss { playlist_entries: 1, ..RescanLoss::default() };
+        assert_eq!(rescan_message("Music", &listed),
+                   "Rescanning Music from scratch will take 1 track out of its playlist. This \
+                    can't be undone.");
+    }
+
+    #[test]
+    fn volumes_are_saved_once_they_settle() {
+        let now = Instant::now();
+        assert!(!volume_save_due(None, now));
+        assert!(!volume_save_due(Some(now), now));
+        assert!(!volume_save_due(Some(now), now + VOLUME_SAVE_DELAY / 2));
+        assert!(volume_save_due(Some(now), now + VOLUME_SAVE_DELAY));
+    }
+
+    #[test]
+    fn tracks_that_stop_well_short_of_their_end_failed_partway() {
+        let start = Instant::now();
+        let position = PlaybackPosition::start(180, Duration::default(), start);
+
+        let at = |secs| failed_partway(&position, start + Duration::from_secs(secs));
+        assert_eq!(at(60), Some(Duration::from_secs(60)));
+        assert_eq!(at(174), Some(Duration::from_secs(174)));
+        // Lengths are only so accurate
+        assert_eq!(at(176), None);
+        assert_eq!(at(180), None);
+        // Nor can anything be said of tracks whose length isn't known
+        let unknown = PlaybackPosition::start(0, Duration::default(), start);
+        assert_eq!(failed_partway(&unknown, start), None);
+
+        // Tracks whose trailing silence is skipped end where their sound does
+        let trimmed = PlaybackPosition::start(180, Duration::default(), start)
+            .ending_at(Some(Duration::from_secs(160)));
+        assert_eq!(failed_partway(&trimmed, start + Duration::from_secs(160)), None);
+        assert_eq!(failed_partway(&trimmed, start + Duration::from_secs(150)),
+                   Some(Duration::from_secs(150)));
+    }
+
+    #[test]
+    fn playback_goes_on_after_a_bad_track_but_not_without_an_output() {
+        assert!(advances_after(&PlaybackError::Missing));
+        assert!(advances_after(&PlaybackError::Decode(DecodeError::Unsupported)));
+        assert!(advances_after(&PlaybackError::Open(io::Error::from(io::ErrorKind::NotFound))));
+        assert!(!advances_after(&PlaybackError::Output(PlayError::NoDevice)));
+    }
 }
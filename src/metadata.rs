@@ -0,0 +1,125 @@
+use druid::{Data, Lens, Widget, WidgetExt};
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, TextBox};
+
+use crate::commands::SAVE_METADATA;
+use crate::db::{Track, TrackMetadata};
+
+/// Tall enough for a few lines of comment.
+const COMMENT_HEIGHT: f64 = 80.;
+
+/// The metadata editor's fields, as typed.
+#[derive(Debug, Clone, Data, Lens, Default)]
+pub struct MetadataForm {
+    track_id: i64,
+    title: String,
+    artist: String,
+    album: String,
+    comment: String,
+    genre: String,
+    year: String,
+    track: String,
+}
+
+impl MetadataForm {
+    pub fn from_track(track: &Track) -> Self {
+        MetadataForm {
+            track_id: track.id(),
+            title: track.title().unwrap_or_default().to_string(),
+            artist: track.artist().unwrap_or_default().to_string(),
+            album: track.album().unwrap_or_default().to_string(),
+            comment: track.comment().unwrap_or_default().to_string(),
+            genre: track.genre().unwrap_or_default().to_string(),
+            year: track.year().map(|year| year.to_string()).unwrap_or_default(),
+            track: track.track().map(|track| track.to_string()).unwrap_or_default(),
+        }
+    }
+
+    pub fn track_id(&self) -> i64 {
+        self.track_id
+    }
+
+    /// Blank fields clear their tag. Fails with a message for the user if a number doesn't parse.
+    pub fn to_metadata(&self) -> Result<TrackMetadata, String> {
+        Ok(TrackMetadata {
+            title: text_field(&self.title),
+            artist: text_field(&self.artist),
+            album: text_field(&self.album),
+            comment: text_field(&self.comment),
+            genre: text_field(&self.genre),
+            year: number_field(&self.year, "year")?,
+            track: number_field(&self.track, "track number")?,
+        })
+    }
+}
+
+fn text_field(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+fn number_field(value: &str, name: &str) -> Result<Option<i32>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value.parse()
+            .map(Some)
+            .map_err(|_| format!("The {} must be a number, not '{}'.", name, value)),
+    }
+}
+
+/// Text fields for each tag, and a save button. Saving closes the window if it works.
+pub fn make_metadata_editor() -> impl Widget<MetadataForm> {
+    Flex::column()
+        .with_child(make_field("Title", MetadataForm::title))
+        .with_child(make_field("Artist", MetadataForm::artist))
+        .with_child(make_field("Album", MetadataForm::album))
+        .with_child(make_field("Genre", MetadataForm::genre))
+        .with_child(make_field("Year", MetadataForm::year))
+        .with_child(make_field("Track", MetadataForm::track))
+        .with_child(make_comment_field())
+        .with_spacer(8.)
+        .with_child(Button::new("Save")
+            .on_click(|ctx, _data: &mut MetadataForm, _env| {
+                ctx.submit_command(SAVE_METADATA.with(ctx.window_id()));
+            })
+            .align_right())
+        .padding(10.)
+}
+
+fn make_field(name: &str, lens: impl Lens<MetadataForm, String> + 'static) -> impl Widget<MetadataForm> {
+    Flex::row()
+        .with_child(Label::new(name).fix_width(60.))
+        .with_flex_child(TextBox::new().expand_width().lens(lens), 1.0)
+        .padding((0., 2.))
+}
+
+/// Comments can run to several lines, like liner notes, so they get a box that wraps.
+fn make_comment_field() -> impl Widget<MetadataForm> {
+    let text = TextBox::multiline()
+        .with_line_wrapping(true)
+        .expand_width()
+        .fix_height(COMMENT_HEIGHT)
+        .lens(MetadataForm::comment);
+
+    Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new("Comment").fix_width(60.))
+        .with_flex_child(text, 1.0)
+        .padding((0., 2.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_keep_their_lines_but_not_surrounding_blanks() {
+        let track = Track::for_test(1, "So What").with_comment("Side A\nTrack 1");
+        let mut form = MetadataForm::from_track(&track);
+        assert_eq!(form.to_metadata().unwrap().comment.as_deref(), Some("Side A\nTrack 1"));
+
+        form.comment = "\n  Liner notes\n\n".to_string();
+        assert_eq!(form.to_metadata().unwrap().comment.as_deref(), Some("Liner notes"));
+        form.comment = " \n".to_string();
+        assert_eq!(form.to_metadata().unwrap().comment, None);
+    }
+}
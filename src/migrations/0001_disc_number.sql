@@ -0,0 +1,2 @@
+ALTER TABLE track
+    ADD COLUMN disc INTEGER;
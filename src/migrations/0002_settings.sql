@@ -0,0 +1,5 @@
+CREATE TABLE setting
+(
+    key   TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Seconds since the epoch, as of when the tags were last read
+ALTER TABLE track
+    ADD COLUMN mtime INTEGER;
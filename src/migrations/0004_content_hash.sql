@@ -0,0 +1,3 @@
+-- See fingerprint::content_hash
+ALTER TABLE track
+    ADD COLUMN hash TEXT;
@@ -0,0 +1,9 @@
+-- Order within the playlist, from 0. Entries until now were ordered by when they were added.
+ALTER TABLE playlist_tracks
+    ADD COLUMN position INTEGER;
+
+UPDATE playlist_tracks
+SET position = (SELECT COUNT(*)
+                FROM playlist_tracks AS earlier
+                WHERE earlier.id = playlist_tracks.id
+                  AND earlier.rowid < playlist_tracks.rowid);
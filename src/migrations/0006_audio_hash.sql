@@ -0,0 +1,3 @@
+-- See fingerprint::audio_hash
+ALTER TABLE track
+    ADD COLUMN audio_hash TEXT;
@@ -0,0 +1,9 @@
+-- The play queue, saved when mus closes so it can pick up where it left off. The entry that was
+-- playing has how far into it playback had got.
+CREATE TABLE queue
+(
+    position   INTEGER PRIMARY KEY,
+    track_id   INTEGER NOT NULL,
+    elapsed_ms INTEGER,
+    FOREIGN KEY (track_id) REFERENCES track (id)
+);
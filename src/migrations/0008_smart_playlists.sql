@@ -0,0 +1,13 @@
+-- Smart playlists hold criteria instead of tracks. See Database::query_tracks
+ALTER TABLE playlist
+    ADD COLUMN smart INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE playlist_criteria
+(
+    playlist_id INTEGER NOT NULL,
+    position    INTEGER NOT NULL,
+    field       TEXT    NOT NULL, -- See TrackField::column
+    operator    TEXT    NOT NULL, -- See Operator::symbol
+    value       TEXT    NOT NULL,
+    FOREIGN KEY (playlist_id) REFERENCES playlist (id)
+);
@@ -0,0 +1,3 @@
+-- REPLAYGAIN_TRACK_GAIN in dB, if the file is tagged with it
+ALTER TABLE track
+    ADD COLUMN replay_gain REAL;
@@ -0,0 +1,6 @@
+-- How many times each track has been played past halfway, and when it last was (seconds since
+-- the Unix epoch)
+ALTER TABLE track
+    ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE track
+    ADD COLUMN last_played INTEGER;
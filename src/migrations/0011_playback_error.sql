@@ -0,0 +1,6 @@
+-- The last time each track couldn't be played, why: 'open' if it wouldn't start at all, or
+-- 'partway' if it stopped partway through; and what went wrong
+ALTER TABLE track
+    ADD COLUMN playback_error_kind TEXT;
+ALTER TABLE track
+    ADD COLUMN playback_error TEXT;
@@ -0,0 +1,6 @@
+-- Integrated loudness in LUFS, measured from each track's audio, and that of its album as a
+-- whole
+ALTER TABLE track
+    ADD COLUMN loudness REAL;
+ALTER TABLE track
+    ADD COLUMN album_loudness REAL;
@@ -0,0 +1,8 @@
+-- Where each track's sound starts and ends in milliseconds, between the silence before and
+-- after it, and the level in dB that was taken for silence, so a new one measures it again
+ALTER TABLE track
+    ADD COLUMN sound_start_ms INTEGER;
+ALTER TABLE track
+    ADD COLUMN sound_end_ms INTEGER;
+ALTER TABLE track
+    ADD COLUMN silence_threshold REAL;
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use druid::{Color, Command, Data, Env, Event, EventCtx, Lens, RenderContext, TimerToken, Widget,
+            WidgetExt};
+use druid::widget::{Button, Controller, Flex, Label, List, Painter};
+
+use crate::commands::{DISMISS_NOTIFICATION, NOTIFY};
+
+/// How long info and warning notifications stay up. Errors stay until closed.
+const AUTO_DISMISS: Duration = Duration::from_secs(5);
+/// How often we check for expired notifications while any are up.
+const EXPIRY_CHECK: Duration = Duration::from_millis(500);
+/// Older notifications are dropped beyond this many.
+const MAX_NOTIFICATIONS: usize = 5;
+
+#[derive(Debug, Copy, Clone, Data, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn auto_dismisses(self) -> bool {
+        self != Severity::Error
+    }
+
+    fn background_color(self) -> Color {
+        match self {
+            Severity::Info    => Color::grey8(80),
+            Severity::Warning => Color::rgb8(150, 110, 20),
+            Severity::Error   => Color::rgb8(150, 30, 30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Data)]
+pub struct Notification {
+    id: u64,
+    severity: Severity,
+    message: String,
+    #[data(ignore)]
+    expires: Option<Instant>,
+}
+
+/// The notifications currently on screen, newest last.
+#[derive(Debug, Clone, Data, Lens, Default)]
+pub struct NotificationStack {
+    items: Arc<Vec<Notification>>,
+    next_id: u64,
+}
+
+impl NotificationStack {
+    pub fn push(&mut self, severity: Severity, message: String, now: Instant) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let items = Arc::make_mut(&mut self.items);
+        items.push(Notification {
+            id,
+            severity,
+            message,
+            expires: if severity.auto_dismisses() { Some(now + AUTO_DISMISS) } else { None },
+        });
+
+        if items.len() > MAX_NOTIFICATIONS {
+            items.remove(0);
+        }
+
+        id
+    }
+
+    pub fn dismiss(&mut self, id: u64) {
+        Arc::make_mut(&mut self.items).retain(|n| n.id != id);
+    }
+
+    /// Drop the notifications that have expired by `now`. Returns whether any that will expire
+    /// later are left.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.items.iter().any(|n| n.expires.map_or(false, |expires| expires <= now)) {
+            Arc::make_mut(&mut self.items)
+                .retain(|n| n.expires.map_or(true, |expires| expires > now));
+        }
+
+        self.items.iter().any(|n| n.expires.is_some())
+    }
+}
+
+/// Show a notification. Can be sent from any thread through an `ExtEventSink`.
+pub fn notify(severity: Severity, message: impl Into<String>) -> Command {
+    NOTIFY.with((severity, message.into()))
+}
+
+/// A column of notifications, each with a close button.
+pub fn make_notification_area() -> impl Widget<NotificationStack> {
+    List::new(make_notification)
+        .lens(NotificationStack::items)
+        .controller(NotificationController { timer: TimerToken::INVALID })
+}
+
+fn make_notification() -> impl Widget<Notification> {
+    let background = Painter::new(|ctx, data: &Notification, _env| {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &data.severity.background_color());
+    });
+
+    let message = Label::new(|data: &Notification, _env: &Env| data.message.clone());
+    let close = Button::new("✕")
+        .on_click(|ctx, data: &mut Notification, _env| {
+            ctx.submit_command(DISMISS_NOTIFICATION.with(data.id));
+        });
+
+    Flex::row()
+        .with_flex_child(message, 1.0)
+        .with_child(close)
+        .padding(4.)
+        .background(background)
+        .padding((0., 2.))
+}
+
+/// Adds and removes notifications in response to commands, and expires them on a timer.
+struct NotificationController {
+    timer: TimerToken,
+}
+
+impl<W: Widget<NotificationStack>> Controller<NotificationStack, W> for NotificationController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event,
+             data: &mut NotificationStack, env: &Env) {
+        match event {
+            Event::Command(cmd) if cmd.is(NOTIFY) => {
+                let (severity, message) = cmd.get_unchecked(NOTIFY).clone();
+                data.push(severity, message, Instant::now());
+
+                if self.timer == TimerToken::INVALID {
+                    self.timer = ctx.request_timer(EXPIRY_CHECK);
+                }
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(DISMISS_NOTIFICATION) => {
+                data.dismiss(*cmd.get_unchecked(DISMISS_NOTIFICATION));
+                ctx.set_handled();
+            }
+            Event::Timer(token) if *token == self.timer => {
+                self.timer = if data.expire(Instant::now()) {
+                    ctx.request_timer(EXPIRY_CHECK)
+                } else {
+                    TimerToken::INVALID
+                };
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(stack: &NotificationStack) -> Vec<&str> {
+        stack.items.iter().map(|n| n.message.as_str()).collect()
+    }
+
+    #[test]
+    fn notifications_stack_up_newest_last() {
+        let now = Instant::now();
+        let mut stack = NotificationStack::default();
+
+        let first = stack.push(Severity::Info, "first".to_string(), now);
+        let second = stack.push(Severity::Error, "second".to_string(), now);
+
+        assert_ne!(first, second);
+        assert_eq!(messages(&stack), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn only_the_newest_notifications_are_kept() {
+        let now = Instant::now();
+        let mut stack = NotificationStack::default();
+
+        for i in 0..MAX_NOTIFICATIONS + 2 {
+            stack.push(Severity::Error, i.to_string(), now);
+        }
+
+        assert_eq!(stack.items.len(), MAX_NOTIFICATIONS);
+        assert_eq!(stack.items[0].message, "2");
+    }
+
+    #[test]
+    fn dismissed_notifications_are_closed() {
+        let now = Instant::now();
+        let mut stack = NotificationStack::default();
+        let first = stack.push(Severity::Error, "first".to_string(), now);
+        stack.push(Severity::Error, "second".to_string(), now);
+
+        stack.dismiss(first);
+        assert_eq!(messages(&stack), vec!["second"]);
+        // Already gone, e.g. clicked twice
+        stack.dismiss(first);
+        assert_eq!(messages(&stack), vec!["second"]);
+    }
+
+    #[test]
+    fn info_and_warnings_expire_but_errors_stay() {
+        let now = Instant::now();
+        let mut stack = NotificationStack::default();
+        stack.push(Severity::Info, "info".to_string(), now);
+        stack.push(Severity::Warning, "warning".to_string(), now + Duration::from_secs(1));
+        stack.push(Severity::Error, "error".to_string(), now);
+
+        assert!(stack.expire(now + AUTO_DISMISS - Duration::from_millis(1)));
+        assert_eq!(messages(&stack), vec!["info", "warning", "error"]);
+
+        // The warning has a second left
+        assert!(stack.expire(now + AUTO_DISMISS));
+        assert_eq!(messages(&stack), vec!["warning", "error"]);
+
+        // Nothing's left to expire
+        assert!(!stack.expire(now + AUTO_DISMISS + Duration::from_secs(1)));
+        assert_eq!(messages(&stack), vec!["error"]);
+    }
+
+    #[test]
+    fn each_severity_has_its_own_color() {
+        let colors: Vec<u32> = [Severity::Info, Severity::Warning, Severity::Error].iter()
+            .map(|severity| severity.background_color().as_rgba_u32())
+            .collect();
+
+        assert_ne!(colors[0], colors[1]);
+        assert_ne!(colors[1], colors[2]);
+        assert_ne!(colors[0], colors[2]);
+        assert_eq!(colors[2], Color::rgb8(150, 30, 30).as_rgba_u32());
+    }
+}
@@ -0,0 +1,111 @@
+use thiserror::Error;
+
+/// Whether the main window can be kept above the others. druid 0.7 has no way to do it, so it's
+/// done straight through the platform, which so far is only Windows.
+pub const SUPPORTED: bool = cfg!(windows);
+
+/// What to do about keeping the window on top when mus starts, given the saved setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StartupOnTop {
+    /// Keep it on top, as it was left.
+    Apply,
+    /// It was left on top, but that can't be done here, e.g. with a database from another
+    /// platform.
+    Unsupported,
+    /// Leave the window as it opens.
+    Leave,
+}
+
+pub fn startup_on_top(saved: bool, supported: bool) -> StartupOnTop {
+    match (saved, supported) {
+        (true, true) => StartupOnTop::Apply,
+        (true, false) => StartupOnTop::Unsupported,
+        (false, _) => StartupOnTop::Leave,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum OnTopError {
+    #[error("The main window couldn't be found")]
+    NoWindow,
+    #[error("Keeping the window on top isn't supported on this platform")]
+    Unsupported,
+    #[error("The window couldn't be moved: error {0}")]
+    Platform(u32),
+}
+
+/// Keep the main window above the others, or stop doing that. Has to be called on the UI thread,
+/// which is where the window is found.
+#[cfg(windows)]
+pub fn set_always_on_top(on_top: bool) -> Result<(), OnTopError> {
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::winuser::{HWND_NOTOPMOST, HWND_TOPMOST, SWP_NOMOVE, SWP_NOSIZE, SetWindowPos};
+
+    let window = windows::main_window().ok_or(OnTopError::NoWindow)?;
+    let after = if on_top { HWND_TOPMOST } else { HWND_NOTOPMOST };
+    // Safe since the handle is one of this thread's windows, which are only closed on it
+    if unsafe { SetWindowPos(window, after, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE) } == 0 {
+        return Err(OnTopError::Platform(unsafe { GetLastError() }));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub fn set_always_on_top(_on_top: bool) -> Result<(), OnTopError> {
+    Err(OnTopError::Unsupported)
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ptr::null_mut;
+
+    use winapi::shared::minwindef::{BOOL, FALSE, LPARAM, TRUE};
+    use winapi::shared::windef::HWND;
+    use winapi::um::processthreadsapi::GetCurrentThreadId;
+    use winapi::um::winuser::{EnumThreadWindows, GetWindow, GetWindowLongW, GWL_STYLE, GW_OWNER,
+                              WS_CAPTION};
+
+    /// The first captioned, unowned window on this thread. That's the main window, since it's
+    /// opened before any other; the thread's other top-level windows, like the one for input
+    /// methods, have no caption.
+    pub fn main_window() -> Option<HWND> {
+        let mut found: HWND = null_mut();
+        // Safe since the callback only writes through the pointer it's given, which outlives it
+        unsafe {
+            EnumThreadWindows(GetCurrentThreadId(), Some(visit),
+                              &mut found as *mut HWND as LPARAM);
+        }
+
+        if found.is_null() { None } else { Some(found) }
+    }
+
+    unsafe extern "system" fn visit(window: HWND, found: LPARAM) -> BOOL {
+        let captioned = GetWindowLongW(window, GWL_STYLE) as u32 & WS_CAPTION == WS_CAPTION;
+        if captioned && GetWindow(window, GW_OWNER).is_null() {
+            *(found as *mut HWND) = window;
+            FALSE
+        } else {
+            TRUE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_saved_setting_is_applied_at_startup_where_supported() {
+        assert_eq!(startup_on_top(true, true), StartupOnTop::Apply);
+        assert_eq!(startup_on_top(true, false), StartupOnTop::Unsupported);
+        assert_eq!(startup_on_top(false, true), StartupOnTop::Leave);
+        assert_eq!(startup_on_top(false, false), StartupOnTop::Leave);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unsupported_platforms_say_so() {
+        assert!(matches!(set_always_on_top(true), Err(OnTopError::Unsupported)));
+    }
+}
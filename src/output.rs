@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use druid::{Env, RenderContext, Widget, WidgetExt};
+use druid::widget::{Flex, Label, List, Painter, Scroll};
+use log::warn;
+use rodio::{Device, OutputStream, OutputStreamHandle, StreamError};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::colors::ALT_BACKGROUND_COLOR;
+use crate::commands::SET_OUTPUT_DEVICE;
+
+/// Names of the output devices that can be played through right now. Goes through rodio's
+/// copy of cpal, so the devices are ones rodio can open.
+pub fn output_devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            warn!("Could not list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Open the output device called `name`, or the default one if that's None. If the device is
+/// gone, e.g. because it was unplugged, this falls back to the default too.
+pub fn open_output(name: Option<&str>) -> Result<(OutputStream, OutputStreamHandle), StreamError> {
+    if let Some(name) = name {
+        match find_device(name) {
+            Some(device) => match OutputStream::try_from_device(&device) {
+                Ok(output) => return Ok(output),
+                Err(e) => warn!("Could not open output device {}, using the default instead: {}",
+                                name, e),
+            },
+            None => warn!("Output device {} isn't available, using the default instead", name),
+        }
+    }
+
+    OutputStream::try_default()
+}
+
+fn find_device(name: &str) -> Option<Device> {
+    rodio::cpal::default_host().output_devices().ok()?
+        .find(|device| device.name().map_or(false, |device_name| device_name == name))
+}
+
+/// The system default and every output device, with the one picked highlighted. Clicking one
+/// switches to it. Each row needs to know which is picked, to highlight it.
+pub fn make_output_device_dialog() -> impl Widget<(Option<String>, Arc<Vec<String>>)> {
+    let default_background = Painter::new(
+        |ctx, (picked, _): &(Option<String>, Arc<Vec<String>>), env| {
+            if picked.is_none() {
+                let bounds = ctx.size().to_rect();
+                ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+            }
+        });
+
+    let default = Label::new("System Default")
+        .expand_width()
+        .padding((6., 3.))
+        .background(default_background)
+        .on_click(|ctx, _data: &mut (Option<String>, Arc<Vec<String>>), _env| {
+            ctx.submit_command(SET_OUTPUT_DEVICE.with(None));
+        });
+
+    Flex::column()
+        .with_child(default)
+        .with_flex_child(Scroll::new(List::new(make_device_entry)).vertical(), 1.0)
+        .padding(10.)
+}
+
+fn make_device_entry() -> impl Widget<(Option<String>, String)> {
+    let background = Painter::new(|ctx, (picked, device): &(Option<String>, String), env| {
+        if picked.as_ref() == Some(device) {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    Label::new(|(_, device): &(Option<String>, String), _env: &Env| device.clone())
+        .expand_width()
+        .padding((6., 3.))
+        .background(background)
+        .on_click(|ctx, (_, device): &mut (Option<String>, String), _env| {
+            ctx.submit_command(SET_OUTPUT_DEVICE.with(Some(device.clone())));
+        })
+}
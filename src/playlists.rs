@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use druid::{ContextMenu, Data, Env, Event, EventCtx, Lens, LocalizedString, MenuDesc, MenuItem,
+            MouseButton, RenderContext, Widget, WidgetExt};
+use druid::lens;
+use druid::widget::{Button, Controller, Flex, Label, LineBreaking, List, Painter, Scroll, TextBox};
+
+use crate::AppData;
+use crate::browse::make_artist_tree;
+use crate::colors::{ALT_BACKGROUND_COLOR, SECONDARY_TEXT_COLOR};
+use crate::commands::{ADD_SELECTION_TO_PLAYLIST, DELETE_PLAYLIST, EDIT_SMART_PLAYLIST, NEW_PLAYLIST,
+                      NEW_SMART_PLAYLIST, SAVE_SMART_PLAYLIST, SHOW_VIEW};
+use crate::criteria::{format_criteria, parse_criteria, Criterion};
+use crate::db::Playlist;
+use crate::libraries::SidebarLibrary;
+use crate::settings::View;
+
+/// Marks smart playlists in the sidebar.
+const SMART_BADGE: &str = "⚙";
+/// How far the libraries are indented under 'All Libraries'.
+const INDENT: f64 = 12.0;
+
+/// A playlist as listed in the sidebar.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct PlaylistEntry {
+    id: i64,
+    name: String,
+    smart: bool,
+}
+
+pub fn playlist_entries(playlists: Vec<Playlist>) -> Arc<Vec<PlaylistEntry>> {
+    Arc::new(playlists.into_iter()
+        .map(|playlist| PlaylistEntry {
+            id: playlist.id(),
+            name: playlist.name().clone(),
+            smart: playlist.is_smart(),
+        })
+        .collect())
+}
+
+/// The smart playlist dialog's fields.
+#[derive(Debug, Clone, Data, Lens, Default)]
+pub struct SmartPlaylistForm {
+    /// None for a new smart playlist.
+    playlist: Option<i64>,
+    name: String,
+    /// As written for `parse_criteria`.
+    criteria: String,
+}
+
+impl SmartPlaylistForm {
+    pub fn new(name: String) -> Self {
+        SmartPlaylistForm {
+            playlist: None,
+            name,
+            criteria: String::new(),
+        }
+    }
+
+    pub fn edit(id: i64, name: String, criteria: &[Criterion]) -> Self {
+        SmartPlaylistForm {
+            playlist: Some(id),
+            name,
+            criteria: format_criteria(criteria),
+        }
+    }
+
+    pub fn playlist(&self) -> Option<i64> {
+        self.playlist
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn criteria(&self) -> Result<Vec<Criterion>, String> {
+        parse_criteria(&self.criteria)
+    }
+}
+
+pub fn make_smart_playlist_dialog() -> impl Widget<SmartPlaylistForm> {
+    Flex::column()
+        .with_child(Flex::row()
+            .with_child(Label::new("Name").fix_width(60.))
+            .with_flex_child(TextBox::new().expand_width().lens(SmartPlaylistForm::name), 1.0))
+        .with_spacer(8.)
+        .with_child(Flex::row()
+            .with_child(Label::new("Tracks").fix_width(60.))
+            .with_flex_child(TextBox::new()
+                .with_placeholder("rating >= 4 and genre = Jazz")
+                .expand_width()
+                .lens(SmartPlaylistForm::criteria), 1.0))
+        .with_spacer(4.)
+        .with_child(Label::new("Fields can be compared with =, !=, <, <=, >, >= or contains. \
+                                Put quotes around values with spaces.")
+            .with_text_color(SECONDARY_TEXT_COLOR)
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .align_left())
+        .with_spacer(8.)
+        .with_child(Button::new("Save")
+            .on_click(|ctx, _data: &mut SmartPlaylistForm, _env| {
+                ctx.submit_command(SAVE_SMART_PLAYLIST.with(ctx.window_id()));
+            })
+            .align_right())
+        .padding(10.)
+}
+
+/// Every library together, each library on its own, the play history views, every playlist, and
+/// the artist tree. Clicking one shows it in the main track list.
+pub fn make_sidebar() -> impl Widget<AppData> {
+    let library_background = Painter::new(|ctx, data: &AppData, env| {
+        if data.view == View::Library && data.main_tracklist_data.scope().is_none() {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    let library = Label::new("All Libraries")
+        .expand_width()
+        .padding((6., 3.))
+        .background(library_background)
+        .on_click(|ctx, _data: &mut AppData, _env| ctx.submit_command(SHOW_VIEW.with(View::Library)));
+
+    // Each row needs to know what's being shown, to highlight it
+    let libraries = List::new(make_library_link)
+        .lens(lens::Map::new(
+            |data: &AppData| (data.view, data.sidebar_libraries.clone()),
+            |_data: &mut AppData, _libraries| ()))
+        .padding((INDENT, 0., 0., 0.));
+
+    let playlists = List::new(make_playlist_entry)
+        .lens(lens::Map::new(
+            |data: &AppData| (data.view, data.playlists.clone()),
+            |_data: &mut AppData, _playlists| ()));
+
+    let artists = make_artist_tree()
+        .lens(lens::Map::new(
+            |data: &AppData| (data.main_tracklist_data.scope().cloned(), data.artists.clone()),
+            |_data: &mut AppData, _artists| ()));
+
+    Flex::column()
+        .with_child(library)
+        .with_child(libraries)
+        .with_spacer(8.)
+        .with_child(make_view_link("Most Played", View::MostPlayed))
+        .with_child(make_view_link("Recently Played", View::RecentlyPlayed))
+        .with_spacer(8.)
+        .with_child(Label::new("Playlists").align_left())
+        .with_flex_child(Scroll::new(playlists).vertical(), 1.0)
+        .with_child(Button::new("New Playlist")
+            .on_click(|ctx, _data: &mut AppData, _env| ctx.submit_command(NEW_PLAYLIST)))
+        .with_child(Button::new("New Smart Playlist")
+            .on_click(|ctx, _data: &mut AppData, _env| ctx.submit_command(NEW_SMART_PLAYLIST)))
+        .with_spacer(8.)
+        .with_child(Label::new("Artists").align_left())
+        .with_flex_child(Scroll::new(artists).vertical(), 1.0)
+}
+
+/// A link to a view that's always there, highlighted while it's shown.
+fn make_view_link(name: &str, view: View) -> impl Widget<AppData> {
+    let background = Painter::new(move |ctx, data: &AppData, env| {
+        if data.view == view {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    Label::new(name)
+        .expand_width()
+        .padding((6., 3.))
+        .background(background)
+        .on_click(move |ctx, _data: &mut AppData, _env| ctx.submit_command(SHOW_VIEW.with(view)))
+}
+
+fn make_library_link() -> impl Widget<(View, SidebarLibrary)> {
+    let background = Painter::new(|ctx, (view, library): &(View, SidebarLibrary), env| {
+        if *view == View::SingleLibrary(library.id()) {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    let status = Label::new(|(_, library): &(View, SidebarLibrary), _env: &Env| {
+            library.status().badge()
+        })
+        .with_text_color(SECONDARY_TEXT_COLOR);
+
+    Flex::row()
+        .with_flex_child(Label::new(|(_, library): &(View, SidebarLibrary), _env: &Env| {
+                library.name().to_string()
+            })
+            .expand_width(), 1.0)
+        .with_child(status)
+        .padding((6., 3.))
+        .background(background)
+        .on_click(|ctx, (_, library): &mut (View, SidebarLibrary), _env| {
+            ctx.submit_command(SHOW_VIEW.with(View::SingleLibrary(library.id())));
+        })
+}
+
+fn make_playlist_entry() -> impl Widget<(View, PlaylistEntry)> {
+    let background = Painter::new(|ctx, (view, entry): &(View, PlaylistEntry), env| {
+        if *view == View::Playlist(entry.id) {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(ALT_BACKGROUND_COLOR));
+        }
+    });
+
+    Label::new(|(_, entry): &(View, PlaylistEntry), _env: &Env| if entry.smart {
+            format!("{} {}", SMART_BADGE, entry.name)
+        } else {
+            entry.name.clone()
+        })
+        .expand_width()
+        .padding((6., 3.))
+        .background(background)
+        .on_click(|ctx, (_, entry): &mut (View, PlaylistEntry), _env| {
+            ctx.submit_command(SHOW_VIEW.with(View::Playlist(entry.id)));
+        })
+        .controller(PlaylistMenuController)
+}
+
+/// Smart playlists pick their own tracks, so they can be edited but not added to.
+fn playlist_menu(id: i64, smart: bool) -> MenuDesc<AppData> {
+    MenuDesc::empty()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-add-to-playlist").with_placeholder("Add Selection Here"),
+            ADD_SELECTION_TO_PLAYLIST.with(id),
+        ).disabled_if(move || smart))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-edit-smart-playlist").with_placeholder("Edit Smart Playlist…"),
+            EDIT_SMART_PLAYLIST.with(id),
+        ).disabled_if(move || !smart))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-delete-playlist").with_placeholder("Delete Playlist"),
+            DELETE_PLAYLIST.with(id),
+        ))
+}
+
+/// Shows the playlist menu on right click.
+struct PlaylistMenuController;
+
+impl<W: Widget<(View, PlaylistEntry)>> Controller<(View, PlaylistEntry), W> for PlaylistMenuController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event,
+             data: &mut (View, PlaylistEntry), env: &Env) {
+        match event {
+            Event::MouseDown(evt) if evt.button == MouseButton::Right => {
+                ctx.show_context_menu(ContextMenu::new(playlist_menu(data.1.id, data.1.smart),
+                                                     evt.window_pos));
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use druid::Data;
+use rodio::Source;
+
+use crate::decode::TrackSource;
+
+/// How many samples are decoded between checks that a prefetch is still wanted.
+const CANCEL_CHECK_SAMPLES: usize = 4096;
+
+/// How many samples `amount` of a track at `sample_rate` with `channels` is, in whole frames.
+pub fn prefetch_samples(amount: Duration, sample_rate: u32, channels: u16) -> usize {
+    let frames = (amount.as_secs_f64() * sample_rate as f64).ceil() as usize;
+    frames * channels as usize
+}
+
+/// Decodes the start of the next track ahead of time on a background thread, so it's ready
+/// to play the moment it comes on rather than waiting on its file. Only one track is ever
+/// prefetched; asking for another, or cancelling, discards what was staged for the last.
+#[derive(Clone, Default)]
+pub struct Prefetcher(Arc<Mutex<PrefetchState>>);
+
+#[derive(Default)]
+struct PrefetchState {
+    /// Counts up with every prefetch started or cancelled, so work on an old one is discarded.
+    generation: u64,
+    /// The id of the track being prefetched, if any.
+    target: Option<i64>,
+    staged: Option<Staged>,
+}
+
+/// The start of a track, decoded, and the decoder to carry on from.
+struct Staged {
+    buffered: Vec<i16>,
+    rest: TrackSource,
+}
+
+impl Data for Prefetcher {
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Prefetcher {
+    /// Decode `amount` of the track `track_id`, opened by `open`, in the background. Does
+    /// nothing if that track's already being prefetched.
+    pub fn prefetch(&self, track_id: i64, amount: Duration,
+                    open: impl FnOnce() -> Option<TrackSource> + Send + 'static) {
+        let generation = match self.begin(track_id) {
+            Some(generation) => generation,
+            None => return,
+        };
+
+        let prefetcher = self.clone();
+        thread::spawn(move || {
+            let mut rest = match open() {
+                Some(source) => source,
+                None => return,
+            };
+
+            let wanted = prefetch_samples(amount, rest.sample_rate(), rest.channels());
+            let mut buffered = Vec::with_capacity(wanted);
+            while buffered.len() < wanted {
+                let checking = buffered.len() % CANCEL_CHECK_SAMPLES == 0;
+                if checking && !prefetcher.is_current(generation) {
+                    return;
+                }
+                match rest.next() {
+                    Some(sample) => buffered.push(sample),
+                    None => break,
+                }
+            }
+
+            prefetcher.stage(generation, Staged { buffered, rest });
+        });
+    }
+
+    /// Stop prefetching, and discard anything staged.
+    pub fn cancel(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.generation += 1;
+        state.target = None;
+        state.staged = None;
+    }
+
+    /// The prefetched track `track_id`, to play from its start, if it's been staged. Taking it
+    /// ends the prefetch either way, since the track is about to be opened. Other tracks that
+    /// are staged are left alone.
+    pub fn take(&self, track_id: i64) -> Option<TrackSource> {
+        let staged = {
+            let mut state = self.0.lock().unwrap();
+            if state.target != Some(track_id) {
+                return None;
+            }
+            state.staged.take()
+        };
+        self.cancel();
+
+        staged.map(|staged| -> TrackSource {
+            Box::new(Prefetched {
+                channels: staged.rest.channels(),
+                sample_rate: staged.rest.sample_rate(),
+                total_duration: staged.rest.total_duration(),
+                buffered: staged.buffered.into_iter(),
+                rest: staged.rest,
+            })
+        })
+    }
+
+    /// Start prefetching `track_id` in place of whatever was, returning the generation to stage
+    /// it under. None if it's already being prefetched.
+    fn begin(&self, track_id: i64) -> Option<u64> {
+        let mut state = self.0.lock().unwrap();
+        if state.target == Some(track_id) {
+            return None;
+        }
+
+        state.generation += 1;
+        state.target = Some(track_id);
+        state.staged = None;
+        Some(state.generation)
+    }
+
+    fn is_current(&self, generation: u64) -> bool {
+        self.0.lock().unwrap().generation == generation
+    }
+
+    /// Keep `staged` for `take`, unless the prefetch it's from has since been cancelled or
+    /// replaced. Returns whether it was kept.
+    fn stage(&self, generation: u64, staged: Staged) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if state.generation != generation {
+            return false;
+        }
+
+        state.staged = Some(staged);
+        true
+    }
+}
+
+/// A prefetched track: what was decoded ahead of time, then the rest as it's decoded.
+struct Prefetched {
+    buffered: std::vec::IntoIter<i16>,
+    rest: TrackSource,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+}
+
+impl Iterator for Prefetched {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        self.buffered.next().or_else(|| self.rest.next())
+    }
+}
+
+impl Source for Prefetched {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    fn source(samples: &[i16]) -> TrackSource {
+        Box::new(SamplesBuffer::new(2, 44100, samples.to_vec()))
+    }
+
+    fn staged(buffered: &[i16], rest: &[i16]) -> Staged {
+        Staged { buffered: buffered.to_vec(), rest: source(rest) }
+    }
+
+    #[test]
+    fn prefetches_are_whole_frames() {
+        assert_eq!(prefetch_samples(Duration::from_secs(10), 44100, 2), 882_000);
+        assert_eq!(prefetch_samples(Duration::from_millis(500), 48000, 1), 24000);
+        // Part of a frame rounds up to the whole of it
+        assert_eq!(prefetch_samples(Duration::from_micros(10), 44100, 2), 2);
+        assert_eq!(prefetch_samples(Duration::default(), 44100, 2), 0);
+    }
+
+    #[test]
+    fn staged_tracks_play_on_from_where_the_prefetch_stopped() {
+        let prefetcher = Prefetcher::default();
+        let generation = prefetcher.begin(1).unwrap();
+        assert!(prefetcher.stage(generation, staged(&[1, 2], &[3, 4])));
+
+        let played = prefetcher.take(1).unwrap();
+        assert_eq!((played.channels(), played.sample_rate()), (2, 44100));
+        assert_eq!(played.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        // Only once
+        assert!(prefetcher.take(1).is_none());
+    }
+
+    #[test]
+    fn cancelling_discards_what_was_staged() {
+        let prefetcher = Prefetcher::default();
+        let generation = prefetcher.begin(1).unwrap();
+        prefetcher.stage(generation, staged(&[1, 2], &[3, 4]));
+
+        prefetcher.cancel();
+        assert!(prefetcher.take(1).is_none());
+    }
+
+    #[test]
+    fn prefetches_cancelled_partway_are_never_staged() {
+        let prefetcher = Prefetcher::default();
+        let generation = prefetcher.begin(1).unwrap();
+        prefetcher.cancel();
+
+        assert!(!prefetcher.stage(generation, staged(&[1, 2], &[3, 4])));
+        assert!(prefetcher.take(1).is_none());
+
+        // Nor are ones for a track that's no longer next
+        let generation = prefetcher.begin(1).unwrap();
+        prefetcher.begin(2).unwrap();
+        assert!(!prefetcher.stage(generation, staged(&[1, 2], &[3, 4])));
+        assert!(prefetcher.take(1).is_none());
+    }
+
+    #[test]
+    fn only_the_prefetched_track_is_taken() {
+        let prefetcher = Prefetcher::default();
+        let generation = prefetcher.begin(1).unwrap();
+        prefetcher.stage(generation, staged(&[1], &[2]));
+
+        // e.g. seeking in the current track
+        assert!(prefetcher.take(2).is_none());
+        assert!(prefetcher.take(1).is_some());
+    }
+
+    #[test]
+    fn prefetching_the_same_track_again_keeps_it() {
+        let prefetcher = Prefetcher::default();
+        let generation = prefetcher.begin(1).unwrap();
+        prefetcher.stage(generation, staged(&[1], &[2]));
+
+        assert!(prefetcher.begin(1).is_none());
+        assert!(prefetcher.take(1).is_some());
+    }
+
+    #[test]
+    fn prefetching_decodes_the_amount_in_the_background() {
+        let prefetcher = Prefetcher::default();
+        let samples: Vec<i16> = (0..1000).collect();
+        let track = samples.clone();
+        // 100 frames of 2 channels
+        prefetcher.prefetch(1, Duration::from_secs(100) / 44100, move || Some(source(&track)));
+
+        let started = Instant::now();
+        while prefetcher.0.lock().unwrap().staged.is_none() {
+            assert!(started.elapsed() < Duration::from_secs(10), "nothing was staged");
+            thread::yield_now();
+        }
+        assert_eq!(prefetcher.0.lock().unwrap().staged.as_ref().unwrap().buffered.len(), 200);
+        assert_eq!(prefetcher.take(1).unwrap().collect::<Vec<_>>(), samples);
+    }
+}
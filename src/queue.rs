@@ -0,0 +1,633 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use druid::{BoxConstraints, ContextMenu, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle,
+            LifeCycleCtx, LocalizedString, MenuDesc, MenuItem, MouseButton, PaintCtx, Point, Rect,
+            RenderContext, Size, UpdateCtx, Widget, WidgetExt};
+use druid::theme::SELECTION_COLOR;
+use druid::widget::{Either, Flex, Label, List, Scroll};
+use walkdir::WalkDir;
+
+use crate::AppData;
+use crate::collation::compare_text;
+use crate::colors::SECONDARY_TEXT_COLOR;
+use crate::commands::{INSERT_FILE_INTO_QUEUE, INSERT_FOLDER_INTO_QUEUE, MOVE_QUEUE_ENTRY};
+use crate::db::Track;
+
+/// Every row in the queue window is this tall, so where something's dropped comes straight from
+/// how far down it is.
+const ROW_HEIGHT: f64 = 22.;
+/// How thick the line showing where a drag will drop is.
+const DROP_LINE_WIDTH: f64 = 2.;
+
+/// How far playback has got through an entry in the queue.
+#[derive(Debug, Copy, Clone, Data, PartialEq)]
+pub enum EntryState {
+    Played,
+    Playing,
+    Upcoming,
+}
+
+/// The tracks lined up to play, and which of them is playing.
+#[derive(Debug, Clone, Data, Default)]
+pub struct Queue {
+    tracks: Arc<Vec<Track>>,
+    /// None when nothing is playing.
+    current: Option<usize>,
+}
+
+impl Queue {
+    /// A queue saved from an earlier session. An index past the end means nothing is playing.
+    pub fn restore(tracks: Vec<Track>, current: Option<usize>) -> Self {
+        Queue {
+            current: current.filter(|&current| current < tracks.len()),
+            tracks: Arc::new(tracks),
+        }
+    }
+
+    /// Add tracks to the end of the queue.
+    pub fn enqueue(&mut self, tracks: impl IntoIterator<Item = Track>) {
+        Arc::make_mut(&mut self.tracks).extend(tracks);
+    }
+
+    /// Put tracks in the queue before the entry at `index`, or at the end if that's past it. The
+    /// same track keeps playing, even if they go in before it.
+    pub fn insert(&mut self, index: usize, tracks: Vec<Track>) {
+        let index = index.min(self.tracks.len());
+        let count = tracks.len();
+        Arc::make_mut(&mut self.tracks).splice(index..index, tracks);
+
+        if let Some(current) = self.current.as_mut().filter(|current| index <= **current) {
+            *current += count;
+        }
+    }
+
+    /// Move the entry at `from` to the gap before the entry at `to`, where `to` counts entries
+    /// from before the move, so `tracks().len()` is the end. The same track keeps playing.
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        let len = self.tracks.len();
+        if from >= len {
+            return;
+        }
+        // Taking the entry out moves everything after it up one
+        let to = if to > from { to.min(len) - 1 } else { to };
+
+        let tracks = Arc::make_mut(&mut self.tracks);
+        let track = tracks.remove(from);
+        tracks.insert(to, track);
+
+        self.current = self.current.map(|current| {
+            if current == from {
+                to
+            } else if from < current && current <= to {
+                current - 1
+            } else if to <= current && current < from {
+                current + 1
+            } else {
+                current
+            }
+        });
+    }
+
+    /// Put tracks right after the current one and skip to the first of them.
+    pub fn play_now(&mut self, tracks: Vec<Track>) -> Option<&Track> {
+        if tracks.is_empty() {
+            return self.current();
+        }
+
+        let index = self.current.map_or(self.tracks.len(), |current| current + 1);
+        Arc::make_mut(&mut self.tracks).splice(index..index, tracks);
+        self.current = Some(index);
+
+        self.current()
+    }
+
+    /// Move on to the next track. Advancing past the end clears the queue and stops. When
+    /// nothing is playing, starts from the beginning.
+    pub fn play_next(&mut self) -> Option<&Track> {
+        let next = self.current.map_or(0, |current| current + 1);
+
+        if next < self.tracks.len() {
+            self.current = Some(next);
+        } else {
+            self.tracks = Arc::new(Vec::new());
+            self.current = None;
+        }
+
+        self.current()
+    }
+
+    /// Reorder the tracks still to come with `shuffle`, leaving the current track and those
+    /// played before it where they are. With nothing playing, that's the whole queue.
+    pub fn shuffle_upcoming(&mut self, shuffle: impl FnOnce(Vec<Track>) -> Vec<Track>) {
+        let start = self.current.map_or(0, |current| current + 1).min(self.tracks.len());
+        let tracks = Arc::make_mut(&mut self.tracks);
+        let upcoming = shuffle(tracks.split_off(start));
+        tracks.extend(upcoming);
+    }
+
+    /// Swap in new copies of the tracks in `updated`, wherever they are in the queue.
+    pub fn update_tracks(&mut self, updated: &[Track]) {
+        for entry in Arc::make_mut(&mut self.tracks).iter_mut() {
+            if let Some(track) = updated.iter().find(|track| track.id() == entry.id()) {
+                *entry = track.clone();
+            }
+        }
+    }
+
+    /// Go back a track. At the first track, this restarts it instead.
+    pub fn play_previous(&mut self) -> Option<&Track> {
+        if let Some(current) = self.current {
+            self.current = Some(current.saturating_sub(1));
+        }
+
+        self.current()
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.current.and_then(|current| self.tracks.get(current))
+    }
+
+    /// The track `play_next` would move on to, if anything's playing.
+    pub fn next(&self) -> Option<&Track> {
+        self.current.and_then(|current| self.tracks.get(current + 1))
+    }
+
+    /// Whether the entry at `index` has played, is playing or is still to come. Like
+    /// `position_text`, that's by the order the tracks play in, shuffled or not.
+    pub fn entry_state(&self, index: usize) -> EntryState {
+        match self.current {
+            Some(current) if index < current => EntryState::Played,
+            Some(current) if index == current => EntryState::Playing,
+            _ => EntryState::Upcoming,
+        }
+    }
+
+    /// Where the current track is in the queue, like "3 / 27", or None if nothing's playing.
+    /// Shuffling reorders the queue itself, so this is always the position in the order the
+    /// tracks play in.
+    pub fn position_text(&self) -> Option<String> {
+        self.current.map(|current| format!("{} / {}", current + 1, self.tracks.len()))
+    }
+}
+
+/// One entry in the queue window.
+#[derive(Debug, Clone, Data)]
+pub struct QueueRow {
+    state: EntryState,
+    paused: bool,
+    title: String,
+}
+
+impl QueueRow {
+    fn marker(&self) -> &'static str {
+        match (self.state, self.paused) {
+            (EntryState::Playing, false) => "▶",
+            (EntryState::Playing, true) => "⏸",
+            _ => "",
+        }
+    }
+}
+
+/// What the queue window shows for `queue`, as it plays or while it's `paused`.
+pub fn queue_rows(queue: &Queue, paused: bool) -> Arc<Vec<QueueRow>> {
+    Arc::new(queue.tracks().iter().enumerate()
+        .map(|(index, track)| QueueRow {
+            state: queue.entry_state(index),
+            paused,
+            title: format!("{} — {}", track.title().unwrap_or("Unknown Title"),
+                           track.artist().unwrap_or("Unknown Artist")),
+        })
+        .collect())
+}
+
+/// Where between the queue's `rows` something dropped `y` down them goes, as the index of the
+/// entry it goes before: the row it's over if it's in the top half, or the next if the bottom.
+pub fn drop_index(y: f64, row_height: f64, rows: usize) -> usize {
+    // Above the top, or not a number at all
+    if !(y > 0.) {
+        return 0;
+    }
+
+    ((y / row_height).round() as usize).min(rows)
+}
+
+/// Every file in `paths`, with folders swapped for the files in them and their subfolders. Each
+/// folder's entries are in natural order, so tracks named by number play in order, and its
+/// subfolders' files go where the subfolders come in that order. Hidden files are left out.
+pub fn expand_folders(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths.iter()
+        .flat_map(|path| {
+            WalkDir::new(path)
+                .sort_by(|a, b| compare_text(&a.file_name().to_string_lossy(),
+                                             &b.file_name().to_string_lossy()))
+                .into_iter()
+                .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.path()))
+                .filter_map(Result::ok)
+                .filter(|entry| !entry.file_type().is_dir())
+                .map(|entry| entry.into_path())
+        })
+        .collect()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().map_or(false, |name| name.to_string_lossy().starts_with('.'))
+}
+
+/// The queue in play order, with a marker on what's playing and what's already played dimmed.
+/// Entries can be dragged to reorder them, and files or folders put in between them from the
+/// context menu. druid doesn't pass on files dropped from outside the app, so that's the way in
+/// for those; files and folders dropped on mus itself are queued through `cli`.
+pub fn make_queue_view() -> impl Widget<Arc<Vec<QueueRow>>> {
+    let rows = Scroll::new(QueueRows::new(List::new(make_queue_row))).vertical();
+
+    Flex::column()
+        .with_child(Label::new(|rows: &Arc<Vec<QueueRow>>, _env: &Env| match rows.len() {
+                0 => "Nothing's queued.".to_string(),
+                1 => "1 track in the queue".to_string(),
+                n => format!("{} tracks in the queue", n),
+            })
+            .align_left())
+        .with_spacer(8.)
+        .with_flex_child(rows, 1.0)
+        .padding(10.)
+}
+
+fn make_queue_row() -> impl Widget<QueueRow> {
+    let title = Either::new(
+        |row: &QueueRow, _env| row.state == EntryState::Played,
+        Label::new(|row: &QueueRow, _env: &Env| row.title.clone())
+            .with_text_color(SECONDARY_TEXT_COLOR),
+        Label::new(|row: &QueueRow, _env: &Env| row.title.clone()));
+
+    Flex::row()
+        .with_child(Label::new(|row: &QueueRow, _env: &Env| row.marker().to_string())
+            .fix_width(20.))
+        .with_flex_child(title.align_left(), 1.0)
+        .fix_height(ROW_HEIGHT)
+}
+
+/// The queue's rows, which can be dragged to reorder them and right-clicked to put files in
+/// between.
+struct QueueRows<W> {
+    rows: W,
+    /// The entry being dragged, and the gap it would drop into, once it's moved.
+    dragging: Option<(usize, Option<usize>)>,
+}
+
+impl<W> QueueRows<W> {
+    fn new(rows: W) -> Self {
+        QueueRows { rows, dragging: None }
+    }
+}
+
+impl<W: Widget<Arc<Vec<QueueRow>>>> Widget<Arc<Vec<QueueRow>>> for QueueRows<W> {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Arc<Vec<QueueRow>>,
+             env: &Env) {
+        match event {
+            Event::MouseDown(evt) if evt.button == MouseButton::Left => {
+                let row = (evt.pos.y / ROW_HEIGHT).floor();
+                if row >= 0. && (row as usize) < data.len() {
+                    self.dragging = Some((row as usize, None));
+                    ctx.set_active(true);
+                }
+            }
+            Event::MouseMove(evt) if ctx.is_active() => {
+                if let Some((from, _)) = self.dragging {
+                    let to = drop_index(evt.pos.y, ROW_HEIGHT, data.len());
+                    self.dragging = Some((from, Some(to)));
+                    ctx.request_paint();
+                }
+            }
+            Event::MouseUp(evt) if evt.button == MouseButton::Left && ctx.is_active() => {
+                ctx.set_active(false);
+                // Dropping either side of itself leaves it where it was
+                if let Some((from, Some(to))) = self.dragging.take() {
+                    if to != from && to != from + 1 {
+                        ctx.submit_command(MOVE_QUEUE_ENTRY.with((from, to)));
+                    }
+                }
+                ctx.request_paint();
+            }
+            Event::MouseDown(evt) if evt.button == MouseButton::Right => {
+                let index = drop_index(evt.pos.y, ROW_HEIGHT, data.len());
+                ctx.show_context_menu(ContextMenu::new(insert_menu(index), evt.window_pos));
+            }
+            _ => (),
+        }
+
+        self.rows.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle,
+                 data: &Arc<Vec<QueueRow>>, env: &Env) {
+        self.rows.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Arc<Vec<QueueRow>>,
+              data: &Arc<Vec<QueueRow>>, env: &Env) {
+        self.rows.update(ctx, old_data, data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &Arc<Vec<QueueRow>>,
+              env: &Env) -> Size {
+        self.rows.layout(ctx, bc, data, env)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Arc<Vec<QueueRow>>, env: &Env) {
+        self.rows.paint(ctx, data, env);
+
+        if let Some((_, Some(to))) = self.dragging {
+            let y = to as f64 * ROW_HEIGHT - DROP_LINE_WIDTH / 2.;
+            let line = Rect::from_origin_size(Point::new(0., y.max(0.)),
+                                              (ctx.size().width, DROP_LINE_WIDTH));
+            ctx.fill(line, &env.get(SELECTION_COLOR));
+        }
+    }
+}
+
+/// What right-clicking the queue at the gap before `index` offers.
+fn insert_menu(index: usize) -> MenuDesc<AppData> {
+    MenuDesc::empty()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-insert-file").with_placeholder("Insert File Here…"),
+            INSERT_FILE_INTO_QUEUE.with(index),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-insert-folder")
+                .with_placeholder("Insert Folder Here…"),
+            INSERT_FOLDER_INTO_QUEUE.with(index),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn queue_of(ids: &[i64]) -> Queue {
+        let mut queue = Queue::default();
+        queue.enqueue(ids.iter().map(|&id| Track::for_test(id, &id.to_string())));
+        queue
+    }
+
+    fn current_id(queue: &Queue) -> Option<i64> {
+        queue.current().map(Track::id)
+    }
+
+    fn ids(queue: &Queue) -> Vec<i64> {
+        queue.tracks().iter().map(Track::id).collect()
+    }
+
+    #[test]
+    fn play_next_starts_from_the_beginning() {
+        let mut queue = queue_of(&[1, 2]);
+        assert_eq!(current_id(&queue), None);
+        assert_eq!(queue.next().map(Track::id), None);
+
+        assert_eq!(queue.play_next().map(Track::id), Some(1));
+        assert_eq!(queue.next().map(Track::id), Some(2));
+        assert_eq!(queue.play_next().map(Track::id), Some(2));
+        assert_eq!(queue.next().map(Track::id), None);
+    }
+
+    #[test]
+    fn updated_tracks_are_swapped_in_wherever_they_are_queued() {
+        let mut queue = queue_of(&[1, 2, 1]);
+        queue.play_next();
+
+        queue.update_tracks(&[Track::for_test(1, "One"), Track::for_test(3, "Three")]);
+        let titles: Vec<_> = queue.tracks().iter().map(|track| track.title().unwrap()).collect();
+        assert_eq!(titles, vec!["One", "2", "One"]);
+        assert_eq!(current_id(&queue), Some(1));
+    }
+
+    #[test]
+    fn advancing_past_the_end_clears_the_queue() {
+        let mut queue = queue_of(&[1]);
+        queue.play_next();
+
+        assert!(queue.play_next().is_none());
+        assert!(queue.tracks().is_empty());
+        assert_eq!(queue.current_index(), None);
+    }
+
+    #[test]
+    fn play_previous_restarts_the_first_track() {
+        let mut queue = queue_of(&[1, 2]);
+        assert!(queue.play_previous().is_none());
+
+        queue.play_next();
+        queue.play_next();
+        assert_eq!(queue.play_previous().map(Track::id), Some(1));
+        assert_eq!(queue.play_previous().map(Track::id), Some(1));
+    }
+
+    #[test]
+    fn play_now_inserts_after_the_current_track() {
+        let mut queue = queue_of(&[1, 2]);
+        queue.play_next();
+
+        let now = vec![Track::for_test(3, "3"), Track::for_test(4, "4")];
+        assert_eq!(queue.play_now(now).map(Track::id), Some(3));
+        assert_eq!(ids(&queue), vec![1, 3, 4, 2]);
+        assert_eq!(queue.next().map(Track::id), Some(4));
+
+        // Nothing to play keeps playing what was
+        assert_eq!(queue.play_now(Vec::new()).map(Track::id), Some(3));
+    }
+
+    #[test]
+    fn play_now_with_nothing_playing_appends() {
+        let mut queue = queue_of(&[1]);
+
+        assert_eq!(queue.play_now(vec![Track::for_test(2, "2")]).map(Track::id), Some(2));
+        assert_eq!(ids(&queue), vec![1, 2]);
+        assert_eq!(queue.current_index(), Some(1));
+    }
+
+    #[test]
+    fn the_position_counts_from_one() {
+        let mut queue = queue_of(&[1, 2, 3]);
+        assert_eq!(queue.position_text(), None);
+
+        queue.play_next();
+        assert_eq!(queue.position_text().as_deref(), Some("1 / 3"));
+        queue.play_next();
+        queue.play_next();
+        assert_eq!(queue.position_text().as_deref(), Some("3 / 3"));
+
+        queue.play_now(vec![Track::for_test(4, "4")]);
+        assert_eq!(queue.position_text().as_deref(), Some("4 / 4"));
+    }
+
+    #[test]
+    fn an_empty_queue_has_no_position() {
+        let mut queue = Queue::default();
+        assert_eq!(queue.position_text(), None);
+
+        let mut finished = queue_of(&[1]);
+        finished.play_next();
+        finished.play_next();
+        assert_eq!(finished.position_text(), None);
+
+        queue.play_next();
+        assert_eq!(queue.position_text(), None);
+    }
+
+    fn states(queue: &Queue) -> Vec<EntryState> {
+        (0..queue.tracks().len()).map(|index| queue.entry_state(index)).collect()
+    }
+
+    #[test]
+    fn entries_before_the_current_one_have_played() {
+        use EntryState::*;
+        let mut queue = queue_of(&[1, 2, 3]);
+        assert_eq!(states(&queue), vec![Upcoming, Upcoming, Upcoming]);
+
+        queue.play_next();
+        assert_eq!(states(&queue), vec![Playing, Upcoming, Upcoming]);
+        queue.play_next();
+        assert_eq!(states(&queue), vec![Played, Playing, Upcoming]);
+        queue.play_previous();
+        assert_eq!(states(&queue), vec![Playing, Upcoming, Upcoming]);
+    }
+
+    #[test]
+    fn tracks_played_now_are_marked_where_they_were_put() {
+        use EntryState::*;
+        let mut queue = queue_of(&[1, 2]);
+        queue.play_next();
+        queue.play_now(vec![Track::for_test(3, "3")]);
+
+        assert_eq!(states(&queue), vec![Played, Playing, Upcoming]);
+        assert_eq!(current_id(&queue), Some(3));
+    }
+
+    #[test]
+    fn only_the_playing_row_is_marked() {
+        let mut queue = queue_of(&[1, 2, 3]);
+        queue.play_next();
+        queue.play_next();
+
+        let markers = |paused| -> Vec<&'static str> {
+            queue_rows(&queue, paused).iter().map(QueueRow::marker).collect()
+        };
+        assert_eq!(markers(false), vec!["", "▶", ""]);
+        assert_eq!(markers(true), vec!["", "⏸", ""]);
+        assert_eq!(queue_rows(&queue, false)[1].title, "2 — Unknown Artist");
+    }
+
+    #[test]
+    fn restoring_drops_a_current_index_past_the_end() {
+        let tracks = vec![Track::for_test(1, "1"), Track::for_test(2, "2")];
+
+        assert_eq!(current_id(&Queue::restore(tracks.clone(), Some(1))), Some(2));
+        assert_eq!(Queue::restore(tracks.clone(), Some(2)).current_index(), None);
+        assert_eq!(Queue::restore(tracks, None).current_index(), None);
+    }
+
+    #[test]
+    fn shuffling_leaves_what_has_played_alone() {
+        let mut queue = queue_of(&[1, 2, 3, 4]);
+        queue.play_next();
+        queue.play_next();
+
+        queue.shuffle_upcoming(|mut tracks| {
+            tracks.reverse();
+            tracks
+        });
+        assert_eq!(ids(&queue), vec![1, 2, 4, 3]);
+        assert_eq!(current_id(&queue), Some(2));
+    }
+
+    #[test]
+    fn shuffling_with_nothing_playing_shuffles_everything() {
+        let mut queue = queue_of(&[1, 2, 3]);
+        queue.shuffle_upcoming(|mut tracks| {
+            tracks.reverse();
+            tracks
+        });
+
+        assert_eq!(ids(&queue), vec![3, 2, 1]);
+        assert_eq!(current_id(&queue), None);
+    }
+
+    #[test]
+    fn inserting_before_the_current_track_keeps_it_playing() {
+        let mut queue = queue_of(&[1, 2, 3]);
+        queue.play_next();
+        queue.play_next();
+
+        queue.insert(0, vec![Track::for_test(4, "4"), Track::for_test(5, "5")]);
+        assert_eq!(ids(&queue), vec![4, 5, 1, 2, 3]);
+        assert_eq!(current_id(&queue), Some(2));
+
+        queue.insert(4, vec![Track::for_test(6, "6")]);
+        queue.insert(99, vec![Track::for_test(7, "7")]);
+        assert_eq!(ids(&queue), vec![4, 5, 1, 2, 6, 3, 7]);
+        assert_eq!(current_id(&queue), Some(2));
+    }
+
+    #[test]
+    fn moved_entries_land_in_the_gap_they_were_dropped_on() {
+        let moved = |from, to| {
+            let mut queue = queue_of(&[1, 2, 3, 4]);
+            queue.play_next();
+            queue.play_next();
+            queue.move_entry(from, to);
+            (ids(&queue), current_id(&queue))
+        };
+
+        assert_eq!(moved(0, 4), (vec![2, 3, 4, 1], Some(2)));
+        assert_eq!(moved(3, 0), (vec![4, 1, 2, 3], Some(2)));
+        assert_eq!(moved(1, 3), (vec![1, 3, 2, 4], Some(2)));
+        assert_eq!(moved(2, 1), (vec![1, 3, 2, 4], Some(2)));
+        // Dropped either side of itself, it stays put
+        assert_eq!(moved(1, 1), (vec![1, 2, 3, 4], Some(2)));
+        assert_eq!(moved(1, 2), (vec![1, 2, 3, 4], Some(2)));
+        assert_eq!(moved(9, 0), (vec![1, 2, 3, 4], Some(2)));
+    }
+
+    #[test]
+    fn drops_go_in_the_nearest_gap_between_rows() {
+        assert_eq!(drop_index(0., 20., 3), 0);
+        assert_eq!(drop_index(9., 20., 3), 0);
+        assert_eq!(drop_index(11., 20., 3), 1);
+        assert_eq!(drop_index(29., 20., 3), 1);
+        assert_eq!(drop_index(31., 20., 3), 2);
+        // Past either end, or nowhere at all
+        assert_eq!(drop_index(-50., 20., 3), 0);
+        assert_eq!(drop_index(500., 20., 3), 3);
+        assert_eq!(drop_index(f64::NAN, 20., 3), 0);
+        assert_eq!(drop_index(15., 20., 0), 0);
+    }
+
+    #[test]
+    fn folders_expand_in_natural_order() {
+        let dir = TempDir::new().unwrap();
+        for name in &["10.wav", "2.wav", ".hidden.wav", "b/1.wav", "b/.c/1.wav"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let single = dir.path().join("2.wav");
+
+        let expanded = expand_folders(&[single.clone(), dir.path().to_path_buf()]);
+        let names: Vec<_> = expanded.iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(names, vec!["2.wav", "2.wav", "b/1.wav", "10.wav"]);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use druid::Data;
+use rodio::Source;
+
+use crate::decode::TrackSource;
+
+/// How carefully to work out the samples between a track's own.
+#[derive(Debug, Copy, Clone, Data, PartialEq)]
+pub enum ResampleQuality {
+    /// Straight lines between samples. Cheapest, but dulls the highs a little.
+    Linear,
+    /// Curves through the samples either side (Catmull-Rom). Costs a little more.
+    Cubic,
+}
+
+impl ResampleQuality {
+    pub fn to_setting(self) -> String {
+        match self {
+            ResampleQuality::Linear => "linear",
+            ResampleQuality::Cubic  => "cubic",
+        }.to_string()
+    }
+
+    pub fn from_setting(value: &str) -> Option<ResampleQuality> {
+        match value {
+            "linear" => Some(ResampleQuality::Linear),
+            "cubic"  => Some(ResampleQuality::Cubic),
+            _ => None
+        }
+    }
+}
+
+/// Whether a track at `from` Hz needs resampling to come out at `to`. None plays every track at
+/// its own rate.
+pub fn needs_resampling(from: u32, to: Option<u32>) -> bool {
+    to.map_or(false, |to| to != from)
+}
+
+/// `source` at `to` Hz, if that's set and it isn't already. Otherwise it's passed straight
+/// through, untouched.
+pub fn resample(source: TrackSource, to: Option<u32>, quality: ResampleQuality) -> TrackSource {
+    match to {
+        Some(to) if needs_resampling(source.sample_rate(), Some(to)) => {
+            Box::new(Resampled::new(source, to, quality))
+        }
+        _ => source,
+    }
+}
+
+/// A source played at another sample rate. The rate it was at to begin with is taken to hold
+/// throughout, as it does for all but the strangest files.
+pub struct Resampled<S> {
+    source: S,
+    channels: u16,
+    from: u32,
+    to: u32,
+    quality: ResampleQuality,
+    /// The input frames around the next output frame, the first of them being input frame
+    /// `first`.
+    frames: VecDeque<Vec<f32>>,
+    first: u64,
+    /// How many input frames have been read, and whether that's all of them.
+    read: u64,
+    ended: bool,
+    /// How many output frames have been made.
+    made: u64,
+    /// The output frame being played, and how much of it has been.
+    frame: Vec<i16>,
+    played: usize,
+}
+
+impl<S: Source<Item = i16>> Resampled<S> {
+    pub fn new(source: S, to: u32, quality: ResampleQuality) -> Self {
+        Resampled {
+            channels: source.channels().max(1),
+            from: source.sample_rate().max(1),
+            source,
+            to: to.max(1),
+            quality,
+            frames: VecDeque::new(),
+            first: 0,
+            read: 0,
+            ended: false,
+            made: 0,
+            frame: Vec::new(),
+            played: 0,
+        }
+    }
+
+    /// Read input frames until frame `index` has been, or the input ends.
+    fn read_until(&mut self, index: u64) {
+        while self.read <= index && !self.ended {
+            let frame: Vec<f32> = self.source.by_ref()
+                .take(self.channels as usize)
+                .map(f32::from)
+                .collect();
+            if frame.len() < self.channels as usize {
+                // A partial frame at the end can't be placed, so it's dropped
+                self.ended = true;
+            } else {
+                self.frames.push_back(frame);
+                self.read += 1;
+            }
+        }
+    }
+
+    /// Input frame `index`, or the nearest there is; the ends are held to interpolate past them.
+    fn input(&self, index: i64) -> &[f32] {
+        let last = self.first + self.frames.len() as u64 - 1;
+        let index = (index.max(0) as u64).max(self.first).min(last);
+        &self.frames[(index - self.first) as usize]
+    }
+
+    /// Work out the next output frame into `frame`. False once the input has run out.
+    fn make_frame(&mut self) -> bool {
+        // Exactly where the frame falls in the input, as a whole frame and a fraction
+        let position = self.made * self.from as u64;
+        let (index, fraction) = (position / self.to as u64,
+                                 (position % self.to as u64) as f32 / self.to as f32);
+
+        self.read_until(index + 2);
+        if index >= self.read {
+            return false;
+        }
+        // Only the frame before is needed again
+        while self.first + 1 < index {
+            self.frames.pop_front();
+            self.first += 1;
+        }
+
+        let index = index as i64;
+        self.frame.clear();
+        for channel in 0..self.channels as usize {
+            let sample = |offset: i64| self.input(index + offset)[channel];
+            let value = match self.quality {
+                ResampleQuality::Linear => {
+                    let (a, b) = (sample(0), sample(1));
+                    a + (b - a) * fraction
+                }
+                ResampleQuality::Cubic => {
+                    let (p0, p1, p2, p3) = (sample(-1), sample(0), sample(1), sample(2));
+                    p1 + 0.5 * fraction * (p2 - p0 + fraction * (2. * p0 - 5. * p1 + 4. * p2 - p3
+                        + fraction * (3. * (p1 - p2) + p3 - p0)))
+                }
+            };
+            let value = value.round().max(i16::MIN as f32).min(i16::MAX as f32) as i16;
+            self.frame.push(value);
+        }
+
+        self.made += 1;
+        self.played = 0;
+        true
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Resampled<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.played == self.frame.len() && !self.make_frame() {
+            return None;
+        }
+
+        self.played += 1;
+        Some(self.frame[self.played - 1])
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Resampled<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.to
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    fn ramp(channels: u16, rate: u32, frames: usize) -> SamplesBuffer<i16> {
+        let samples = (0..frames * channels as usize).map(|i| i as i16).collect::<Vec<_>>();
+        SamplesBuffer::new(channels, rate, samples)
+    }
+
+    #[test]
+    fn resampled_tracks_are_as_long_at_the_new_rate() {
+        for &(from, to) in &[(44100, 48000), (48000, 44100), (8000, 44100), (44100, 22050),
+                             (96000, 44100)] {
+            for &quality in &[ResampleQuality::Linear, ResampleQuality::Cubic] {
+                let resampled = Resampled::new(ramp(2, from, 1000), to, quality);
+                assert_eq!(resampled.sample_rate(), to);
+
+                // Rounded up, so the last of the input is always heard
+                let frames = (1000 * to as u64 + from as u64 - 1) / from as u64;
+                assert_eq!(resampled.count() as u64, 2 * frames, "{} to {} Hz", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn halving_the_rate_keeps_every_other_sample() {
+        let resampled = Resampled::new(ramp(1, 44100, 6), 22050, ResampleQuality::Cubic);
+        assert_eq!(resampled.collect::<Vec<_>>(), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn doubling_the_rate_fills_in_between() {
+        let resampled = Resampled::new(ramp(1, 22050, 3), 44100, ResampleQuality::Linear);
+        // Held at the end, since there's nothing after the last sample
+        assert_eq!(resampled.collect::<Vec<_>>(), vec![0, 1, 1, 2, 2, 2]);
+
+        // Away from the ends, a straight line is a straight line however it's curved
+        let resampled = Resampled::new(ramp(1, 22050, 4), 44100, ResampleQuality::Cubic);
+        assert_eq!(resampled.skip(2).take(3).collect::<Vec<_>>(), vec![1, 2, 2]);
+    }
+
+    #[test]
+    fn channels_are_resampled_apart() {
+        // Left counts up in even numbers, right in odd
+        let resampled = Resampled::new(ramp(2, 22050, 2), 44100, ResampleQuality::Linear);
+        assert_eq!(resampled.collect::<Vec<_>>(), vec![0, 1, 1, 2, 2, 3, 2, 3]);
+    }
+
+    #[test]
+    fn tracks_already_at_the_rate_pass_straight_through() {
+        assert!(!needs_resampling(44100, None));
+        assert!(!needs_resampling(44100, Some(44100)));
+        assert!(needs_resampling(44100, Some(48000)));
+
+        let source: TrackSource = Box::new(ramp(2, 44100, 10));
+        let passed = resample(source, Some(44100), ResampleQuality::Cubic);
+        assert_eq!(passed.sample_rate(), 44100);
+        assert_eq!(passed.collect::<Vec<_>>(), (0..20).collect::<Vec<i16>>());
+
+        let source: TrackSource = Box::new(ramp(2, 44100, 10));
+        assert_eq!(resample(source, None, ResampleQuality::Cubic).count(), 20);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::db::{Track, TrackField};
+
+/// One word of what's typed in the filter box.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// Matches tracks with this in any of the fields searched by default.
+    Anywhere(String),
+    /// Matches tracks with this in one field, from `field:word`.
+    In(TrackField, String),
+}
+
+/// Read a search like `artist:miles "kind of blue"`, lowercased. Words with a field name and a
+/// colon in front only match that field; field names are those of `TrackField::column`. Double
+/// quotes keep words together, so `genre:"smooth jazz"` is one term. Anything else with a colon
+/// in it is searched for as it is.
+pub fn parse_search(text: &str) -> Vec<Term> {
+    split_words(&text.to_lowercase()).into_iter()
+        .map(|word| {
+            if let Some(colon) = word.find(':') {
+                let (name, value) = (&word[..colon], &word[colon + 1..]);
+                if let (Some(field), false) = (TrackField::from_column(name), value.is_empty()) {
+                    return Term::In(field, value.replace('"', ""));
+                }
+            }
+            Term::Anywhere(word.replace('"', ""))
+        })
+        .filter(|term| !matches!(term, Term::Anywhere(word) if word.is_empty()))
+        .collect()
+}
+
+/// Split `text` on whitespace outside of double quotes, keeping the quotes.
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+
+        if c.is_whitespace() && !quoted {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        } else {
+            word.push(c);
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Everything a track's unscoped terms are matched against, lowercased in one string: its
+/// title, artist and album, and with `everywhere` its path and comment too.
+pub fn search_key(track: &Track, everywhere: bool) -> String {
+    let mut fields = vec![track.title(), track.artist(), track.album()];
+    if everywhere {
+        fields.push(Some(track.path()));
+        fields.push(track.comment());
+    }
+
+    fields.iter()
+        .flatten()
+        .map(|field| field.to_lowercase())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Whether a track, with this `search_key`, matches every term.
+pub fn matches_search(terms: &[Term], key: &str, track: &Track) -> bool {
+    terms.iter().all(|term| match term {
+        Term::Anywhere(word) => key.contains(word.as_str()),
+        Term::In(field, word) => {
+            track.get_field_as_string(*field).to_lowercase().contains(word.as_str())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anywhere(word: &str) -> Term {
+        Term::Anywhere(word.to_string())
+    }
+
+    fn scoped(field: TrackField, word: &str) -> Term {
+        Term::In(field, word.to_string())
+    }
+
+    #[test]
+    fn words_are_searched_for_anywhere() {
+        assert_eq!(parse_search("  Blue  Train "), vec![anywhere("blue"), anywhere("train")]);
+        assert_eq!(parse_search(""), Vec::new());
+    }
+
+    #[test]
+    fn field_names_scope_a_word() {
+        assert_eq!(parse_search("artist:Miles genre:jazz blue"),
+                   vec![scoped(TrackField::Artist, "miles"), scoped(TrackField::Genre, "jazz"),
+                        anywhere("blue")]);
+        assert_eq!(parse_search("PATH:live play_count:3"),
+                   vec![scoped(TrackField::Path, "live"), scoped(TrackField::PlayCount, "3")]);
+    }
+
+    #[test]
+    fn quotes_keep_words_together() {
+        assert_eq!(parse_search("genre:\"Smooth Jazz\" \"kind of blue\""),
+                   vec![scoped(TrackField::Genre, "smooth jazz"), anywhere("kind of blue")]);
+        // An unclosed quote runs to the end
+        assert_eq!(parse_search("album:\"kind of"), vec![scoped(TrackField::Album, "kind of")]);
+        assert_eq!(parse_search("\"\" blue"), vec![anywhere("blue")]);
+    }
+
+    #[test]
+    fn other_colons_are_searched_for_as_they_are() {
+        assert_eq!(parse_search("re:union artist:"),
+                   vec![anywhere("re:union"), anywhere("artist:")]);
+    }
+
+    #[test]
+    fn paths_and_comments_are_only_searched_everywhere() {
+        let track = Track::for_test(1, "So What").with_artist("Miles Davis")
+            .with_comment("Ripped from vinyl");
+
+        assert_eq!(search_key(&track, false), "so what\nmiles davis");
+        assert_eq!(search_key(&track, true),
+                   "so what\nmiles davis\n/music/1.flac\nripped from vinyl");
+    }
+
+    #[test]
+    fn scoped_terms_only_match_their_field() {
+        let track = Track::for_test(1, "So What").with_artist("Miles Davis").with_genre("Jazz");
+        let key = search_key(&track, false);
+        let matches = |search| matches_search(&parse_search(search), &key, &track);
+
+        assert!(matches("miles what"));
+        assert!(matches("artist:miles genre:jazz"));
+        assert!(!matches("title:miles"));
+        assert!(!matches("artist:miles blue"));
+        // Scoped terms reach fields that aren't searched by default
+        assert!(matches("path:music"));
+        assert!(!matches("music"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use druid::{BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
+            MouseButton, PaintCtx, Point, Rect, RenderContext, Size, TextLayout, TimerToken,
+            UpdateCtx, Widget};
+use druid::theme::{BACKGROUND_LIGHT, PRIMARY_LIGHT};
+
+use crate::commands::SEEK;
+use crate::tracklist::format_duration;
+
+/// How often the bar redraws while the track is playing.
+const TICK: Duration = Duration::from_millis(250);
+const BAR_HEIGHT: f64 = 6.0;
+
+/// How far through the current track we are. rodio doesn't keep track of this, so we do, from
+/// when playback last started or resumed.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PlaybackPosition {
+    /// None if nothing is playing, or the track's length is unknown.
+    length: Option<Duration>,
+    /// Time played before `resumed_at`.
+    played: Duration,
+    /// None while paused or stopped.
+    resumed_at: Option<Instant>,
+    /// Where playback stops short of the end of the track, if it skips silence there.
+    end: Option<Duration>,
+}
+
+impl Data for PlaybackPosition {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl PlaybackPosition {
+    /// Start playing a track `length` seconds long, `offset` in. Lengths of 0 or less are
+    /// unknown.
+    pub fn start(length: i32, offset: Duration, now: Instant) -> Self {
+        PlaybackPosition {
+            length: if length > 0 { Some(Duration::from_secs(length as u64)) } else { None },
+            played: offset,
+            resumed_at: Some(now),
+            end: None,
+        }
+    }
+
+    /// The same, but stopping at `end` rather than the end of the track, if it's set.
+    pub fn ending_at(self, end: Option<Duration>) -> Self {
+        PlaybackPosition { end, ..self }
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        self.played = self.elapsed(now);
+        self.resumed_at = None;
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        if self.resumed_at.is_none() {
+            self.resumed_at = Some(now);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.resumed_at.is_some()
+    }
+
+    pub fn length(&self) -> Option<Duration> {
+        self.length
+    }
+
+    /// How much of the track is left to play, if its length is known.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.length.map(|length| {
+            let end = self.end.map_or(length, |end| end.min(length));
+            end.saturating_sub(self.elapsed(now))
+        })
+    }
+
+    /// Whether at least half of the track has been played, so it counts as having been played.
+    /// Never true of tracks whose length is unknown.
+    pub fn is_past_halfway(&self, now: Instant) -> bool {
+        self.length.map_or(false, |length| self.elapsed(now) >= length / 2)
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        let elapsed = self.played + self.resumed_at.map_or(Duration::default(), |resumed_at| {
+            now.saturating_duration_since(resumed_at)
+        });
+
+        match self.length {
+            Some(length) => elapsed.min(length),
+            None => elapsed,
+        }
+    }
+}
+
+/// A progress bar with the elapsed and total time. Clicking it seeks there.
+pub struct SeekBar {
+    text: TextLayout<String>,
+    timer: TimerToken,
+}
+
+impl SeekBar {
+    pub fn new() -> Self {
+        SeekBar {
+            text: TextLayout::from_text("0:00"),
+            timer: TimerToken::INVALID,
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut EventCtx, data: &PlaybackPosition) {
+        self.timer = if data.is_playing() {
+            ctx.request_timer(TICK)
+        } else {
+            TimerToken::INVALID
+        };
+    }
+}
+
+impl Widget<PlaybackPosition> for SeekBar {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut PlaybackPosition, _env: &Env) {
+        match event {
+            Event::WindowConnected => self.tick(ctx, data),
+            Event::Timer(token) if *token == self.timer => {
+                self.tick(ctx, data);
+                ctx.request_paint();
+            }
+            Event::AnimFrame(_) if self.timer == TimerToken::INVALID => self.tick(ctx, data),
+            Event::MouseDown(evt) if evt.button == MouseButton::Left => {
+                // Can't seek in a track if we don't know how long it is
+                if let Some(length) = data.length {
+                    let fraction = (evt.pos.x / ctx.size().width).max(0.).min(1.);
+                    ctx.submit_command(SEEK.with(length.mul_f64(fraction)));
+                    ctx.set_handled();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &PlaybackPosition,
+                 _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &PlaybackPosition, data: &PlaybackPosition,
+              _env: &Env) {
+        // Timers can only be requested from events, so get one started with an animation frame
+        if data.is_playing() && self.timer == TimerToken::INVALID {
+            ctx.request_anim_frame();
+        }
+        ctx.request_paint();
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &PlaybackPosition,
+              env: &Env) -> Size {
+        self.text.rebuild_if_needed(ctx.text(), env);
+        bc.constrain(Size::new(bc.max().width, self.text.size().height + BAR_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &PlaybackPosition, env: &Env) {
+        let elapsed = data.elapsed(Instant::now());
+        let text = match data.length {
+            Some(length) => format!("{} / {}", format_duration(elapsed.as_secs() as i64),
+                                    format_duration(length.as_secs() as i64)),
+            None => format_duration(elapsed.as_secs() as i64),
+        };
+        self.text.set_text(text);
+        self.text.rebuild_if_needed(ctx.text(), env);
+
+        let width = ctx.size().width;
+        let bar = Rect::from_origin_size(Point::ORIGIN, Size::new(width, BAR_HEIGHT));
+        ctx.fill(bar, &env.get(BACKGROUND_LIGHT));
+
+        if let Some(length) = data.length {
+            let fraction = elapsed.as_secs_f64() / length.as_secs_f64();
+            ctx.fill(bar.with_size(Size::new(width * fraction, BAR_HEIGHT)), &env.get(PRIMARY_LIGHT));
+        }
+
+        self.text.draw(ctx, Point::new(0., BAR_HEIGHT));
+    }
+}
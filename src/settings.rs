@@ -0,0 +1,1319 @@
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use druid::Data;
+use log::warn;
+
+use crate::browse::Grouping;
+use crate::db::{Database, DatabaseError, ScanOptions, TrackField};
+use crate::loudness::Normalization;
+use crate::resample::ResampleQuality;
+use crate::shuffle::ShuffleWeights;
+
+const STARTUP_VIEW: &str = "startup_view";
+const LAST_VIEW: &str = "last_view";
+const STARTUP_SCAN: &str = "startup_scan";
+const EMPTY_AREA_DOUBLE_CLICK: &str = "empty_area_double_click";
+const SCAN_THROTTLE_MS: &str = "scan_throttle_ms";
+const SCAN_SKIP_HIDDEN: &str = "scan_skip_hidden";
+const SCAN_HASH_FILES: &str = "scan_hash_files";
+const MIN_WINDOW_WIDTH: &str = "min_window_width";
+const MIN_WINDOW_HEIGHT: &str = "min_window_height";
+const WINDOW_WIDTH: &str = "window_width";
+const WINDOW_HEIGHT: &str = "window_height";
+const WINDOW_X: &str = "window_x";
+const WINDOW_Y: &str = "window_y";
+const TWO_LINE_ROWS: &str = "two_line_rows";
+const ALWAYS_ON_TOP: &str = "always_on_top";
+const WINDOW_TITLE_FORMAT: &str = "window_title_format";
+const STRICT_SORT: &str = "strict_sort";
+const SEARCH_EVERYWHERE: &str = "search_everywhere";
+const REMEMBER_VIEW_POSITIONS: &str = "remember_view_positions";
+const REDUCE_MOTION: &str = "reduce_motion";
+const REPLAY_GAIN: &str = "replay_gain";
+const NORMALIZATION: &str = "normalization";
+const CROSSFADE_SECS: &str = "crossfade_secs";
+const CROSSFADE_ON_SKIP: &str = "crossfade_on_skip";
+const PAUSE_FADE_MS: &str = "pause_fade_ms";
+const SMART_SHUFFLE: &str = "smart_shuffle";
+const SHUFFLE_RATING_WEIGHT: &str = "shuffle_rating_weight";
+const SHUFFLE_RECENCY_WEIGHT: &str = "shuffle_recency_weight";
+const SHUFFLE_PLAY_COUNT_WEIGHT: &str = "shuffle_play_count_weight";
+const OUTPUT_DEVICE: &str = "output_device";
+const OUTPUT_SAMPLE_RATE: &str = "output_sample_rate";
+const RESAMPLE_QUALITY: &str = "resample_quality";
+const PREFETCH_SECS: &str = "prefetch_secs";
+const TRIM_SILENCE: &str = "trim_silence";
+const SILENCE_THRESHOLD_DB: &str = "silence_threshold_db";
+const DELETE_TO_TRASH: &str = "delete_to_trash";
+const CONFIRM_FULL_RESCAN: &str = "confirm_full_rescan";
+const FEATURED_ARTIST_PATTERNS: &str = "featured_artist_patterns";
+const ALBUM_EDITION_PATTERNS: &str = "album_edition_patterns";
+
+/// Crossfades can be at most this long.
+const MAX_CROSSFADE_SECS: u64 = 12;
+/// Fades on pausing and resuming can be at most this long.
+const MAX_PAUSE_FADE_MS: u64 = 1000;
+/// The sample rates tracks can be played at, besides their own.
+const OUTPUT_SAMPLE_RATES: RangeInclusive<u32> = 8000..=384_000;
+/// At most this much of the next track is decoded ahead of time, to keep it to a few megabytes.
+const MAX_PREFETCH_SECS: u64 = 60;
+/// The levels, in dB below full scale, that can be taken for silence. Any louder would cut into
+/// quiet passages.
+const SILENCE_THRESHOLDS: RangeInclusive<f64> = -96.0..=-30.;
+const VOLUME: &str = "volume";
+const COLUMN_FIELDS: &str = "column_fields";
+const COLUMN_WIDTHS: &str = "column_widths";
+
+/// A set of tracks that can be shown in the main track list.
+#[derive(Debug, Copy, Clone, Data, PartialEq, Eq, Hash)]
+pub enum View {
+    /// Every library.
+    Library,
+    /// Just one library's tracks.
+    SingleLibrary(i64),
+    Playlist(i64),
+    /// The tracks played the most times.
+    MostPlayed,
+    /// The tracks played last.
+    RecentlyPlayed,
+}
+
+impl View {
+    /// Whether the view shows the tracks of this library, so it needs to keep up with scans.
+    pub fn includes_library(self, library_id: i64) -> bool {
+        self == View::Library || self == View::SingleLibrary(library_id)
+    }
+
+    fn to_setting(self) -> String {
+        match self {
+            View::Library           => "library".to_string(),
+            View::SingleLibrary(id) => format!("library:{}", id),
+            View::Playlist(id)      => format!("playlist:{}", id),
+            View::MostPlayed        => "most_played".to_string(),
+            View::RecentlyPlayed    => "recently_played".to_string(),
+        }
+    }
+
+    fn from_setting(value: &str) -> Option<View> {
+        if value == "library" {
+            Some(View::Library)
+        } else if value == "most_played" {
+            Some(View::MostPlayed)
+        } else if value == "recently_played" {
+            Some(View::RecentlyPlayed)
+        } else if let Some(id) = value.strip_prefix("library:") {
+            id.parse().ok().map(View::SingleLibrary)
+        } else if let Some(id) = value.strip_prefix("playlist:") {
+            id.parse().ok().map(View::Playlist)
+        } else {
+            None
+        }
+    }
+}
+
+/// What mus opens to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StartupView {
+    View(View),
+    LastUsed,
+}
+
+impl StartupView {
+    fn to_setting(self) -> String {
+        match self {
+            StartupView::View(view) => view.to_setting(),
+            StartupView::LastUsed   => "last_used".to_string(),
+        }
+    }
+
+    fn from_setting(value: &str) -> Option<StartupView> {
+        if value == "last_used" {
+            Some(StartupView::LastUsed)
+        } else {
+            View::from_setting(value).map(StartupView::View)
+        }
+    }
+}
+
+/// Whether, and how thoroughly, libraries are scanned when mus starts.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StartupScan {
+    Off,
+    Incremental,
+    Full,
+}
+
+impl StartupScan {
+    fn to_setting(self) -> String {
+        match self {
+            StartupScan::Off         => "off",
+            StartupScan::Incremental => "incremental",
+            StartupScan::Full        => "full",
+        }.to_string()
+    }
+
+    fn from_setting(value: &str) -> Option<StartupScan> {
+        match value {
+            "off"         => Some(StartupScan::Off),
+            "incremental" => Some(StartupScan::Incremental),
+            "full"        => Some(StartupScan::Full),
+            _ => None
+        }
+    }
+}
+
+/// What double-clicking the empty space below the last row of a track list does.
+#[derive(Debug, Copy, Clone, Data, PartialEq)]
+pub enum EmptyAreaAction {
+    Nothing,
+    ClearSelection,
+}
+
+impl EmptyAreaAction {
+    fn to_setting(self) -> String {
+        match self {
+            EmptyAreaAction::Nothing        => "nothing",
+            EmptyAreaAction::ClearSelection => "clear_selection",
+        }.to_string()
+    }
+
+    fn from_setting(value: &str) -> Option<EmptyAreaAction> {
+        match value {
+            "nothing"         => Some(EmptyAreaAction::Nothing),
+            "clear_selection" => Some(EmptyAreaAction::ClearSelection),
+            _ => None
+        }
+    }
+}
+
+/// User preferences, stored in the `setting` table. Anything missing or unreadable falls back
+/// to its default.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub startup_view: StartupView,
+    /// The view that was open when mus last ran.
+    pub last_view: Option<View>,
+    pub startup_scan: StartupScan,
+    pub empty_area_double_click: EmptyAreaAction,
+    /// Minimum milliseconds between files while scanning; 0 scans at full speed.
+    pub scan_throttle_ms: u64,
+    pub scan_skip_hidden: bool,
+    /// Compute content hashes during scans instead of when they're first needed.
+    pub scan_hash_files: bool,
+    /// The window can't be shrunk below this, so the controls always fit.
+    pub min_window_size: (f64, f64),
+    /// The window's size when mus was last closed.
+    pub window_size: (f64, f64),
+    /// Where the window's top left corner was when mus was last closed. None leaves it up to
+    /// the system.
+    pub window_position: Option<(f64, f64)>,
+    /// The main window's title while a track is playing, with fields like `{artist}` filled in
+    /// from it. It's just "mus" otherwise, or always if this is empty.
+    pub window_title_format: String,
+    /// Show the artist, album and length under each title instead of in columns.
+    pub two_line_rows: bool,
+    /// Keep the main window above other windows, where that's supported.
+    pub always_on_top: bool,
+    /// Sort text so that case and accents matter.
+    pub strict_sort: bool,
+    /// Search paths and comments too, not just titles, artists and albums.
+    pub search_everywhere: bool,
+    /// Go back to the selected track and scroll position a view was left at, rather than the
+    /// top, when it's shown again.
+    pub remember_view_positions: bool,
+    /// Change the now-playing display straight away when the track changes, rather than fading
+    /// the new one in.
+    pub reduce_motion: bool,
+    /// Position of the volume slider, from 0 to 1. See `volume_gain` for how loud that is.
+    pub volume: f64,
+    /// Adjust each track's volume by `normalization`, so they all sound about as loud.
+    pub replay_gain: bool,
+    /// Whether volumes are normalized by ReplayGain tags, or by the loudness measured from each
+    /// track or its whole album.
+    pub normalization: Normalization,
+    /// How long to fade each track into the next, in seconds, up to 12. 0 turns crossfading off.
+    pub crossfade_secs: u64,
+    /// Crossfade when skipping to another track by hand too, instead of cutting straight to it.
+    pub crossfade_on_skip: bool,
+    /// How long to fade out on pausing and back in on resuming, in milliseconds, up to 1000, so
+    /// playback doesn't stop or start with a click. 0 turns it off.
+    pub pause_fade_ms: u64,
+    /// Shuffle towards higher-rated tracks and ones that haven't played much or lately, by the
+    /// weights below, rather than treating every track alike.
+    pub smart_shuffle: bool,
+    /// How much more often a smart shuffle picks 5 star tracks early. Weights aren't negative,
+    /// and 0 leaves that out; see `ShuffleWeights`.
+    pub shuffle_rating_weight: f64,
+    /// How much more often a smart shuffle picks tracks that haven't played for a while.
+    pub shuffle_recency_weight: f64,
+    /// How much less often a smart shuffle picks a track for each time it's played.
+    pub shuffle_play_count_weight: f64,
+    /// The name of the device to play through. None is the system default.
+    pub output_device: Option<String>,
+    /// Play every track at this sample rate, in Hz, resampling those that aren't, for devices
+    /// that glitch at some rates. 0 plays each at its own rate.
+    pub output_sample_rate: u32,
+    pub resample_quality: ResampleQuality,
+    /// How much of the next track to decode ahead of time, in seconds, up to 60, so it starts
+    /// without a gap while its file is read. 0 turns it off.
+    pub prefetch_secs: u64,
+    /// Skip the silence before and after each track, once it's been found by analyzing them, so
+    /// tracks meant to run into each other do without a gap. Files are left as they are.
+    pub trim_silence: bool,
+    /// Anything quieter than this, in dB below full scale, is silence when analyzing tracks.
+    /// From -96 to -30.
+    pub silence_threshold_db: f64,
+    /// Move deleted tracks' files to the trash instead of deleting them for good.
+    pub delete_to_trash: bool,
+    /// Ask before a full rescan that would lose playlists, plays or ratings.
+    pub confirm_full_rescan: bool,
+    /// Words that introduce a featured artist, like "feat.". The artist tree groups artists
+    /// without them and what follows, so "A feat. B" is under "A". Empty keeps every artist
+    /// as tagged.
+    pub featured_artist_patterns: Vec<String>,
+    /// Words that mark an edition of an album, like "deluxe". The artist tree groups albums
+    /// without a trailing "(...)", "[...]" or " - ..." that has one, so "A (Deluxe Edition)"
+    /// is under "A". Empty keeps every album as tagged, bar spacing and case.
+    pub album_edition_patterns: Vec<String>,
+    /// The track list's columns, left to right, each with its share of the width.
+    pub columns: Vec<(TrackField, f64)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            startup_view: StartupView::View(View::Library),
+            last_view: None,
+            startup_scan: StartupScan::Incremental,
+            empty_area_double_click: EmptyAreaAction::Nothing,
+            scan_throttle_ms: 0,
+            scan_skip_hidden: true,
+            scan_hash_files: false,
+            min_window_size: (720., 360.),
+            window_size: (1920., 1080.),
+            window_position: None,
+            window_title_format: "{artist} — {title} · mus".to_string(),
+            two_line_rows: false,
+            always_on_top: false,
+            strict_sort: false,
+            search_everywhere: false,
+            remember_view_positions: true,
+            reduce_motion: false,
+            volume: 1.0,
+            replay_gain: true,
+            normalization: Normalization::ReplayGain,
+            crossfade_secs: 0,
+            crossfade_on_skip: false,
+            pause_fade_ms: 50,
+            smart_shuffle: false,
+            shuffle_rating_weight: 1.,
+            shuffle_recency_weight: 1.,
+            shuffle_play_count_weight: 0.1,
+            output_device: None,
+            output_sample_rate: 0,
+            resample_quality: ResampleQuality::Cubic,
+            prefetch_secs: 10,
+            trim_silence: false,
+            silence_threshold_db: -60.,
+            delete_to_trash: true,
+            confirm_full_rescan: true,
+            featured_artist_patterns: vec!["feat.".to_string(), "ft.".to_string(),
+                                           "featuring".to_string()],
+            album_edition_patterns: vec!["deluxe".to_string(), "remaster".to_string(),
+                                         "expanded".to_string(), "anniversary".to_string()],
+            columns: vec![(TrackField::Title, 0.5), (TrackField::Artist, 0.5)],
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(db: &Database) -> Result<Settings, DatabaseError> {
+        let mut settings = Settings::default();
+
+        if let Some(value) = db.setting(STARTUP_VIEW)? {
+            match StartupView::from_setting(&value) {
+                Some(startup_view) => settings.startup_view = startup_view,
+                None => warn!("Ignoring unknown startup view {:?}", value),
+            }
+        }
+
+        settings.last_view = db.setting(LAST_VIEW)?
+            .and_then(|value| View::from_setting(&value));
+
+        if let Some(value) = db.setting(STARTUP_SCAN)? {
+            match StartupScan::from_setting(&value) {
+                Some(startup_scan) => settings.startup_scan = startup_scan,
+                None => warn!("Ignoring unknown startup scan setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(EMPTY_AREA_DOUBLE_CLICK)? {
+            match EmptyAreaAction::from_setting(&value) {
+                Some(action) => settings.empty_area_double_click = action,
+                None => warn!("Ignoring unknown empty area action {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(SCAN_THROTTLE_MS)? {
+            match value.parse() {
+                Ok(scan_throttle_ms) => settings.scan_throttle_ms = scan_throttle_ms,
+                Err(_) => warn!("Ignoring invalid scan throttle {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(SCAN_SKIP_HIDDEN)? {
+            match value.parse() {
+                Ok(scan_skip_hidden) => settings.scan_skip_hidden = scan_skip_hidden,
+                Err(_) => warn!("Ignoring invalid hidden file setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(SCAN_HASH_FILES)? {
+            match value.parse() {
+                Ok(scan_hash_files) => settings.scan_hash_files = scan_hash_files,
+                Err(_) => warn!("Ignoring invalid hashing setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(MIN_WINDOW_WIDTH)? {
+            match value.parse() {
+                Ok(width) if width > 0. => settings.min_window_size.0 = width,
+                _ => warn!("Ignoring invalid minimum window width {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(MIN_WINDOW_HEIGHT)? {
+            match value.parse() {
+                Ok(height) if height > 0. => settings.min_window_size.1 = height,
+                _ => warn!("Ignoring invalid minimum window height {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(WINDOW_WIDTH)? {
+            match value.parse() {
+                Ok(width) if width > 0. => settings.window_size.0 = width,
+                _ => warn!("Ignoring invalid window width {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(WINDOW_HEIGHT)? {
+            match value.parse() {
+                Ok(height) if height > 0. => settings.window_size.1 = height,
+                _ => warn!("Ignoring invalid window height {:?}", value),
+            }
+        }
+
+        // Only both coordinates together make a position
+        if let (Some(x), Some(y)) = (db.setting(WINDOW_X)?, db.setting(WINDOW_Y)?) {
+            match (x.parse(), y.parse()) {
+                (Ok(x), Ok(y)) => settings.window_position = Some((x, y)),
+                _ => warn!("Ignoring invalid window position {:?}, {:?}", x, y),
+            }
+        }
+
+        if let Some(value) = db.setting(WINDOW_TITLE_FORMAT)? {
+            settings.window_title_format = value;
+        }
+
+        if let Some(value) = db.setting(TWO_LINE_ROWS)? {
+            match value.parse() {
+                Ok(two_line_rows) => settings.two_line_rows = two_line_rows,
+                Err(_) => warn!("Ignoring invalid two-line row setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(ALWAYS_ON_TOP)? {
+            match value.parse() {
+                Ok(always_on_top) => settings.always_on_top = always_on_top,
+                Err(_) => warn!("Ignoring invalid always on top setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(STRICT_SORT)? {
+            match value.parse() {
+                Ok(strict_sort) => settings.strict_sort = strict_sort,
+                Err(_) => warn!("Ignoring invalid strict sort setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(SEARCH_EVERYWHERE)? {
+            match value.parse() {
+                Ok(search_everywhere) => settings.search_everywhere = search_everywhere,
+                Err(_) => warn!("Ignoring invalid search setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(REMEMBER_VIEW_POSITIONS)? {
+            match value.parse() {
+                Ok(remember) => settings.remember_view_positions = remember,
+                Err(_) => warn!("Ignoring invalid view position setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(REDUCE_MOTION)? {
+            match value.parse() {
+                Ok(reduce_motion) => settings.reduce_motion = reduce_motion,
+                Err(_) => warn!("Ignoring invalid reduce motion setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(REPLAY_GAIN)? {
+            match value.parse() {
+                Ok(replay_gain) => settings.replay_gain = replay_gain,
+                Err(_) => warn!("Ignoring invalid ReplayGain setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(NORMALIZATION)? {
+            match Normalization::from_setting(&value) {
+                Some(normalization) => settings.normalization = normalization,
+                None => warn!("Ignoring unknown normalization {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(CROSSFADE_SECS)? {
+            match value.parse() {
+                Ok(crossfade_secs) if crossfade_secs <= MAX_CROSSFADE_SECS => {
+                    settings.crossfade_secs = crossfade_secs;
+                }
+                _ => warn!("Ignoring invalid crossfade length {:?}, it should be 0 to {} seconds",
+                           value, MAX_CROSSFADE_SECS),
+            }
+        }
+
+        if let Some(value) = db.setting(CROSSFADE_ON_SKIP)? {
+            match value.parse() {
+                Ok(crossfade_on_skip) => settings.crossfade_on_skip = crossfade_on_skip,
+                Err(_) => warn!("Ignoring invalid crossfade on skip setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(PAUSE_FADE_MS)? {
+            match value.parse() {
+                Ok(pause_fade_ms) if pause_fade_ms <= MAX_PAUSE_FADE_MS => {
+                    settings.pause_fade_ms = pause_fade_ms;
+                }
+                _ => warn!("Ignoring invalid pause fade length {:?}, it should be 0 to {} ms",
+                           value, MAX_PAUSE_FADE_MS),
+            }
+        }
+
+        if let Some(value) = db.setting(SMART_SHUFFLE)? {
+            match value.parse() {
+                Ok(smart_shuffle) => settings.smart_shuffle = smart_shuffle,
+                Err(_) => warn!("Ignoring invalid smart shuffle setting {:?}", value),
+            }
+        }
+
+        let weights = [(SHUFFLE_RATING_WEIGHT, &mut settings.shuffle_rating_weight),
+                       (SHUFFLE_RECENCY_WEIGHT, &mut settings.shuffle_recency_weight),
+                       (SHUFFLE_PLAY_COUNT_WEIGHT, &mut settings.shuffle_play_count_weight)];
+        for (key, weight) in weights {
+            if let Some(value) = db.setting(key)? {
+                match value.parse::<f64>() {
+                    Ok(parsed) if parsed.is_finite() && parsed >= 0. => *weight = parsed,
+                    _ => warn!("Ignoring invalid {} {:?}, it can't be negative", key, value),
+                }
+            }
+        }
+
+        // Saved as an empty string for the default
+        if let Some(value) = db.setting(OUTPUT_DEVICE)? {
+            settings.output_device = Some(value).filter(|value| !value.is_empty());
+        }
+
+        if let Some(value) = db.setting(OUTPUT_SAMPLE_RATE)? {
+            match value.parse() {
+                Ok(rate) if rate == 0 || OUTPUT_SAMPLE_RATES.contains(&rate) => {
+                    settings.output_sample_rate = rate;
+                }
+                _ => warn!("Ignoring invalid output sample rate {:?}, it should be 0 or {} to {} \
+                            Hz", value, OUTPUT_SAMPLE_RATES.start(), OUTPUT_SAMPLE_RATES.end()),
+            }
+        }
+
+        if let Some(value) = db.setting(RESAMPLE_QUALITY)? {
+            match ResampleQuality::from_setting(&value) {
+                Some(quality) => settings.resample_quality = quality,
+                None => warn!("Ignoring unknown resampling quality {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(PREFETCH_SECS)? {
+            match value.parse() {
+                Ok(prefetch_secs) if prefetch_secs <= MAX_PREFETCH_SECS => {
+                    settings.prefetch_secs = prefetch_secs;
+                }
+                _ => warn!("Ignoring invalid prefetch length {:?}, it should be 0 to {} seconds",
+                           value, MAX_PREFETCH_SECS),
+            }
+        }
+
+        if let Some(value) = db.setting(TRIM_SILENCE)? {
+            match value.parse() {
+                Ok(trim_silence) => settings.trim_silence = trim_silence,
+                Err(_) => warn!("Ignoring invalid silence trimming setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(SILENCE_THRESHOLD_DB)? {
+            match value.parse() {
+                Ok(threshold) if SILENCE_THRESHOLDS.contains(&threshold) => {
+                    settings.silence_threshold_db = threshold;
+                }
+                _ => warn!("Ignoring invalid silence threshold {:?}, it should be {} to {} dB",
+                           value, SILENCE_THRESHOLDS.start(), SILENCE_THRESHOLDS.end()),
+            }
+        }
+
+        if let Some(value) = db.setting(DELETE_TO_TRASH)? {
+            match value.parse() {
+                Ok(delete_to_trash) => settings.delete_to_trash = delete_to_trash,
+                Err(_) => warn!("Ignoring invalid trash setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(CONFIRM_FULL_RESCAN)? {
+            match value.parse() {
+                Ok(confirm_full_rescan) => settings.confirm_full_rescan = confirm_full_rescan,
+                Err(_) => warn!("Ignoring invalid rescan confirmation setting {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(FEATURED_ARTIST_PATTERNS)? {
+            settings.featured_artist_patterns = parse_word_list(&value);
+        }
+
+        if let Some(value) = db.setting(ALBUM_EDITION_PATTERNS)? {
+            settings.album_edition_patterns = parse_word_list(&value);
+        }
+
+        if let Some(value) = db.setting(VOLUME)? {
+            match value.parse() {
+                Ok(volume) if (0. ..=1.).contains(&volume) => settings.volume = volume,
+                _ => warn!("Ignoring invalid volume {:?}", value),
+            }
+        }
+
+        if let Some(value) = db.setting(COLUMN_FIELDS)? {
+            match parse_column_fields(&value) {
+                Some(fields) => {
+                    let width = 1. / fields.len() as f64;
+                    settings.columns = fields.into_iter().map(|field| (field, width)).collect();
+                }
+                None => warn!("Ignoring invalid columns {:?}", value),
+            }
+        }
+
+        // Widths saved before the fields were still line up with the default columns
+        if let Some(value) = db.setting(COLUMN_WIDTHS)? {
+            match parse_column_widths(&value) {
+                Some(widths) if widths.len() == settings.columns.len() => {
+                    for (column, width) in settings.columns.iter_mut().zip(widths) {
+                        column.1 = width;
+                    }
+                }
+                _ => warn!("Ignoring invalid column widths {:?}", value),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), DatabaseError> {
+        db.set_setting(STARTUP_VIEW, &self.startup_view.to_setting())?;
+        db.set_setting(STARTUP_SCAN, &self.startup_scan.to_setting())?;
+        db.set_setting(EMPTY_AREA_DOUBLE_CLICK, &self.empty_area_double_click.to_setting())?;
+        db.set_setting(SCAN_THROTTLE_MS, &self.scan_throttle_ms.to_string())?;
+        db.set_setting(SCAN_SKIP_HIDDEN, &self.scan_skip_hidden.to_string())?;
+        db.set_setting(SCAN_HASH_FILES, &self.scan_hash_files.to_string())?;
+        db.set_setting(MIN_WINDOW_WIDTH, &self.min_window_size.0.to_string())?;
+        db.set_setting(MIN_WINDOW_HEIGHT, &self.min_window_size.1.to_string())?;
+        db.set_setting(WINDOW_TITLE_FORMAT, &self.window_title_format)?;
+        db.set_setting(TWO_LINE_ROWS, &self.two_line_rows.to_string())?;
+        save_always_on_top(db, self.always_on_top)?;
+        save_strict_sort(db, self.strict_sort)?;
+        save_search_everywhere(db, self.search_everywhere)?;
+        db.set_setting(REMEMBER_VIEW_POSITIONS, &self.remember_view_positions.to_string())?;
+        save_reduce_motion(db, self.reduce_motion)?;
+        save_replay_gain(db, self.replay_gain)?;
+        save_normalization(db, self.normalization)?;
+        db.set_setting(CROSSFADE_SECS, &self.crossfade_secs.to_string())?;
+        db.set_setting(CROSSFADE_ON_SKIP, &self.crossfade_on_skip.to_string())?;
+        db.set_setting(PAUSE_FADE_MS, &self.pause_fade_ms.to_string())?;
+        save_smart_shuffle(db, self.smart_shuffle)?;
+        db.set_setting(SHUFFLE_RATING_WEIGHT, &self.shuffle_rating_weight.to_string())?;
+        db.set_setting(SHUFFLE_RECENCY_WEIGHT, &self.shuffle_recency_weight.to_string())?;
+        db.set_setting(SHUFFLE_PLAY_COUNT_WEIGHT, &self.shuffle_play_count_weight.to_string())?;
+        save_output_device(db, self.output_device.as_deref())?;
+        db.set_setting(OUTPUT_SAMPLE_RATE, &self.output_sample_rate.to_string())?;
+        db.set_setting(RESAMPLE_QUALITY, &self.resample_quality.to_setting())?;
+        db.set_setting(PREFETCH_SECS, &self.prefetch_secs.to_string())?;
+        save_trim_silence(db, self.trim_silence)?;
+        db.set_setting(SILENCE_THRESHOLD_DB, &self.silence_threshold_db.to_string())?;
+        db.set_setting(DELETE_TO_TRASH, &self.delete_to_trash.to_string())?;
+        save_confirm_full_rescan(db, self.confirm_full_rescan)?;
+        db.set_setting(FEATURED_ARTIST_PATTERNS, &self.featured_artist_patterns.join(","))?;
+        db.set_setting(ALBUM_EDITION_PATTERNS, &self.album_edition_patterns.join(","))?;
+        save_window_geometry(db, self.window_size, self.window_position)?;
+        save_volume(db, self.volume)?;
+        save_columns(db, &self.columns)?;
+
+        if let Some(last_view) = self.last_view {
+            save_last_view(db, last_view)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            throttle: if self.scan_throttle_ms == 0 {
+                None
+            } else {
+                Some(Duration::from_millis(self.scan_throttle_ms))
+            },
+            skip_hidden: self.scan_skip_hidden,
+            hash_files: self.scan_hash_files,
+        }
+    }
+
+    /// How long crossfades are. Zero means tracks don't crossfade.
+    pub fn crossfade(&self) -> Duration {
+        Duration::from_secs(self.crossfade_secs)
+    }
+
+    /// How long pausing and resuming fade. Zero means they don't.
+    pub fn pause_fade(&self) -> Duration {
+        Duration::from_millis(self.pause_fade_ms)
+    }
+
+    /// How much of the next track to decode ahead of time, if any.
+    pub fn prefetch(&self) -> Option<Duration> {
+        if self.prefetch_secs == 0 { None } else { Some(Duration::from_secs(self.prefetch_secs)) }
+    }
+
+    /// The sample rate to play every track at, if not each at its own.
+    pub fn output_rate(&self) -> Option<u32> {
+        if self.output_sample_rate == 0 { None } else { Some(self.output_sample_rate) }
+    }
+
+    /// What a smart shuffle weighs tracks by.
+    pub fn shuffle_weights(&self) -> ShuffleWeights {
+        ShuffleWeights {
+            rating: self.shuffle_rating_weight,
+            recency: self.shuffle_recency_weight,
+            play_count: self.shuffle_play_count_weight,
+        }
+    }
+
+    /// How the artist tree groups tags.
+    pub fn grouping(&self) -> Grouping {
+        Grouping::new(self.featured_artist_patterns.clone(), self.album_edition_patterns.clone())
+    }
+}
+
+/// Save just the volume. The slider changes it far more often than anything else, so it's saved
+/// once it settles, and again when mus closes.
+pub fn save_volume(db: &Database, volume: f64) -> Result<(), DatabaseError> {
+    db.set_setting(VOLUME, &volume.to_string())
+}
+
+/// Save the window's size and position, which are only known once it's open.
+pub fn save_window_geometry(db: &Database, size: (f64, f64),
+                            position: Option<(f64, f64)>) -> Result<(), DatabaseError> {
+    db.set_setting(WINDOW_WIDTH, &size.0.to_string())?;
+    db.set_setting(WINDOW_HEIGHT, &size.1.to_string())?;
+
+    if let Some((x, y)) = position {
+        db.set_setting(WINDOW_X, &x.to_string())?;
+        db.set_setting(WINDOW_Y, &y.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Save just the strict sort setting, which can be toggled from the View menu.
+pub fn save_strict_sort(db: &Database, strict_sort: bool) -> Result<(), DatabaseError> {
+    db.set_setting(STRICT_SORT, &strict_sort.to_string())
+}
+
+/// Save just the setting for searching paths and comments, which can be toggled from the View
+/// menu.
+pub fn save_search_everywhere(db: &Database, search_everywhere: bool) -> Result<(), DatabaseError> {
+    db.set_setting(SEARCH_EVERYWHERE, &search_everywhere.to_string())
+}
+
+/// Save just the reduce motion setting, which can be toggled from the View menu.
+pub fn save_reduce_motion(db: &Database, reduce_motion: bool) -> Result<(), DatabaseError> {
+    db.set_setting(REDUCE_MOTION, &reduce_motion.to_string())
+}
+
+/// Save just whether to confirm full rescans, which can be turned off from the confirmation.
+pub fn save_confirm_full_rescan(db: &Database, confirm: bool) -> Result<(), DatabaseError> {
+    db.set_setting(CONFIRM_FULL_RESCAN, &confirm.to_string())
+}
+
+/// Save just the ReplayGain setting, which can be toggled from the Playback menu.
+pub fn save_replay_gain(db: &Database, replay_gain: bool) -> Result<(), DatabaseError> {
+    db.set_setting(REPLAY_GAIN, &replay_gain.to_string())
+}
+
+/// Save just how volumes are normalized, which is picked from the Playback menu.
+pub fn save_normalization(db: &Database,
+                          normalization: Normalization) -> Result<(), DatabaseError> {
+    db.set_setting(NORMALIZATION, &normalization.to_setting())
+}
+
+/// Save just whether silence is trimmed, which can be toggled from the Playback menu.
+pub fn save_trim_silence(db: &Database, trim_silence: bool) -> Result<(), DatabaseError> {
+    db.set_setting(TRIM_SILENCE, &trim_silence.to_string())
+}
+
+/// Save just whether the main window is kept on top, which is toggled from the View menu.
+pub fn save_always_on_top(db: &Database, always_on_top: bool) -> Result<(), DatabaseError> {
+    db.set_setting(ALWAYS_ON_TOP, &always_on_top.to_string())
+}
+
+/// Save just whether shuffles are smart ones, which can be toggled from the Playback menu.
+pub fn save_smart_shuffle(db: &Database, smart_shuffle: bool) -> Result<(), DatabaseError> {
+    db.set_setting(SMART_SHUFFLE, &smart_shuffle.to_string())
+}
+
+/// Save just the output device, which is picked from the Playback menu.
+pub fn save_output_device(db: &Database, device: Option<&str>) -> Result<(), DatabaseError> {
+    db.set_setting(OUTPUT_DEVICE, device.unwrap_or_default())
+}
+
+/// Save which view is open, for `StartupView::LastUsed`.
+pub fn save_last_view(db: &Database, view: View) -> Result<(), DatabaseError> {
+    db.set_setting(LAST_VIEW, &view.to_setting())
+}
+
+/// Save the track list's columns and their widths, which change whenever one is resized.
+pub fn save_columns(db: &Database, columns: &[(TrackField, f64)]) -> Result<(), DatabaseError> {
+    let fields: Vec<&str> = columns.iter().map(|&(field, _)| field.column()).collect();
+    let widths: Vec<String> = columns.iter().map(|(_, width)| width.to_string()).collect();
+    db.set_setting(COLUMN_FIELDS, &fields.join(","))?;
+    db.set_setting(COLUMN_WIDTHS, &widths.join(","))
+}
+
+/// The columns `view` was last shown with, if they were changed while it was shown. Views
+/// without their own use `Settings::columns`.
+pub fn load_view_columns(db: &Database,
+                         view: View) -> Result<Option<Vec<(TrackField, f64)>>, DatabaseError> {
+    let (fields_key, widths_key) = view_column_keys(view);
+    let fields = match db.setting(&fields_key)? {
+        Some(value) => match parse_column_fields(&value) {
+            Some(fields) => fields,
+            None => {
+                warn!("Ignoring invalid columns {:?} for {:?}", value, view);
+                return Ok(None);
+            }
+        },
+        None => return Ok(None),
+    };
+
+    let width = 1. / fields.len() as f64;
+    let mut columns: Vec<(TrackField, f64)> = fields.into_iter()
+        .map(|field| (field, width))
+        .collect();
+
+    if let Some(value) = db.setting(&widths_key)? {
+        match parse_column_widths(&value) {
+            Some(widths) if widths.len() == columns.len() => {
+                for (column, width) in columns.iter_mut().zip(widths) {
+                    column.1 = width;
+                }
+            }
+            _ => warn!("Ignoring invalid column widths {:?} for {:?}", value, view),
+        }
+    }
+
+    Ok(Some(columns))
+}
+
+/// Save the columns `view` is shown with, apart from other views'.
+pub fn save_view_columns(db: &Database, view: View,
+                         columns: &[(TrackField, f64)]) -> Result<(), DatabaseError> {
+    let (fields_key, widths_key) = view_column_keys(view);
+    let fields: Vec<&str> = columns.iter().map(|&(field, _)| field.column()).collect();
+    let widths: Vec<String> = columns.iter().map(|(_, width)| width.to_string()).collect();
+    db.set_setting(&fields_key, &fields.join(","))?;
+    db.set_setting(&widths_key, &widths.join(","))
+}
+
+/// Where a view's own columns are saved, like `column_fields.playlist:3`.
+fn view_column_keys(view: View) -> (String, String) {
+    let view = view.to_setting();
+    (format!("{}.{}", COLUMN_FIELDS, view), format!("{}.{}", COLUMN_WIDTHS, view))
+}
+
+/// Comma-separated field names, at least one, none repeated.
+fn parse_column_fields(value: &str) -> Option<Vec<TrackField>> {
+    let fields = value.split(',')
+        .map(|field| TrackField::from_column(field.trim()))
+        .collect::<Option<Vec<TrackField>>>()?;
+    let repeated = fields.iter().enumerate().any(|(i, field)| fields[..i].contains(field));
+
+    if fields.is_empty() || repeated {
+        None
+    } else {
+        Some(fields)
+    }
+}
+
+/// Comma-separated words, lowercased. Blank ones are dropped, so an empty list is just empty.
+fn parse_word_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Comma-separated fractions. They're scaled to add up to 1, in case of rounding.
+fn parse_column_widths(value: &str) -> Option<Vec<f64>> {
+    let widths = value.split(',')
+        .map(|width| width.trim().parse().ok().filter(|&width: &f64| width > 0.))
+        .collect::<Option<Vec<f64>>>()?;
+    let total: f64 = widths.iter().sum();
+
+    if widths.is_empty() || !total.is_finite() {
+        None
+    } else {
+        Some(widths.into_iter().map(|width| width / total).collect())
+    }
+}
+
+/// How loud a volume slider position is, as a gain for `Sink::set_volume`. Loudness is
+/// roughly logarithmic, so the slider covers 60 dB rather than mapping straight to amplitude,
+/// which would crowd everything audible into the top of the range.
+pub fn volume_gain(volume: f64) -> f32 {
+    if volume <= 0. {
+        0.
+    } else {
+        10f64.powf((volume.min(1.) - 1.) * 60. / 20.) as f32
+    }
+}
+
+/// A ReplayGain adjustment in dB as a factor to multiply `volume_gain` by.
+pub fn replay_gain_factor(gain: f64) -> f32 {
+    10f64.powf(gain / 20.) as f32
+}
+
+/// Work out which view to open at startup. A view that no longer exists (e.g. a deleted
+/// playlist or library) falls back to the full library.
+pub fn resolve_startup_view(startup_view: StartupView,
+                            last_view: Option<View>,
+                            playlist_exists: impl Fn(i64) -> bool,
+                            library_exists: impl Fn(i64) -> bool) -> View {
+    let view = match startup_view {
+        StartupView::View(view) => view,
+        StartupView::LastUsed   => last_view.unwrap_or(View::Library),
+    };
+
+    match view {
+        View::Playlist(id) if !playlist_exists(id) => {
+            warn!("Playlist {} no longer exists, opening the library instead", id);
+            View::Library
+        }
+        View::SingleLibrary(id) if !library_exists(id) => {
+            warn!("Library {} no longer exists, opening every library instead", id);
+            View::Library
+        }
+        view => view
+    }
+}
+
+/// Decide whether to scan the libraries at startup. Returns `Some(full_rescan)` to scan, or None
+/// to skip it. On the first run the new library is empty, so it always gets a full scan.
+pub fn startup_scan(setting: StartupScan, first_run: bool) -> Option<bool> {
+    if first_run {
+        return Some(true);
+    }
+
+    match setting {
+        StartupScan::Off         => None,
+        StartupScan::Incremental => Some(false),
+        StartupScan::Full        => Some(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_survive_being_saved() {
+        let views = [View::Library, View::SingleLibrary(2), View::Playlist(7), View::MostPlayed,
+                     View::RecentlyPlayed];
+        for &view in views.iter() {
+            assert_eq!(View::from_setting(&view.to_setting()), Some(view));
+            assert_eq!(StartupView::from_setting(&StartupView::View(view).to_setting()),
+                       Some(StartupView::View(view)));
+        }
+        assert_eq!(StartupView::from_setting("last_used"), Some(StartupView::LastUsed));
+    }
+
+    #[test]
+    fn unreadable_views_are_rejected() {
+        assert_eq!(View::from_setting(""), None);
+        assert_eq!(View::from_setting("albums"), None);
+        assert_eq!(View::from_setting("playlist:"), None);
+        assert_eq!(View::from_setting("playlist:seven"), None);
+        assert_eq!(View::from_setting("library:-"), None);
+    }
+
+    #[test]
+    fn the_chosen_startup_view_opens_if_it_still_exists() {
+        let view = resolve_startup_view(StartupView::View(View::Playlist(3)), None,
+                                        |id| id == 3, |_| false);
+        assert_eq!(view, View::Playlist(3));
+
+        let view = resolve_startup_view(StartupView::View(View::SingleLibrary(2)),
+                                        Some(View::MostPlayed), |_| false, |id| id == 2);
+        assert_eq!(view, View::SingleLibrary(2));
+
+        let view = resolve_startup_view(StartupView::View(View::RecentlyPlayed), None,
+                                        |_| false, |_| false);
+        assert_eq!(view, View::RecentlyPlayed);
+    }
+
+    #[test]
+    fn startup_views_that_are_gone_fall_back_to_the_library() {
+        let view = resolve_startup_view(StartupView::View(View::Playlist(3)), None,
+                                        |_| false, |_| true);
+        assert_eq!(view, View::Library);
+
+        let view = resolve_startup_view(StartupView::View(View::SingleLibrary(2)), None,
+                                        |_| true, |_| false);
+        assert_eq!(view, View::Library);
+
+        let view = resolve_startup_view(StartupView::LastUsed, Some(View::Playlist(3)),
+                                        |_| false, |_| true);
+        assert_eq!(view, View::Library);
+    }
+
+    #[test]
+    fn the_last_used_view_opens_when_chosen() {
+        let view = resolve_startup_view(StartupView::LastUsed, Some(View::Playlist(3)),
+                                        |id| id == 3, |_| true);
+        assert_eq!(view, View::Playlist(3));
+
+        // Nothing's been used yet
+        let view = resolve_startup_view(StartupView::LastUsed, None, |_| true, |_| true);
+        assert_eq!(view, View::Library);
+    }
+
+    #[test]
+    fn saved_startup_views_load_again() {
+        let db = Database::in_memory().unwrap();
+        let settings = Settings {
+            startup_view: StartupView::LastUsed,
+            last_view: Some(View::Playlist(4)),
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+
+        let loaded = Settings::load(&db).unwrap();
+        assert_eq!(loaded.startup_view, StartupView::LastUsed);
+        assert_eq!(loaded.last_view, Some(View::Playlist(4)));
+    }
+
+    #[test]
+    fn unknown_startup_views_load_as_the_default() {
+        let db = Database::in_memory().unwrap();
+        db.set_setting(STARTUP_VIEW, "albums").unwrap();
+
+        let loaded = Settings::load(&db).unwrap();
+        assert_eq!(loaded.startup_view, StartupView::View(View::Library));
+    }
+
+    #[test]
+    fn libraries_are_scanned_at_startup_as_configured() {
+        assert_eq!(startup_scan(StartupScan::Off, false), None);
+        assert_eq!(startup_scan(StartupScan::Incremental, false), Some(false));
+        assert_eq!(startup_scan(StartupScan::Full, false), Some(true));
+    }
+
+    #[test]
+    fn the_first_run_always_gets_a_full_scan() {
+        assert_eq!(startup_scan(StartupScan::Off, true), Some(true));
+        assert_eq!(startup_scan(StartupScan::Incremental, true), Some(true));
+        assert_eq!(startup_scan(StartupScan::Full, true), Some(true));
+    }
+
+    #[test]
+    fn startup_scans_default_to_incremental() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().startup_scan, StartupScan::Incremental);
+
+        db.set_setting(STARTUP_SCAN, "sometimes").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().startup_scan, StartupScan::Incremental);
+
+        db.set_setting(STARTUP_SCAN, "off").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().startup_scan, StartupScan::Off);
+    }
+
+    #[test]
+    fn deleted_files_go_to_the_trash_by_default() {
+        let db = Database::in_memory().unwrap();
+        assert!(Settings::load(&db).unwrap().delete_to_trash);
+
+        let settings = Settings { delete_to_trash: false, ..Settings::default() };
+        settings.save(&db).unwrap();
+        assert!(!Settings::load(&db).unwrap().delete_to_trash);
+
+        db.set_setting(DELETE_TO_TRASH, "sometimes").unwrap();
+        assert!(Settings::load(&db).unwrap().delete_to_trash);
+    }
+
+    #[test]
+    fn searching_everywhere_is_saved() {
+        let db = Database::in_memory().unwrap();
+        assert!(!Settings::load(&db).unwrap().search_everywhere);
+
+        save_search_everywhere(&db, true).unwrap();
+        assert!(Settings::load(&db).unwrap().search_everywhere);
+    }
+
+    #[test]
+    fn views_keep_their_own_columns() {
+        let db = Database::in_memory().unwrap();
+        let playlist = vec![(TrackField::Track, 0.2), (TrackField::Title, 0.8)];
+        let library = vec![(TrackField::Title, 0.5), (TrackField::PlayCount, 0.5)];
+
+        save_view_columns(&db, View::Playlist(3), &playlist).unwrap();
+        save_view_columns(&db, View::Library, &library).unwrap();
+
+        assert_eq!(load_view_columns(&db, View::Playlist(3)).unwrap(), Some(playlist));
+        assert_eq!(load_view_columns(&db, View::Library).unwrap(), Some(library));
+    }
+
+    #[test]
+    fn views_without_their_own_columns_use_the_default() {
+        let db = Database::in_memory().unwrap();
+        save_view_columns(&db, View::Playlist(3), &[(TrackField::Track, 1.)]).unwrap();
+
+        assert_eq!(load_view_columns(&db, View::Playlist(4)).unwrap(), None);
+        assert_eq!(load_view_columns(&db, View::MostPlayed).unwrap(), None);
+        // Nor do they change the default
+        assert_eq!(Settings::load(&db).unwrap().columns, Settings::default().columns);
+    }
+
+    #[test]
+    fn invalid_view_columns_are_ignored() {
+        let db = Database::in_memory().unwrap();
+        db.set_setting("column_fields.playlist:3", "title,colour").unwrap();
+        assert_eq!(load_view_columns(&db, View::Playlist(3)).unwrap(), None);
+
+        // Widths that don't line up with the fields are shared out equally instead
+        db.set_setting("column_fields.playlist:3", "title,artist").unwrap();
+        db.set_setting("column_widths.playlist:3", "1").unwrap();
+        assert_eq!(load_view_columns(&db, View::Playlist(3)).unwrap(),
+                   Some(vec![(TrackField::Title, 0.5), (TrackField::Artist, 0.5)]));
+    }
+
+    #[test]
+    fn featured_artist_patterns_are_a_word_list() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().featured_artist_patterns,
+                   vec!["feat.", "ft.", "featuring"]);
+
+        db.set_setting(FEATURED_ARTIST_PATTERNS, " Feat. ,, with ").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().featured_artist_patterns, vec!["feat.", "with"]);
+
+        // Turned off
+        db.set_setting(FEATURED_ARTIST_PATTERNS, "").unwrap();
+        let settings = Settings::load(&db).unwrap();
+        assert!(settings.featured_artist_patterns.is_empty());
+
+        settings.save(&db).unwrap();
+        assert!(Settings::load(&db).unwrap().featured_artist_patterns.is_empty());
+    }
+
+    #[test]
+    fn album_edition_patterns_are_a_word_list() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().album_edition_patterns,
+                   vec!["deluxe", "remaster", "expanded", "anniversary"]);
+
+        let settings = Settings {
+            album_edition_patterns: vec!["live".to_string(), "mono".to_string()],
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+        assert_eq!(Settings::load(&db).unwrap().album_edition_patterns, vec!["live", "mono"]);
+
+        db.set_setting(ALBUM_EDITION_PATTERNS, "").unwrap();
+        assert!(Settings::load(&db).unwrap().album_edition_patterns.is_empty());
+    }
+
+    #[test]
+    fn pause_fades_are_short_by_default_and_can_be_turned_off() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().pause_fade(), Duration::from_millis(50));
+
+        db.set_setting(PAUSE_FADE_MS, "0").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().pause_fade(), Duration::default());
+
+        // Too long to be a fade
+        db.set_setting(PAUSE_FADE_MS, "5000").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().pause_fade_ms, 50);
+    }
+
+    #[test]
+    fn the_next_track_is_prefetched_unless_turned_off() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().prefetch(), Some(Duration::from_secs(10)));
+
+        db.set_setting(PREFETCH_SECS, "0").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().prefetch(), None);
+
+        // Too much to hold in memory
+        db.set_setting(PREFETCH_SECS, "600").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().prefetch_secs, 10);
+    }
+
+    #[test]
+    fn silence_is_only_trimmed_when_turned_on() {
+        let db = Database::in_memory().unwrap();
+        let settings = Settings::load(&db).unwrap();
+        assert!(!settings.trim_silence);
+        assert_eq!(settings.silence_threshold_db, -60.);
+
+        save_trim_silence(&db, true).unwrap();
+        db.set_setting(SILENCE_THRESHOLD_DB, "-72.5").unwrap();
+        let settings = Settings::load(&db).unwrap();
+        assert!(settings.trim_silence);
+        assert_eq!(settings.silence_threshold_db, -72.5);
+
+        // Loud enough to cut into the music
+        db.set_setting(SILENCE_THRESHOLD_DB, "-6").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().silence_threshold_db, -60.);
+    }
+
+    #[test]
+    fn window_title_formats_are_saved() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().window_title_format, "{artist} — {title} · mus");
+
+        let settings = Settings {
+            window_title_format: "{title}".to_string(),
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+        assert_eq!(Settings::load(&db).unwrap().window_title_format, "{title}");
+    }
+
+    #[test]
+    fn full_rescans_are_confirmed_until_turned_off() {
+        let db = Database::in_memory().unwrap();
+        assert!(Settings::load(&db).unwrap().confirm_full_rescan);
+
+        save_confirm_full_rescan(&db, false).unwrap();
+        assert!(!Settings::load(&db).unwrap().confirm_full_rescan);
+    }
+
+    #[test]
+    fn view_positions_are_remembered_until_turned_off() {
+        let db = Database::in_memory().unwrap();
+        assert!(Settings::load(&db).unwrap().remember_view_positions);
+
+        let settings = Settings {
+            remember_view_positions: false,
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+        assert!(!Settings::load(&db).unwrap().remember_view_positions);
+    }
+
+    #[test]
+    fn shuffle_weights_are_saved_unless_negative() {
+        let db = Database::in_memory().unwrap();
+        let settings = Settings::load(&db).unwrap();
+        assert!(!settings.smart_shuffle);
+        assert_eq!(settings.shuffle_weights(),
+                   ShuffleWeights { rating: 1., recency: 1., play_count: 0.1 });
+
+        let settings = Settings {
+            smart_shuffle: true,
+            shuffle_rating_weight: 2.5,
+            shuffle_play_count_weight: 0.,
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+        let loaded = Settings::load(&db).unwrap();
+        assert!(loaded.smart_shuffle);
+        assert_eq!(loaded.shuffle_weights(),
+                   ShuffleWeights { rating: 2.5, recency: 1., play_count: 0. });
+
+        db.set_setting(SHUFFLE_RECENCY_WEIGHT, "-1").unwrap();
+        db.set_setting(SHUFFLE_RATING_WEIGHT, "NaN").unwrap();
+        let loaded = Settings::load(&db).unwrap();
+        assert_eq!(loaded.shuffle_recency_weight, 1.);
+        assert_eq!(loaded.shuffle_rating_weight, 1.);
+    }
+
+    #[test]
+    fn reducing_motion_is_saved() {
+        let db = Database::in_memory().unwrap();
+        assert!(!Settings::load(&db).unwrap().reduce_motion);
+
+        save_reduce_motion(&db, true).unwrap();
+        assert!(Settings::load(&db).unwrap().reduce_motion);
+
+        db.set_setting(REDUCE_MOTION, "lots").unwrap();
+        assert!(!Settings::load(&db).unwrap().reduce_motion);
+    }
+
+    #[test]
+    fn keeping_the_window_on_top_is_saved() {
+        let db = Database::in_memory().unwrap();
+        assert!(!Settings::load(&db).unwrap().always_on_top);
+
+        save_always_on_top(&db, true).unwrap();
+        assert!(Settings::load(&db).unwrap().always_on_top);
+
+        db.set_setting(ALWAYS_ON_TOP, "yes").unwrap();
+        assert!(!Settings::load(&db).unwrap().always_on_top);
+    }
+
+    #[test]
+    fn tracks_play_at_their_own_rate_unless_one_is_set() {
+        let db = Database::in_memory().unwrap();
+        let settings = Settings::load(&db).unwrap();
+        assert_eq!(settings.output_rate(), None);
+        assert_eq!(settings.resample_quality, ResampleQuality::Cubic);
+
+        let settings = Settings {
+            output_sample_rate: 48000,
+            resample_quality: ResampleQuality::Linear,
+            ..Settings::default()
+        };
+        settings.save(&db).unwrap();
+        let loaded = Settings::load(&db).unwrap();
+        assert_eq!(loaded.output_rate(), Some(48000));
+        assert_eq!(loaded.resample_quality, ResampleQuality::Linear);
+
+        // Not a rate anything plays at
+        db.set_setting(OUTPUT_SAMPLE_RATE, "12").unwrap();
+        db.set_setting(RESAMPLE_QUALITY, "sinc").unwrap();
+        let loaded = Settings::load(&db).unwrap();
+        assert_eq!(loaded.output_rate(), None);
+        assert_eq!(loaded.resample_quality, ResampleQuality::Cubic);
+    }
+
+    #[test]
+    fn volumes_are_normalized_by_replay_gain_unless_another_way_is_picked() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(Settings::load(&db).unwrap().normalization, Normalization::ReplayGain);
+
+        save_normalization(&db, Normalization::Album).unwrap();
+        assert_eq!(Settings::load(&db).unwrap().normalization, Normalization::Album);
+
+        db.set_setting(NORMALIZATION, "loudest").unwrap();
+        assert_eq!(Settings::load(&db).unwrap().normalization, Normalization::ReplayGain);
+    }
+}
@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+
+use rand::Rng;
+
+use crate::db::{Track, MAX_RATING};
+
+/// How many days it takes a track that's just played to get halfway back to the weight of one
+/// that's never played.
+const RECOVERY_DAYS: f64 = 30.;
+
+const SECS_PER_DAY: f64 = 24. * 60. * 60.;
+
+/// How strongly a smart shuffle favours each thing about a track. Zero leaves it out, so with
+/// every weight at zero all tracks are equally likely.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShuffleWeights {
+    /// Towards higher-rated tracks.
+    pub rating: f64,
+    /// Towards tracks that haven't played for a while.
+    pub recency: f64,
+    /// Away from tracks that have played a lot.
+    pub play_count: f64,
+}
+
+impl ShuffleWeights {
+    pub const UNIFORM: ShuffleWeights = ShuffleWeights { rating: 0., recency: 0., play_count: 0. };
+}
+
+/// How likely `track` is to come early in a shuffle, relative to the others, as of `now` in
+/// seconds since the epoch. Unrated tracks count as halfway up the scale. Always positive, for
+/// weights that aren't negative.
+pub fn track_weight(track: &Track, weights: &ShuffleWeights, now: i64) -> f64 {
+    let rating = track.rating().map_or(0.5, |rating| rating as f64 / MAX_RATING as f64);
+    let freshness = match track.last_played() {
+        Some(played) => {
+            let days = (now - played).max(0) as f64 / SECS_PER_DAY;
+            1. - 0.5_f64.powf(days / RECOVERY_DAYS)
+        }
+        None => 1.,
+    };
+    let plays = track.play_count().max(0) as f64;
+
+    (1. + weights.rating * rating) * (1. + weights.recency * freshness)
+        / (1. + weights.play_count * plays)
+}
+
+/// Shuffle `tracks` so that each position is filled by picking from the tracks left, each with
+/// a chance in proportion to its `track_weight`. The same `rng` state gives the same order.
+pub fn weighted_shuffle(tracks: Vec<Track>, weights: &ShuffleWeights, now: i64,
+                        rng: &mut impl Rng) -> Vec<Track> {
+    // Sorting by u^(1/weight) for uniform u picks in proportion to weight (Efraimidis and
+    // Spirakis); its log keeps tiny weights from rounding to zero
+    let mut keyed: Vec<(f64, Track)> = tracks.into_iter()
+        .map(|track| {
+            let u: f64 = rng.gen();
+            (u.ln() / track_weight(&track, weights, now), track)
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+    keyed.into_iter().map(|(_, track)| track).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    const NOW: i64 = 1_600_000_000;
+    const DAY: i64 = 24 * 60 * 60;
+
+    const WEIGHTS: ShuffleWeights = ShuffleWeights { rating: 1., recency: 1., play_count: 0.5 };
+
+    fn weight(track: &Track) -> f64 {
+        track_weight(track, &WEIGHTS, NOW)
+    }
+
+    fn ids(tracks: &[Track]) -> Vec<i64> {
+        tracks.iter().map(Track::id).collect()
+    }
+
+    #[test]
+    fn higher_ratings_weigh_more() {
+        let unplayed = Track::for_test(1, "a");
+        assert_eq!(weight(&unplayed.clone().with_rating(5)), 2. * 2.);
+        assert_eq!(weight(&unplayed.clone().with_rating(1)), 1.2 * 2.);
+        assert_eq!(weight(&unplayed), 1.5 * 2.);
+    }
+
+    #[test]
+    fn recent_and_frequent_plays_weigh_less() {
+        let track = Track::for_test(1, "a").with_rating(5);
+        assert_eq!(weight(&track.clone().with_plays(1, NOW)), 2. / 1.5);
+        assert_eq!(weight(&track.clone().with_plays(1, NOW - 30 * DAY)), 2. * 1.5 / 1.5);
+        assert_eq!(weight(&track.clone().with_plays(3, NOW - 30 * DAY)), 2. * 1.5 / 2.5);
+        // However long ago, it's never worth more than a track that's never played
+        assert!(weight(&track.clone().with_plays(1, NOW - 3650 * DAY)) < weight(&track));
+        // Clocks that have gone backwards don't make plays count for more
+        assert_eq!(weight(&track.clone().with_plays(1, NOW + DAY)), 2. / 1.5);
+    }
+
+    #[test]
+    fn uniform_weights_are_all_the_same() {
+        let tracks = [Track::for_test(1, "a").with_rating(5).with_plays(12, NOW),
+                      Track::for_test(2, "b")];
+        for track in tracks.iter() {
+            assert_eq!(track_weight(track, &ShuffleWeights::UNIFORM, NOW), 1.);
+        }
+    }
+
+    #[test]
+    fn shuffles_keep_every_track_and_follow_the_seed() {
+        let tracks: Vec<Track> = (0..20).map(|id| Track::for_test(id, "a")).collect();
+        let shuffle = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            ids(&weighted_shuffle(tracks.clone(), &WEIGHTS, NOW, &mut rng))
+        };
+
+        let mut sorted = shuffle(42);
+        sorted.sort_unstable();
+        assert_eq!(sorted, ids(&tracks));
+        assert_eq!(shuffle(42), shuffle(42));
+        assert_ne!(shuffle(42), shuffle(43));
+    }
+
+    #[test]
+    fn tracks_come_first_in_proportion_to_their_weight() {
+        // Weights of 4, 2.4 and 1.2
+        let tracks = vec![
+            Track::for_test(1, "a").with_rating(5),
+            Track::for_test(2, "b").with_rating(1),
+            Track::for_test(3, "c").with_rating(1).with_plays(1, NOW - 30 * DAY),
+        ];
+        let weights: Vec<f64> = tracks.iter().map(weight).collect();
+        let total: f64 = weights.iter().sum();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let draws = 20_000;
+        let mut firsts = [0; 3];
+        for _ in 0..draws {
+            let first = weighted_shuffle(tracks.clone(), &WEIGHTS, NOW, &mut rng)[0].id();
+            firsts[first as usize - 1] += 1;
+        }
+
+        for (count, weight) in firsts.iter().zip(weights) {
+            let (share, expected) = (*count as f64 / draws as f64, weight / total);
+            assert!((share - expected).abs() < 0.015, "{} vs {}", share, expected);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use walkdir::WalkDir;
+
+use crate::db::{Database, DatabaseError};
+
+/// What a sidecar file says about the track it sits next to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SidecarValues {
+    pub rating: Option<i32>,
+}
+
+/// A sidecar format. Sidecars are named after their track plus the format's extension, e.g.
+/// `song.flac.json` for `song.flac`.
+pub trait SidecarFormat {
+    /// Without the leading dot.
+    fn extension(&self) -> &'static str;
+
+    fn parse(&self, contents: &str) -> Result<SidecarValues, String>;
+}
+
+/// `{"rating": 4}`, with the rating in stars. Unknown keys are ignored.
+pub struct JsonSidecar;
+
+impl SidecarFormat for JsonSidecar {
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn parse(&self, contents: &str) -> Result<SidecarValues, String> {
+        let value: serde_json::Value = serde_json::from_str(contents)
+            .map_err(|e| e.to_string())?;
+
+        let rating = match value.get("rating") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(rating) => Some(rating.as_i64().ok_or("rating is not an integer")? as i32),
+        };
+
+        Ok(SidecarValues {
+            rating,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Ids of the tracks that had values applied.
+    pub applied: Vec<i64>,
+    /// Sidecars that don't belong to any track in the database.
+    pub unmatched: Vec<PathBuf>,
+    /// Sidecars that couldn't be read or parsed, or had invalid values, and why.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Apply every sidecar of the given format under `root` to the track it belongs to.
+pub fn import_sidecars(db: &mut Database, root: &Path, format: &dyn SidecarFormat)
+                       -> Result<ImportReport, DatabaseError> {
+    info!("Importing .{} sidecars from {}", format.extension(), root.display());
+    let mut report = ImportReport::default();
+
+    for entry in WalkDir::new(root).follow_links(true) {
+        let entry = entry?;
+        let sidecar = entry.path();
+
+        if !entry.file_type().is_file()
+            || sidecar.extension().and_then(|e| e.to_str()) != Some(format.extension()) {
+            continue;
+        }
+
+        // The track path is the sidecar path without the sidecar extension
+        let track_path = match sidecar.with_extension("").canonicalize().ok()
+            .and_then(|path| path.into_os_string().into_string().ok()) {
+            Some(track_path) => track_path,
+            None => {
+                report.unmatched.push(sidecar.to_path_buf());
+                continue;
+            }
+        };
+
+        let track = match db.track_by_path(&track_path)? {
+            Some(track) => track,
+            None => {
+                report.unmatched.push(sidecar.to_path_buf());
+                continue;
+            }
+        };
+
+        let values = match fs::read_to_string(sidecar).map_err(|e| e.to_string())
+            .and_then(|contents| format.parse(&contents)) {
+            Ok(values) => values,
+            Err(e) => {
+                warn!("Could not read sidecar {}: {}", sidecar.display(), e);
+                report.failed.push((sidecar.to_path_buf(), e));
+                continue;
+            }
+        };
+
+        if values.rating.is_some() {
+            match db.set_rating(track.id(), values.rating) {
+                Ok(()) => report.applied.push(track.id()),
+                Err(DatabaseError::InvalidRating(rating)) => {
+                    report.failed.push((sidecar.to_path_buf(), format!("invalid rating {}", rating)));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    info!("Applied {} sidecars, {} unmatched, {} failed",
+          report.applied.len(), report.unmatched.len(), report.failed.len());
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::test_support::{canonical, write_wav};
+
+    use super::*;
+
+    #[test]
+    fn json_sidecars_give_a_rating() {
+        assert_eq!(JsonSidecar.parse(r#"{"rating": 4, "comment": "great"}"#),
+                   Ok(SidecarValues { rating: Some(4) }));
+        assert_eq!(JsonSidecar.parse(r#"{"rating": null}"#), Ok(SidecarValues { rating: None }));
+        assert_eq!(JsonSidecar.parse("{}"), Ok(SidecarValues { rating: None }));
+    }
+
+    #[test]
+    fn unreadable_json_sidecars_are_rejected() {
+        assert!(JsonSidecar.parse("").is_err());
+        assert!(JsonSidecar.parse(r#"{"rating": "four"}"#).is_err());
+        assert!(JsonSidecar.parse(r#"{"rating": 4.5}"#).is_err());
+    }
+
+    /// A track at `dir/name`, already in the database.
+    fn seed_track(db: &mut Database, dir: &Path, name: &str) -> i64 {
+        let path = dir.join(name);
+        write_wav(&path, 1, 1);
+        db.add_individual_track(&canonical(&path)).unwrap().unwrap().id()
+    }
+
+    #[test]
+    fn sidecars_rate_the_tracks_next_to_them() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let a = seed_track(&mut db, dir.path(), "a.wav");
+        let b = seed_track(&mut db, &dir.path().join("sub"), "b.wav");
+        let unrated = seed_track(&mut db, dir.path(), "c.wav");
+        fs::write(dir.path().join("a.wav.json"), r#"{"rating": 5}"#).unwrap();
+        fs::write(dir.path().join("sub").join("b.wav.json"), r#"{"rating": 2}"#).unwrap();
+        // Other files are left alone, even if they look like sidecars of another format
+        fs::write(dir.path().join("c.wav.xmp"), "<x:xmpmeta/>").unwrap();
+
+        let report = import_sidecars(&mut db, dir.path(), &JsonSidecar).unwrap();
+
+        let mut applied = report.applied.clone();
+        applied.sort_unstable();
+        assert_eq!(applied, vec![a, b]);
+        assert!(report.unmatched.is_empty());
+        assert!(report.failed.is_empty());
+        assert_eq!(db.track(a).unwrap().unwrap().rating(), Some(5));
+        assert_eq!(db.track(b).unwrap().unwrap().rating(), Some(2));
+        assert_eq!(db.track(unrated).unwrap().unwrap().rating(), None);
+    }
+
+    #[test]
+    fn sidecars_without_a_track_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        // A file that isn't in the database, and one that doesn't exist at all
+        write_wav(&dir.path().join("unknown.wav"), 1, 1);
+        fs::write(dir.path().join("unknown.wav.json"), r#"{"rating": 3}"#).unwrap();
+        fs::write(dir.path().join("missing.wav.json"), r#"{"rating": 3}"#).unwrap();
+
+        let mut report = import_sidecars(&mut db, dir.path(), &JsonSidecar).unwrap();
+
+        report.unmatched.sort();
+        assert!(report.applied.is_empty());
+        assert_eq!(report.unmatched, vec![dir.path().join("missing.wav.json"),
+                                          dir.path().join("unknown.wav.json")]);
+    }
+
+    #[test]
+    fn sidecars_with_bad_values_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::in_memory().unwrap();
+        let a = seed_track(&mut db, dir.path(), "a.wav");
+        let b = seed_track(&mut db, dir.path(), "b.wav");
+        fs::write(dir.path().join("a.wav.json"), r#"{"rating": 9}"#).unwrap();
+        fs::write(dir.path().join("b.wav.json"), "not json").unwrap();
+
+        let mut report = import_sidecars(&mut db, dir.path(), &JsonSidecar).unwrap();
+
+        report.failed.sort();
+        assert!(report.applied.is_empty());
+        let failed: Vec<&PathBuf> = report.failed.iter().map(|(path, _)| path).collect();
+        assert_eq!(failed, vec![&dir.path().join("a.wav.json"), &dir.path().join("b.wav.json")]);
+        assert_eq!(db.track(a).unwrap().unwrap().rating(), None);
+        assert_eq!(db.track(b).unwrap().unwrap().rating(), None);
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+/// Finds where a track's sound starts and ends, from its interleaved samples, so the silence
+/// before and after it can be skipped. A frame with any channel louder than the threshold is
+/// sound; everything else is silence.
+pub struct SilenceDetector {
+    channels: u64,
+    sample_rate: u64,
+    /// The loudest a sample can be and still be silent, out of 32768.
+    threshold: f64,
+    samples: u64,
+    /// The first and last frames with any sound in them.
+    first_sound: Option<u64>,
+    last_sound: u64,
+}
+
+impl SilenceDetector {
+    /// Detect silence at `threshold` dB below full scale, or quieter.
+    pub fn new(channels: u16, sample_rate: u32, threshold: f64) -> Self {
+        SilenceDetector {
+            channels: channels.max(1) as u64,
+            sample_rate: sample_rate.max(1) as u64,
+            threshold: 10f64.powf(threshold / 20.) * 32768.,
+            samples: 0,
+            first_sound: None,
+            last_sound: 0,
+        }
+    }
+
+    pub fn add_samples(&mut self, samples: impl IntoIterator<Item = i16>) {
+        for sample in samples {
+            if (sample as f64).abs() > self.threshold {
+                let frame = self.samples / self.channels;
+                self.first_sound.get_or_insert(frame);
+                self.last_sound = frame;
+            }
+            self.samples += 1;
+        }
+    }
+
+    /// Where the sound starts and ends, in frames: the first frame of it, and the one after the
+    /// last. None if it's silent throughout, since there's nothing to trim to.
+    pub fn sound_frames(&self) -> Option<(u64, u64)> {
+        self.first_sound.map(|first| (first, self.last_sound + 1))
+    }
+
+    /// Where the sound starts and ends, from the start of the track. Rounded to the millisecond
+    /// outwards, so none of it is ever cut.
+    pub fn sound(&self) -> Option<(Duration, Duration)> {
+        let rate = self.sample_rate;
+        self.sound_frames().map(|(start, end)| {
+            (Duration::from_millis(start * 1000 / rate),
+             Duration::from_millis((end * 1000 + rate - 1) / rate))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo at 1 kHz, so a frame is a millisecond: `silent` frames of silence, then `sound`
+    /// frames at `level` in both channels, then `after` frames of silence.
+    fn buffer(silent: usize, sound: usize, after: usize, level: i16) -> Vec<i16> {
+        let mut samples = vec![0; 2 * silent];
+        samples.extend((0..sound).flat_map(|i| {
+            // Swinging either side of zero, as a wave would
+            let sample = if i % 2 == 0 { level } else { -level };
+            vec![sample, sample]
+        }));
+        samples.extend(vec![0; 2 * after]);
+        samples
+    }
+
+    fn detect(samples: &[i16], threshold: f64) -> SilenceDetector {
+        let mut detector = SilenceDetector::new(2, 1000, threshold);
+        detector.add_samples(samples.iter().copied());
+        detector
+    }
+
+    #[test]
+    fn sound_is_found_between_the_silent_regions() {
+        let detector = detect(&buffer(300, 400, 250, 8000), -60.);
+        assert_eq!(detector.sound_frames(), Some((300, 700)));
+        assert_eq!(detector.sound(),
+                   Some((Duration::from_millis(300), Duration::from_millis(700))));
+
+        // Without any silence, the whole track is sound
+        assert_eq!(detect(&buffer(0, 500, 0, 8000), -60.).sound_frames(), Some((0, 500)));
+    }
+
+    #[test]
+    fn sound_in_any_channel_counts() {
+        let mut samples = vec![0; 2 * 100];
+        samples[2 * 10 + 1] = 8000;
+        samples[2 * 60] = -8000;
+
+        assert_eq!(detect(&samples, -60.).sound_frames(), Some((10, 61)));
+    }
+
+    #[test]
+    fn quiet_noise_is_silence_below_the_threshold() {
+        // About -70 dB
+        let samples = buffer(100, 200, 100, 10);
+        assert_eq!(detect(&samples, -60.).sound_frames(), None);
+        assert_eq!(detect(&samples, -80.).sound_frames(), Some((100, 300)));
+
+        // A sound right at the threshold is still silence
+        let at = (10f64.powf(-40. / 20.) * 32768.) as i16;
+        assert_eq!(detect(&buffer(10, 10, 10, at), -40.).sound_frames(), None);
+        assert_eq!(detect(&buffer(10, 10, 10, at + 1), -40.).sound_frames(), Some((10, 20)));
+    }
+
+    #[test]
+    fn silent_tracks_have_nothing_to_trim_to() {
+        assert_eq!(detect(&[], -60.).sound(), None);
+        assert_eq!(detect(&buffer(500, 0, 0, 0), -60.).sound(), None);
+    }
+
+    #[test]
+    fn sound_is_rounded_outwards_to_the_millisecond() {
+        // 50 frames is 1.13 ms at 44.1 kHz, and 100 is 2.27 ms
+        let mut samples = vec![0; 2 * 150];
+        samples[2 * 50] = 8000;
+        samples[2 * 99] = 8000;
+        let mut detector = SilenceDetector::new(2, 44100, -60.);
+        detector.add_samples(samples);
+
+        assert_eq!(detector.sound_frames(), Some((50, 100)));
+        assert_eq!(detector.sound(), Some((Duration::from_millis(1), Duration::from_millis(3))));
+    }
+}
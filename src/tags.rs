@@ -0,0 +1,189 @@
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use lofty::{Accessor, ItemKey, PictureType, TaggedFileExt};
+use taglib::File;
+use thiserror::Error;
+
+use crate::db::TrackMetadata;
+
+/// Everything we store about a track that comes from the file itself.
+#[derive(Debug, Clone)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub comment: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub length: u32,
+    pub bitrate: u32,
+    pub samplerate: u32,
+    pub disc: Option<u32>,
+    /// The track's ReplayGain adjustment in dB, from its `REPLAYGAIN_TRACK_GAIN` tag.
+    pub replay_gain: Option<f64>,
+    /// Modification time of the file when it was read, in seconds since the epoch.
+    pub mtime: Option<i64>,
+}
+
+impl TrackTags {
+    /// Read the tags of the file at `path`. Returns None if it isn't something taglib
+    /// recognizes as a track.
+    pub fn read(path: &str) -> Option<TrackTags> {
+        let file = File::new(path).ok()?;
+        let tag = file.tag().ok()?;
+        let properties = file.audioproperties().ok()?;
+        let extended = ExtendedTags::read(path);
+
+        Some(TrackTags {
+            title: tag.title(),
+            artist: tag.artist(),
+            album: tag.album(),
+            comment: tag.comment(),
+            genre: tag.genre(),
+            year: tag.year(),
+            track: tag.track(),
+            length: properties.length(),
+            bitrate: properties.bitrate(),
+            samplerate: properties.samplerate(),
+            disc: extended.disc,
+            replay_gain: extended.replay_gain,
+            mtime: file_mtime(path),
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TagWriteError {
+    #[error("the file is read-only")]
+    ReadOnly,
+    #[error("its format isn't supported")]
+    Unsupported,
+    #[error("the file could not be saved")]
+    SaveFailed,
+}
+
+/// Write editable tags to the file at `path`. Tags that are None are cleared.
+pub fn write_tags(path: &str, metadata: &TrackMetadata) -> Result<(), TagWriteError> {
+    if fs::metadata(path).map_or(false, |metadata| metadata.permissions().readonly()) {
+        return Err(TagWriteError::ReadOnly);
+    }
+
+    let file = File::new(path).map_err(|_| TagWriteError::Unsupported)?;
+    let mut tag = file.tag().map_err(|_| TagWriteError::Unsupported)?;
+
+    tag.set_title(metadata.title.as_deref().unwrap_or_default());
+    tag.set_artist(metadata.artist.as_deref().unwrap_or_default());
+    tag.set_album(metadata.album.as_deref().unwrap_or_default());
+    tag.set_comment(metadata.comment.as_deref().unwrap_or_default());
+    tag.set_genre(metadata.genre.as_deref().unwrap_or_default());
+    // taglib uses 0 for no year or track number
+    tag.set_year(metadata.year.unwrap_or(0).max(0) as u32);
+    tag.set_track(metadata.track.unwrap_or(0).max(0) as u32);
+
+    if file.save() {
+        Ok(())
+    } else {
+        Err(TagWriteError::SaveFailed)
+    }
+}
+
+/// Modification time of the file at `path` in seconds since the epoch, if it can be read.
+pub fn file_mtime(path: &str) -> Option<i64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+/// Whether the tags we stored for a file may be out of date, given the modification time we
+/// stored with them and the file's current one. A file we can't stat isn't stale, it's missing;
+/// and tracks added before we recorded mtimes have nothing to compare against.
+pub fn is_stale(stored_mtime: Option<i64>, current_mtime: Option<i64>) -> bool {
+    match (stored_mtime, current_mtime) {
+        (Some(stored), Some(current)) => current > stored,
+        _ => false,
+    }
+}
+
+/// The cover art embedded in the file at `path`: the front cover if it's marked as one, otherwise
+/// the first picture. taglib's C interface doesn't expose pictures, so this goes through lofty.
+pub fn embedded_art(path: &str) -> Option<Vec<u8>> {
+    let tagged = lofty::read_from_path(path).ok()?;
+    let pictures: Vec<_> = tagged.tags().iter().flat_map(|tag| tag.pictures()).collect();
+
+    pictures.iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|picture| picture.data().to_vec())
+}
+
+/// Tags that taglib's C interface doesn't expose, read through lofty instead.
+#[derive(Debug, Clone, Default)]
+struct ExtendedTags {
+    disc: Option<u32>,
+    replay_gain: Option<f64>,
+}
+
+impl ExtendedTags {
+    /// Files lofty can't parse just get the defaults, since taglib is the authority on whether
+    /// something is a track at all.
+    fn read(path: &str) -> ExtendedTags {
+        let tagged = match lofty::read_from_path(path) {
+            Ok(tagged) => tagged,
+            Err(_) => return ExtendedTags::default(),
+        };
+
+        let tag = match tagged.primary_tag().or_else(|| tagged.first_tag()) {
+            Some(tag) => tag,
+            None => return ExtendedTags::default(),
+        };
+
+        ExtendedTags {
+            disc: tag.disk(),
+            replay_gain: tag.get_string(&ItemKey::ReplayGainTrackGain).and_then(parse_gain),
+        }
+    }
+}
+
+/// Read a ReplayGain value like "-6.52 dB".
+fn parse_gain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = match value.len().checked_sub(2).and_then(|end| value.get(end..)) {
+        Some(unit) if unit.eq_ignore_ascii_case("db") => &value[..value.len() - 2],
+        _ => value,
+    };
+
+    number.trim().parse().ok().filter(|gain: &f64| gain.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn files_modified_after_their_tags_were_read_are_stale() {
+        assert!(is_stale(Some(100), Some(101)));
+        assert!(!is_stale(Some(100), Some(100)));
+        // e.g. restored from a backup
+        assert!(!is_stale(Some(100), Some(99)));
+    }
+
+    #[test]
+    fn files_without_both_mtimes_are_not_stale() {
+        assert!(!is_stale(None, Some(100)));
+        assert!(!is_stale(Some(100), None));
+        assert!(!is_stale(None, None));
+    }
+
+    #[test]
+    fn only_files_that_exist_have_an_mtime() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.flac");
+        fs::write(&path, "").unwrap();
+
+        assert!(file_mtime(path.to_str().unwrap()).is_some());
+        assert_eq!(file_mtime(dir.path().join("missing.flac").to_str().unwrap()), None);
+    }
+}
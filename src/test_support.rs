@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::Path;
+
+/// Write a mono, 16-bit, 8 kHz WAV file `seconds` long, which taglib and rodio can both read.
+/// Files written with different `seed`s have different samples, and so different hashes.
+pub fn write_wav(path: &Path, seconds: u32, seed: u8) {
+    const SAMPLE_RATE: u32 = 8000;
+    let data_size = seconds * SAMPLE_RATE * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for i in 0..seconds * SAMPLE_RATE {
+        let sample = (i as i16).wrapping_mul(i16::from(seed) + 1);
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    fs::write(path, wav).unwrap();
+}
+
+/// `path` as the canonical string that scans store.
+pub fn canonical(path: &Path) -> String {
+    path.canonicalize().unwrap().into_os_string().into_string().unwrap()
+}
@@ -1,42 +1,502 @@
-use std::ops::Deref;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, RangeInclusive};
 use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use druid::{Affine, BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, Lens, LifeCycle,
-            LifeCycleCtx, MouseButton, PaintCtx, Point, Rect, RenderContext, Size, TextLayout,
-            UpdateCtx, Vec2, Widget};
+use druid::{Affine, BoxConstraints, ContextMenu, Cursor, Data, Env, Event, EventCtx, KbKey, LayoutCtx,
+            Lens, LifeCycle, LifeCycleCtx, LocalizedString, MenuDesc, MenuItem, MouseButton,
+            PaintCtx, Point, Rect, RenderContext, Size, TextLayout, UpdateCtx, Vec2, Widget,
+            WidgetExt};
+use druid::piet::{InterpolationMode, PietImage};
 use druid::scroll_component::ScrollComponent;
 use druid::theme::SELECTION_COLOR;
-use druid::widget::Viewport;
+use druid::widget::{Controller, Label, TextBox, Viewport};
 use log::trace;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-use crate::colors::ALT_BACKGROUND_COLOR;
-use crate::db::{Track, TrackField};
-use crate::WrappedTrackList;
+use crate::art::ArtCache;
+use crate::browse::Scope;
+use crate::collation::{compare_natural, compare_text};
+use crate::colors::{ALT_BACKGROUND_COLOR, SECONDARY_TEXT_COLOR};
+use crate::commands::{COLUMNS_CHANGED, DELETE_SELECTION, EDIT_METADATA, ENQUEUE_SELECTION,
+                      GO_TO_RANDOM_TRACK, OPEN_CONTAINING_FOLDER, PLAY_SELECTION, PLAY_TRACK,
+                      RATE_SELECTION, REFRESH_TRACK, REMOVE_SELECTION,
+                      REMOVE_SELECTION_FROM_PLAYLIST, REORDER_PLAYLIST, SET_RATING, TOGGLE_COLUMN,
+                      UNDO_RATE_SELECTION};
+use crate::db::{Database, Track, TrackField, MAX_RATING};
+use crate::search::{matches_search, parse_search, search_key};
+use crate::settings::EmptyAreaAction;
+use crate::{AppData, WrappedTrackList};
 
 // equal space on the top/bottom
 const SPACER_SIZE: f64 = 6.0;
+/// Columns can't be dragged or squeezed narrower than this, unless the list's too narrow to fit
+/// them all.
+const MIN_COLUMN_WIDTH: f64 = 40.0;
+/// The most of the row one column can take, so that it can't push the others out.
+const MAX_COLUMN_SHARE: f64 = 0.8;
+/// How close to a column boundary the mouse has to be to drag it.
+const DIVIDER_GRAB_WIDTH: f64 = 4.0;
+/// The columns that can be shown, in the order they're offered in the header menu.
+const AVAILABLE_COLUMNS: [TrackField; 15] = [
+    TrackField::Title, TrackField::Artist, TrackField::Album, TrackField::Genre, TrackField::Year,
+    TrackField::Track, TrackField::Disc, TrackField::Length, TrackField::Rating,
+    TrackField::PlayCount, TrackField::LastPlayed, TrackField::Bitrate, TrackField::Samplerate,
+    TrackField::Comment, TrackField::Path,
+];
+/// Seconds in a day.
+const DAY: i64 = 24 * 60 * 60;
+const STAR: &str = "★";
+const EMPTY_STAR: &str = "☆";
+const SORT_ASCENDING: &str = "▲";
+const SORT_DESCENDING: &str = "▼";
+/// Below this width, the columns get too cramped and we collapse to a single column.
+const COMPACT_WIDTH: f64 = 500.0;
 
 #[derive(Clone, Data, Lens)]
 pub struct TrackListData {
     tracks: WrappedTrackList,
+    /// Indices into `tracks` of the rows shown, in the order they're shown.
+    rows: Arc<Vec<usize>>,
+    /// What's drawn, top to bottom: the rows, plus album headers if they're grouped.
+    lines: Arc<Vec<Line>>,
+    /// Where each row is in `lines`.
+    row_lines: Arc<Vec<usize>>,
+    /// Group the rows by album, each under a header, in track number order.
+    group_by_album: bool,
+    /// The field the rows are sorted by, and whether it's ascending.
+    sort: Option<(TrackField, bool)>,
+    /// Sort text so that case and accents matter, rather than the way people expect.
+    strict_sort: bool,
+    /// Only tracks matching every term of this are shown. See `parse_search`.
+    filter: String,
+    /// Match unscoped search terms against paths and comments too.
+    search_everywhere: bool,
+    /// Only tracks by this artist, or on this album, are shown.
+    scope: Option<Scope>,
+    /// Each track's `search_key`, for filtering without allocating.
+    search_keys: Arc<Vec<String>>,
+    /// Rows, not indices into `tracks`.
     selected_tracks: Arc<RwLock<Vec<usize>>>,
+    /// How far down the rows are scrolled, kept up to date by the list. A new one scrolls the
+    /// list to it.
+    scroll_offset: Arc<RwLock<f64>>,
+    /// Ids of tracks whose files have changed since their tags were read.
+    stale_tracks: Arc<HashSet<i64>>,
+    /// The playlist these tracks are from, if they're from one.
+    playlist: Option<i64>,
+    /// The columns this view was last shown with, if it has its own. Otherwise the track list
+    /// shows its default ones.
+    columns: Option<Arc<Vec<(TrackField, f64)>>>,
 }
 
 impl TrackListData {
     pub fn new(tracks: Vec<Track>) -> Self {
         TrackListData {
+            rows: Arc::new((0..tracks.len()).collect()),
+            lines: Arc::new((0..tracks.len()).map(Line::Track).collect()),
+            row_lines: Arc::new((0..tracks.len()).collect()),
+            group_by_album: false,
+            search_keys: Arc::new(tracks.iter().map(|track| search_key(track, false)).collect()),
             tracks: Arc::new(RwLock::new(tracks)),
+            sort: None,
+            strict_sort: false,
+            filter: String::new(),
+            search_everywhere: false,
+            scope: None,
             selected_tracks: Arc::new(RwLock::new(Vec::new())),
+            scroll_offset: Arc::new(RwLock::new(0.)),
+            stale_tracks: Arc::new(HashSet::new()),
+            playlist: None,
+            columns: None,
         }
     }
+
+    /// The tracks of a playlist, in its order.
+    pub fn for_playlist(id: i64, tracks: Vec<Track>) -> Self {
+        TrackListData {
+            playlist: Some(id),
+            ..TrackListData::new(tracks)
+        }
+    }
+
+    pub fn playlist(&self) -> Option<i64> {
+        self.playlist
+    }
+
+    /// Show these columns, from e.g. `load_view_columns`, instead of the track list's default
+    /// ones. None goes back to the default.
+    pub fn set_columns(&mut self, columns: Option<Vec<(TrackField, f64)>>) {
+        self.columns = columns.map(Arc::new);
+    }
+
+    pub fn group_by_album(&self) -> bool {
+        self.group_by_album
+    }
+
+    pub fn set_group_by_album(&mut self, group_by_album: bool) {
+        self.group_by_album = group_by_album;
+        self.update_rows();
+    }
+
+    pub fn search_everywhere(&self) -> bool {
+        self.search_everywhere
+    }
+
+    /// Match unscoped search terms against paths and comments too, or only against titles,
+    /// artists and albums.
+    pub fn set_search_everywhere(&mut self, search_everywhere: bool) {
+        self.search_everywhere = search_everywhere;
+        let keys = self.tracks.read().unwrap().iter()
+            .map(|track| search_key(track, search_everywhere))
+            .collect();
+        self.search_keys = Arc::new(keys);
+        self.update_rows();
+    }
+
+    pub fn strict_sort(&self) -> bool {
+        self.strict_sort
+    }
+
+    pub fn set_strict_sort(&mut self, strict_sort: bool) {
+        self.strict_sort = strict_sort;
+        self.update_rows();
+    }
+
+    pub fn scope(&self) -> Option<&Scope> {
+        self.scope.as_ref()
+    }
+
+    pub fn set_scope(&mut self, scope: Option<Scope>) {
+        self.scope = scope;
+        self.update_rows();
+    }
+
+    pub fn set_stale_tracks(&mut self, ids: impl IntoIterator<Item = i64>) {
+        self.stale_tracks = Arc::new(ids.into_iter().collect());
+    }
+
+    /// Swap in a freshly read copy of a track, which is no longer stale.
+    pub fn replace_track(&mut self, track: Track) {
+        let mut stale_tracks = self.stale_tracks.deref().clone();
+        stale_tracks.remove(&track.id());
+        self.stale_tracks = Arc::new(stale_tracks);
+
+        self.update_track(track);
+    }
+
+    /// Swap in a new copy of a track, e.g. after changing its rating.
+    pub fn update_track(&mut self, track: Track) {
+        self.update_tracks(vec![track]);
+    }
+
+    /// Swap in new copies of several tracks at once, e.g. after measuring their loudness.
+    pub fn update_tracks(&mut self, updated: Vec<Track>) {
+        let mut updated: HashMap<i64, Track> = updated.into_iter()
+            .map(|track| (track.id(), track))
+            .collect();
+        let mut tracks = self.tracks.read().unwrap().clone();
+        for (index, old) in tracks.iter_mut().enumerate() {
+            if let Some(track) = updated.remove(&old.id()) {
+                let key = search_key(&track, self.search_everywhere);
+                Arc::make_mut(&mut self.search_keys)[index] = key;
+                *old = track;
+            }
+        }
+        // A new Arc, so the list sees the change and rebuilds its text
+        self.tracks = Arc::new(RwLock::new(tracks));
+        // The new tags might put it somewhere else
+        self.update_rows();
+    }
+
+    /// Add tracks to the end of the list, e.g. from a newly scanned library.
+    pub fn add_tracks(&mut self, new_tracks: Vec<Track>) {
+        let mut tracks = self.tracks.read().unwrap().clone();
+        let mut search_keys = self.search_keys.to_vec();
+
+        let everywhere = self.search_everywhere;
+        search_keys.extend(new_tracks.iter().map(|track| search_key(track, everywhere)));
+        tracks.extend(new_tracks);
+
+        self.tracks = Arc::new(RwLock::new(tracks));
+        self.search_keys = Arc::new(search_keys);
+        self.update_rows();
+    }
+
+    /// Drop every track in this library from the list.
+    pub fn remove_library(&mut self, library_id: i64) {
+        let ids: HashSet<i64> = self.tracks.read().unwrap().iter()
+            .filter(|track| track.library_id() == library_id)
+            .map(|track| track.id())
+            .collect();
+
+        self.remove_tracks(&ids);
+    }
+
+    /// Drop these tracks from the list, e.g. after removing them from the library.
+    pub fn remove_tracks(&mut self, ids: &HashSet<i64>) {
+        let (tracks, search_keys): (Vec<Track>, Vec<String>) = self.tracks.read().unwrap().iter()
+            .zip(self.search_keys.iter())
+            .filter(|(track, _)| !ids.contains(&track.id()))
+            .map(|(track, key)| (track.clone(), key.clone()))
+            .unzip();
+
+        // The remaining tracks move up, so rows no longer line up with what was selected
+        self.selected_tracks.write().unwrap().clear();
+        self.tracks = Arc::new(RwLock::new(tracks));
+        self.search_keys = Arc::new(search_keys);
+        self.update_rows();
+    }
+
+    /// Sort by `field`, or reverse the sort if we're already sorted by it.
+    pub fn sort_by(&mut self, field: TrackField) {
+        self.sort = match self.sort {
+            Some((sorted, ascending)) if sorted == field => Some((field, !ascending)),
+            _ => Some((field, true)),
+        };
+        self.update_rows();
+    }
+
+    /// Work out which rows to show and in what order, keeping the same tracks selected.
+    fn update_rows(&mut self) {
+        let tracks = self.tracks.read().unwrap();
+        let mut selected_tracks = self.selected_tracks.write().unwrap();
+        let selected: HashSet<usize> = selected_tracks.iter()
+            .filter_map(|&row| self.rows.get(row).copied())
+            .collect();
+
+        let terms = parse_search(&self.filter);
+        let mut rows: Vec<usize> = (0..tracks.len())
+            .filter(|&index| matches_search(&terms, &self.search_keys[index], &tracks[index]))
+            .filter(|&index| self.scope.as_ref().map_or(true, |scope| scope.contains(&tracks[index])))
+            .collect();
+        // Stable, so tracks that compare equal stay in database order
+        if self.group_by_album {
+            // Within an album, the sort (if any) replaces track number order
+            rows.sort_by(|&a, &b| {
+                let (a, b) = (&tracks[a], &tracks[b]);
+                compare_albums(a, b).then_with(|| match self.sort {
+                    Some((field, true))  => compare_by(a, b, field, self.strict_sort),
+                    Some((field, false)) => compare_by(a, b, field, self.strict_sort).reverse(),
+                    None => a.disc().cmp(&b.disc()).then_with(|| a.track().cmp(&b.track())),
+                })
+            });
+        } else if let Some((field, ascending)) = self.sort {
+            rows.sort_by(|&a, &b| {
+                let ordering = compare_by(&tracks[a], &tracks[b], field, self.strict_sort);
+                if ascending { ordering } else { ordering.reverse() }
+            });
+        }
+
+        let mut lines = Vec::with_capacity(rows.len());
+        let mut row_lines = Vec::with_capacity(rows.len());
+        for (row, &index) in rows.iter().enumerate() {
+            let new_album = row == 0
+                || compare_albums(&tracks[rows[row - 1]], &tracks[index]) != Ordering::Equal;
+            if self.group_by_album && new_album {
+                lines.push(Line::Header(row));
+            }
+            row_lines.push(lines.len());
+            lines.push(Line::Track(row));
+        }
+
+        *selected_tracks = rows.iter().enumerate()
+            .filter(|(_, index)| selected.contains(index))
+            .map(|(row, _)| row)
+            .collect();
+
+        drop(selected_tracks);
+        drop(tracks);
+        self.rows = Arc::new(rows);
+        self.lines = Arc::new(lines);
+        self.row_lines = Arc::new(row_lines);
+    }
+
+    fn n_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Rows can be dragged around to reorder a playlist, as long as they're shown in its order.
+    fn can_reorder(&self) -> bool {
+        self.playlist.is_some() && self.sort.is_none() && self.filter.is_empty() && !self.group_by_album
+    }
+
+    /// Move the selected rows together so the `grabbed` one ends up at `to`, e.g. while they're
+    /// being dragged. Only the rows change, until `commit_order`. Returns where `grabbed` is now.
+    fn move_selection(&mut self, grabbed: usize, to: usize) -> usize {
+        let mut selected_tracks = self.selected_tracks.write().unwrap();
+        let mut selected = selected_tracks.clone();
+        selected.sort_unstable();
+
+        let offset = selected.iter().filter(|&&row| row < grabbed).count();
+        let block: Vec<usize> = selected.iter().map(|&row| self.rows[row]).collect();
+        let mut rows: Vec<usize> = self.rows.iter().enumerate()
+            .filter(|(row, _)| selected.binary_search(row).is_err())
+            .map(|(_, &index)| index)
+            .collect();
+
+        let start = to.saturating_sub(offset).min(rows.len());
+        rows.splice(start..start, block);
+        *selected_tracks = (start..start + selected.len()).collect();
+
+        drop(selected_tracks);
+        // Lines refer to rows, and there are no headers while reordering, so they still line up
+        self.rows = Arc::new(rows);
+        start + offset
+    }
+
+    /// Put the tracks in the order their rows are shown in, returning their ids in that order.
+    fn commit_order(&mut self) -> Vec<i64> {
+        let (tracks, search_keys): (Vec<Track>, Vec<String>) = {
+            let tracks = self.tracks.read().unwrap();
+            self.rows.iter()
+                .map(|&index| (tracks[index].clone(), self.search_keys[index].clone()))
+                .unzip()
+        };
+
+        let ids = tracks.iter().map(Track::id).collect();
+        self.rows = Arc::new((0..tracks.len()).collect());
+        self.tracks = Arc::new(RwLock::new(tracks));
+        self.search_keys = Arc::new(search_keys);
+        ids
+    }
+
+    /// Where the list is: the first selected track, and how far it's scrolled.
+    pub fn position(&self) -> ListPosition {
+        let row = self.selected_tracks.read().unwrap().iter().min().copied();
+        ListPosition {
+            track: row.and_then(|row| self.track_at(row)).map(|track| track.id()),
+            scroll_offset: *self.scroll_offset.read().unwrap(),
+        }
+    }
+
+    /// Go back to a position from `position`, selecting its track again. If that track isn't
+    /// shown any more, the list starts at the top instead.
+    pub fn restore_position(&mut self, position: ListPosition) {
+        let row = position.track.map(|id| {
+            let tracks = self.tracks.read().unwrap();
+            self.rows.iter().position(|&index| tracks[index].id() == id)
+        });
+
+        let (selection, scroll_offset) = match row {
+            Some(Some(row)) => (vec![row], position.scroll_offset),
+            Some(None) => (Vec::new(), 0.),
+            None => (Vec::new(), position.scroll_offset),
+        };
+        self.selected_tracks = Arc::new(RwLock::new(selection));
+        self.scroll_offset = Arc::new(RwLock::new(scroll_offset));
+    }
+
+    /// The selected tracks, in list order.
+    pub fn selection(&self) -> Vec<Track> {
+        let mut rows = self.selected_tracks.read().unwrap().clone();
+        rows.sort_unstable();
+
+        rows.into_iter().filter_map(|row| self.track_at(row)).collect()
+    }
+
+    fn track_at(&self, row: usize) -> Option<Track> {
+        let index = *self.rows.get(row)?;
+        self.tracks.read().unwrap().get(index).cloned()
+    }
+
+    fn stale_track_at(&self, row: usize) -> Option<i64> {
+        let index = *self.rows.get(row)?;
+        self.tracks.read().unwrap().get(index)
+            .map(|track| track.id())
+            .filter(|id| self.stale_tracks.contains(id))
+    }
+}
+
+/// Where a view's list was left, to go back to when it's shown again.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ListPosition {
+    /// The id of the first selected track, if any were.
+    track: Option<i64>,
+    scroll_offset: f64,
+}
+
+/// One line of the list: a track's row, or the header above an album's first row.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Line {
+    Header(usize),
+    Track(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LayoutMode {
+    /// One line per row, one text layout per column.
+    Columns,
+    /// A single "Artist — Title" column, with the album on a second line.
+    Compact,
+    /// A single column with the title on one line and "Artist · Album · MM:SS" on the next.
+    Detailed,
+}
+
+impl LayoutMode {
+    fn choose(width: f64, two_line_rows: bool) -> LayoutMode {
+        if two_line_rows {
+            LayoutMode::Detailed
+        } else if width < COMPACT_WIDTH {
+            LayoutMode::Compact
+        } else {
+            LayoutMode::Columns
+        }
+    }
+
+    fn lines_per_row(self) -> usize {
+        match self {
+            LayoutMode::Columns => 1,
+            LayoutMode::Compact | LayoutMode::Detailed => 2,
+        }
+    }
+
+    /// How tall each row is, given how tall a line of text is.
+    fn row_height(self, line_height: f64) -> f64 {
+        line_height * self.lines_per_row() as f64 + SPACER_SIZE
+    }
 }
 
 pub struct TrackList {
+    /// `lines_per_row` layouts for each column of each track, in database order.
     children: Vec<TextLayout<String>>,
     columns: Vec<(TrackField, f64)>,
+    /// The columns shown in `LayoutMode::Columns`, with their share of the width.
+    column_layout: Vec<(TrackField, f64)>,
+    /// The columns shown for views without their own.
+    default_column_layout: Vec<(TrackField, f64)>,
+    /// The divider being dragged: the one after this column.
+    dragging: Option<usize>,
+    /// How narrow a column can be, in pixels, and how much of the row it can take.
+    column_limits: (f64, f64),
+    /// The row being dragged to reorder a playlist, and whether it's moved yet.
+    reordering: Option<(usize, bool)>,
+    /// Column titles, with an arrow on the sorted one.
+    header: Vec<TextLayout<String>>,
+    /// Album names for the headers when grouped by album, by the row each one is above.
+    album_headers: HashMap<usize, TextLayout<String>>,
+    /// Where to get album art for the headers from, if they should have it.
+    album_art: Option<(Arc<RwLock<Database>>, ArtCache)>,
+    /// Album art ready to draw, by the id of the track it's from.
+    header_images: HashMap<i64, Option<PietImage>>,
+    mode: LayoutMode,
     scroll: ScrollComponent,
     viewport: Option<Viewport>,
     dummy_text: TextLayout<String>,
+    /// Drawn at the end of stale rows; clicking it re-reads the track's tags.
+    refresh_badge: TextLayout<String>,
+    /// For measuring where the stars in the rating column are.
+    star: TextLayout<String>,
+    /// For picking random tracks.
+    rng: StdRng,
+    /// The row Shift+Click selects from: the last one clicked without Shift.
+    anchor: Option<usize>,
+    /// Where to scroll the rows to at the next layout, once there's a viewport to scroll.
+    pending_scroll: Option<f64>,
+    empty_area_action: EmptyAreaAction,
+    two_line_rows: bool,
 }
 
 impl TrackList {
@@ -45,33 +505,452 @@ impl TrackList {
         TrackList {
             children: Vec::new(),
             columns: Vec::new(),
+            column_layout: vec![(TrackField::Title, 0.5), (TrackField::Artist, 0.5)],
+            default_column_layout: vec![(TrackField::Title, 0.5), (TrackField::Artist, 0.5)],
+            dragging: None,
+            column_limits: (MIN_COLUMN_WIDTH, MAX_COLUMN_SHARE),
+            reordering: None,
+            header: Vec::new(),
+            album_headers: HashMap::new(),
+            album_art: None,
+            header_images: HashMap::new(),
+            mode: LayoutMode::Columns,
             scroll: ScrollComponent::new(),
             viewport: None,
             dummy_text: TextLayout::from_text("dummy"),
+            refresh_badge: TextLayout::from_text("↻"),
+            star: TextLayout::from_text(STAR),
+            rng: StdRng::from_entropy(),
+            anchor: None,
+            pending_scroll: None,
+            empty_area_action: EmptyAreaAction::Nothing,
+            two_line_rows: false,
+        }
+    }
+
+    /// Always show rows as two lines with extra detail, however wide the list is.
+    pub fn with_two_line_rows(mut self, two_line_rows: bool) -> Self {
+        self.two_line_rows = two_line_rows;
+        self
+    }
+
+    /// Show these columns, in this order, sharing the width equally.
+    pub fn with_columns(mut self, fields: &[TrackField]) -> Self {
+        self.set_columns(fields);
+        self
+    }
+
+    /// Start with these columns, each with its width as a fraction of the whole, e.g. from a
+    /// previous session, and show them for views without their own. Ignored if there are none.
+    pub fn with_column_layout(mut self, columns: &[(TrackField, f64)]) -> Self {
+        if !columns.is_empty() {
+            self.column_layout = columns.to_vec();
+            self.default_column_layout = columns.to_vec();
+        }
+        self
+    }
+
+    /// Keep columns at least `min` pixels wide, and each to at most `max_share` of the row.
+    pub fn with_column_limits(mut self, min: f64, max_share: f64) -> Self {
+        self.column_limits = (min, max_share);
+        self
+    }
+
+    /// Show album art in the album headers.
+    pub fn with_album_art(mut self, db: Arc<RwLock<Database>>, cache: ArtCache) -> Self {
+        self.album_art = Some((db, cache));
+        self
+    }
+
+    pub fn with_empty_area_action(mut self, action: EmptyAreaAction) -> Self {
+        self.empty_area_action = action;
+        self
+    }
+
+    /// Show these columns, in this order, sharing the width equally, for views without their
+    /// own. Takes effect when the children are next rebuilt.
+    pub fn set_columns(&mut self, fields: &[TrackField]) {
+        let width = 1. / fields.len() as f64;
+        self.column_layout = fields.iter().map(|&field| (field, width)).collect();
+        self.default_column_layout = self.column_layout.clone();
+    }
+
+    /// Show the view's own columns, if it has them, or else the default ones.
+    fn use_view_columns(&mut self, data: &TrackListData) {
+        self.column_layout = match &data.columns {
+            Some(columns) => columns.to_vec(),
+            None => self.default_column_layout.clone(),
+        };
+    }
+
+    /// Add a column at the end that gets an equal share of the width, or remove one and give its
+    /// width to the rest. The last column can't be removed.
+    fn toggle_column(&mut self, field: TrackField) {
+        match self.column_layout.iter().position(|&(shown, _)| shown == field) {
+            Some(_) if self.column_layout.len() == 1 => (),
+            Some(index) => {
+                let (_, removed) = self.column_layout.remove(index);
+                for column in &mut self.column_layout {
+                    column.1 /= 1. - removed;
+                }
+            }
+            None => {
+                let n = self.column_layout.len() as f64;
+                for column in &mut self.column_layout {
+                    column.1 *= n / (n + 1.);
+                }
+                self.column_layout.push((field, 1. / (n + 1.)));
+            }
         }
     }
 
+    fn column_menu(&self) -> MenuDesc<AppData> {
+        AVAILABLE_COLUMNS.iter().fold(
+            MenuDesc::new(LocalizedString::new("mus-menu-columns").with_placeholder("Columns")),
+            |menu, &field| {
+                let shown = self.column_layout.iter().any(|&(shown, _)| shown == field);
+                let only = shown && self.column_layout.len() == 1;
+
+                menu.append(MenuItem::new(
+                    LocalizedString::new("mus-menu-column").with_placeholder(column_title(field)),
+                    TOGGLE_COLUMN.with(field),
+                ).selected_if(move || shown).disabled_if(move || only))
+            })
+    }
+
     fn update_children(&mut self, data: &TrackListData) {
         let data = data.tracks.read().unwrap();
 
         self.children = Vec::new();
-        self.columns = vec![(TrackField::Title, 0.5), (TrackField::Artist, 0.5)];
 
-        for elem in data.iter() {
-            self.children.push(TextLayout::from_text(elem.title().unwrap_or_default()));
-            self.children.push(TextLayout::from_text(elem.artist().unwrap_or_default()));
+        match self.mode {
+            LayoutMode::Columns => {
+                self.columns = self.column_layout.clone();
+
+                for elem in data.iter() {
+                    for &(field, _) in &self.columns {
+                        self.children.push(TextLayout::from_text(cell_text(elem, field)));
+                    }
+                }
+            }
+            LayoutMode::Compact => {
+                self.columns = vec![(TrackField::Title, 1.0)];
+
+                for elem in data.iter() {
+                    let primary = match (elem.artist(), elem.title()) {
+                        (Some(artist), Some(title)) => format!("{} — {}", artist, title),
+                        (Some(artist), None) => artist.to_string(),
+                        (None, title) => title.unwrap_or_default().to_string(),
+                    };
+                    let mut secondary = TextLayout::from_text(elem.album().unwrap_or_default());
+                    secondary.set_text_color(SECONDARY_TEXT_COLOR);
+
+                    self.children.push(TextLayout::from_text(primary));
+                    self.children.push(secondary);
+                }
+            }
+            LayoutMode::Detailed => {
+                self.columns = vec![(TrackField::Title, 1.0)];
+
+                for elem in data.iter() {
+                    let length = format_duration(elem.length().into());
+                    let details: Vec<&str> = vec![elem.artist(), elem.album(), Some(length.as_str())]
+                        .into_iter()
+                        .flatten()
+                        .collect();
+                    let mut secondary = TextLayout::from_text(details.join(" · "));
+                    secondary.set_text_color(SECONDARY_TEXT_COLOR);
+
+                    self.children.push(TextLayout::from_text(elem.title().unwrap_or_default()));
+                    self.children.push(secondary);
+                }
+            }
         }
     }
 
-    fn total_size(&self, avail_size: Size) -> Size {
-        let n_rows = self.children.len() / self.columns.len();
-        let height = n_rows as f64 * self.row_height();
+    fn update_album_headers(&mut self, data: &TrackListData) {
+        let tracks = data.tracks.read().unwrap();
+
+        self.album_headers = data.lines.iter()
+            .filter_map(|&line| match line {
+                Line::Header(row) => Some(row),
+                Line::Track(_) => None,
+            })
+            .map(|row| {
+                let track = &tracks[data.rows[row]];
+                let text = match (track.album(), track.artist()) {
+                    (Some(album), Some(artist)) => format!("{} — {}", album, artist),
+                    (Some(album), None) => album.to_string(),
+                    (None, _) => "Unknown Album".to_string(),
+                };
+                (row, TextLayout::from_text(text))
+            })
+            .collect();
+    }
+
+    fn update_header(&mut self, data: &TrackListData) {
+        self.header = self.columns.iter()
+            .map(|&(field, _)| {
+                let title = column_title(field);
+                TextLayout::from_text(match data.sort {
+                    Some((sorted, true)) if sorted == field => format!("{} {}", title, SORT_ASCENDING),
+                    Some((sorted, false)) if sorted == field => format!("{} {}", title, SORT_DESCENDING),
+                    _ => title.to_string(),
+                })
+            })
+            .collect();
+    }
+
+    fn cells_per_row(&self) -> usize {
+        self.columns.len() * self.mode.lines_per_row()
+    }
+
+    fn n_rows(&self, data: &TrackListData) -> usize {
+        // With no columns there is nothing to show
+        if self.cells_per_row() == 0 {
+            0
+        } else {
+            data.n_rows()
+        }
+    }
+
+    /// Rows plus album headers, which are as tall as rows.
+    fn n_lines(&self, data: &TrackListData) -> usize {
+        if self.cells_per_row() == 0 {
+            0
+        } else {
+            data.lines.len()
+        }
+    }
+
+    /// Size of the scrolling part of the list, below the header.
+    fn total_size(&self, avail_size: Size, data: &TrackListData) -> Size {
+        let height = self.n_lines(data) as f64 * self.row_height();
 
         Size::new(avail_size.width, avail_size.height.max(height))
     }
 
+    fn header_height(&self) -> f64 {
+        self.line_height() + SPACER_SIZE
+    }
+
+    fn line_height(&self) -> f64 {
+        self.dummy_text.size().height
+    }
+
     fn row_height(&self) -> f64 {
-        self.dummy_text.size().height + SPACER_SIZE
+        self.mode.row_height(self.line_height())
+    }
+
+    /// The line at `y` below the header, or None for the empty space below the last one.
+    fn line_at(&self, y: f64, data: &TrackListData) -> Option<Line> {
+        let abs_pos = self.viewport.unwrap().rect.y0 + y - self.header_height();
+        let line = (abs_pos / self.row_height()) as usize;
+
+        if abs_pos >= 0. && line < self.n_lines(data) {
+            Some(data.lines[line])
+        } else {
+            None
+        }
+    }
+
+    /// The row at `y` below the header, or None for an album header or the empty space below
+    /// the last row.
+    fn row_at(&self, y: f64, data: &TrackListData) -> Option<usize> {
+        match self.line_at(y, data)? {
+            Line::Track(row) => Some(row),
+            Line::Header(_) => None,
+        }
+    }
+
+    /// Carry out the configured action for a double-click below the last row. Returns whether
+    /// anything needs repainting.
+    fn empty_area_double_clicked(&self, data: &TrackListData) -> bool {
+        match self.empty_area_action {
+            EmptyAreaAction::Nothing => false,
+            EmptyAreaAction::ClearSelection => {
+                data.selected_tracks.write().unwrap().clear();
+                true
+            }
+        }
+    }
+
+    /// Scroll so that `row` is in the middle of the viewport, as far as the content allows.
+    fn scroll_to_row(&mut self, row: usize, data: &TrackListData) {
+        let row_height = self.row_height();
+        let line = data.row_lines[row];
+
+        if let Some(viewport) = self.viewport.as_mut() {
+            let height = viewport.rect.height();
+            let max_y0 = (viewport.content_size.height - height).max(0.);
+            let y0 = line as f64 * row_height - (height - row_height) / 2.;
+
+            viewport.rect = viewport.rect.with_origin(Point::new(0., y0.max(0.).min(max_y0)));
+        }
+    }
+
+    /// The column at `x` in a list `width` wide, if any, and where it starts.
+    fn column_at(&self, x: f64, width: f64, data: &TrackListData) -> Option<(TrackField, f64)> {
+        let avail_width = self.avail_width(width, data);
+        let mut start = SPACER_SIZE;
+
+        for &(field, share) in &self.columns {
+            let end = start + avail_width * share;
+            if x < end {
+                return Some((field, start));
+            }
+            start = end;
+        }
+
+        None
+    }
+
+    /// The star at `x` in the rating column, counting from 1, if there is one there.
+    fn star_at(&self, x: f64, width: f64, data: &TrackListData) -> Option<i32> {
+        if self.mode != LayoutMode::Columns {
+            return None;
+        }
+
+        match self.column_at(x, width, data) {
+            Some((TrackField::Rating, start)) => {
+                let star = ((x - start) / self.star.size().width) as i32 + 1;
+                if star <= MAX_RATING { Some(star) } else { None }
+            }
+            _ => None
+        }
+    }
+
+    /// The divider at `x` in a list `width` wide, as the index of the column before it. Only
+    /// the column layout has dividers.
+    fn divider_at(&self, x: f64, width: f64, data: &TrackListData) -> Option<usize> {
+        if self.mode != LayoutMode::Columns {
+            return None;
+        }
+
+        let avail_width = self.avail_width(width, data);
+        let mut divider = SPACER_SIZE;
+
+        // There's no divider after the last column
+        let n_dividers = self.columns.len().saturating_sub(1);
+        for (col, &(_, share)) in self.columns.iter().enumerate().take(n_dividers) {
+            divider += avail_width * share;
+            if (x - divider).abs() <= DIVIDER_GRAB_WIDTH {
+                return Some(col);
+            }
+        }
+
+        None
+    }
+
+    /// Move the divider after column `col` to `x`, resizing only the columns on either side of it.
+    fn drag_divider(&mut self, col: usize, x: f64, width: f64, data: &TrackListData) {
+        let avail_width = self.avail_width(width, data);
+        let start = SPACER_SIZE + avail_width * self.columns[..col].iter().map(|c| c.1).sum::<f64>();
+        let pair = self.columns[col].1 + self.columns[col + 1].1;
+        let (min, max) = (self.column_limits.0 / avail_width, self.column_limits.1);
+        let (lowest, highest) = (min.max(pair - max), max.min(pair - min));
+
+        // Too narrow to resize without squashing one of them
+        if lowest > highest {
+            return;
+        }
+
+        let left = ((x - start) / avail_width).max(lowest).min(highest);
+        self.columns[col].1 = left;
+        self.columns[col + 1].1 = pair - left;
+        self.column_layout = self.columns.clone();
+    }
+
+    /// Width shared between the columns, leaving out the right spacer and the refresh badges.
+    fn avail_width(&self, width: f64, data: &TrackListData) -> f64 {
+        width - SPACER_SIZE - self.badge_width(data)
+    }
+
+    /// Scroll as little as possible to bring `row` fully into view.
+    fn scroll_row_into_view(&mut self, row: usize, data: &TrackListData) {
+        let row_height = self.row_height();
+        let line = data.row_lines[row];
+
+        if let Some(viewport) = self.viewport.as_mut() {
+            let height = viewport.rect.height();
+            let max_y0 = (viewport.content_size.height - height).max(0.);
+            let top = line as f64 * row_height;
+            let y0 = if top < viewport.rect.y0 {
+                top
+            } else if top + row_height > viewport.rect.y1 {
+                top + row_height - height
+            } else {
+                return;
+            };
+
+            viewport.rect = viewport.rect.with_origin(Point::new(0., y0.max(0.).min(max_y0)));
+        }
+    }
+
+    /// The row a navigation key moves the selection to, if it's one of them and there are rows.
+    fn navigate(&self, key: &KbKey, data: &TrackListData) -> Option<usize> {
+        let n_rows = self.n_rows(data);
+        if n_rows == 0 {
+            return None;
+        }
+
+        let last = n_rows - 1;
+        let current = self.anchor.filter(|&row| row < n_rows);
+        let page = self.viewport
+            .map_or(1, |viewport| (viewport.rect.height() / self.row_height()) as usize)
+            .max(1);
+
+        match key {
+            KbKey::ArrowUp   => Some(current.map_or(0, |row| row.saturating_sub(1))),
+            KbKey::ArrowDown => Some(current.map_or(0, |row| (row + 1).min(last))),
+            KbKey::PageUp    => Some(current.map_or(0, |row| row.saturating_sub(page))),
+            KbKey::PageDown  => Some(current.map_or(0, |row| (row + page).min(last))),
+            KbKey::Home      => Some(0),
+            KbKey::End       => Some(last),
+            _ => None
+        }
+    }
+
+    /// Draw the header above `row`, the first of its album, with its top `y` below the top of
+    /// the line.
+    fn paint_album_header(&mut self, ctx: &mut PaintCtx, row: usize, y: f64, data: &TrackListData,
+                          env: &Env) {
+        let background_rect = Rect::from_origin_size(
+            Point::new(0., y - (SPACER_SIZE / 2.)),
+            Size::new(ctx.size().width, self.row_height()),
+        );
+        ctx.fill(background_rect, &env.get(ALT_BACKGROUND_COLOR));
+
+        let mut x = SPACER_SIZE;
+        if let Some((db, cache)) = &self.album_art {
+            let art_size = self.row_height() - SPACER_SIZE;
+
+            if let Some(track) = data.track_at(row) {
+                // Turning the art into something drawable is slow too, so keep that around
+                let image = self.header_images.entry(track.id()).or_insert_with(|| {
+                    cache.get(&db.read().unwrap(), &track).map(|art| art.to_image(ctx.render_ctx))
+                });
+                if let Some(image) = image {
+                    let rect = Rect::from_origin_size(Point::new(x, y), Size::new(art_size, art_size));
+                    ctx.draw_image(image, rect, InterpolationMode::Bilinear);
+                }
+            }
+            x += art_size + SPACER_SIZE;
+        }
+
+        if let Some(text) = self.album_headers.get(&row) {
+            text.draw(ctx, Point::new(x, y));
+        }
+    }
+
+    /// Width of the gutter at the end of each row that holds the refresh badge, if any track
+    /// needs one.
+    fn badge_width(&self, data: &TrackListData) -> f64 {
+        if data.stale_tracks.is_empty() {
+            0.
+        } else {
+            self.refresh_badge.size().width + SPACER_SIZE
+        }
     }
 }
 
@@ -83,50 +962,308 @@ impl Widget<TrackListData> for TrackList {
 
         if !ctx.is_handled() {
             match event {
+                Event::Command(cmd) if cmd.is(GO_TO_RANDOM_TRACK) => {
+                    if let Some(row) = random_row(&mut self.rng, self.n_rows(data)) {
+                        let mut tr = data.selected_tracks.write().unwrap();
+                        tr.clear();
+                        tr.push(row);
+                        trace!("Random row selected: {}", row);
+                        self.anchor = Some(row);
+
+                        self.scroll_to_row(row, data);
+                        ctx.request_paint();
+                    }
+                    ctx.set_handled();
+                }
+                Event::Command(cmd) if cmd.is(TOGGLE_COLUMN) => {
+                    self.toggle_column(*cmd.get_unchecked(TOGGLE_COLUMN));
+                    self.update_children(data);
+                    self.update_header(data);
+                    ctx.submit_command(COLUMNS_CHANGED.with(self.column_layout.clone()));
+                    ctx.request_layout();
+                    ctx.set_handled();
+                }
+                Event::MouseDown(evt) if evt.button == MouseButton::Right
+                    && evt.pos.y < self.header_height() => {
+                    ctx.show_context_menu(ContextMenu::new(self.column_menu(), evt.window_pos));
+                    ctx.set_handled();
+                }
+                Event::MouseDown(evt) if evt.button == MouseButton::Right => {
+                    if let Some(row) = self.row_at(evt.pos.y, data) {
+                        // The menu acts on the selection, so make sure it includes this row
+                        let mut tr = data.selected_tracks.write().unwrap();
+                        if !tr.contains(&row) {
+                            tr.clear();
+                            tr.push(row);
+                            self.anchor = Some(row);
+                            ctx.request_paint();
+                        }
+
+                        ctx.show_context_menu(ContextMenu::new(track_menu(data.playlist.is_some()),
+                                                               evt.window_pos));
+                    }
+                    ctx.set_handled();
+                }
+                Event::MouseMove(evt) if self.reordering.is_some() => {
+                    let (grabbed, moved) = self.reordering.unwrap();
+                    let to = self.row_at(evt.pos.y, data).unwrap_or_else(|| {
+                        if evt.pos.y < self.header_height() { 0 } else { data.n_rows().saturating_sub(1) }
+                    });
+
+                    if to != grabbed {
+                        let grabbed = data.move_selection(grabbed, to);
+                        self.reordering = Some((grabbed, true));
+                        self.anchor = Some(grabbed);
+                        self.scroll_row_into_view(grabbed, data);
+                        ctx.request_paint();
+                    } else {
+                        self.reordering = Some((grabbed, moved));
+                    }
+                    ctx.set_handled();
+                }
+                Event::MouseUp(evt) if evt.button == MouseButton::Left && self.reordering.is_some() => {
+                    let (grabbed, moved) = self.reordering.take().unwrap();
+                    ctx.set_active(false);
+
+                    if moved {
+                        if let Some(playlist) = data.playlist {
+                            let ids = data.commit_order();
+                            ctx.submit_command(REORDER_PLAYLIST.with((playlist, ids)));
+                        }
+                    } else {
+                        // Just a click, so it selects the row like any other
+                        let mut tr = data.selected_tracks.write().unwrap();
+                        tr.clear();
+                        tr.push(grabbed);
+                    }
+                    ctx.request_paint();
+                    ctx.set_handled();
+                }
+                Event::MouseMove(evt) => {
+                    let width = ctx.size().width;
+
+                    if let Some(col) = self.dragging {
+                        self.drag_divider(col, evt.pos.x, width, data);
+                        ctx.request_paint();
+                        ctx.set_handled();
+                    } else if self.divider_at(evt.pos.x, width, data).is_some() {
+                        ctx.set_cursor(&Cursor::ResizeLeftRight);
+                    } else {
+                        ctx.clear_cursor();
+                    }
+                }
+                Event::MouseUp(evt) if evt.button == MouseButton::Left && self.dragging.is_some() => {
+                    self.dragging = None;
+                    ctx.set_active(false);
+                    ctx.submit_command(COLUMNS_CHANGED.with(self.column_layout.clone()));
+                    ctx.set_handled();
+                }
                 Event::MouseDown(evt) => {
                     if let MouseButton::Left = evt.button {
+                        if let Some(col) = self.divider_at(evt.pos.x, ctx.size().width, data) {
+                            self.dragging = Some(col);
+                            ctx.set_active(true);
+                            ctx.set_handled();
+                            return;
+                        }
+
+                        if evt.pos.y < self.header_height() {
+                            if let Some((field, _)) = self.column_at(evt.pos.x, ctx.size().width, data) {
+                                data.sort_by(field);
+                                // The anchor row now holds some other track
+                                self.anchor = None;
+                            }
+                            ctx.set_handled();
+                            return;
+                        }
+
+                        let row = match self.line_at(evt.pos.y, data) {
+                            Some(Line::Track(row)) => row,
+                            // Album headers can't be selected
+                            Some(Line::Header(_)) => {
+                                ctx.set_handled();
+                                return;
+                            }
+                            None => {
+                                if evt.count == 2 && self.empty_area_double_clicked(data) {
+                                    ctx.request_paint();
+                                }
+                                ctx.set_handled();
+                                return;
+                            }
+                        };
+
+                        if evt.pos.x >= ctx.size().width - self.badge_width(data) {
+                            if let Some(id) = data.stale_track_at(row) {
+                                ctx.submit_command(REFRESH_TRACK.with(id));
+                                ctx.set_handled();
+                                return;
+                            }
+                        }
+
+                        if let Some(star) = self.star_at(evt.pos.x, ctx.size().width, data) {
+                            if let Some(track) = data.track_at(row) {
+                                // Clicking the track's current rating clears it
+                                let rating = if track.rating() == Some(star) { None } else { Some(star) };
+                                ctx.submit_command(SET_RATING.with((track.id(), rating)));
+                                ctx.set_handled();
+                                return;
+                            }
+                        }
+
+                        let toggle = evt.mods.ctrl() || evt.mods.meta();
+
+                        // Pressing on the selection may be the start of dragging it somewhere else
+                        if evt.count == 1 && !evt.mods.shift() && !toggle && data.can_reorder()
+                            && data.selected_tracks.read().unwrap().contains(&row) {
+                            self.reordering = Some((row, false));
+                            self.anchor = Some(row);
+                            ctx.set_active(true);
+                            ctx.request_focus();
+                            ctx.set_handled();
+                            return;
+                        }
+
                         // Set selection
-                        let abs_pos = self.viewport.unwrap().rect.y0 + evt.pos.y;
                         let mut tr = data.selected_tracks.write().unwrap();
-                        tr.clear();
-                        tr.push((abs_pos / self.row_height()) as usize);
+
+                        if evt.mods.shift() {
+                            // Ctrl+Shift adds the range to what's already selected
+                            if !toggle {
+                                tr.clear();
+                            }
+                            for row in select_range(self.anchor.unwrap_or(row), row) {
+                                if !tr.contains(&row) {
+                                    tr.push(row);
+                                }
+                            }
+                        } else if toggle {
+                            match tr.iter().position(|&selected| selected == row) {
+                                Some(index) => { tr.remove(index); }
+                                None => tr.push(row),
+                            }
+                            self.anchor = Some(row);
+                        } else {
+                            tr.clear();
+                            tr.push(row);
+                            self.anchor = Some(row);
+                        }
                         trace!("Rows selected: {:?}", tr.deref());
+
+                        if evt.count == 2 && !evt.mods.shift() && !toggle {
+                            if let Some(track) = data.track_at(row) {
+                                ctx.submit_command(PLAY_TRACK.with(track));
+                            }
+                        }
+
+                        ctx.request_focus();
                         ctx.request_paint();
                         ctx.set_handled();
                     }
                 }
+                Event::KeyDown(key) => {
+                    if let Some(row) = self.navigate(&key.key, data) {
+                        let mut tr = data.selected_tracks.write().unwrap();
+                        tr.clear();
+                        tr.push(row);
+                        self.anchor = Some(row);
+
+                        self.scroll_row_into_view(row, data);
+                        ctx.request_paint();
+                        ctx.set_handled();
+                    } else if key.key == KbKey::Enter {
+                        let row = data.selected_tracks.read().unwrap().first().copied();
+                        if let Some(track) = row.and_then(|row| data.track_at(row)) {
+                            ctx.submit_command(PLAY_TRACK.with(track));
+                        }
+                        ctx.set_handled();
+                    }
+                }
                 _ => ()
             }
         }
+
+        if let Some(viewport) = self.viewport {
+            *data.scroll_offset.write().unwrap() = viewport.rect.y0;
+        }
     }
 
     fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &TrackListData, env: &Env) {
         self.scroll.lifecycle(ctx, event, env);
 
-        if let LifeCycle::WidgetAdded = event {
-            self.update_children(data);
+        match event {
+            LifeCycle::WidgetAdded => {
+                self.pending_scroll = Some(*data.scroll_offset.read().unwrap());
+                self.use_view_columns(data);
+                self.update_children(data);
+                self.update_header(data);
+                self.update_album_headers(data);
+            }
+            LifeCycle::BuildFocusChain => ctx.register_for_focus(),
+            _ => (),
         }
     }
 
-    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &TrackListData, data: &TrackListData, _env: &Env) {
-        self.update_children(data);
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &TrackListData, data: &TrackListData, _env: &Env) {
+        if !old_data.scroll_offset.same(&data.scroll_offset) {
+            self.pending_scroll = Some(*data.scroll_offset.read().unwrap());
+        }
+        let columns_changed = !old_data.columns.same(&data.columns);
+        if columns_changed {
+            self.use_view_columns(data);
+        }
+        // Re-sorting only changes which track goes in which row, so the text can stay
+        if !old_data.tracks.same(&data.tracks) {
+            self.update_children(data);
+            self.header_images.clear();
+        } else if columns_changed {
+            self.update_children(data);
+        }
+        if !old_data.lines.same(&data.lines) {
+            self.update_album_headers(data);
+        }
+        self.update_header(data);
         ctx.request_layout();
     }
 
     // This widget DOES NOT WORK with infinite-width containers
-    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &TrackListData, env: &Env) -> Size {
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &TrackListData, env: &Env) -> Size {
+        let mode = LayoutMode::choose(bc.max().width, self.two_line_rows);
+        if mode != self.mode {
+            self.mode = mode;
+            self.update_children(data);
+            self.update_header(data);
+        }
+        if self.mode == LayoutMode::Columns {
+            // The layout keeps the widths as they were chosen, so that a column squeezed while
+            // the window's small gets its width back when it's made bigger again
+            let (min, max_share) = self.column_limits;
+            self.columns = self.column_layout.clone();
+            clamp_columns(&mut self.columns, self.avail_width(bc.max().width, data), min,
+                          max_share);
+        }
+
         self.dummy_text.rebuild_if_needed(ctx.text(), env);
-        for elem in &mut self.children {
+        self.refresh_badge.rebuild_if_needed(ctx.text(), env);
+        self.star.rebuild_if_needed(ctx.text(), env);
+        let layouts = self.children.iter_mut()
+            .chain(&mut self.header)
+            .chain(self.album_headers.values_mut());
+        for elem in layouts {
             elem.rebuild_if_needed(ctx.text(), env);
         }
 
+        // The header stays put; only the rows below it scroll
+        let rows_size = Size::new(bc.max().width, (bc.max().height - self.header_height()).max(0.));
+        let content_size = self.total_size(rows_size, data);
+        let y0 = match (self.pending_scroll.take(), self.viewport) {
+            (Some(y0), _) => y0.min((content_size.height - rows_size.height).max(0.)),
+            (None, Some(v)) => v.rect.y0,
+            (None, None) => 0.,
+        };
         self.viewport = Some(Viewport {
-            content_size: self.total_size(bc.max()),
-            rect: if let Some(v) = self.viewport {
-                Rect::new(0., v.rect.y0, bc.max().width, v.rect.y0 + bc.max().height)
-            } else {
-                Rect::new(0., 0., bc.max().width, bc.max().height)
-            },
+            content_size,
+            rect: Rect::new(0., y0, rows_size.width, y0 + rows_size.height),
         });
 
         bc.max()
@@ -136,29 +1273,54 @@ impl Widget<TrackListData> for TrackList {
         // Now we have to draw the subset of the screen that fits into the viewport
         // It's a bit of a pain to do this custom but otherwise performance tanks hard
 
-        let avail_width = ctx.size().width - SPACER_SIZE; // Accounting for the right spacer
+        let avail_width = self.avail_width(ctx.size().width, data);
         let viewport = self.viewport.expect("Something is seriously wrong with the layout code...");
+        let header_height = self.header_height();
+
+        let header_rect = Rect::from_origin_size(Point::ORIGIN,
+                                                 Size::new(ctx.size().width, header_height));
+        ctx.fill(header_rect, &env.get(ALT_BACKGROUND_COLOR));
+
+        let mut x = SPACER_SIZE;
+        for (title, &(_, width)) in self.header.iter().zip(&self.columns) {
+            let clip_rect = Rect::from_origin_size(Point::new(x, 0.),
+                                                   Size::new(avail_width * width - SPACER_SIZE, header_height));
+            ctx.with_save(|ctx| {
+                ctx.clip(clip_rect);
+                title.draw(ctx, Point::new(x, SPACER_SIZE / 2.));
+            });
+            x += avail_width * width;
+        }
 
         let div = viewport.rect.y0 / self.row_height();
         let div2 = viewport.rect.y1 / self.row_height();
-        let start_row = div as usize;
-        let end_row = div2 as usize;
+        let start_line = div as usize;
+        let end_line = div2 as usize;
         let offset = (div - div.floor()) * self.row_height();
 
         ctx.save().unwrap();
         let size = ctx.size();
-        ctx.clip(Rect::from_origin_size(Point::default(), size));
+        ctx.clip(Rect::new(0., header_height, size.width, size.height));
         ctx.transform(Affine::translate(Vec2 {
             x: 0.,
-            y: -offset,
+            y: header_height - offset,
         }));
 
         let mut y = SPACER_SIZE / 2.;
 
-        for row in start_row..=end_row {
-            if row >= self.children.len() / self.columns.len() {
+        for line in start_line..=end_line {
+            if line >= self.n_lines(data) {
                 continue;
             }
+            let row = match data.lines[line] {
+                Line::Track(row) => row,
+                Line::Header(row) => {
+                    self.paint_album_header(ctx, row, y, data, env);
+                    y += self.row_height();
+                    continue;
+                }
+            };
+            let index = data.rows[row];
 
             let background_rect = Rect::from_origin_size(
                 Point::new(0., y - (SPACER_SIZE / 2.)),
@@ -175,27 +1337,774 @@ impl Widget<TrackListData> for TrackList {
             }
 
             let mut x = SPACER_SIZE;
+            let lines = self.mode.lines_per_row();
+            let line_height = self.line_height();
 
             for col in 0..self.columns.len() {
                 let point = Point::new(x, y);
                 let size = Size::new(avail_width * self.columns[col].1 - SPACER_SIZE,
                                      self.row_height());
-                let child = &self.children[row * self.columns.len() + col];
+                let first = index * self.cells_per_row() + col * lines;
+                let cell = &self.children[first..first + lines];
                 let clip_rect = Rect::from_origin_size(point, size);
 
                 ctx.with_save(|ctx| {
                     ctx.clip(clip_rect);
-                    child.draw(ctx, point);
+                    for (line, child) in cell.iter().enumerate() {
+                        child.draw(ctx, Point::new(x, y + line as f64 * line_height));
+                    }
                 });
 
                 x += size.width + SPACER_SIZE;
             }
 
+            if data.stale_track_at(row).is_some() {
+                self.refresh_badge.draw(ctx, Point::new(x, y));
+            }
+
             y += self.row_height();
         }
 
         ctx.restore().unwrap();
 
-        self.scroll.draw_bars(ctx, self.viewport.as_ref().unwrap(), env);
+        ctx.with_save(|ctx| {
+            ctx.transform(Affine::translate(Vec2 { x: 0., y: header_height }));
+            self.scroll.draw_bars(ctx, &viewport, env);
+        });
+    }
+}
+
+/// Pick one of the `n_rows` rows in the list, or None if it's empty.
+fn random_row(rng: &mut impl Rng, n_rows: usize) -> Option<usize> {
+    if n_rows == 0 {
+        None
+    } else {
+        Some(rng.gen_range(0..n_rows))
+    }
+}
+
+/// Make each column at least `min` pixels of `avail_width` and at most `max_share` of it. What
+/// a column gains or gives up comes out of the others in proportion to their widths, so they
+/// still fill the row. If they can't, because the row's too narrow to fit them all at `min` or
+/// too wide to fill at `max_share`, they share it equally.
+fn clamp_columns(columns: &mut [(TrackField, f64)], avail_width: f64, min: f64,
+                 max_share: f64) {
+    if columns.is_empty() {
+        return;
+    }
+
+    let n = columns.len() as f64;
+    let min = min / avail_width;
+    let total: f64 = columns.iter().map(|column| column.1.max(0.)).sum();
+    if avail_width <= 0. || min * n >= 1. || max_share * n <= 1. || total <= 0. {
+        for column in columns.iter_mut() {
+            column.1 = 1. / n;
+        }
+        return;
+    }
+
+    for column in columns.iter_mut() {
+        column.1 = column.1.max(0.) / total;
+    }
+
+    // Raise the narrowest columns to the minimum first, which only ever narrows the rest, then
+    // bring the widest down, which only ever widens the rest. The ones raised to the minimum can
+    // grow again then.
+    hold_at_limit(columns, min, |share| share < min);
+    hold_at_limit(columns, max_share, |share| share > max_share);
+}
+
+/// Set every column that's `out_of_bounds` to `limit`, scaling the rest to fill the row, until
+/// none of the rest are out of bounds either.
+fn hold_at_limit(columns: &mut [(TrackField, f64)], limit: f64,
+                 out_of_bounds: impl Fn(f64) -> bool) {
+    let mut held = vec![false; columns.len()];
+
+    loop {
+        let n_held = held.iter().filter(|&&held| held).count();
+        let free: f64 = columns.iter().zip(&held)
+            .filter(|(_, &held)| !held)
+            .map(|(column, _)| column.1)
+            .sum();
+        if free > 0. {
+            let scale = (1. - limit * n_held as f64) / free;
+            for (column, _) in columns.iter_mut().zip(&held).filter(|(_, &held)| !held) {
+                column.1 *= scale;
+            }
+        }
+
+        let mut changed = false;
+        for (column, held) in columns.iter_mut().zip(&mut held) {
+            if !*held && out_of_bounds(column.1) {
+                column.1 = limit;
+                *held = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+/// A text box that filters the track list as you type.
+pub fn make_filter_box() -> impl Widget<TrackListData> {
+    TextBox::new()
+        .with_placeholder("Filter")
+        .lens(TrackListData::filter)
+        .expand_width()
+        .controller(FilterController)
+}
+
+/// Re-filters the rows whenever the filter text changes.
+struct FilterController;
+
+impl<W: Widget<TrackListData>> Controller<TrackListData, W> for FilterController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut TrackListData,
+             env: &Env) {
+        let old_filter = data.filter.clone();
+        child.event(ctx, event, data, env);
+
+        if data.filter != old_filter {
+            data.update_rows();
+        }
     }
-}
\ No newline at end of file
+}
+
+/// How many rows are shown and how long they are altogether, plus the same for the selection if
+/// anything's selected. The track list needs a `SelectionController` for this to keep up.
+pub fn make_status_bar() -> impl Widget<TrackListData> {
+    Label::new(|data: &TrackListData, _env: &Env| {
+        let tracks = data.tracks.read().unwrap();
+        let selected = data.selected_tracks.read().unwrap();
+        let count = |n: usize| if n == 1 { "1 track".to_string() } else { format!("{} tracks", n) };
+
+        let length: i64 = data.rows.iter().map(|&index| tracks[index].length() as i64).sum();
+        let mut text = format!("{}, total {}", count(data.rows.len()), format_duration(length));
+
+        if !selected.is_empty() {
+            let length: i64 = selected.iter()
+                .filter_map(|&row| data.rows.get(row))
+                .map(|&index| tracks[index].length() as i64)
+                .sum();
+            text += &format!(" · {} selected, total {}", selected.len(), format_duration(length));
+        }
+
+        text
+    })
+}
+
+/// The track list changes the selection in place, which `Data` can't see. This swaps in a new
+/// `Arc` whenever it changes, so that widgets showing it, like the status bar, are updated.
+pub struct SelectionController;
+
+impl<W: Widget<TrackListData>> Controller<TrackListData, W> for SelectionController {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut TrackListData,
+             env: &Env) {
+        let old_selection = data.selected_tracks.read().unwrap().clone();
+        child.event(ctx, event, data, env);
+
+        let selection = data.selected_tracks.read().unwrap().clone();
+        if selection != old_selection {
+            data.selected_tracks = Arc::new(RwLock::new(selection));
+        }
+    }
+}
+
+/// The rows Shift+Click selects, from the anchor to the clicked row in either direction.
+fn select_range(anchor: usize, row: usize) -> RangeInclusive<usize> {
+    anchor.min(row)..=anchor.max(row)
+}
+
+/// Format a number of seconds as M:SS, or H:MM:SS if it's an hour or more.
+pub fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// How long ago a time in seconds since the Unix epoch was, roughly, e.g. "3 days ago".
+fn format_time_ago(timestamp: i64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs() as i64);
+    let seconds = (now - timestamp).max(0);
+
+    let (count, unit) = match seconds {
+        s if s < 60        => return "Just now".to_string(),
+        s if s < 60 * 60   => (s / 60, "minute"),
+        s if s < DAY       => (s / (60 * 60), "hour"),
+        s if s < 30 * DAY  => (s / DAY, "day"),
+        s if s < 365 * DAY => (s / (30 * DAY), "month"),
+        s                  => (s / (365 * DAY), "year"),
+    };
+
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
+
+fn column_title(field: TrackField) -> &'static str {
+    match field {
+        TrackField::Path       => "Path",
+        TrackField::Title      => "Title",
+        TrackField::Artist     => "Artist",
+        TrackField::Album      => "Album",
+        TrackField::Comment    => "Comment",
+        TrackField::Genre      => "Genre",
+        TrackField::Year       => "Year",
+        TrackField::Track      => "Track",
+        TrackField::Length     => "Length",
+        TrackField::Bitrate    => "Bitrate",
+        TrackField::Samplerate => "Sample Rate",
+        TrackField::Rating     => "Rating",
+        TrackField::Disc       => "Disc",
+        TrackField::PlayCount  => "Plays",
+        TrackField::LastPlayed => "Last Played",
+    }
+}
+
+/// The right-click menu for the selected tracks.
+fn track_menu(in_playlist: bool) -> MenuDesc<AppData> {
+    MenuDesc::empty()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-play").with_placeholder("Play"),
+            PLAY_SELECTION,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-enqueue").with_placeholder("Add to Queue"),
+            ENQUEUE_SELECTION,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-edit-metadata").with_placeholder("Edit Metadata…"),
+            EDIT_METADATA,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-open-folder").with_placeholder("Open Containing Folder"),
+            OPEN_CONTAINING_FOLDER,
+        ))
+        .append_separator()
+        .append(rating_menu())
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-clear-rating").with_placeholder("Clear Rating"),
+            RATE_SELECTION.with(None),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-undo-rating").with_placeholder("Undo Rating Change"),
+            UNDO_RATE_SELECTION,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-remove-from-playlist").with_placeholder("Remove from Playlist"),
+            REMOVE_SELECTION_FROM_PLAYLIST,
+        ).disabled_if(move || !in_playlist))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-remove").with_placeholder("Remove from Library"),
+            REMOVE_SELECTION,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("mus-menu-delete").with_placeholder("Delete from Disk…"),
+            DELETE_SELECTION,
+        ))
+}
+
+/// Rate every selected track with one to `MAX_RATING` stars.
+fn rating_menu() -> MenuDesc<AppData> {
+    (1..=MAX_RATING).fold(
+        MenuDesc::new(LocalizedString::new("mus-menu-set-rating").with_placeholder("Set Rating")),
+        |menu, rating| {
+            menu.append(MenuItem::new(
+                LocalizedString::new("mus-menu-rating")
+                    .with_placeholder(STAR.repeat(rating as usize)),
+                RATE_SELECTION.with(Some(rating)),
+            ))
+        })
+}
+
+/// Album names alphabetically, ignoring case and with numbers in natural order, with tracks that
+/// have no album last.
+fn compare_albums(a: &Track, b: &Track) -> Ordering {
+    match (a.album(), b.album()) {
+        (Some(a), Some(b)) => compare_natural(&a.to_lowercase(), &b.to_lowercase()),
+        (Some(_), None)    => Ordering::Less,
+        (None, Some(_))    => Ordering::Greater,
+        (None, None)       => Ordering::Equal,
+    }
+}
+
+/// What to show for `field` in a column.
+fn cell_text(track: &Track, field: TrackField) -> String {
+    match field {
+        TrackField::Length => format_duration(track.length().into()),
+        TrackField::Rating => {
+            let stars = track.rating().unwrap_or(0).max(0).min(MAX_RATING) as usize;
+            STAR.repeat(stars) + &EMPTY_STAR.repeat(MAX_RATING as usize - stars)
+        }
+        TrackField::LastPlayed => track.last_played().map(format_time_ago).unwrap_or_default(),
+        // Rows are one line, so a comment's lines are run together
+        TrackField::Comment => track.comment().unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" / "),
+        field => track.get_field_as_string(field),
+    }
+}
+
+/// Order two tracks by `field`. Numbers compare as numbers, and missing values come first.
+/// Text is compared ignoring case and accents, unless `strict`, when they matter. Either way,
+/// numbers in text are in natural order, so "Track 2" comes before "Track 10".
+fn compare_by(a: &Track, b: &Track, field: TrackField, strict: bool) -> Ordering {
+    let text = |a: Option<&str>, b: Option<&str>| match (a, b) {
+        (Some(a), Some(b)) if strict => compare_natural(a, b),
+        (Some(a), Some(b)) => compare_text(a, b),
+        // Missing values first, as for numbers
+        (a, b) => a.cmp(&b),
+    };
+
+    match field {
+        TrackField::Path       => text(Some(a.path()), Some(b.path())),
+        TrackField::Title      => text(a.title(), b.title()),
+        TrackField::Artist     => text(a.artist(), b.artist()),
+        TrackField::Album      => text(a.album(), b.album()),
+        TrackField::Comment    => text(a.comment(), b.comment()),
+        TrackField::Genre      => text(a.genre(), b.genre()),
+        TrackField::Year       => a.year().cmp(&b.year()),
+        TrackField::Track      => a.track().cmp(&b.track()),
+        TrackField::Length     => a.length().cmp(&b.length()),
+        TrackField::Bitrate    => a.bitrate().cmp(&b.bitrate()),
+        TrackField::Samplerate => a.samplerate().cmp(&b.samplerate()),
+        TrackField::Rating     => a.rating().cmp(&b.rating()),
+        TrackField::Disc       => a.disc().cmp(&b.disc()),
+        TrackField::PlayCount  => a.play_count().cmp(&b.play_count()),
+        TrackField::LastPlayed => a.last_played().cmp(&b.last_played()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The titles of the rows, in order.
+    fn titles(data: &TrackListData) -> Vec<String> {
+        let tracks = data.tracks.read().unwrap();
+        data.rows.iter()
+            .map(|&index| tracks[index].title().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn refreshing_a_stale_track_clears_its_badge() {
+        let mut data = TrackListData::new(vec![Track::for_test(1, "a"), Track::for_test(2, "b")]);
+        data.set_stale_tracks(vec![1, 2]);
+        assert_eq!(data.stale_track_at(0), Some(1));
+
+        data.replace_track(Track::for_test(1, "a, retagged"));
+
+        assert_eq!(data.stale_track_at(0), None);
+        assert_eq!(data.stale_track_at(1), Some(2));
+        assert_eq!(titles(&data), vec!["a, retagged", "b"]);
+    }
+
+    #[test]
+    fn random_rows_are_always_shown_rows() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(random_row(&mut rng, 0), None);
+        for _ in 0..100 {
+            assert!(random_row(&mut rng, 3).unwrap() < 3);
+        }
+        assert_eq!(random_row(&mut rng, 1), Some(0));
+    }
+
+    #[test]
+    fn random_rows_are_the_same_for_the_same_seed() {
+        let picks = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..20).map(|_| random_row(&mut rng, 1000).unwrap()).collect::<Vec<usize>>()
+        };
+
+        assert_eq!(picks(42), picks(42));
+        assert_ne!(picks(42), picks(43));
+    }
+
+    #[test]
+    fn random_tracks_are_picked_from_the_filtered_rows() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "Blue"), Track::for_test(2, "Red"), Track::for_test(3, "Blue Moon"),
+        ]);
+        data.filter = "blue".to_string();
+        data.update_rows();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let tracks = data.tracks.read().unwrap();
+        for _ in 0..20 {
+            let row = random_row(&mut rng, data.n_rows()).unwrap();
+            assert!(tracks[data.rows[row]].title().unwrap().starts_with("Blue"));
+        }
+    }
+
+    /// A list showing the title and artist, scrolled to the top.
+    fn laid_out_list(action: EmptyAreaAction) -> TrackList {
+        let mut list = TrackList::new().with_empty_area_action(action);
+        // As if the children had been built
+        list.columns = list.column_layout.clone();
+        list.viewport = Some(Viewport { content_size: Size::ZERO, rect: Rect::ZERO });
+        list
+    }
+
+    #[test]
+    fn clicks_below_the_last_row_hit_nothing() {
+        let list = laid_out_list(EmptyAreaAction::Nothing);
+        let data = TrackListData::new(vec![Track::for_test(1, "a"), Track::for_test(2, "b")]);
+        let (header, row) = (list.header_height(), list.row_height());
+
+        assert_eq!(list.row_at(header + row * 0.5, &data), Some(0));
+        assert_eq!(list.row_at(header + row * 1.5, &data), Some(1));
+        assert_eq!(list.row_at(header + row * 2.5, &data), None);
+        assert_eq!(list.row_at(header + row * 100., &data), None);
+        // The header isn't a row either
+        assert_eq!(list.row_at(header - 1., &data), None);
+    }
+
+    #[test]
+    fn clicks_on_two_line_rows_hit_the_right_track() {
+        let mut list = laid_out_list(EmptyAreaAction::Nothing);
+        let data = TrackListData::new(vec![
+            Track::for_test(1, "a"), Track::for_test(2, "b"), Track::for_test(3, "c"),
+        ]);
+        let one_line = list.row_height();
+        list.mode = LayoutMode::Detailed;
+        let (header, row) = (list.header_height(), list.row_height());
+
+        assert_eq!(row - SPACER_SIZE, 2. * (one_line - SPACER_SIZE));
+        // Both lines of a row belong to it
+        assert_eq!(list.row_at(header + row * 0.25, &data), Some(0));
+        assert_eq!(list.row_at(header + row * 0.75, &data), Some(0));
+        assert_eq!(list.row_at(header + row * 1.25, &data), Some(1));
+        assert_eq!(list.row_at(header + row * 2.75, &data), Some(2));
+        assert_eq!(list.row_at(header + row * 3.25, &data), None);
+    }
+
+    #[test]
+    fn double_clicking_the_empty_area_does_what_it_is_set_to() {
+        let data = TrackListData::new(vec![Track::for_test(1, "a"), Track::for_test(2, "b")]);
+        data.selected_tracks.write().unwrap().push(1);
+
+        assert!(!laid_out_list(EmptyAreaAction::Nothing).empty_area_double_clicked(&data));
+        assert_eq!(*data.selected_tracks.read().unwrap(), vec![1]);
+
+        assert!(laid_out_list(EmptyAreaAction::ClearSelection).empty_area_double_clicked(&data));
+        assert!(data.selected_tracks.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn lists_without_columns_show_nothing() {
+        let mut list = laid_out_list(EmptyAreaAction::Nothing);
+        list.columns.clear();
+        let data = TrackListData::new(vec![Track::for_test(1, "a"), Track::for_test(2, "b")]);
+
+        assert_eq!(list.cells_per_row(), 0);
+        assert_eq!(list.n_rows(&data), 0);
+        assert_eq!(list.n_lines(&data), 0);
+        assert_eq!(list.total_size(Size::new(300., 200.), &data), Size::new(300., 200.));
+        assert_eq!(list.row_at(list.header_height() + 1., &data), None);
+        assert_eq!(list.column_at(SPACER_SIZE + 1., 300., &data), None);
+    }
+
+    #[test]
+    fn lists_with_one_column_give_it_the_whole_width() {
+        let mut list = laid_out_list(EmptyAreaAction::Nothing);
+        list.columns = vec![(TrackField::Title, 1.)];
+        let data = TrackListData::new(vec![Track::for_test(1, "a"), Track::for_test(2, "b")]);
+
+        assert_eq!(list.cells_per_row(), 1);
+        assert_eq!(list.n_rows(&data), 2);
+        let height = (2. * list.row_height()).max(1.);
+        assert_eq!(list.total_size(Size::new(300., 1.), &data), Size::new(300., height));
+        assert_eq!(list.column_at(SPACER_SIZE + 1., 300., &data),
+                   Some((TrackField::Title, SPACER_SIZE)));
+        assert_eq!(list.column_at(300. - SPACER_SIZE - 1., 300., &data),
+                   Some((TrackField::Title, SPACER_SIZE)));
+        assert_eq!(list.column_at(300., 300., &data), None);
+    }
+
+    #[test]
+    fn shift_click_ranges_run_from_the_anchor_either_way() {
+        assert_eq!(select_range(2, 5).collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+        assert_eq!(select_range(5, 2).collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn shift_clicking_the_anchor_selects_just_it() {
+        assert_eq!(select_range(3, 3).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(select_range(0, 0).collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn number_fields_sort_as_numbers() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "Track 10").with_disc(None, Some(10)),
+            Track::for_test(2, "Track 2").with_disc(None, Some(2)),
+            Track::for_test(3, "Track 1").with_disc(None, Some(1)),
+            Track::for_test(4, "Untracked"),
+        ]);
+
+        data.sort_by(TrackField::Track);
+        // Missing values first
+        assert_eq!(titles(&data), vec!["Untracked", "Track 1", "Track 2", "Track 10"]);
+    }
+
+    #[test]
+    fn titles_sort_in_natural_order() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "Track 10"), Track::for_test(2, "track 2"),
+            Track::for_test(3, "Track 1"),
+        ]);
+
+        data.sort_by(TrackField::Title);
+        assert_eq!(titles(&data), vec!["Track 1", "track 2", "Track 10"]);
+
+        data.set_strict_sort(true);
+        assert_eq!(titles(&data), vec!["Track 1", "Track 10", "track 2"]);
+    }
+
+    #[test]
+    fn narrow_lists_collapse_to_one_column() {
+        assert_eq!(LayoutMode::choose(COMPACT_WIDTH - 1., false), LayoutMode::Compact);
+        assert_eq!(LayoutMode::choose(0., false), LayoutMode::Compact);
+        assert_eq!(LayoutMode::choose(COMPACT_WIDTH, false), LayoutMode::Columns);
+        assert_eq!(LayoutMode::choose(1920., false), LayoutMode::Columns);
+    }
+
+    #[test]
+    fn two_line_rows_are_used_at_any_width() {
+        assert_eq!(LayoutMode::choose(COMPACT_WIDTH - 1., true), LayoutMode::Detailed);
+        assert_eq!(LayoutMode::choose(1920., true), LayoutMode::Detailed);
+    }
+
+    #[test]
+    fn two_line_rows_are_twice_as_tall_apart_from_the_spacing() {
+        assert_eq!(LayoutMode::Columns.row_height(20.), 20. + SPACER_SIZE);
+        assert_eq!(LayoutMode::Compact.row_height(20.), 40. + SPACER_SIZE);
+        assert_eq!(LayoutMode::Detailed.row_height(20.), 40. + SPACER_SIZE);
+    }
+
+    #[test]
+    fn albums_are_grouped_in_disc_then_track_order() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "2-1").with_album("Album").with_disc(Some(2), Some(1)),
+            Track::for_test(2, "1-2").with_album("Album").with_disc(Some(1), Some(2)),
+            Track::for_test(3, "1-1").with_album("Album").with_disc(Some(1), Some(1)),
+            // Without a disc number, a track is on the first disc
+            Track::for_test(4, "x-3").with_album("Album").with_disc(None, Some(3)),
+        ]);
+
+        data.set_group_by_album(true);
+
+        assert_eq!(titles(&data), vec!["1-1", "1-2", "x-3", "2-1"]);
+    }
+
+    #[test]
+    fn sorting_by_disc_treats_a_missing_disc_as_the_first() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "2").with_disc(Some(2), None),
+            Track::for_test(2, "none").with_disc(None, None),
+            Track::for_test(3, "3").with_disc(Some(3), None),
+        ]);
+
+        data.sort_by(TrackField::Disc);
+        assert_eq!(titles(&data), vec!["none", "2", "3"]);
+
+        data.sort_by(TrackField::Disc);
+        assert_eq!(titles(&data), vec!["3", "2", "none"]);
+    }
+
+    fn clamped(shares: &[f64], avail_width: f64) -> Vec<f64> {
+        let mut columns: Vec<(TrackField, f64)> = shares.iter()
+            .map(|&share| (TrackField::Title, share))
+            .collect();
+        clamp_columns(&mut columns, avail_width, MIN_COLUMN_WIDTH, MAX_COLUMN_SHARE);
+        columns.into_iter().map(|(_, share)| share).collect()
+    }
+
+    fn assert_shares(shares: Vec<f64>, expected: &[f64]) {
+        assert_eq!(shares.len(), expected.len());
+        for (share, expected) in shares.iter().zip(expected) {
+            assert!((share - expected).abs() < 1e-9, "{:?} isn't {:?}", shares, expected);
+        }
+    }
+
+    #[test]
+    fn columns_within_limits_are_left_alone() {
+        assert_shares(clamped(&[0.5, 0.5], 400.), &[0.5, 0.5]);
+        assert_shares(clamped(&[0.2, 0.3, 0.5], 400.), &[0.2, 0.3, 0.5]);
+    }
+
+    #[test]
+    fn narrow_columns_are_widened_at_the_others_expense() {
+        assert_shares(clamped(&[0., 1.], 400.), &[0.2, 0.8]);
+        // In proportion to how wide the others are
+        assert_shares(clamped(&[0.02, 0.49, 0.49], 1000.), &[0.04, 0.48, 0.48]);
+        assert_shares(clamped(&[0.01, 0.01, 0.98], 400.), &[0.1, 0.1, 0.8]);
+    }
+
+    #[test]
+    fn wide_columns_give_their_space_to_the_others() {
+        assert_shares(clamped(&[0.9, 0.1], 400.), &[0.8, 0.2]);
+        assert_shares(clamped(&[0.96, 0.02, 0.02], 1000.), &[0.8, 0.1, 0.1]);
+    }
+
+    #[test]
+    fn shrinking_the_window_widens_columns_squeezed_below_the_minimum() {
+        // Fine at 1000 pixels, but the second column would be 20 pixels wide at 100
+        assert_shares(clamped(&[0.8, 0.2], 1000.), &[0.8, 0.2]);
+        assert_shares(clamped(&[0.8, 0.2], 100.), &[0.6, 0.4]);
+    }
+
+    #[test]
+    fn columns_share_a_very_narrow_list_equally() {
+        assert_shares(clamped(&[0.3, 0.3, 0.4], 100.), &[1. / 3., 1. / 3., 1. / 3.]);
+        assert_shares(clamped(&[0.9, 0.1], 0.), &[0.5, 0.5]);
+        assert_shares(clamped(&[0.9, 0.1], -10.), &[0.5, 0.5]);
+        // And an only column gets all of it
+        assert_shares(clamped(&[0.4], 400.), &[1.]);
+    }
+
+    #[test]
+    fn shares_that_dont_add_up_are_renormalized() {
+        assert_shares(clamped(&[1., 1.], 400.), &[0.5, 0.5]);
+        assert_shares(clamped(&[0., 0.], 400.), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn dragging_a_divider_stops_at_the_limits() {
+        let mut list = laid_out_list(EmptyAreaAction::Nothing);
+        let data = TrackListData::new(Vec::new());
+        let width = 400. + SPACER_SIZE;
+
+        // The second column can't take more than its share, before the first gets too narrow
+        list.drag_divider(0, 0., width, &data);
+        assert_shares(list.columns.iter().map(|column| column.1).collect(), &[0.2, 0.8]);
+        list.drag_divider(0, width, width, &data);
+        assert_shares(list.column_layout.iter().map(|column| column.1).collect(), &[0.8, 0.2]);
+        list.drag_divider(0, SPACER_SIZE + 100., width, &data);
+        assert_shares(list.column_layout.iter().map(|column| column.1).collect(), &[0.25, 0.75]);
+    }
+
+    #[test]
+    fn the_filter_matches_paths_and_comments_when_searching_everywhere() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "So What").with_comment("Ripped from vinyl"),
+            Track::for_test(2, "Vinyl Frontier"),
+            Track::for_test(3, "Freddie Freeloader"),
+        ]);
+
+        data.filter = "vinyl".to_string();
+        data.update_rows();
+        assert_eq!(titles(&data), vec!["Vinyl Frontier"]);
+
+        data.set_search_everywhere(true);
+        assert_eq!(titles(&data), vec!["So What", "Vinyl Frontier"]);
+
+        // Every track's path has its id in it
+        data.filter = "3.flac".to_string();
+        data.update_rows();
+        assert_eq!(titles(&data), vec!["Freddie Freeloader"]);
+    }
+
+    #[test]
+    fn scoped_filters_only_match_their_field() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "Miles Ahead").with_artist("Gil Evans").with_genre("Jazz"),
+            Track::for_test(2, "So What").with_artist("Miles Davis").with_genre("Jazz"),
+            Track::for_test(3, "Milestones").with_artist("Miles Davis").with_genre("Bop"),
+        ]);
+
+        data.filter = "miles".to_string();
+        data.update_rows();
+        assert_eq!(titles(&data), vec!["Miles Ahead", "So What", "Milestones"]);
+
+        data.filter = "artist:miles".to_string();
+        data.update_rows();
+        assert_eq!(titles(&data), vec!["So What", "Milestones"]);
+
+        data.filter = "artist:miles genre:jazz".to_string();
+        data.update_rows();
+        assert_eq!(titles(&data), vec!["So What"]);
+    }
+
+    #[test]
+    fn retagged_tracks_are_searched_the_same_way() {
+        let mut data = TrackListData::new(vec![Track::for_test(1, "So What")]);
+        data.set_search_everywhere(true);
+        data.filter = "vinyl".to_string();
+        data.update_rows();
+        assert!(titles(&data).is_empty());
+
+        data.update_track(Track::for_test(1, "So What").with_comment("Vinyl rip"));
+        data.add_tracks(vec![Track::for_test(2, "Blue in Green").with_comment("Vinyl too")]);
+        assert_eq!(titles(&data), vec!["So What", "Blue in Green"]);
+    }
+
+    #[test]
+    fn views_without_their_own_columns_show_the_default_ones() {
+        let default = [(TrackField::Title, 0.5), (TrackField::Album, 0.5)];
+        let mut list = TrackList::new().with_column_layout(&default);
+        let mut data = TrackListData::new(Vec::new());
+
+        data.set_columns(Some(vec![(TrackField::Track, 0.25), (TrackField::Title, 0.75)]));
+        list.use_view_columns(&data);
+        assert_eq!(list.column_layout, vec![(TrackField::Track, 0.25), (TrackField::Title, 0.75)]);
+
+        data.set_columns(None);
+        list.use_view_columns(&data);
+        assert_eq!(list.column_layout, default.to_vec());
+    }
+
+    #[test]
+    fn positions_follow_their_track_to_its_new_row() {
+        let mut data = TrackListData::new(vec![
+            Track::for_test(1, "b"), Track::for_test(2, "c"), Track::for_test(3, "a"),
+        ]);
+        *data.selected_tracks.write().unwrap() = vec![1, 0];
+        *data.scroll_offset.write().unwrap() = 40.;
+        let position = data.position();
+        assert_eq!(position, ListPosition { track: Some(1), scroll_offset: 40. });
+
+        let mut reloaded = TrackListData::new(data.tracks.read().unwrap().clone());
+        reloaded.sort_by(TrackField::Title);
+        reloaded.restore_position(position);
+
+        assert_eq!(titles(&reloaded), vec!["a", "b", "c"]);
+        assert_eq!(*reloaded.selected_tracks.read().unwrap(), vec![1]);
+        assert_eq!(*reloaded.scroll_offset.read().unwrap(), 40.);
+    }
+
+    #[test]
+    fn positions_whose_track_is_gone_start_at_the_top() {
+        let mut data = TrackListData::new(vec![Track::for_test(1, "a")]);
+        data.restore_position(ListPosition { track: Some(2), scroll_offset: 40. });
+
+        assert!(data.selected_tracks.read().unwrap().is_empty());
+        assert_eq!(*data.scroll_offset.read().unwrap(), 0.);
+    }
+
+    #[test]
+    fn positions_without_a_selection_keep_their_scroll() {
+        let mut data = TrackListData::new(vec![Track::for_test(1, "a")]);
+        assert_eq!(data.position(), ListPosition::default());
+
+        data.restore_position(ListPosition { track: None, scroll_offset: 40. });
+        assert!(data.selected_tracks.read().unwrap().is_empty());
+        assert_eq!(*data.scroll_offset.read().unwrap(), 40.);
+    }
+
+    #[test]
+    fn multi_line_comments_are_shown_on_one_line() {
+        let track = Track::for_test(1, "So What").with_comment("Side A\n\n  Track 1\n");
+        assert_eq!(cell_text(&track, TrackField::Comment), "Side A / Track 1");
+        assert_eq!(cell_text(&Track::for_test(2, "Freddie"), TrackField::Comment), "");
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use druid::{BoxConstraints, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx,
+            RenderContext, Size, UpdateCtx, Widget};
+use druid::theme::WINDOW_BACKGROUND_COLOR;
+
+use crate::AppData;
+use crate::db::Track;
+
+/// How long the now-playing display takes to fade in a new track.
+pub const TRANSITION_DURATION: Duration = Duration::from_millis(250);
+
+/// A fade in of the now-playing display after the track changes. There's only ever one: a
+/// change partway through starts it over rather than queueing another, so skipping quickly
+/// through tracks never leaves fades to catch up on.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Transition {
+    /// How far in the fade is, if it's running.
+    elapsed: Option<Duration>,
+}
+
+impl Transition {
+    /// Fade in the track that's just come on, from the start. With `reduce_motion` it's shown
+    /// straight away, and any fade already running stops.
+    pub fn start(&mut self, reduce_motion: bool) {
+        self.elapsed = if reduce_motion { None } else { Some(Duration::default()) };
+    }
+
+    /// Move the fade on by `interval`, the time since the last frame. Returns whether there's
+    /// more of it, so another frame is needed.
+    pub fn advance(&mut self, interval: Duration) -> bool {
+        self.elapsed = self.elapsed
+            .map(|elapsed| elapsed + interval)
+            .filter(|&elapsed| elapsed < TRANSITION_DURATION);
+        self.is_running()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    /// How much of the new track is still hidden behind the background, from 1 at the start of
+    /// the fade to 0 at the end. Eased out, so it slows as it finishes.
+    pub fn cover(&self) -> f64 {
+        match self.elapsed {
+            Some(elapsed) => {
+                let progress = elapsed.as_secs_f64() / TRANSITION_DURATION.as_secs_f64();
+                (1. - progress.min(1.)).powi(2)
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// Fades `child` in whenever the current track changes, unless motion is reduced.
+pub struct TrackTransition<W> {
+    child: W,
+    transition: Transition,
+}
+
+impl<W> TrackTransition<W> {
+    pub fn new(child: W) -> Self {
+        TrackTransition { child, transition: Transition::default() }
+    }
+}
+
+impl<W: Widget<AppData>> Widget<AppData> for TrackTransition<W> {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppData, env: &Env) {
+        if let Event::AnimFrame(interval) = event {
+            if self.transition.advance(Duration::from_nanos(*interval)) {
+                ctx.request_anim_frame();
+            }
+            ctx.request_paint();
+        }
+
+        self.child.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &AppData,
+                 env: &Env) {
+        self.child.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData, env: &Env) {
+        let id = |data: &AppData| data.queue.current().map(Track::id);
+        if id(old_data) != id(data) {
+            // A frame is only asked for when none already is, so fades never overlap
+            let running = self.transition.is_running();
+            self.transition.start(data.reduce_motion);
+            if self.transition.is_running() && !running {
+                ctx.request_anim_frame();
+            }
+            ctx.request_paint();
+        }
+
+        self.child.update(ctx, old_data, data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &AppData,
+              env: &Env) -> Size {
+        self.child.layout(ctx, bc, data, env)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppData, env: &Env) {
+        self.child.paint(ctx, data, env);
+
+        let cover = self.transition.cover();
+        if cover > 0. {
+            let bounds = ctx.size().to_rect();
+            ctx.fill(bounds, &env.get(WINDOW_BACKGROUND_COLOR).with_alpha(cover));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    /// How many frames it takes `transition` to finish.
+    fn frames_left(transition: &mut Transition) -> usize {
+        let mut frames = 0;
+        while transition.advance(FRAME) {
+            frames += 1;
+        }
+        frames + 1
+    }
+
+    #[test]
+    fn transitions_fade_in_then_stop() {
+        let mut transition = Transition::default();
+        assert!(!transition.is_running());
+        assert_eq!(transition.cover(), 0.);
+
+        transition.start(false);
+        assert_eq!(transition.cover(), 1.);
+        assert!(transition.advance(TRANSITION_DURATION / 2));
+        assert!(transition.cover() > 0. && transition.cover() < 0.5);
+
+        assert!(!transition.advance(TRANSITION_DURATION / 2));
+        assert_eq!(transition.cover(), 0.);
+        // Frames that were already asked for don't bring it back
+        assert!(!transition.advance(FRAME));
+    }
+
+    #[test]
+    fn rapid_changes_restart_the_one_transition() {
+        let mut fresh = Transition::default();
+        fresh.start(false);
+        let whole = frames_left(&mut fresh);
+
+        let mut transition = Transition::default();
+
+        // Skipping through ten tracks, a couple of frames apart
+        for _ in 0..10 {
+            transition.start(false);
+            transition.advance(FRAME);
+            transition.advance(FRAME);
+        }
+        assert!(transition.is_running());
+        assert_eq!(frames_left(&mut transition), whole - 2);
+
+        // However many changes come in one frame, there's just the one fade
+        for _ in 0..10 {
+            transition.start(false);
+        }
+        assert_eq!(transition.cover(), 1.);
+        assert_eq!(frames_left(&mut transition), whole);
+    }
+
+    #[test]
+    fn reduced_motion_skips_transitions() {
+        let mut transition = Transition::default();
+        transition.start(true);
+        assert!(!transition.is_running());
+        assert_eq!(transition.cover(), 0.);
+
+        // Including one that's already running
+        transition.start(false);
+        transition.advance(FRAME);
+        transition.start(true);
+        assert!(!transition.is_running());
+        assert_eq!(transition.cover(), 0.);
+    }
+}
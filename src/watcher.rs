@@ -0,0 +1,294 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use druid::{ExtEventSink, Target};
+use log::{error, info, trace, warn};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use walkdir::WalkDir;
+
+use crate::commands::{LIBRARY_CHANGED, LIBRARY_SCANNED, LIBRARY_SCAN_STATUS};
+use crate::db::{Database, DatabaseError, FileRemoval, Library, ScanOptions, Track};
+use crate::libraries::ScanEvent;
+
+/// How long a file has to be left alone before we read it, so that a file being copied in is
+/// read once, when it's all there.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long to wait for more events after one arrives, so that e.g. moving a whole album in
+/// updates the track list once rather than once per track.
+const BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+/// What happened to a library's tracks as its files changed. Sent by its `LibraryWatcher`.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryChanges {
+    pub added: Vec<Track>,
+    /// Tracks that were retagged or moved, as they are now.
+    pub changed: Vec<Track>,
+    pub removed: Vec<i64>,
+}
+
+impl LibraryChanges {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Keeps a library's tracks in step with its folder, so that files added, removed or retagged
+/// outside of mus show up without a rescan. Changes are read on a thread of their own, with its
+/// own connection, and sent to the app as `LIBRARY_CHANGED`. Dropping the watcher stops it.
+pub struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl LibraryWatcher {
+    /// Start watching `library`'s folder. 'Individual Tracks' has none, so it can't be watched.
+    pub fn new(library: Library, options: ScanOptions,
+               event_sink: ExtEventSink) -> notify::Result<LibraryWatcher> {
+        let root = library.path().ok_or(notify::Error::PathNotFound)?;
+        // Scans store canonical paths, so events need to come in with them too
+        let root = Path::new(root).canonicalize()?;
+
+        let (tx, rx) = channel();
+        let mut watcher = watcher(tx, DEBOUNCE)?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        info!("Watching {} for changes", root.display());
+
+        thread::spawn(move || watch_library(library, root, options, rx, event_sink));
+
+        Ok(LibraryWatcher { _watcher: watcher })
+    }
+}
+
+/// Apply a library's file events to the database in batches, until its watcher is dropped.
+fn watch_library(library: Library, root: PathBuf, options: ScanOptions,
+                 events: Receiver<DebouncedEvent>, event_sink: ExtEventSink) {
+    let db = match Database::new() {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Could not open the database to watch {}: {}", library.name(), e);
+            return;
+        }
+    };
+
+    let mut sync = LibrarySync {
+        db,
+        library,
+        root,
+        options,
+        changes: LibraryChanges::default(),
+        rescan: false,
+    };
+
+    // The watcher hangs up when it's dropped
+    while let Ok(event) = events.recv() {
+        let mut batch = vec![event];
+        loop {
+            match events.recv_timeout(BATCH_WINDOW) {
+                Ok(event) => batch.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        for event in batch {
+            if let Err(e) = sync.apply(event) {
+                error!("Could not update {} after its files changed: {}", sync.library.name(), e);
+            }
+        }
+
+        let changes = std::mem::take(&mut sync.changes);
+        if !changes.is_empty()
+            && event_sink.submit_command(LIBRARY_CHANGED, changes, Target::Auto).is_err() {
+            // The app has closed
+            return;
+        }
+
+        if std::mem::take(&mut sync.rescan) {
+            let id = sync.library.id();
+            event_sink.submit_command(LIBRARY_SCAN_STATUS, (id, ScanEvent::Started), Target::Auto)
+                .ok();
+            let event = match sync.rescan_library(&event_sink) {
+                Ok(()) => ScanEvent::Finished,
+                Err(e) => {
+                    error!("Could not rescan {}: {}", sync.library.name(), e);
+                    ScanEvent::Failed(e.to_string())
+                }
+            };
+            event_sink.submit_command(LIBRARY_SCAN_STATUS, (id, event), Target::Auto).ok();
+        }
+    }
+}
+
+/// The state of a watched library as its events are applied.
+struct LibrarySync {
+    db: Database,
+    library: Library,
+    /// The library's path, canonicalized.
+    root: PathBuf,
+    options: ScanOptions,
+    /// What's changed so far in this batch.
+    changes: LibraryChanges,
+    /// Whether an event couldn't be followed file by file, so the whole library needs a rescan.
+    rescan: bool,
+}
+
+impl LibrarySync {
+    fn apply(&mut self, event: DebouncedEvent) -> Result<(), DatabaseError> {
+        match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => self.add(&path),
+            DebouncedEvent::Remove(path) => self.remove(&path),
+            DebouncedEvent::Rename(from, to) => self.rename(&from, &to),
+            // Events were lost, e.g. because too many happened at once
+            DebouncedEvent::Rescan => {
+                self.rescan = true;
+                Ok(())
+            }
+            DebouncedEvent::Error(e, path) => {
+                match path {
+                    Some(path) => warn!("Error watching {}: {}", path.display(), e),
+                    None => warn!("Error watching {}: {}", self.library.name(), e),
+                }
+                Ok(())
+            }
+            // Only previews of the debounced events to come
+            DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_) => Ok(()),
+        }
+    }
+
+    /// Read a file that's appeared or been modified, or everything in a folder that's appeared.
+    fn add(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        if self.is_skipped(path) {
+            return Ok(());
+        }
+
+        if !path.is_dir() {
+            return self.add_file(path);
+        }
+
+        let skip_hidden = self.options.skip_hidden;
+        let walk = WalkDir::new(path).follow_links(true).into_iter()
+            .filter_entry(|entry| !(skip_hidden && entry.depth() > 0
+                && entry.file_name().to_str().map_or(false, |name| name.starts_with('.'))));
+        for entry in walk {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                self.add_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        // It may already be gone again
+        let path = match path.canonicalize().map(PathBuf::into_os_string) {
+            Ok(path) => path,
+            Err(_) => return Ok(()),
+        };
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        match self.db.scan_file(&self.library, path, &self.options)? {
+            Some((track, true)) => self.changes.added.push(track),
+            Some((track, false)) => self.changes.changed.push(track),
+            None => trace!("Ignoring {}, it isn't a track", path),
+        }
+
+        Ok(())
+    }
+
+    /// Forget the track at `path`, or every track under it if it was a folder.
+    fn remove(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        let removed = match path.to_str() {
+            Some(path_str) => match self.db.track_by_path(path_str)? {
+                Some(track) => vec![track],
+                None => self.db.tracks_under(path)?,
+            },
+            None => return Ok(()),
+        };
+
+        for track in removed {
+            self.db.remove_track(track.id(), FileRemoval::Keep)?;
+            self.changes.removed.push(track.id());
+        }
+
+        Ok(())
+    }
+
+    /// Follow a file or folder to its new path, keeping its tracks' ratings and playlists.
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<(), DatabaseError> {
+        // Moved somewhere we don't scan, so as far as the library's concerned it's gone
+        if !to.starts_with(&self.root) || self.is_skipped(to) {
+            return self.remove(from);
+        }
+
+        if to.is_dir() {
+            return match self.db.rewrite_root(from, to) {
+                // Nothing we knew about, e.g. because it was hidden before
+                Ok(0) => self.add(to),
+                Ok(_) => {
+                    let moved = self.db.tracks_under(to)?;
+                    self.changes.changed.extend(moved);
+                    Ok(())
+                }
+                // Some of its files moved on again already
+                Err(DatabaseError::MissingFiles(_)) => {
+                    self.rescan = true;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            };
+        }
+
+        if let (Some(from_str), Some(to_str)) = (from.to_str(), to.to_str()) {
+            if self.db.track_by_path(to_str)?.is_none() {
+                if let Some(track) = self.db.move_track(from_str, to_str)? {
+                    self.changes.changed.push(track);
+                    return Ok(());
+                }
+            }
+        }
+
+        // Renamed over another track, e.g. by a tag editor saving to a temporary file first
+        self.remove(from)?;
+        self.add_file(to)
+    }
+
+    /// Whether `path` is somewhere scans leave alone.
+    fn is_skipped(&self, path: &Path) -> bool {
+        self.options.skip_hidden && path.strip_prefix(&self.root).map_or(false, |relative| {
+            relative.components().any(|component| {
+                component.as_os_str().to_str().map_or(false, |name| name.starts_with('.'))
+            })
+        })
+    }
+
+    /// Rescan the whole library, incrementally, and send the app what's in it now.
+    fn rescan_library(&mut self, event_sink: &ExtEventSink) -> Result<(), DatabaseError> {
+        let id = self.library.id();
+        // Pick up a new name, or stop if the library's been removed since
+        let library = match self.db.libraries()?.into_iter().find(|library| library.id() == id) {
+            Some(library) => library,
+            None => return Ok(()),
+        };
+
+        let mut added = 0;
+        let mut count_track = |_track: Track| added += 1;
+        self.db.scan_library(library.clone(), false, &self.options, None,
+                             Some(&mut count_track))?;
+        let tracks = self.db.library_tracks(id)?;
+        self.library = library.clone();
+
+        if event_sink.submit_command(LIBRARY_SCANNED, (library, tracks, added), Target::Auto)
+            .is_err() {
+            warn!("Could not report the rescan of {}, the app has already closed",
+                  self.library.name());
+        }
+        Ok(())
+    }
+}